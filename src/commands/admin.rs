@@ -0,0 +1,240 @@
+use futures::{future, Future};
+use regex::Captures;
+
+use db::{DirectRooms, Reminders};
+use room_state::RoomStateCache;
+
+use super::{send_reply, Command, CommandContext};
+
+/// Admin command listing every user's pending reminders. As these are
+/// private it only works in the admin's direct room with the bot.
+pub struct ListAllCommand {
+    reminders: Reminders,
+    room_state: RoomStateCache,
+    direct_rooms: DirectRooms,
+}
+
+impl ListAllCommand {
+    pub fn new(
+        reminders: Reminders,
+        room_state: RoomStateCache,
+        direct_rooms: DirectRooms,
+    ) -> ListAllCommand {
+        ListAllCommand {
+            reminders,
+            room_state,
+            direct_rooms,
+        }
+    }
+}
+
+impl Command for ListAllCommand {
+    fn name(&self) -> &'static str {
+        "list all"
+    }
+
+    fn pattern(&self) -> &'static str {
+        r"^list\s+all\s*$"
+    }
+
+    fn usage(&self) -> &'static str {
+        "list all"
+    }
+
+    fn description(&self) -> &'static str {
+        "List everyone's pending reminders"
+    }
+
+    fn admin_only(&self) -> bool {
+        true
+    }
+
+    fn handle(&self, ctx: &CommandContext, _args: &Captures) -> Box<Future<Item = (), Error = ()>> {
+        match self.direct_rooms.get_room_for_user(&ctx.event.sender) {
+            Ok(Some(ref room_id)) if room_id == ctx.room_id => {}
+            Ok(_) => {
+                return ctx.reply(
+                    "Error: Everyone's reminders can only be listed in a direct message with me",
+                );
+            }
+            Err(err) => {
+                error!(ctx.logger, "Failed to get direct room"; "error" => %err);
+                return ctx.reply(&format!("Error: Failed to get reminders: {}", err));
+            }
+        }
+
+        let reminders = match self.reminders.get_all_pending_reminders() {
+            Ok(reminders) => reminders,
+            Err(err) => {
+                error!(ctx.logger, "Failed to get reminders"; "error" => %err);
+                return ctx.reply(&format!("Error: Failed to get reminders: {}", err));
+            }
+        };
+
+        if reminders.is_empty() {
+            return ctx.reply("There are no pending reminders");
+        }
+
+        let lines: Vec<String> = reminders
+            .iter()
+            .map(|reminder| {
+                let room = reminder
+                    .room_id
+                    .as_ref()
+                    .map(|room_id| format!(" in {}", self.room_state.room_name(room_id)))
+                    .unwrap_or_default();
+
+                format!(
+                    "{} for {} via {}{} at '{}': '{}'",
+                    reminder.id,
+                    reminder.destination,
+                    reminder.channel.as_str(),
+                    room,
+                    reminder.due.to_rfc2822(),
+                    reminder.text
+                )
+            })
+            .collect();
+
+        ctx.reply(&format!(
+            "{} pending reminders:\n{}",
+            lines.len(),
+            lines.join("\n")
+        ))
+    }
+}
+
+/// Admin command cancelling all of a user's pending reminders.
+pub struct PurgeCommand {
+    reminders: Reminders,
+}
+
+impl PurgeCommand {
+    pub fn new(reminders: Reminders) -> PurgeCommand {
+        PurgeCommand { reminders }
+    }
+}
+
+impl Command for PurgeCommand {
+    fn name(&self) -> &'static str {
+        "purge"
+    }
+
+    fn pattern(&self) -> &'static str {
+        r"^purge\s+(@[^\s:]+:\S+)\s*$"
+    }
+
+    fn usage(&self) -> &'static str {
+        "purge <user id>"
+    }
+
+    fn description(&self) -> &'static str {
+        "Cancel all of a user's pending reminders"
+    }
+
+    fn admin_only(&self) -> bool {
+        true
+    }
+
+    fn handle(&self, ctx: &CommandContext, args: &Captures) -> Box<Future<Item = (), Error = ()>> {
+        let user_id = &args[1];
+
+        match self.reminders.cancel_all_reminders(user_id) {
+            Ok(count) => {
+                info!(ctx.logger, "Purged reminders"; "user_id" => user_id, "count" => count);
+                ctx.reply(&format!(
+                    "Cancelled {} pending reminder(s) for {}",
+                    count, user_id
+                ))
+            }
+            Err(err) => {
+                error!(ctx.logger, "Failed to purge reminders"; "error" => %err);
+                ctx.reply(&format!("Error: Failed to cancel reminders: {}", err))
+            }
+        }
+    }
+}
+
+/// Admin command sending a message to every room the bot is in.
+#[derive(Default)]
+pub struct BroadcastCommand;
+
+impl BroadcastCommand {
+    pub fn new() -> BroadcastCommand {
+        BroadcastCommand
+    }
+}
+
+impl Command for BroadcastCommand {
+    fn name(&self) -> &'static str {
+        "broadcast"
+    }
+
+    fn pattern(&self) -> &'static str {
+        r"(?s)^broadcast\s+(.+)$"
+    }
+
+    fn usage(&self) -> &'static str {
+        "broadcast <message>"
+    }
+
+    fn description(&self) -> &'static str {
+        "Send a message to every room the bot is in"
+    }
+
+    fn admin_only(&self) -> bool {
+        true
+    }
+
+    fn handle(&self, ctx: &CommandContext, args: &Captures) -> Box<Future<Item = (), Error = ()>> {
+        let message = args[1].to_string();
+        let message_sender = ctx.message_sender.clone();
+        let room_id = ctx.room_id.to_string();
+        let event = ctx.event.clone();
+        let logger = ctx.logger.clone();
+
+        let f = ctx.message_sender.get_joined_rooms().then(
+            move |res| -> Box<Future<Item = (), Error = ()>> {
+                let rooms = match res {
+                    Ok(rooms) => rooms,
+                    Err(()) => {
+                        return send_reply(
+                            &message_sender,
+                            &room_id,
+                            &event,
+                            "Error: Failed to get the bot's rooms",
+                        );
+                    }
+                };
+
+                info!(logger, "Broadcasting message"; "rooms" => rooms.len());
+
+                // Failures are logged by the sender, so carry on with the
+                // other rooms and count how many worked.
+                let sends: Vec<_> = rooms
+                    .iter()
+                    .map(|room| {
+                        message_sender
+                            .send_text_message(room, &message)
+                            .then(|res| Ok::<_, ()>(res.is_ok()))
+                    })
+                    .collect();
+
+                let total = rooms.len();
+                let f = future::join_all(sends).and_then(move |results| {
+                    let sent = results.iter().filter(|&&ok| ok).count();
+                    send_reply(
+                        &message_sender,
+                        &room_id,
+                        &event,
+                        &format!("Broadcast sent to {} of {} rooms", sent, total),
+                    )
+                });
+
+                Box::new(f)
+            },
+        );
+
+        Box::new(f)
+    }
+}
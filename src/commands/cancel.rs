@@ -0,0 +1,167 @@
+use chrono::{Duration, Utc};
+use futures::Future;
+use regex::Captures;
+
+use db::Reminders;
+
+use super::{Command, CommandContext};
+
+/// How long after creating a reminder it can be undone.
+const UNDO_WINDOW_MINS: i64 = 5;
+
+/// Cancels one of the user's pending reminders.
+pub struct CancelCommand {
+    reminders: Reminders,
+}
+
+impl CancelCommand {
+    pub fn new(reminders: Reminders) -> CancelCommand {
+        CancelCommand { reminders }
+    }
+}
+
+impl Command for CancelCommand {
+    fn name(&self) -> &'static str {
+        "cancel"
+    }
+
+    fn pattern(&self) -> &'static str {
+        r"^cancel\s+(\S+)\s*$"
+    }
+
+    fn usage(&self) -> &'static str {
+        "cancel <id>"
+    }
+
+    fn description(&self) -> &'static str {
+        "Cancel a reminder"
+    }
+
+    fn handle(&self, ctx: &CommandContext, args: &Captures) -> Box<Future<Item = (), Error = ()>> {
+        let reminder_id = &args[1];
+
+        match self
+            .reminders
+            .cancel_reminder(reminder_id, &ctx.event.sender)
+        {
+            Ok(true) => {
+                info!(ctx.logger, "Cancelled reminder"; "reminder_id" => reminder_id);
+                ctx.reply(&format!("Cancelled reminder {}", reminder_id))
+            }
+            Ok(false) => ctx.reply(&format!(
+                "Error: No pending reminder with ID {}",
+                reminder_id
+            )),
+            Err(err) => {
+                error!(ctx.logger, "Failed to cancel reminder"; "error" => %err);
+                ctx.reply(&format!("Error: Failed to cancel reminder: {}", err))
+            }
+        }
+    }
+}
+
+/// Cancels all of the user's pending reminders. This needs registering
+/// before `CancelCommand`, which would otherwise match it.
+pub struct CancelAllCommand {
+    reminders: Reminders,
+}
+
+impl CancelAllCommand {
+    pub fn new(reminders: Reminders) -> CancelAllCommand {
+        CancelAllCommand { reminders }
+    }
+}
+
+impl Command for CancelAllCommand {
+    fn name(&self) -> &'static str {
+        "cancel all"
+    }
+
+    fn pattern(&self) -> &'static str {
+        r"^cancel\s+all\s*$"
+    }
+
+    fn usage(&self) -> &'static str {
+        "cancel all"
+    }
+
+    fn description(&self) -> &'static str {
+        "Cancel all your pending reminders"
+    }
+
+    fn handle(&self, ctx: &CommandContext, _args: &Captures) -> Box<Future<Item = (), Error = ()>> {
+        match self.reminders.cancel_all_reminders(&ctx.event.sender) {
+            Ok(count) => {
+                info!(ctx.logger, "Cancelled all reminders"; "count" => count);
+                ctx.reply(&format!("Cancelled {} pending reminder(s)", count))
+            }
+            Err(err) => {
+                error!(ctx.logger, "Failed to cancel reminders"; "error" => %err);
+                ctx.reply(&format!("Error: Failed to cancel reminders: {}", err))
+            }
+        }
+    }
+}
+
+/// Cancels the reminder the user just created, e.g. if the date was parsed
+/// wrongly.
+pub struct UndoCommand {
+    reminders: Reminders,
+}
+
+impl UndoCommand {
+    pub fn new(reminders: Reminders) -> UndoCommand {
+        UndoCommand { reminders }
+    }
+}
+
+impl Command for UndoCommand {
+    fn name(&self) -> &'static str {
+        "undo"
+    }
+
+    fn pattern(&self) -> &'static str {
+        r"^undo\s*$"
+    }
+
+    fn usage(&self) -> &'static str {
+        "undo"
+    }
+
+    fn description(&self) -> &'static str {
+        "Cancel the reminder you created in the last few minutes"
+    }
+
+    fn handle(&self, ctx: &CommandContext, _args: &Captures) -> Box<Future<Item = (), Error = ()>> {
+        let since = Utc::now() - Duration::minutes(UNDO_WINDOW_MINS);
+
+        let reminder = match self
+            .reminders
+            .get_last_created_reminder(&ctx.event.sender, &since)
+        {
+            Ok(Some(reminder)) => reminder,
+            Ok(None) => {
+                return ctx.reply(&format!(
+                    "Error: You haven't created a reminder in the last {} minutes",
+                    UNDO_WINDOW_MINS
+                ));
+            }
+            Err(err) => {
+                error!(ctx.logger, "Failed to get last reminder"; "error" => %err);
+                return ctx.reply(&format!("Error: Failed to get reminder: {}", err));
+            }
+        };
+
+        if let Err(err) = self.reminders.remove_reminder(&reminder.id) {
+            error!(ctx.logger, "Failed to undo reminder"; "error" => %err);
+            return ctx.reply(&format!("Error: Failed to cancel reminder: {}", err));
+        }
+
+        info!(ctx.logger, "Undid reminder"; "reminder_id" => &reminder.id);
+
+        ctx.reply(&format!(
+            "Cancelled reminder {} '{}'",
+            reminder.id, reminder.text
+        ))
+    }
+}
@@ -0,0 +1,58 @@
+use chrono::Utc;
+use futures::Future;
+use regex::Captures;
+
+use db::Reminders;
+
+use super::{Command, CommandContext};
+
+/// Marks a delivered reminder as done, which also stops it nagging.
+pub struct DoneCommand {
+    reminders: Reminders,
+}
+
+impl DoneCommand {
+    pub fn new(reminders: Reminders) -> DoneCommand {
+        DoneCommand { reminders }
+    }
+}
+
+impl Command for DoneCommand {
+    fn name(&self) -> &'static str {
+        "done"
+    }
+
+    fn pattern(&self) -> &'static str {
+        r"^done\s+(\S+)\s*$"
+    }
+
+    fn usage(&self) -> &'static str {
+        "done <id>"
+    }
+
+    fn description(&self) -> &'static str {
+        "Mark a delivered reminder as done, which stops one set with '!nag' from being sent again"
+    }
+
+    fn handle(&self, ctx: &CommandContext, args: &Captures) -> Box<Future<Item = (), Error = ()>> {
+        let reminder_id = &args[1];
+
+        match self
+            .reminders
+            .mark_reminder_done(reminder_id, &ctx.event.sender, &Utc::now())
+        {
+            Ok(true) => {
+                info!(ctx.logger, "Marked reminder done"; "reminder_id" => reminder_id);
+                ctx.reply(&format!("OK, reminder {} is done", reminder_id))
+            }
+            Ok(false) => ctx.reply(&format!(
+                "Error: No delivered reminder with ID {} that isn't done already",
+                reminder_id
+            )),
+            Err(err) => {
+                error!(ctx.logger, "Failed to mark reminder done"; "error" => %err);
+                ctx.reply(&format!("Error: Failed to mark reminder done: {}", err))
+            }
+        }
+    }
+}
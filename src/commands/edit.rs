@@ -0,0 +1,300 @@
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use futures::Future;
+use regex::Captures;
+
+use date::{parse_human_datetime, Recurrence};
+use db::{Reminders, UserSettings};
+
+use super::{date_error_message, get_parse_settings, get_timezone, Command, CommandContext};
+
+/// Changes the text of a pending reminder.
+pub struct EditCommand {
+    reminders: Reminders,
+}
+
+impl EditCommand {
+    pub fn new(reminders: Reminders) -> EditCommand {
+        EditCommand { reminders }
+    }
+}
+
+impl Command for EditCommand {
+    fn name(&self) -> &'static str {
+        "edit"
+    }
+
+    fn pattern(&self) -> &'static str {
+        r"^edit\s+(\S+)\s+to\s+(.+)$"
+    }
+
+    fn usage(&self) -> &'static str {
+        "edit <id> to <what>"
+    }
+
+    fn description(&self) -> &'static str {
+        "Change the text of a reminder"
+    }
+
+    fn handle(&self, ctx: &CommandContext, args: &Captures) -> Box<Future<Item = (), Error = ()>> {
+        let reminder_id = &args[1];
+        let text = &args[2];
+
+        match self
+            .reminders
+            .update_reminder_text(reminder_id, &ctx.event.sender, text)
+        {
+            Ok(true) => {
+                info!(ctx.logger, "Edited reminder"; "reminder_id" => reminder_id);
+                ctx.reply(&format!("Updated reminder {} to '{}'", reminder_id, text))
+            }
+            Ok(false) => ctx.reply(&format!(
+                "Error: No pending reminder with ID {}",
+                reminder_id
+            )),
+            Err(err) => {
+                error!(ctx.logger, "Failed to edit reminder"; "error" => %err);
+                ctx.reply(&format!("Error: Failed to edit reminder: {}", err))
+            }
+        }
+    }
+}
+
+/// Changes when a pending reminder is due.
+pub struct RescheduleCommand {
+    reminders: Reminders,
+    user_settings: UserSettings,
+}
+
+impl RescheduleCommand {
+    pub fn new(reminders: Reminders, user_settings: UserSettings) -> RescheduleCommand {
+        RescheduleCommand {
+            reminders,
+            user_settings,
+        }
+    }
+}
+
+impl Command for RescheduleCommand {
+    fn name(&self) -> &'static str {
+        "reschedule"
+    }
+
+    fn pattern(&self) -> &'static str {
+        r"^reschedule\s+(\S+)\s+(.+)$"
+    }
+
+    fn usage(&self) -> &'static str {
+        "reschedule <id> <when>"
+    }
+
+    fn description(&self) -> &'static str {
+        "Change when a reminder is due"
+    }
+
+    fn handle(&self, ctx: &CommandContext, args: &Captures) -> Box<Future<Item = (), Error = ()>> {
+        let logger = ctx.logger;
+        let reminder_id = &args[1];
+        let at = &args[2];
+
+        let tz = get_timezone(&self.user_settings, ctx);
+        let now = Utc::now().with_timezone(&tz);
+        let settings = get_parse_settings(&self.user_settings, ctx);
+
+        let due = match parse_human_datetime(at, now, &settings) {
+            Ok(date) => date,
+            Err(err) => {
+                info!(logger, "Failed to parse date {}", at);
+                return ctx.reply(&date_error_message(at, &err));
+            }
+        };
+
+        if due < now {
+            return ctx.reply(&format!("Error: Due date in past: {}", due.to_rfc2822()));
+        }
+
+        match self.reminders.reschedule_reminder(
+            reminder_id,
+            &ctx.event.sender,
+            &due.with_timezone(&Utc),
+        ) {
+            Ok(true) => {
+                info!(logger, "Rescheduled reminder"; "reminder_id" => reminder_id);
+                ctx.reply(&format!(
+                    "Rescheduled reminder {} to '{}'",
+                    reminder_id,
+                    due.to_rfc2822()
+                ))
+            }
+            Ok(false) => ctx.reply(&format!(
+                "Error: No pending reminder with ID {}",
+                reminder_id
+            )),
+            Err(err) => {
+                error!(logger, "Failed to reschedule reminder"; "error" => %err);
+                ctx.reply(&format!("Error: Failed to reschedule reminder: {}", err))
+            }
+        }
+    }
+}
+
+/// Moves a recurring reminder on past its next occurrence, keeping the rest
+/// of the series.
+pub struct SkipCommand {
+    reminders: Reminders,
+    user_settings: UserSettings,
+}
+
+impl SkipCommand {
+    pub fn new(reminders: Reminders, user_settings: UserSettings) -> SkipCommand {
+        SkipCommand {
+            reminders,
+            user_settings,
+        }
+    }
+}
+
+impl Command for SkipCommand {
+    fn name(&self) -> &'static str {
+        "skip"
+    }
+
+    fn pattern(&self) -> &'static str {
+        r"^skip\s+(\S+)\s*$"
+    }
+
+    fn usage(&self) -> &'static str {
+        "skip <id>"
+    }
+
+    fn description(&self) -> &'static str {
+        "Skip the next time a repeating reminder is due, without cancelling the rest"
+    }
+
+    fn handle(&self, ctx: &CommandContext, args: &Captures) -> Box<Future<Item = (), Error = ()>> {
+        let logger = ctx.logger;
+        let reminder_id = &args[1];
+
+        let reminder = match self
+            .reminders
+            .get_pending_reminder(reminder_id, &ctx.event.sender)
+        {
+            Ok(Some(reminder)) => reminder,
+            Ok(None) => {
+                return ctx.reply(&format!(
+                    "Error: No pending reminder with ID {}",
+                    reminder_id
+                ));
+            }
+            Err(err) => {
+                error!(logger, "Failed to get reminder to skip"; "error" => %err);
+                return ctx.reply(&format!("Error: Failed to get reminder: {}", err));
+            }
+        };
+
+        let recurrence = match reminder.recurrence {
+            Some(ref recurrence) => recurrence,
+            None => {
+                return ctx.reply(&format!(
+                    "Error: Reminder {} doesn't repeat, use 'cancel {}' instead",
+                    reminder.id, reminder.id
+                ));
+            }
+        };
+
+        // Repeats keep to the timezone the reminder was set in
+        let tz = reminder
+            .timezone
+            .unwrap_or_else(|| get_timezone(&self.user_settings, ctx));
+        let due = reminder.due.with_timezone(&tz);
+        let now = Utc::now().with_timezone(&tz);
+
+        let next = match next_after_skip(recurrence, due, reminder.repeat_until, now) {
+            Some(next) => next,
+            None => {
+                return ctx.reply(&format!(
+                    "Error: '{}' is the last time reminder {} is due, use 'cancel {}' instead",
+                    due.to_rfc2822(),
+                    reminder.id,
+                    reminder.id
+                ));
+            }
+        };
+
+        match self.reminders.reschedule_reminder(
+            &reminder.id,
+            &ctx.event.sender,
+            &next.with_timezone(&Utc),
+        ) {
+            Ok(true) => {
+                info!(logger, "Skipped reminder occurrence"; "reminder_id" => &reminder.id);
+                ctx.reply(&format!(
+                    "Skipped reminder {} on '{}', it's next due '{}'",
+                    reminder.id,
+                    due.to_rfc2822(),
+                    next.to_rfc2822()
+                ))
+            }
+            Ok(false) => ctx.reply(&format!(
+                "Error: No pending reminder with ID {}",
+                reminder_id
+            )),
+            Err(err) => {
+                error!(logger, "Failed to skip reminder"; "error" => %err);
+                ctx.reply(&format!("Error: Failed to skip reminder: {}", err))
+            }
+        }
+    }
+}
+
+/// The occurrence a repeating reminder moves on to when the one due is
+/// skipped. If it's overdue, e.g. as delivery is paused, any others that
+/// have already gone by are skipped too.
+fn next_after_skip(
+    recurrence: &Recurrence,
+    due: DateTime<Tz>,
+    until: Option<DateTime<Utc>>,
+    now: DateTime<Tz>,
+) -> Option<DateTime<Tz>> {
+    let mut next = recurrence.next_occurrence(due);
+    while let Some(date) = next {
+        if date > now {
+            break;
+        }
+        next = recurrence.next_occurrence(date);
+    }
+
+    next.filter(|next| until.map_or(true, |until| next.with_timezone(&Utc) <= until))
+}
+
+#[test]
+fn next_after_skip_test() {
+    use chrono::TimeZone;
+    use chrono_tz::UTC;
+
+    let daily = Recurrence::Days(1);
+    let due = UTC.ymd(2014, 7, 8).and_hms(9, 0, 0);
+
+    // Skipping one that's coming up moves on to the one after
+    assert_eq!(
+        next_after_skip(&daily, due, None, UTC.ymd(2014, 7, 8).and_hms(8, 0, 0)),
+        Some(UTC.ymd(2014, 7, 9).and_hms(9, 0, 0))
+    );
+
+    // An overdue one moves on past now
+    assert_eq!(
+        next_after_skip(&daily, due, None, UTC.ymd(2014, 7, 11).and_hms(12, 0, 0)),
+        Some(UTC.ymd(2014, 7, 12).and_hms(9, 0, 0))
+    );
+
+    // Unless that's after the series ends
+    assert_eq!(
+        next_after_skip(
+            &daily,
+            due,
+            Some(Utc.ymd(2014, 7, 11).and_hms(9, 0, 0)),
+            UTC.ymd(2014, 7, 11).and_hms(12, 0, 0)
+        ),
+        None
+    );
+}
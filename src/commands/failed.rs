@@ -0,0 +1,70 @@
+use futures::Future;
+use regex::Captures;
+
+use db::FailedReminders;
+
+use super::{Command, CommandContext};
+
+/// Admin command listing reminders we gave up trying to deliver.
+pub struct FailedCommand {
+    failed_reminders: FailedReminders,
+}
+
+impl FailedCommand {
+    pub fn new(failed_reminders: FailedReminders) -> FailedCommand {
+        FailedCommand { failed_reminders }
+    }
+}
+
+impl Command for FailedCommand {
+    fn name(&self) -> &'static str {
+        "failed"
+    }
+
+    fn pattern(&self) -> &'static str {
+        r"^failed\s*$"
+    }
+
+    fn usage(&self) -> &'static str {
+        "failed"
+    }
+
+    fn description(&self) -> &'static str {
+        "List reminders that couldn't be delivered"
+    }
+
+    fn admin_only(&self) -> bool {
+        true
+    }
+
+    fn handle(&self, ctx: &CommandContext, _args: &Captures) -> Box<Future<Item = (), Error = ()>> {
+        let failures = match self.failed_reminders.get_recent_failures(20) {
+            Ok(failures) => failures,
+            Err(err) => {
+                error!(ctx.logger, "Failed to get failed reminders"; "error" => %err);
+                return ctx.reply(&format!("Error: Failed to get failed reminders: {}", err));
+            }
+        };
+
+        if failures.is_empty() {
+            return ctx.reply("There are no failed reminders");
+        }
+
+        let lines: Vec<String> = failures
+            .iter()
+            .map(|failed| {
+                format!(
+                    "{} for {} via {} at '{}', {} attempts: {}",
+                    failed.reminder_id,
+                    failed.destination,
+                    failed.channel,
+                    failed.failed_at.to_rfc2822(),
+                    failed.attempts,
+                    failed.error
+                )
+            })
+            .collect();
+
+        ctx.reply(&format!("Failed reminders:\n{}", lines.join("\n")))
+    }
+}
@@ -0,0 +1,52 @@
+use futures::Future;
+use regex::Captures;
+
+use super::{Command, CommandContext};
+
+/// Lists the registered commands, hiding admin commands from non-admins.
+pub struct HelpCommand {
+    /// The prefix to show in the usage of each command
+    prefix: String,
+}
+
+impl HelpCommand {
+    pub fn new(prefix: String) -> HelpCommand {
+        HelpCommand { prefix }
+    }
+}
+
+impl Command for HelpCommand {
+    fn name(&self) -> &'static str {
+        "help"
+    }
+
+    fn pattern(&self) -> &'static str {
+        r"^help\s*$"
+    }
+
+    fn usage(&self) -> &'static str {
+        "help"
+    }
+
+    fn description(&self) -> &'static str {
+        "Show this message"
+    }
+
+    fn handle(&self, ctx: &CommandContext, _args: &Captures) -> Box<Future<Item = (), Error = ()>> {
+        let lines: Vec<String> = ctx
+            .commands
+            .iter()
+            .filter(|command| ctx.is_admin || !command.admin_only())
+            .map(|command| {
+                format!(
+                    "{}: {} - {}",
+                    self.prefix,
+                    command.usage(),
+                    command.description()
+                )
+            })
+            .collect();
+
+        ctx.reply(&format!("Supported commands:\n{}", lines.join("\n")))
+    }
+}
@@ -0,0 +1,198 @@
+use chrono::{NaiveDate, Utc};
+use futures::Future;
+use regex::Captures;
+
+use date::parse_calendar_date;
+use db::{Calendar, UserSettings};
+
+use super::{get_parse_settings, get_timezone, Command, CommandContext};
+
+/// Parses the date of a holiday relative to the sender's current time.
+fn parse_holiday(
+    user_settings: &UserSettings,
+    ctx: &CommandContext,
+    input: &str,
+) -> Result<NaiveDate, String> {
+    let tz = get_timezone(user_settings, ctx);
+    let now = Utc::now().with_timezone(&tz);
+    let settings = get_parse_settings(user_settings, ctx);
+
+    parse_calendar_date(input, now, &settings)
+        .map_err(|err| format!("Error: Failed to parse date {}: {}", input, err))
+}
+
+/// e.g. "Thu 25 December 2024"
+fn format_holiday(date: NaiveDate) -> String {
+    date.format("%a %-d %B %Y").to_string()
+}
+
+/// Admin command adding a day off to the holiday calendar, which business
+/// days skip.
+pub struct AddHolidayCommand {
+    user_settings: UserSettings,
+    calendar: Calendar,
+}
+
+impl AddHolidayCommand {
+    pub fn new(user_settings: UserSettings, calendar: Calendar) -> AddHolidayCommand {
+        AddHolidayCommand {
+            user_settings,
+            calendar,
+        }
+    }
+}
+
+impl Command for AddHolidayCommand {
+    fn name(&self) -> &'static str {
+        "add holiday"
+    }
+
+    fn pattern(&self) -> &'static str {
+        r"^add\s+holiday\s+(.+?)\s*$"
+    }
+
+    fn usage(&self) -> &'static str {
+        "add holiday <date>"
+    }
+
+    fn description(&self) -> &'static str {
+        "Add a day off that 'next business day' and 'in 3 working days' skip, e.g. 'add holiday 25 december 2024'"
+    }
+
+    fn admin_only(&self) -> bool {
+        true
+    }
+
+    fn handle(&self, ctx: &CommandContext, args: &Captures) -> Box<Future<Item = (), Error = ()>> {
+        let date = match parse_holiday(&self.user_settings, ctx, &args[1]) {
+            Ok(date) => date,
+            Err(msg) => return ctx.reply(&msg),
+        };
+
+        match self.calendar.add_holiday(date) {
+            Ok(true) => {
+                info!(ctx.logger, "Added holiday"; "date" => %date);
+                ctx.reply(&format!("Added holiday {}", format_holiday(date)))
+            }
+            Ok(false) => ctx.reply(&format!("{} is already a holiday", format_holiday(date))),
+            Err(err) => {
+                error!(ctx.logger, "Failed to add holiday"; "error" => %err);
+                ctx.reply(&format!("Error: Failed to persist holiday: {}", err))
+            }
+        }
+    }
+}
+
+/// Admin command removing a day from the holiday calendar.
+pub struct RemoveHolidayCommand {
+    user_settings: UserSettings,
+    calendar: Calendar,
+}
+
+impl RemoveHolidayCommand {
+    pub fn new(user_settings: UserSettings, calendar: Calendar) -> RemoveHolidayCommand {
+        RemoveHolidayCommand {
+            user_settings,
+            calendar,
+        }
+    }
+}
+
+impl Command for RemoveHolidayCommand {
+    fn name(&self) -> &'static str {
+        "remove holiday"
+    }
+
+    fn pattern(&self) -> &'static str {
+        r"^remove\s+holiday\s+(.+?)\s*$"
+    }
+
+    fn usage(&self) -> &'static str {
+        "remove holiday <date>"
+    }
+
+    fn description(&self) -> &'static str {
+        "Remove a day from the holiday calendar. Reminders already set are kept"
+    }
+
+    fn admin_only(&self) -> bool {
+        true
+    }
+
+    fn handle(&self, ctx: &CommandContext, args: &Captures) -> Box<Future<Item = (), Error = ()>> {
+        let date = match parse_holiday(&self.user_settings, ctx, &args[1]) {
+            Ok(date) => date,
+            Err(msg) => return ctx.reply(&msg),
+        };
+
+        match self.calendar.remove_holiday(date) {
+            Ok(true) => {
+                info!(ctx.logger, "Removed holiday"; "date" => %date);
+                ctx.reply(&format!("Removed holiday {}", format_holiday(date)))
+            }
+            Ok(false) => ctx.reply(&format!("Error: {} isn't a holiday", format_holiday(date))),
+            Err(err) => {
+                error!(ctx.logger, "Failed to remove holiday"; "error" => %err);
+                ctx.reply(&format!("Error: Failed to remove holiday: {}", err))
+            }
+        }
+    }
+}
+
+/// Lists the upcoming days in the holiday calendar.
+pub struct ListHolidaysCommand {
+    user_settings: UserSettings,
+    calendar: Calendar,
+}
+
+impl ListHolidaysCommand {
+    pub fn new(user_settings: UserSettings, calendar: Calendar) -> ListHolidaysCommand {
+        ListHolidaysCommand {
+            user_settings,
+            calendar,
+        }
+    }
+}
+
+impl Command for ListHolidaysCommand {
+    fn name(&self) -> &'static str {
+        "holidays"
+    }
+
+    fn pattern(&self) -> &'static str {
+        r"^(?:list\s+)?holidays\s*$"
+    }
+
+    fn usage(&self) -> &'static str {
+        "holidays"
+    }
+
+    fn description(&self) -> &'static str {
+        "List the upcoming days off that business days skip"
+    }
+
+    fn handle(&self, ctx: &CommandContext, _args: &Captures) -> Box<Future<Item = (), Error = ()>> {
+        let holidays = match self.calendar.get_holidays() {
+            Ok(holidays) => holidays,
+            Err(err) => {
+                error!(ctx.logger, "Failed to get holidays"; "error" => %err);
+                return ctx.reply(&format!("Error: Failed to get holidays: {}", err));
+            }
+        };
+
+        let tz = get_timezone(&self.user_settings, ctx);
+        let today = Utc::now().with_timezone(&tz).naive_local().date();
+
+        let lines: Vec<String> = holidays
+            .into_iter()
+            .filter(|date| *date >= today)
+            .map(format_holiday)
+            .collect();
+
+        if lines.is_empty() {
+            return ctx.reply("There are no upcoming holidays");
+        }
+
+        ctx.reply(&format!("Upcoming holidays:\n{}", lines.join("\n")))
+    }
+}
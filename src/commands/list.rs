@@ -0,0 +1,159 @@
+use chrono_tz::Tz;
+use futures::Future;
+use regex::Captures;
+
+use date::format_repeat;
+use db::{Reminder, Reminders, UserSettings};
+
+use super::{get_timezone, Command, CommandContext};
+
+/// Lists the user's pending reminders.
+pub struct ListCommand {
+    reminders: Reminders,
+    user_settings: UserSettings,
+}
+
+impl ListCommand {
+    pub fn new(reminders: Reminders, user_settings: UserSettings) -> ListCommand {
+        ListCommand {
+            reminders,
+            user_settings,
+        }
+    }
+}
+
+impl Command for ListCommand {
+    fn name(&self) -> &'static str {
+        "list"
+    }
+
+    fn pattern(&self) -> &'static str {
+        r"^list\s*$"
+    }
+
+    fn usage(&self) -> &'static str {
+        "list"
+    }
+
+    fn description(&self) -> &'static str {
+        "List your pending reminders"
+    }
+
+    fn handle(&self, ctx: &CommandContext, _args: &Captures) -> Box<Future<Item = (), Error = ()>> {
+        let reminders = match self.reminders.get_reminders_for_user(&ctx.event.sender) {
+            Ok(reminders) => reminders,
+            Err(err) => {
+                error!(ctx.logger, "Failed to get reminders"; "error" => %err);
+                return ctx.reply(&format!("Error: Failed to get reminders: {}", err));
+            }
+        };
+
+        if reminders.is_empty() {
+            return ctx.reply("You have no pending reminders");
+        }
+
+        let tz = get_timezone(&self.user_settings, ctx);
+
+        let lines: Vec<String> = reminders
+            .iter()
+            .map(|reminder| format_reminder(reminder, tz))
+            .collect();
+
+        ctx.reply(&format!("Pending reminders:\n{}", lines.join("\n")))
+    }
+}
+
+/// Searches the user's pending reminders by text.
+pub struct FindCommand {
+    reminders: Reminders,
+    user_settings: UserSettings,
+}
+
+impl FindCommand {
+    pub fn new(reminders: Reminders, user_settings: UserSettings) -> FindCommand {
+        FindCommand {
+            reminders,
+            user_settings,
+        }
+    }
+}
+
+impl Command for FindCommand {
+    fn name(&self) -> &'static str {
+        "find"
+    }
+
+    fn pattern(&self) -> &'static str {
+        r"^find\s+(.+?)\s*$"
+    }
+
+    fn usage(&self) -> &'static str {
+        "find <text>"
+    }
+
+    fn description(&self) -> &'static str {
+        "Search your pending reminders, e.g. 'find groceries'"
+    }
+
+    fn handle(&self, ctx: &CommandContext, args: &Captures) -> Box<Future<Item = (), Error = ()>> {
+        let query = &args[1];
+
+        let reminders = match self.reminders.search_reminders(&ctx.event.sender, query) {
+            Ok(reminders) => reminders,
+            Err(err) => {
+                error!(ctx.logger, "Failed to search reminders"; "error" => %err);
+                return ctx.reply(&format!("Error: Failed to search reminders: {}", err));
+            }
+        };
+
+        if reminders.is_empty() {
+            return ctx.reply(&format!("No pending reminders matching '{}'", query));
+        }
+
+        let tz = get_timezone(&self.user_settings, ctx);
+
+        let lines: Vec<String> = reminders
+            .iter()
+            .map(|reminder| format_reminder(reminder, tz))
+            .collect();
+
+        ctx.reply(&format!(
+            "Reminders matching '{}':\n{}",
+            query,
+            lines.join("\n")
+        ))
+    }
+}
+
+fn format_reminder(reminder: &Reminder, tz: Tz) -> String {
+    let repeat = reminder
+        .recurrence
+        .as_ref()
+        .map(|r| {
+            format!(
+                " ({})",
+                format_repeat(
+                    r,
+                    reminder.due.with_timezone(&tz),
+                    reminder.repeat_until.map(|until| until.with_timezone(&tz))
+                )
+            )
+        })
+        .unwrap_or_default();
+
+    // Reminders set for someone else say who for
+    let recipient = if reminder.destination != reminder.creator {
+        format!(" for {}", reminder.destination)
+    } else {
+        String::new()
+    };
+
+    format!(
+        "{}: '{}'{} at '{}'{}",
+        reminder.id,
+        reminder.text,
+        recipient,
+        reminder.due.with_timezone(&tz).to_rfc2822(),
+        repeat
+    )
+}
@@ -0,0 +1,277 @@
+use chrono_tz::{Tz, UTC};
+use failure::Error;
+use futures::Future;
+use regex::{Captures, Regex};
+use slog::Logger;
+
+use std::rc::Rc;
+
+use date::{AmbiguousDate, ParseSettings, PastTime, TooFarAway};
+use db::{ReminderImage, RoomConfig, UserSettings};
+use matrix::types::Event;
+use matrix::MessageSender;
+use room_state::RoomStateCache;
+
+mod admin;
+mod cancel;
+mod done;
+mod edit;
+mod failed;
+mod help;
+mod holidays;
+mod list;
+mod named_dates;
+mod phone;
+mod remind;
+mod settings;
+mod snooze;
+mod status;
+
+pub use self::admin::{BroadcastCommand, ListAllCommand, PurgeCommand};
+pub use self::cancel::{CancelAllCommand, CancelCommand, UndoCommand};
+pub use self::done::DoneCommand;
+pub use self::edit::{EditCommand, RescheduleCommand, SkipCommand};
+pub use self::failed::FailedCommand;
+pub use self::help::HelpCommand;
+pub use self::holidays::{AddHolidayCommand, ListHolidaysCommand, RemoveHolidayCommand};
+pub use self::list::{FindCommand, ListCommand};
+pub use self::named_dates::{AddNamedDateCommand, ListNamedDatesCommand, RemoveNamedDateCommand};
+pub use self::phone::{ForgetPhoneCommand, SetPhoneCommand, VerifyCommand};
+pub use self::remind::{ConfirmCommand, PendingReminders, RemindCommand};
+pub use self::settings::{
+    AllowOthersCommand, PauseCommand, SetConfirmationsCommand, SetDateOrderCommand,
+    SetDeliveryCommand, SetDigestCommand, SetLanguageCommand, SetPartOfDayCommand,
+    SetQuietHoursCommand, SetRoomConfigCommand, SetTimezoneCommand,
+};
+pub use self::snooze::{AgainCommand, SnoozeCommand};
+pub use self::status::StatusCommand;
+
+/// The message a command is being run for.
+pub struct CommandContext<'a> {
+    pub logger: &'a Logger,
+    pub room_id: &'a str,
+    pub event: &'a Event,
+    pub is_admin: bool,
+    pub commands: &'a Commands,
+    pub message_sender: &'a Rc<MessageSender>,
+    pub room_state: &'a RoomStateCache,
+}
+
+impl<'a> CommandContext<'a> {
+    /// Sends a reply to the command's message.
+    pub fn reply(&self, text: &str) -> Box<Future<Item = (), Error = ()>> {
+        send_reply(self.message_sender, self.room_id, self.event, text)
+    }
+
+    /// The sender's display name in the room.
+    pub fn sender_name(&self) -> String {
+        self.room_state
+            .display_name(self.room_id, &self.event.sender)
+    }
+
+    /// The settings for the room the command was sent in.
+    pub fn room_config(&self) -> RoomConfig {
+        self.room_state.room_config(self.room_id)
+    }
+
+    /// Reacts to the command's message with the given key, e.g. an emoji.
+    pub fn react(&self, key: &str) -> Box<Future<Item = (), Error = ()>> {
+        let event_id = self.event.replaces().unwrap_or(&self.event.event_id);
+
+        let f = self
+            .message_sender
+            .send_reaction(self.room_id, event_id, key)
+            .map(|_| ())
+            .map_err(|_| ());
+
+        Box::new(f)
+    }
+}
+
+/// A command the bot understands.
+pub trait Command {
+    fn name(&self) -> &'static str;
+
+    /// Regex matched against the message with the bot prefix removed
+    fn pattern(&self) -> &'static str;
+
+    fn usage(&self) -> &'static str;
+
+    fn description(&self) -> &'static str;
+
+    /// Only admins can use (and see help for) the command
+    fn admin_only(&self) -> bool {
+        false
+    }
+
+    /// Whether the command is run again when its message is edited. The
+    /// edit is the context's event, and `Event::replaces` gives the
+    /// original.
+    fn handles_edits(&self) -> bool {
+        false
+    }
+
+    /// Runs the command, given the captures of its pattern.
+    fn handle(&self, ctx: &CommandContext, args: &Captures) -> Box<Future<Item = (), Error = ()>>;
+}
+
+/// The set of supported commands, in the order they are tried.
+#[derive(Default)]
+pub struct Commands {
+    commands: Vec<(Regex, Box<Command>)>,
+}
+
+impl Commands {
+    pub fn new() -> Commands {
+        Commands::default()
+    }
+
+    pub fn register<C: Command + 'static>(&mut self, command: C) {
+        let regex = Regex::new(command.pattern()).expect("invalid regex");
+        self.commands.push((regex, Box::new(command)));
+    }
+
+    /// Finds the first command matching the message.
+    pub fn find<'a, 't>(&'a self, body: &'t str) -> Option<(&'a Command, Captures<'t>)> {
+        self.commands
+            .iter()
+            .filter_map(|&(ref regex, ref command)| {
+                regex.captures(body).map(|capt| (&**command, capt))
+            })
+            .next()
+    }
+
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = &'a Command> + 'a {
+        self.commands.iter().map(|&(_, ref command)| &**command)
+    }
+}
+
+/// Gets the sender's timezone, falling back to the room's and then UTC.
+fn get_timezone(user_settings: &UserSettings, ctx: &CommandContext) -> Tz {
+    let tz = user_settings
+        .get_timezone(&ctx.event.sender)
+        .unwrap_or_else(|err| {
+            error!(ctx.logger, "Failed to get timezone"; "error" => %err);
+            None
+        });
+
+    tz.or_else(|| ctx.room_config().timezone).unwrap_or(UTC)
+}
+
+/// Gets how the sender wants dates and times interpreted, falling back to
+/// the room's language.
+fn get_parse_settings(user_settings: &UserSettings, ctx: &CommandContext) -> ParseSettings {
+    let mut settings = user_settings
+        .get_parse_settings(&ctx.event.sender)
+        .unwrap_or_else(|err| {
+            error!(ctx.logger, "Failed to get parse settings"; "error" => %err);
+            ParseSettings::default()
+        });
+
+    if settings.locale.is_none() {
+        settings.locale = ctx.room_config().locale;
+    }
+
+    settings
+}
+
+/// The reply when the date in a command couldn't be understood.
+fn date_error_message(input: &str, err: &Error) -> String {
+    if let Some(err) = err.downcast_ref::<AmbiguousDate>() {
+        return format!("Error: {}", err);
+    }
+    if let Some(err) = err.downcast_ref::<TooFarAway>() {
+        return format!("Error: {}", err);
+    }
+
+    match err.downcast_ref::<PastTime>() {
+        Some(err) => format!("Error: {}", err),
+        None => format!("Error: Failed to parse date {}", input),
+    }
+}
+
+/// Replies to the command's message, for when the `CommandContext` is no
+/// longer around, e.g. after waiting on another request. Failures are logged
+/// by the sender.
+fn send_reply(
+    message_sender: &Rc<MessageSender>,
+    room_id: &str,
+    event: &Event,
+    text: &str,
+) -> Box<Future<Item = (), Error = ()>> {
+    let f = message_sender
+        .send_reply(room_id, event, text)
+        .map(|_| ())
+        .map_err(|_| ());
+
+    Box::new(f)
+}
+
+/// The image sent by the event, if it's an image message.
+pub fn event_image(event: &Event) -> Option<ReminderImage> {
+    if event.content_str("msgtype")? != "m.image" {
+        return None;
+    }
+
+    // If the image has a caption that's the body, and the file name is
+    // given separately.
+    let name = event
+        .content_str("filename")
+        .or_else(|| event.content_str("body"))
+        .unwrap_or("image");
+
+    let mimetype = event
+        .content_value("info")
+        .and_then(|info| info.get("mimetype"))
+        .and_then(|mimetype| mimetype.as_str());
+
+    Some(ReminderImage {
+        url: event.content_str("url")?.to_string(),
+        name: name.to_string(),
+        mimetype: mimetype.map(str::to_string),
+    })
+}
+
+#[test]
+fn event_image_test() {
+    use serde_json;
+
+    let event: Event = serde_json::from_str(
+        r#"{
+            "type": "m.room.message",
+            "event_id": "$image:example.com",
+            "sender": "@alice:example.com",
+            "origin_server_ts": 1532000000000,
+            "content": {
+                "msgtype": "m.image",
+                "body": "testbot: remind me tomorrow to buy this",
+                "filename": "shoes.jpg",
+                "url": "mxc://example.com/abcdef",
+                "info": {"mimetype": "image/jpeg", "size": 1234}
+            }
+        }"#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        event_image(&event),
+        Some(ReminderImage {
+            url: "mxc://example.com/abcdef".to_string(),
+            name: "shoes.jpg".to_string(),
+            mimetype: Some("image/jpeg".to_string()),
+        })
+    );
+
+    let event: Event = serde_json::from_str(
+        r#"{
+            "type": "m.room.message",
+            "event_id": "$text:example.com",
+            "sender": "@alice:example.com",
+            "origin_server_ts": 1532000000000,
+            "content": {"msgtype": "m.text", "body": "testbot: list"}
+        }"#,
+    )
+    .unwrap();
+
+    assert_eq!(event_image(&event), None);
+}
@@ -0,0 +1,188 @@
+use chrono::NaiveDate;
+use futures::Future;
+use regex::Captures;
+
+use date::{parse_day_of_year, NamedDate};
+use db::Calendar;
+
+use super::{Command, CommandContext};
+
+/// e.g. "14 March"
+fn format_day_of_year(month: u32, day: u32) -> String {
+    // A leap year, so the 29th of February can be formatted
+    NaiveDate::from_ymd(2000, month, day)
+        .format("%-d %B")
+        .to_string()
+}
+
+/// Admin command naming a date, e.g. a birthday, so that everyone can set
+/// reminders for it by name.
+pub struct AddNamedDateCommand {
+    calendar: Calendar,
+}
+
+impl AddNamedDateCommand {
+    pub fn new(calendar: Calendar) -> AddNamedDateCommand {
+        AddNamedDateCommand { calendar }
+    }
+}
+
+impl Command for AddNamedDateCommand {
+    fn name(&self) -> &'static str {
+        "add date"
+    }
+
+    fn pattern(&self) -> &'static str {
+        r"^add\s+date\s+(.+?)\s+on\s+(.+?)\s*$"
+    }
+
+    fn usage(&self) -> &'static str {
+        "add date <name> on <day> <month>"
+    }
+
+    fn description(&self) -> &'static str {
+        "Name a date for everyone, e.g. 'add date mum's birthday on 14 march' for 'remind me on mum's birthday to call her'"
+    }
+
+    fn admin_only(&self) -> bool {
+        true
+    }
+
+    fn handle(&self, ctx: &CommandContext, args: &Captures) -> Box<Future<Item = (), Error = ()>> {
+        let name = &args[1];
+
+        let (month, day) = match parse_day_of_year(&args[2]) {
+            Ok(date) => date,
+            Err(err) => {
+                return ctx.reply(&format!(
+                    "Error: Failed to parse date {}: {}",
+                    &args[2], err
+                ));
+            }
+        };
+
+        let named_date = NamedDate {
+            name: name.to_string(),
+            month,
+            day,
+        };
+
+        if let Err(err) = self.calendar.set_named_date(&named_date) {
+            error!(ctx.logger, "Failed to add named date"; "error" => %err);
+            return ctx.reply(&format!("Error: Failed to persist date: {}", err));
+        }
+
+        info!(ctx.logger, "Added named date"; "name" => name, "month" => month, "day" => day);
+
+        ctx.reply(&format!(
+            "'{}' is now {}",
+            name,
+            format_day_of_year(month, day)
+        ))
+    }
+}
+
+/// Admin command removing a named date.
+pub struct RemoveNamedDateCommand {
+    calendar: Calendar,
+}
+
+impl RemoveNamedDateCommand {
+    pub fn new(calendar: Calendar) -> RemoveNamedDateCommand {
+        RemoveNamedDateCommand { calendar }
+    }
+}
+
+impl Command for RemoveNamedDateCommand {
+    fn name(&self) -> &'static str {
+        "remove date"
+    }
+
+    fn pattern(&self) -> &'static str {
+        r"^remove\s+date\s+(.+?)\s*$"
+    }
+
+    fn usage(&self) -> &'static str {
+        "remove date <name>"
+    }
+
+    fn description(&self) -> &'static str {
+        "Remove a named date. Reminders already set for it are kept"
+    }
+
+    fn admin_only(&self) -> bool {
+        true
+    }
+
+    fn handle(&self, ctx: &CommandContext, args: &Captures) -> Box<Future<Item = (), Error = ()>> {
+        let name = &args[1];
+
+        match self.calendar.remove_named_date(name) {
+            Ok(true) => {
+                info!(ctx.logger, "Removed named date"; "name" => name);
+                ctx.reply(&format!("Removed date '{}'", name))
+            }
+            Ok(false) => ctx.reply(&format!("Error: No date named '{}'", name)),
+            Err(err) => {
+                error!(ctx.logger, "Failed to remove named date"; "error" => %err);
+                ctx.reply(&format!("Error: Failed to remove date: {}", err))
+            }
+        }
+    }
+}
+
+/// Lists the dates admins have named.
+pub struct ListNamedDatesCommand {
+    calendar: Calendar,
+}
+
+impl ListNamedDatesCommand {
+    pub fn new(calendar: Calendar) -> ListNamedDatesCommand {
+        ListNamedDatesCommand { calendar }
+    }
+}
+
+impl Command for ListNamedDatesCommand {
+    fn name(&self) -> &'static str {
+        "dates"
+    }
+
+    fn pattern(&self) -> &'static str {
+        r"^(?:list\s+)?dates\s*$"
+    }
+
+    fn usage(&self) -> &'static str {
+        "dates"
+    }
+
+    fn description(&self) -> &'static str {
+        "List the named dates reminders can be set for, as well as holidays such as christmas"
+    }
+
+    fn handle(&self, ctx: &CommandContext, _args: &Captures) -> Box<Future<Item = (), Error = ()>> {
+        let named_dates = match self.calendar.get_named_dates() {
+            Ok(named_dates) => named_dates,
+            Err(err) => {
+                error!(ctx.logger, "Failed to get named dates"; "error" => %err);
+                return ctx.reply(&format!("Error: Failed to get dates: {}", err));
+            }
+        };
+
+        if named_dates.is_empty() {
+            return ctx.reply("There are no named dates");
+        }
+
+        let lines: Vec<String> = named_dates
+            .iter()
+            .map(|named_date| {
+                format!(
+                    "{}: {}",
+                    named_date.name,
+                    format_day_of_year(named_date.month, named_date.day)
+                )
+            })
+            .collect();
+
+        ctx.reply(&format!("Named dates:\n{}", lines.join("\n")))
+    }
+}
@@ -0,0 +1,309 @@
+use chrono::{Duration, Utc};
+use futures::{future, Future};
+use rand::{thread_rng, Rng};
+use regex::Captures;
+
+use std::rc::Rc;
+
+use db::{normalize_msisdn, AddressBook, Verification};
+use delivery::SmsSender;
+use lookup::{LineType, NumberLookup};
+
+use super::{send_reply, Command, CommandContext};
+
+const SET_PHONE_PATTERN: &str = r"^set\s+phone\s+(.+?)\s*$";
+
+/// How long phone number verification codes are valid for.
+const VERIFICATION_CODE_VALIDITY_MINS: i64 = 15;
+
+/// How many wrong codes can be tried before the verification is dropped.
+const MAX_VERIFICATION_ATTEMPTS: u32 = 5;
+
+/// How many verification codes can be sent to a user, or to a number, in
+/// the rate limiting window.
+const MAX_VERIFICATION_SENDS: u32 = 3;
+
+/// The window verification sends are counted over.
+const VERIFICATION_SEND_WINDOW_MINS: i64 = 60;
+
+/// Starts setting the user's phone number by texting them a verification
+/// code.
+pub struct SetPhoneCommand {
+    address_book: AddressBook,
+    sms_sender: Rc<SmsSender>,
+    number_lookup: Option<Rc<NumberLookup>>,
+}
+
+impl SetPhoneCommand {
+    pub fn new(
+        address_book: AddressBook,
+        sms_sender: Rc<SmsSender>,
+        number_lookup: Option<Rc<NumberLookup>>,
+    ) -> SetPhoneCommand {
+        SetPhoneCommand {
+            address_book,
+            sms_sender,
+            number_lookup,
+        }
+    }
+}
+
+impl Command for SetPhoneCommand {
+    fn name(&self) -> &'static str {
+        "set phone"
+    }
+
+    fn pattern(&self) -> &'static str {
+        SET_PHONE_PATTERN
+    }
+
+    fn usage(&self) -> &'static str {
+        "set phone <number>"
+    }
+
+    fn description(&self) -> &'static str {
+        "Set the number to text reminders to, e.g. 'set phone +447700900123'"
+    }
+
+    fn handle(&self, ctx: &CommandContext, args: &Captures) -> Box<Future<Item = (), Error = ()>> {
+        let number = &args[1];
+
+        let msisdn = match normalize_msisdn(number) {
+            Ok(msisdn) => msisdn,
+            Err(err) => {
+                return ctx.reply(&format!("Error: Invalid phone number {}: {}", number, err));
+            }
+        };
+
+        // Texts cost money and can be used to spam people, so limit how
+        // often codes get sent.
+        let now = Utc::now();
+        let since = now - Duration::minutes(VERIFICATION_SEND_WINDOW_MINS);
+        match self
+            .address_book
+            .count_verification_sends(&ctx.event.sender, &msisdn, &since)
+        {
+            Ok((by_user, to_msisdn)) => {
+                if by_user >= MAX_VERIFICATION_SENDS || to_msisdn >= MAX_VERIFICATION_SENDS {
+                    info!(ctx.logger, "Rate limited verification code");
+                    return ctx.reply(
+                        "Error: Too many verification codes have been sent, please try again later",
+                    );
+                }
+            }
+            Err(err) => {
+                error!(ctx.logger, "Failed to count verification codes"; "error" => %err);
+                return ctx.reply(&format!("Error: Failed to set phone number: {}", err));
+            }
+        }
+
+        // Check that the number can actually receive texts, but don't
+        // block setting it if we can't find out.
+        let lookup_f: Box<Future<Item = Option<LineType>, Error = ()>> =
+            if let Some(ref number_lookup) = self.number_lookup {
+                let logger = ctx.logger.clone();
+                Box::new(number_lookup.lookup(&msisdn).or_else(move |err| {
+                    warn!(logger, "Failed to look up phone number"; "error" => %err);
+                    Ok(Some(LineType::Unknown))
+                }))
+            } else {
+                Box::new(future::ok(Some(LineType::Unknown)))
+            };
+
+        // Make sure the user actually owns the number before we start
+        // texting it.
+        let code = format!("{:06}", thread_rng().gen_range(0, 1_000_000));
+        let expiry = now + Duration::minutes(VERIFICATION_CODE_VALIDITY_MINS);
+
+        let address_book = self.address_book.clone();
+        let sms_sender = self.sms_sender.clone();
+        let message_sender = ctx.message_sender.clone();
+        let logger = ctx.logger.clone();
+        let room_id = ctx.room_id.to_string();
+        let event = ctx.event.clone();
+        let user_id = ctx.event.sender.clone();
+
+        let f = lookup_f.and_then(move |line_type| -> Box<Future<Item = (), Error = ()>> {
+            let warning = match line_type {
+                None => {
+                    return send_reply(
+                        &message_sender,
+                        &room_id,
+                        &event,
+                        &format!("Error: {} doesn't appear to be a valid number", msisdn),
+                    )
+                }
+                Some(LineType::Landline) => {
+                    return send_reply(
+                        &message_sender,
+                        &room_id,
+                        &event,
+                        &format!(
+                            "Error: {} is a landline, so can't receive texts. Please use a mobile number",
+                            msisdn
+                        ),
+                    )
+                }
+                Some(LineType::Voip) => " Note: this looks like a VoIP number, so texts may not arrive.",
+                Some(LineType::Mobile) | Some(LineType::Unknown) => "",
+            };
+
+            if let Err(err) = address_book.start_verification(&user_id, &msisdn, &code, &now, &expiry) {
+                error!(logger, "Failed to store verification code"; "error" => %err);
+                return send_reply(
+                    &message_sender,
+                    &room_id,
+                    &event,
+                    &format!("Error: Failed to persist phone number: {}", err),
+                );
+            }
+
+            info!(logger, "Sending verification code");
+
+            let f = sms_sender
+                .send(
+                    logger.clone(),
+                    &msisdn,
+                    &format!("Your reminder bot verification code is {}", code),
+                    None,
+                )
+                .then(move |res| match res {
+                    Ok(()) => send_reply(
+                        &message_sender,
+                        &room_id,
+                        &event,
+                        &format!(
+                            "Sent a verification code to {}, reply with 'testbot: verify <code>' to confirm the number.{}",
+                            msisdn, warning
+                        ),
+                    ),
+                    Err(err) => {
+                        error!(logger, "Failed to send verification code"; "error" => %err);
+                        send_reply(
+                            &message_sender,
+                            &room_id,
+                            &event,
+                            &format!("Error: Failed to send verification code to {}", msisdn),
+                        )
+                    }
+                });
+
+            Box::new(f)
+        });
+
+        Box::new(f)
+    }
+}
+
+/// Confirms a phone number using the code texted to it.
+pub struct VerifyCommand {
+    address_book: AddressBook,
+}
+
+impl VerifyCommand {
+    pub fn new(address_book: AddressBook) -> VerifyCommand {
+        VerifyCommand { address_book }
+    }
+}
+
+impl Command for VerifyCommand {
+    fn name(&self) -> &'static str {
+        "verify"
+    }
+
+    fn pattern(&self) -> &'static str {
+        r"^verify\s+([0-9]+)\s*$"
+    }
+
+    fn usage(&self) -> &'static str {
+        "verify <code>"
+    }
+
+    fn description(&self) -> &'static str {
+        "Confirm your phone number with the code we texted you"
+    }
+
+    fn handle(&self, ctx: &CommandContext, args: &Captures) -> Box<Future<Item = (), Error = ()>> {
+        match self
+            .address_book
+            .complete_verification(
+                &ctx.event.sender,
+                &args[1],
+                &Utc::now(),
+                MAX_VERIFICATION_ATTEMPTS,
+            ) {
+            Ok(Verification::Verified(msisdn)) => {
+                info!(ctx.logger, "Verified phone number");
+                ctx.reply(&format!("Phone number set to {}", msisdn))
+            }
+            Ok(Verification::WrongCode(remaining)) => ctx.reply(&format!(
+                "Error: Invalid verification code, {} attempts left",
+                remaining
+            )),
+            Ok(Verification::NotPending) => ctx.reply(
+                "Error: No pending verification code, it may have expired or had too many wrong attempts",
+            ),
+            Err(err) => {
+                error!(ctx.logger, "Failed to verify phone number"; "error" => %err);
+                ctx.reply(&format!("Error: Failed to verify phone number: {}", err))
+            }
+        }
+    }
+}
+
+/// Removes the user's phone number.
+pub struct ForgetPhoneCommand {
+    address_book: AddressBook,
+}
+
+impl ForgetPhoneCommand {
+    pub fn new(address_book: AddressBook) -> ForgetPhoneCommand {
+        ForgetPhoneCommand { address_book }
+    }
+}
+
+impl Command for ForgetPhoneCommand {
+    fn name(&self) -> &'static str {
+        "forget phone"
+    }
+
+    fn pattern(&self) -> &'static str {
+        r"^forget\s+phone\s*$"
+    }
+
+    fn usage(&self) -> &'static str {
+        "forget phone"
+    }
+
+    fn description(&self) -> &'static str {
+        "Remove your phone number"
+    }
+
+    fn handle(&self, ctx: &CommandContext, _args: &Captures) -> Box<Future<Item = (), Error = ()>> {
+        match self.address_book.forget_msisdn_for_user(&ctx.event.sender) {
+            Ok(true) => {
+                info!(ctx.logger, "Forgot phone number");
+                ctx.reply("Forgotten your phone number")
+            }
+            Ok(false) => ctx.reply("Error: You don't have a phone number set"),
+            Err(err) => {
+                error!(ctx.logger, "Failed to forget phone number"; "error" => %err);
+                ctx.reply(&format!("Error: Failed to forget phone number: {}", err))
+            }
+        }
+    }
+}
+
+#[test]
+fn set_phone_pattern_test() {
+    use regex::Regex;
+
+    let regex = Regex::new(SET_PHONE_PATTERN).unwrap();
+
+    assert_eq!(
+        &regex.captures("set phone +44 7700 900123 ").unwrap()[1],
+        "+44 7700 900123"
+    );
+    assert!(regex.captures("set phone").is_none());
+    assert!(regex.captures("set phonebook +447700900123").is_none());
+}
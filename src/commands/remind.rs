@@ -0,0 +1,820 @@
+use chrono::{DateTime, Duration, Utc};
+use chrono_tz::{Tz, UTC};
+use failure::Error;
+use futures::{future, Future};
+use regex::Captures;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use date::{
+    format_relative, format_repeat, parse_duration, parse_human_datetime, parse_recurrence,
+    split_schedule, AmbiguousDate, DateOrder, ParseSettings, PastTime,
+};
+use db::{Channel, Reminder, Reminders, TooManyReminders, UserSettings};
+
+use super::{
+    date_error_message, event_image, get_parse_settings, get_timezone, Command, CommandContext,
+};
+
+/// How long a question about when a reminder is for waits for an answer.
+const CLARIFICATION_VALIDITY_MINS: i64 = 10;
+
+/// Reminders further away than this are checked with the sender first.
+const FAR_AWAY_DAYS: i64 = 365;
+
+/// How often nagging reminders are sent again, unless the user says.
+const DEFAULT_NAG_MINS: i64 = 10;
+
+const MAX_NAG_MINS: i64 = 24 * 60;
+
+const PATTERN: &str = r"^remind\s*(me|us|here|@[^\s:]+:\S+)\s+(?:(?:by|via)\s+(sms|text|matrix|dm|direct|call|phone)\s+)?((?:.*\s+)?to\s+.*?)(?:,?\s+(?:and\s+)?warn\s+me\s+(.+?)\s+(?:before|ahead))?(\s+!nag(?:\s+(\d+)\s*(?:m|mins?|minutes?))?)?\s*$";
+
+/// Queues a new reminder, either for the sender, for another user who has
+/// opted in to it, or for everyone in the room.
+pub struct RemindCommand {
+    reminders: Reminders,
+    user_settings: UserSettings,
+    /// How long after creating a reminder editing the message replaces it
+    edit_grace_period: Duration,
+    pending: PendingReminders,
+    /// The prefix to show in the answers to clarification questions
+    prefix: String,
+}
+
+impl RemindCommand {
+    pub fn new(
+        reminders: Reminders,
+        user_settings: UserSettings,
+        edit_grace_period: Duration,
+        pending: PendingReminders,
+        prefix: String,
+    ) -> RemindCommand {
+        RemindCommand {
+            reminders,
+            user_settings,
+            edit_grace_period,
+            pending,
+            prefix,
+        }
+    }
+}
+
+impl Command for RemindCommand {
+    fn name(&self) -> &'static str {
+        "remind"
+    }
+
+    fn pattern(&self) -> &'static str {
+        PATTERN
+    }
+
+    fn usage(&self) -> &'static str {
+        "remind me|us|<user> [via sms|matrix|dm|call] <when> to <what> [, warn me <duration> before] [!nag [<minutes>m]], or ... to <what> <when>"
+    }
+
+    fn description(&self) -> &'static str {
+        "Queue a reminder, e.g. 'remind me in 2 hours to call mum', 'remind me to call mum tomorrow at 6pm' or 'remind me every monday at 9am to file timesheet'"
+    }
+
+    fn handles_edits(&self) -> bool {
+        true
+    }
+
+    fn handle(&self, ctx: &CommandContext, args: &Captures) -> Box<Future<Item = (), Error = ()>> {
+        let logger = ctx.logger;
+        let event = ctx.event;
+
+        let settings = get_parse_settings(&self.user_settings, ctx);
+
+        // "!nag" at the end keeps sending the reminder until it's done
+        let nag_interval = if args.get(5).is_some() {
+            let mins = args
+                .get(6)
+                .map_or(Ok(DEFAULT_NAG_MINS), |mins| mins.as_str().parse());
+
+            match mins {
+                Ok(mins) if mins >= 1 && mins <= MAX_NAG_MINS => Some(Duration::minutes(mins)),
+                _ => {
+                    return ctx.reply(&format!(
+                        "Error: Reminders can nag every 1 to {} minutes",
+                        MAX_NAG_MINS
+                    ));
+                }
+            }
+        } else {
+            None
+        };
+
+        // The time is split off by how much the date parser understood, as
+        // the text can contain "to" too. It can come first or last.
+        let (at, text) = match split_schedule(&args[3], &settings) {
+            Some(split) => split,
+            None => return ctx.reply("Error: Expected '<when> to <what>' or 'to <what> <when>'"),
+        };
+
+        // Editing the message replaces the reminder it created, as long as
+        // that was recent enough that it's probably a correction. If the
+        // original didn't create a reminder the edit is treated as new.
+        let replaced = match event.replaces() {
+            Some(original) => match self.reminders.get_reminder_for_event(original) {
+                Ok(Some(reminder)) => {
+                    if reminder.creator != event.sender {
+                        info!(logger, "Ignoring edit by a different user");
+                        return Box::new(future::ok(()));
+                    }
+
+                    let grace_ends = reminder
+                        .created
+                        .map(|created| created + self.edit_grace_period);
+                    if grace_ends.map_or(true, |grace_ends| grace_ends < Utc::now()) {
+                        return ctx.reply(&format!(
+                            "Error: Reminder {} is too old to change by editing, use 'edit' or 'reschedule' instead",
+                            reminder.id
+                        ));
+                    }
+
+                    Some(reminder)
+                }
+                // Replacing a reminder that has already gone off would
+                // just send it again
+                Ok(None) => match self.reminders.get_sent_reminder_for_event(original) {
+                    Ok(Some(reminder)) => {
+                        return ctx.reply(&format!(
+                            "Error: Reminder {} has already been sent, so can't be changed",
+                            reminder.id
+                        ));
+                    }
+                    Ok(None) => None,
+                    Err(err) => {
+                        error!(logger, "Failed to get edited reminder"; "error" => %err);
+                        return ctx.reply(&format!("Error: Failed to get reminder: {}", err));
+                    }
+                },
+                Err(err) => {
+                    error!(logger, "Failed to get edited reminder"; "error" => %err);
+                    return ctx.reply(&format!("Error: Failed to get reminder: {}", err));
+                }
+            },
+            None => None,
+        };
+
+        // Room reminders belong to the sender, so they can manage them, but
+        // always go to the room.
+        let room_wide = match &args[1] {
+            "us" | "here" => true,
+            _ => false,
+        };
+
+        if room_wide && args.get(2).is_some() {
+            return ctx.reply("Error: Reminders for the room are always delivered to the room");
+        }
+
+        let destination = match &args[1] {
+            "me" | "us" | "here" => event.sender.clone(),
+            user_id => user_id.to_string(),
+        };
+
+        if destination != event.sender && !ctx.is_admin {
+            match self.user_settings.get_allow_others(&destination) {
+                Ok(true) => {}
+                Ok(false) => {
+                    info!(logger, "Target doesn't allow reminders from others"; "destination" => &destination);
+                    return ctx.reply(&format!(
+                        "Error: {} doesn't accept reminders from other users",
+                        destination
+                    ));
+                }
+                Err(err) => {
+                    error!(logger, "Failed to get allow_others"; "error" => %err);
+                    return ctx.reply(&format!(
+                        "Error: Failed to check whether {} accepts reminders: {}",
+                        destination, err
+                    ));
+                }
+            }
+        }
+
+        // The time is in the sender's timezone, since they wrote it, but the
+        // reminder goes however the recipient prefers, or else however the
+        // room does.
+        let tz = get_timezone(&self.user_settings, ctx);
+
+        let channel = match args.get(2) {
+            _ if room_wide => Channel::Matrix,
+            Some(channel) => channel.as_str().parse().unwrap_or(Channel::Sms),
+            None => match self.user_settings.get_channel(&destination) {
+                Ok(channel) => channel
+                    .or_else(|| ctx.room_config().channel)
+                    .unwrap_or(Channel::Sms),
+                Err(err) => {
+                    error!(logger, "Failed to get delivery channel"; "error" => %err);
+                    Channel::Sms
+                }
+            },
+        };
+
+        // Each nag would be another paid text or call
+        if nag_interval.is_some() && (channel == Channel::Sms || channel == Channel::Call) {
+            return ctx.reply(&format!(
+                "Error: Reminders sent by {} can't nag",
+                channel.as_str()
+            ));
+        }
+
+        let now = Utc::now().with_timezone(&tz);
+
+        // "..., warn me 1 hour before" adds a heads up ahead of the reminder
+        let heads_up = match args.get(4) {
+            Some(lead) => match parse_duration(lead.as_str(), now, &settings) {
+                Ok(lead_time) => Some(HeadsUp {
+                    lead: lead_time,
+                    description: lead.as_str().to_string(),
+                }),
+                Err(err) => {
+                    info!(logger, "Failed to parse heads up {}", lead.as_str());
+                    return ctx.reply(&date_error_message(lead.as_str(), &err));
+                }
+            },
+            None => None,
+        };
+
+        let parsed = match parse_recurrence(at, now, &settings) {
+            Ok(Some((recurrence, due, until))) => Ok((due, Some(recurrence), until)),
+            Ok(None) => parse_human_datetime(at, now, &settings).map(|due| (due, None, None)),
+            Err(err) => Err(err),
+        };
+
+        // Times that probably aren't what was meant are checked with the
+        // sender before the reminder is stored
+        let (due, recurrence, repeat_until, clarification) = match parsed {
+            Ok((due, recurrence, repeat_until)) => {
+                let clarification =
+                    if recurrence.is_none() && due > now + Duration::days(FAR_AWAY_DAYS) {
+                        Some((
+                            "That's over a year away, did you mean".to_string(),
+                            vec![due],
+                        ))
+                    } else {
+                        None
+                    };
+                (due, recurrence, repeat_until, clarification)
+            }
+            Err(err) => match clarify_date_error(at, now, &settings, &err) {
+                Some((question, choices)) => (choices[0], None, None, Some((question, choices))),
+                None => {
+                    info!(logger, "Failed to parse date {}", at);
+                    return ctx.reply(&date_error_message(at, &err));
+                }
+            },
+        };
+
+        if due < now {
+            info!(logger, "Due date in past: {}", due);
+            return ctx.reply(&format!("Error: Due date in past: {}", due.to_rfc2822()));
+        }
+
+        let reminder = Reminder {
+            id: String::new(),
+            due: due.with_timezone(&Utc),
+            text: String::from(text),
+            destination,
+            recurrence,
+            repeat_until: repeat_until.map(|until| until.with_timezone(&Utc)),
+            room_id: Some(ctx.room_id.to_string()),
+            channel,
+            attempts: 0,
+            creator: event.sender.clone(),
+            created: Some(Utc::now()),
+            // Edits refer to the original message, so later edits can find
+            // the replacement too.
+            event_id: Some(event.replaces().unwrap_or(&event.event_id).to_string()),
+            // Edits of an image's caption needn't include the image again
+            image: event_image(event)
+                .or_else(|| replaced.as_ref().and_then(|old| old.image.clone())),
+            timezone: Some(due.timezone()),
+            nag_interval,
+            parent_id: None,
+        };
+
+        if let Some((question, choices)) = clarification {
+            info!(logger, "Asking which time was meant"; "choices" => choices.len());
+
+            let reply = clarification_question(&self.prefix, &question, &choices);
+            self.pending.insert(
+                ctx.room_id,
+                &event.sender,
+                PendingReminder {
+                    reminder,
+                    heads_up,
+                    replaced,
+                    room_wide,
+                    choices,
+                    expiry: Utc::now() + Duration::minutes(CLARIFICATION_VALIDITY_MINS),
+                },
+            );
+
+            return ctx.reply(&reply);
+        }
+
+        queue_reminder(
+            ctx,
+            &self.reminders,
+            &self.user_settings,
+            reminder,
+            heads_up,
+            replaced,
+            room_wide,
+        )
+    }
+}
+
+/// Answers a clarification question about when a reminder was meant for,
+/// and queues it if one of the times was right.
+pub struct ConfirmCommand {
+    reminders: Reminders,
+    user_settings: UserSettings,
+    pending: PendingReminders,
+    /// The prefix to show in the answers to the question
+    prefix: String,
+}
+
+impl ConfirmCommand {
+    pub fn new(
+        reminders: Reminders,
+        user_settings: UserSettings,
+        pending: PendingReminders,
+        prefix: String,
+    ) -> ConfirmCommand {
+        ConfirmCommand {
+            reminders,
+            user_settings,
+            pending,
+            prefix,
+        }
+    }
+}
+
+impl Command for ConfirmCommand {
+    fn name(&self) -> &'static str {
+        "confirm"
+    }
+
+    fn pattern(&self) -> &'static str {
+        r"^(yes|y|no|n|\d)\s*$"
+    }
+
+    fn usage(&self) -> &'static str {
+        "yes|no|<number>"
+    }
+
+    fn description(&self) -> &'static str {
+        "Answer a question about when a reminder is for"
+    }
+
+    fn handle(&self, ctx: &CommandContext, args: &Captures) -> Box<Future<Item = (), Error = ()>> {
+        let answer = &args[1];
+
+        let mut pending = match self.pending.take(ctx.room_id, &ctx.event.sender) {
+            Some(pending) => pending,
+            None => return ctx.reply("Error: There's no question waiting for an answer"),
+        };
+
+        let choice = match answer {
+            "no" | "n" => {
+                info!(ctx.logger, "Reminder time rejected");
+                return ctx.reply(
+                    "OK, the reminder wasn't queued. Please try again with the time you meant",
+                );
+            }
+            "yes" | "y" if pending.choices.len() == 1 => Some(pending.choices[0]),
+            number => number
+                .parse::<usize>()
+                .ok()
+                .and_then(|number| number.checked_sub(1))
+                .and_then(|index| pending.choices.get(index))
+                .cloned(),
+        };
+
+        let due = match choice {
+            Some(due) => due,
+            None => {
+                let reply = format!(
+                    "Error: Please reply {}",
+                    answers(&self.prefix, pending.choices.len())
+                );
+                self.pending.insert(ctx.room_id, &ctx.event.sender, pending);
+                return ctx.reply(&reply);
+            }
+        };
+
+        // The question may have been open long enough for the time to pass
+        if due.with_timezone(&Utc) < Utc::now() {
+            info!(ctx.logger, "Due date in past: {}", due);
+            return ctx.reply(&format!("Error: Due date in past: {}", due.to_rfc2822()));
+        }
+
+        pending.reminder.due = due.with_timezone(&Utc);
+        pending.reminder.timezone = Some(due.timezone());
+
+        queue_reminder(
+            ctx,
+            &self.reminders,
+            &self.user_settings,
+            pending.reminder,
+            pending.heads_up,
+            pending.replaced,
+            pending.room_wide,
+        )
+    }
+}
+
+/// A reminder waiting for its creator to say which time they meant.
+struct PendingReminder {
+    reminder: Reminder,
+    heads_up: Option<HeadsUp>,
+    /// The reminder it replaces, if it came from an edit
+    replaced: Option<Reminder>,
+    room_wide: bool,
+    choices: Vec<DateTime<Tz>>,
+    expiry: DateTime<Utc>,
+}
+
+/// A reminder sent ahead of the one it's for, e.g. for "warn me 1 hour
+/// before".
+struct HeadsUp {
+    lead: Duration,
+    /// How far ahead, as the sender put it
+    description: String,
+}
+
+/// Reminders waiting on an answer to a clarification question, by room and
+/// sender. These are only kept in memory, as questions are only open for a
+/// few minutes.
+#[derive(Clone, Default)]
+pub struct PendingReminders {
+    pending: Rc<RefCell<HashMap<(String, String), PendingReminder>>>,
+}
+
+impl PendingReminders {
+    pub fn new() -> PendingReminders {
+        PendingReminders::default()
+    }
+
+    /// Adds the sender's pending reminder in the room, replacing any they
+    /// already had. Expired ones are dropped at the same time, as they may
+    /// never be answered.
+    fn insert(&self, room_id: &str, sender: &str, pending: PendingReminder) {
+        let now = Utc::now();
+
+        let mut map = self.pending.borrow_mut();
+        map.retain(|_, pending| pending.expiry > now);
+        map.insert((room_id.to_string(), sender.to_string()), pending);
+    }
+
+    /// Removes the sender's pending reminder in the room, if they have one
+    /// that hasn't expired.
+    fn take(&self, room_id: &str, sender: &str) -> Option<PendingReminder> {
+        let key = (room_id.to_string(), sender.to_string());
+
+        self.pending
+            .borrow_mut()
+            .remove(&key)
+            .filter(|pending| pending.expiry > Utc::now())
+    }
+}
+
+/// For a time that can't be used as given but has likely meanings, what to
+/// ask and the times to choose between.
+fn clarify_date_error(
+    at: &str,
+    now: DateTime<Tz>,
+    settings: &ParseSettings,
+    err: &Error,
+) -> Option<(String, Vec<DateTime<Tz>>)> {
+    if let Some(err) = err.downcast_ref::<PastTime>() {
+        return Some((
+            format!("{} has already passed, did you mean", err.input),
+            vec![err.tomorrow],
+        ));
+    }
+
+    let err = err.downcast_ref::<AmbiguousDate>()?;
+
+    let reading = |order| {
+        let settings = ParseSettings {
+            date_order: Some(order),
+            ..settings.clone()
+        };
+        parse_human_datetime(at, now, &settings).ok()
+    };
+
+    let choices = vec![
+        reading(DateOrder::DayFirst)?,
+        reading(DateOrder::MonthFirst)?,
+    ];
+
+    Some((format!("By {} did you mean", err.input), choices))
+}
+
+/// e.g. "Did you mean 1) Thu 4 Dec 2014 09:30 or 2) Sat 12 Apr 2015 09:30?
+/// Reply ..."
+fn clarification_question(prefix: &str, question: &str, choices: &[DateTime<Tz>]) -> String {
+    let choice_list = if choices.len() == 1 {
+        format_choice(choices[0])
+    } else {
+        choices
+            .iter()
+            .enumerate()
+            .map(|(index, due)| format!("{}) {}", index + 1, format_choice(*due)))
+            .collect::<Vec<String>>()
+            .join(" or ")
+    };
+
+    format!(
+        "{} {}? Reply {}",
+        question,
+        choice_list,
+        answers(prefix, choices.len())
+    )
+}
+
+fn format_choice(due: DateTime<Tz>) -> String {
+    due.format("%a %-d %b %Y %H:%M").to_string()
+}
+
+/// The replies that answer a question with the given number of choices.
+fn answers(prefix: &str, choices: usize) -> String {
+    if choices == 1 {
+        return format!("'{0}: yes' or '{0}: no'", prefix);
+    }
+
+    let numbers = (1..=choices)
+        .map(|number| format!("'{}: {}'", prefix, number))
+        .collect::<Vec<String>>()
+        .join(", ");
+
+    format!("{} or '{}: no'", numbers, prefix)
+}
+
+/// Stores the reminder and any heads up for it, in place of `replaced` if it
+/// came from an edit, and tells the sender.
+fn queue_reminder(
+    ctx: &CommandContext,
+    reminders: &Reminders,
+    user_settings: &UserSettings,
+    mut reminder: Reminder,
+    heads_up: Option<HeadsUp>,
+    replaced: Option<Reminder>,
+    room_wide: bool,
+) -> Box<Future<Item = (), Error = ()>> {
+    let logger = ctx.logger;
+    let event = ctx.event;
+
+    let tz = reminder.timezone.unwrap_or(UTC);
+    let now = Utc::now().with_timezone(&tz);
+    let due = reminder.due.with_timezone(&tz);
+
+    info!(
+        logger,
+        "Queuing message to be sent at '{}'",
+        due.to_rfc2822();
+        "destination" => &reminder.destination,
+    );
+
+    let repeat_msg = reminder
+        .recurrence
+        .as_ref()
+        .map(|r| {
+            format!(
+                ", repeating {}",
+                format_repeat(
+                    r,
+                    due,
+                    reminder.repeat_until.map(|until| until.with_timezone(&tz))
+                )
+            )
+        })
+        .unwrap_or_default();
+
+    let nag_msg = reminder
+        .nag_interval
+        .map(|interval| {
+            format!(
+                ", sending it again every {} minutes until it's done",
+                interval.num_minutes()
+            )
+        })
+        .unwrap_or_default();
+
+    let heads_up_msg = heads_up
+        .as_ref()
+        .map(|heads_up| format!(", with a heads up {} before", heads_up.description))
+        .unwrap_or_default();
+
+    // The heads up is a reminder of its own, linked to this one once it has
+    // an ID. It doesn't repeat by itself, but is queued again whenever this
+    // one moves on to its next occurrence.
+    let heads_up = heads_up.map(|heads_up| Reminder {
+        due: reminder.due - heads_up.lead,
+        recurrence: None,
+        repeat_until: None,
+        text: format!("Heads up: {} in {}", reminder.text, heads_up.description),
+        event_id: None,
+        image: None,
+        nag_interval: None,
+        ..reminder.clone()
+    });
+
+    if heads_up
+        .as_ref()
+        .map_or(false, |heads_up| heads_up.due <= Utc::now())
+    {
+        return ctx.reply("Error: The heads up would be in the past");
+    }
+
+    // Images can only be sent along in Matrix
+    let image_msg = match reminder.channel {
+        Channel::Sms | Channel::Call if reminder.image.is_some() => format!(
+            ", without the image as it's going by {}",
+            reminder.channel.as_str()
+        ),
+        _ => String::new(),
+    };
+
+    // The new reminder replaces the old one in a single transaction, so the
+    // old one is kept if the new one can't be added. The old one is removed
+    // first so it doesn't count towards the pending limit.
+    let res = reminders.in_transaction(|| {
+        if let Some(ref old) = replaced {
+            reminders.remove_reminder(&old.id)?;
+        }
+
+        add_with_heads_up(reminders, &mut reminder, heads_up)
+    });
+
+    if let Err(err) = res {
+        if let Some(TooManyReminders(max)) = err.downcast_ref::<TooManyReminders>() {
+            info!(logger, "Refusing reminder over the pending limit");
+            return ctx.reply(&format!(
+                "Error: You already have {} pending reminders, which is the most allowed. Cancel some with 'cancel <id>' first",
+                max
+            ));
+        }
+
+        error!(logger, "Failed to handle reminder"; "error" => %err);
+        return ctx.reply(&format!("Error: Failed to persist reminder: {}", err));
+    }
+
+    let recipient = if room_wide {
+        " for this room".to_string()
+    } else if reminder.destination == event.sender {
+        String::new()
+    } else {
+        format!(
+            " for {}",
+            ctx.room_state
+                .display_name(ctx.room_id, &reminder.destination)
+        )
+    };
+
+    // Some people would rather not have a message for every reminder
+    let react = match user_settings.get_react_confirmations(&event.sender) {
+        Ok(react) => react,
+        Err(err) => {
+            error!(logger, "Failed to get react_confirmations"; "error" => %err);
+            false
+        }
+    };
+
+    if react {
+        return ctx.react("✅");
+    }
+
+    if let Some(old) = replaced {
+        info!(logger, "Replaced edited reminder"; "old_id" => &old.id, "id" => &reminder.id);
+
+        return ctx.reply(&format!(
+            "Replaced reminder {} with {}{} {}{}{}{}{}",
+            old.id,
+            reminder.id,
+            recipient,
+            format_relative(due, now),
+            repeat_msg,
+            heads_up_msg,
+            nag_msg,
+            image_msg
+        ));
+    }
+
+    ctx.reply(&format!(
+        "OK {}, queued reminder {}{} {}{}{}{}{}",
+        ctx.sender_name(),
+        reminder.id,
+        recipient,
+        format_relative(due, now),
+        repeat_msg,
+        heads_up_msg,
+        nag_msg,
+        image_msg
+    ))
+}
+
+/// Stores the reminder and then its heads up, if it has one. This should be
+/// run in a transaction so neither is kept if the heads up can't be stored.
+fn add_with_heads_up(
+    reminders: &Reminders,
+    reminder: &mut Reminder,
+    heads_up: Option<Reminder>,
+) -> Result<(), Error> {
+    reminders.add_reminder(reminder)?;
+
+    if let Some(mut heads_up) = heads_up {
+        heads_up.parent_id = Some(reminder.id.clone());
+        reminders.add_reminder(&mut heads_up)?;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn remind_pattern_test() {
+    use regex::Regex;
+
+    let regex = Regex::new(PATTERN).unwrap();
+
+    let capt = regex.captures("remind me in 2 hours to call mum").unwrap();
+    assert_eq!(&capt[1], "me");
+    assert!(capt.get(2).is_none());
+    assert_eq!(&capt[3], "in 2 hours to call mum");
+
+    let capt = regex
+        .captures("remind me via call tomorrow at 9am to get up")
+        .unwrap();
+    assert_eq!(&capt[2], "call");
+    assert_eq!(&capt[3], "tomorrow at 9am to get up");
+
+    let capt = regex
+        .captures("remind @alice:example.com in 2h to review PR")
+        .unwrap();
+    assert_eq!(&capt[1], "@alice:example.com");
+    assert_eq!(&capt[3], "in 2h to review PR");
+
+    let capt = regex.captures("remind us at 5pm to go home").unwrap();
+    assert_eq!(&capt[1], "us");
+    assert_eq!(&capt[3], "at 5pm to go home");
+
+    assert!(regex.captures("remind @alice in 2h to review PR").is_none());
+
+    let capt = regex
+        .captures("remind me to call mum tomorrow at 6pm")
+        .unwrap();
+    assert_eq!(&capt[1], "me");
+    assert_eq!(&capt[3], "to call mum tomorrow at 6pm");
+    assert!(capt.get(4).is_none());
+
+    let capt = regex
+        .captures("remind me at 8am to take pills !nag")
+        .unwrap();
+    assert_eq!(&capt[3], "at 8am to take pills");
+    assert!(capt.get(5).is_some());
+    assert!(capt.get(6).is_none());
+
+    let capt = regex
+        .captures("remind me to take pills at 8am !nag 5m")
+        .unwrap();
+    assert_eq!(&capt[3], "to take pills at 8am");
+    assert_eq!(&capt[6], "5");
+
+    let capt = regex
+        .captures("remind me on friday at 3pm to dentist, warn me 1 hour before")
+        .unwrap();
+    assert_eq!(&capt[3], "on friday at 3pm to dentist");
+    assert_eq!(&capt[4], "1 hour");
+
+    let capt = regex
+        .captures("remind me to dentist on friday at 3pm and warn me 2 days ahead !nag")
+        .unwrap();
+    assert_eq!(&capt[3], "to dentist on friday at 3pm");
+    assert_eq!(&capt[4], "2 days");
+    assert!(capt.get(5).is_some());
+}
+
+#[test]
+fn clarification_question_test() {
+    use chrono::TimeZone;
+    use chrono_tz::Europe::London;
+
+    let due = London.ymd(2014, 12, 4).and_hms(9, 30, 0);
+    assert_eq!(
+        clarification_question("testbot", "That's over a year away, did you mean", &[due]),
+        "That's over a year away, did you mean Thu 4 Dec 2014 09:30? Reply 'testbot: yes' or 'testbot: no'"
+    );
+
+    let other = London.ymd(2014, 4, 12).and_hms(9, 30, 0);
+    assert_eq!(
+        clarification_question("remindme", "By 04/12 did you mean", &[due, other]),
+        "By 04/12 did you mean 1) Thu 4 Dec 2014 09:30 or 2) Sat 12 Apr 2014 09:30? Reply 'remindme: 1', 'remindme: 2' or 'remindme: no'"
+    );
+}
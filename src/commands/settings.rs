@@ -0,0 +1,664 @@
+use chrono::Utc;
+use chrono_tz::Tz;
+use futures::Future;
+use regex::Captures;
+
+use date::{parse_time_of_day, DateOrder, Locale};
+use db::{Channel, PartOfDay, QuietHours, UserSettings};
+use matrix::ROOM_CONFIG_EVENT_TYPE;
+use room_state::room_config_content;
+
+use super::{send_reply, Command, CommandContext};
+
+/// Sets the user's default delivery channel.
+pub struct SetDeliveryCommand {
+    user_settings: UserSettings,
+}
+
+impl SetDeliveryCommand {
+    pub fn new(user_settings: UserSettings) -> SetDeliveryCommand {
+        SetDeliveryCommand { user_settings }
+    }
+}
+
+impl Command for SetDeliveryCommand {
+    fn name(&self) -> &'static str {
+        "set delivery"
+    }
+
+    fn pattern(&self) -> &'static str {
+        r"^set\s+delivery\s+(\S+)\s*$"
+    }
+
+    fn usage(&self) -> &'static str {
+        "set delivery sms|matrix|dm|call"
+    }
+
+    fn description(&self) -> &'static str {
+        "Set how your reminders are delivered by default"
+    }
+
+    fn handle(&self, ctx: &CommandContext, args: &Captures) -> Box<Future<Item = (), Error = ()>> {
+        let channel_name = &args[1];
+
+        let channel: Channel = match channel_name.to_lowercase().parse() {
+            Ok(channel) => channel,
+            Err(_) => {
+                return ctx.reply(&format!(
+                    "Error: Unknown delivery method {}, expected 'sms', 'matrix', 'dm' or 'call'",
+                    channel_name
+                ));
+            }
+        };
+
+        if let Err(err) = self.user_settings.set_channel(&ctx.event.sender, channel) {
+            error!(ctx.logger, "Failed to set delivery channel"; "error" => %err);
+            return ctx.reply(&format!(
+                "Error: Failed to persist delivery method: {}",
+                err
+            ));
+        }
+
+        info!(ctx.logger, "Set delivery channel"; "channel" => channel.as_str());
+
+        ctx.reply(&format!(
+            "Reminders will now be delivered by {}",
+            channel.as_str()
+        ))
+    }
+}
+
+/// Sets the timezone the user's reminders are interpreted in.
+pub struct SetTimezoneCommand {
+    user_settings: UserSettings,
+}
+
+impl SetTimezoneCommand {
+    pub fn new(user_settings: UserSettings) -> SetTimezoneCommand {
+        SetTimezoneCommand { user_settings }
+    }
+}
+
+impl Command for SetTimezoneCommand {
+    fn name(&self) -> &'static str {
+        "set timezone"
+    }
+
+    fn pattern(&self) -> &'static str {
+        r"^set\s+timezone\s+(\S+)\s*$"
+    }
+
+    fn usage(&self) -> &'static str {
+        "set timezone <timezone>"
+    }
+
+    fn description(&self) -> &'static str {
+        "Set your timezone, e.g. 'set timezone Europe/London'"
+    }
+
+    fn handle(&self, ctx: &CommandContext, args: &Captures) -> Box<Future<Item = (), Error = ()>> {
+        let tz_name = &args[1];
+
+        let tz: Tz = match tz_name.parse() {
+            Ok(tz) => tz,
+            Err(_) => {
+                info!(ctx.logger, "Unknown timezone {}", tz_name);
+                return ctx.reply(&format!("Error: Unknown timezone {}", tz_name));
+            }
+        };
+
+        if let Err(err) = self.user_settings.set_timezone(&ctx.event.sender, tz) {
+            error!(ctx.logger, "Failed to set timezone"; "error" => %err);
+            return ctx.reply(&format!("Error: Failed to persist timezone: {}", err));
+        }
+
+        info!(ctx.logger, "Set timezone"; "timezone" => tz.name());
+
+        let now = Utc::now().with_timezone(&tz);
+
+        ctx.reply(&format!(
+            "Timezone set to {}, local time is now '{}'",
+            tz.name(),
+            now.to_rfc2822()
+        ))
+    }
+}
+
+/// Opts in to (or out of) other users setting reminders for the user.
+pub struct AllowOthersCommand {
+    user_settings: UserSettings,
+}
+
+impl AllowOthersCommand {
+    pub fn new(user_settings: UserSettings) -> AllowOthersCommand {
+        AllowOthersCommand { user_settings }
+    }
+}
+
+impl Command for AllowOthersCommand {
+    fn name(&self) -> &'static str {
+        "allow others"
+    }
+
+    fn pattern(&self) -> &'static str {
+        r"^(allow|deny)\s+others\s*$"
+    }
+
+    fn usage(&self) -> &'static str {
+        "allow others|deny others"
+    }
+
+    fn description(&self) -> &'static str {
+        "Choose whether other users can set reminders for you (denied by default)"
+    }
+
+    fn handle(&self, ctx: &CommandContext, args: &Captures) -> Box<Future<Item = (), Error = ()>> {
+        let allow = &args[1] == "allow";
+
+        if let Err(err) = self
+            .user_settings
+            .set_allow_others(&ctx.event.sender, allow)
+        {
+            error!(ctx.logger, "Failed to set allow_others"; "error" => %err);
+            return ctx.reply(&format!("Error: Failed to persist setting: {}", err));
+        }
+
+        info!(ctx.logger, "Set allow_others"; "allow" => allow);
+
+        if allow {
+            ctx.reply("Other users can now set reminders for you")
+        } else {
+            ctx.reply("Other users can no longer set reminders for you")
+        }
+    }
+}
+
+/// Chooses whether new reminders are acknowledged with a reaction or a
+/// message.
+pub struct SetConfirmationsCommand {
+    user_settings: UserSettings,
+}
+
+impl SetConfirmationsCommand {
+    pub fn new(user_settings: UserSettings) -> SetConfirmationsCommand {
+        SetConfirmationsCommand { user_settings }
+    }
+}
+
+impl Command for SetConfirmationsCommand {
+    fn name(&self) -> &'static str {
+        "set confirmations"
+    }
+
+    fn pattern(&self) -> &'static str {
+        r"^set\s+confirmations\s+(reactions?|messages?)\s*$"
+    }
+
+    fn usage(&self) -> &'static str {
+        "set confirmations reactions|messages"
+    }
+
+    fn description(&self) -> &'static str {
+        "Choose whether new reminders are confirmed with a ✅ reaction or a message"
+    }
+
+    fn handle(&self, ctx: &CommandContext, args: &Captures) -> Box<Future<Item = (), Error = ()>> {
+        let react = args[1].starts_with("reaction");
+
+        if let Err(err) = self
+            .user_settings
+            .set_react_confirmations(&ctx.event.sender, react)
+        {
+            error!(ctx.logger, "Failed to set react_confirmations"; "error" => %err);
+            return ctx.reply(&format!("Error: Failed to persist setting: {}", err));
+        }
+
+        info!(ctx.logger, "Set react_confirmations"; "react" => react);
+
+        if react {
+            ctx.reply(
+                "New reminders will be confirmed with a reaction, use 'list' to see their IDs",
+            )
+        } else {
+            ctx.reply("New reminders will be confirmed with a message")
+        }
+    }
+}
+
+/// Pauses or resumes delivery of the user's reminders.
+pub struct PauseCommand {
+    user_settings: UserSettings,
+}
+
+impl PauseCommand {
+    pub fn new(user_settings: UserSettings) -> PauseCommand {
+        PauseCommand { user_settings }
+    }
+}
+
+impl Command for PauseCommand {
+    fn name(&self) -> &'static str {
+        "pause"
+    }
+
+    fn pattern(&self) -> &'static str {
+        r"^(pause|resume)\s*$"
+    }
+
+    fn usage(&self) -> &'static str {
+        "pause|resume"
+    }
+
+    fn description(&self) -> &'static str {
+        "Hold back your reminders until you resume, e.g. while on holiday"
+    }
+
+    fn handle(&self, ctx: &CommandContext, args: &Captures) -> Box<Future<Item = (), Error = ()>> {
+        let paused = &args[1] == "pause";
+
+        if let Err(err) = self.user_settings.set_paused(&ctx.event.sender, paused) {
+            error!(ctx.logger, "Failed to set paused"; "error" => %err);
+            return ctx.reply(&format!("Error: Failed to persist setting: {}", err));
+        }
+
+        info!(ctx.logger, "Set paused"; "paused" => paused);
+
+        if paused {
+            ctx.reply("Paused your reminders, say 'resume' to get them again")
+        } else {
+            ctx.reply("Resumed your reminders, any that came due while paused will be sent shortly")
+        }
+    }
+}
+
+/// Sets the hours during which the user doesn't want to be texted or
+/// called.
+pub struct SetQuietHoursCommand {
+    user_settings: UserSettings,
+}
+
+impl SetQuietHoursCommand {
+    pub fn new(user_settings: UserSettings) -> SetQuietHoursCommand {
+        SetQuietHoursCommand { user_settings }
+    }
+}
+
+impl Command for SetQuietHoursCommand {
+    fn name(&self) -> &'static str {
+        "set quiet hours"
+    }
+
+    fn pattern(&self) -> &'static str {
+        r"^set\s+quiet\s+hours\s+(\S+)\s*$"
+    }
+
+    fn usage(&self) -> &'static str {
+        "set quiet hours <start>-<end>|off"
+    }
+
+    fn description(&self) -> &'static str {
+        "Hold back texts and calls during these hours, e.g. 'set quiet hours 22:00-08:00'"
+    }
+
+    fn handle(&self, ctx: &CommandContext, args: &Captures) -> Box<Future<Item = (), Error = ()>> {
+        let quiet_hours = match &args[1] {
+            "off" | "none" => None,
+            value => match value.parse::<QuietHours>() {
+                Ok(quiet_hours) => Some(quiet_hours),
+                Err(err) => {
+                    return ctx.reply(&format!("Error: Invalid quiet hours {}: {}", value, err));
+                }
+            },
+        };
+
+        if let Err(err) = self
+            .user_settings
+            .set_quiet_hours(&ctx.event.sender, quiet_hours)
+        {
+            error!(ctx.logger, "Failed to set quiet hours"; "error" => %err);
+            return ctx.reply(&format!("Error: Failed to persist quiet hours: {}", err));
+        }
+
+        info!(ctx.logger, "Set quiet hours"; "quiet_hours" => ?quiet_hours);
+
+        match quiet_hours {
+            Some(quiet_hours) => ctx.reply(&format!(
+                "Texts and calls will be held back between {} in your timezone",
+                quiet_hours
+            )),
+            None => ctx.reply("Turned off quiet hours"),
+        }
+    }
+}
+
+/// Turns the daily digest on or off.
+pub struct SetDigestCommand {
+    user_settings: UserSettings,
+}
+
+impl SetDigestCommand {
+    pub fn new(user_settings: UserSettings) -> SetDigestCommand {
+        SetDigestCommand { user_settings }
+    }
+}
+
+impl Command for SetDigestCommand {
+    fn name(&self) -> &'static str {
+        "set digest"
+    }
+
+    fn pattern(&self) -> &'static str {
+        r"^set\s+digest\s+(\S+)\s*$"
+    }
+
+    fn usage(&self) -> &'static str {
+        "set digest <time>|off"
+    }
+
+    fn description(&self) -> &'static str {
+        "Get one message each morning listing the day's reminders instead of separate ones, e.g. 'set digest 08:00'"
+    }
+
+    fn handle(&self, ctx: &CommandContext, args: &Captures) -> Box<Future<Item = (), Error = ()>> {
+        let time = match &args[1] {
+            "off" | "none" => None,
+            value => match parse_time_of_day(value) {
+                Ok(time) => Some(time),
+                Err(err) => return ctx.reply(&format!("Error: {}", err)),
+            },
+        };
+
+        if let Err(err) = self.user_settings.set_digest_time(&ctx.event.sender, time) {
+            error!(ctx.logger, "Failed to set digest time"; "error" => %err);
+            return ctx.reply(&format!("Error: Failed to persist digest time: {}", err));
+        }
+
+        info!(ctx.logger, "Set digest time"; "time" => ?time);
+
+        match time {
+            Some(time) => ctx.reply(&format!(
+                "You'll get a digest of each day's reminders at {} in your timezone",
+                time.format("%H:%M")
+            )),
+            None => ctx.reply("Turned off the daily digest, reminders will be sent individually"),
+        }
+    }
+}
+
+/// Changes what the user means by "morning", "afternoon", "evening" or "end
+/// of day".
+pub struct SetPartOfDayCommand {
+    user_settings: UserSettings,
+}
+
+impl SetPartOfDayCommand {
+    pub fn new(user_settings: UserSettings) -> SetPartOfDayCommand {
+        SetPartOfDayCommand { user_settings }
+    }
+}
+
+impl Command for SetPartOfDayCommand {
+    fn name(&self) -> &'static str {
+        "set morning/afternoon/evening/end of day"
+    }
+
+    fn pattern(&self) -> &'static str {
+        r"^set\s+(morning|afternoon|evening|end\s+of\s+day)\s+(\S+)\s*$"
+    }
+
+    fn usage(&self) -> &'static str {
+        "set morning|afternoon|evening|end of day <time>|default"
+    }
+
+    fn description(&self) -> &'static str {
+        "Change when e.g. 'tomorrow morning', 'this evening' or 'end of the week' is, e.g. 'set evening 18:30'"
+    }
+
+    fn handle(&self, ctx: &CommandContext, args: &Captures) -> Box<Future<Item = (), Error = ()>> {
+        let name = args[1].split_whitespace().collect::<Vec<_>>().join(" ");
+        let part: PartOfDay = match name.parse() {
+            Ok(part) => part,
+            Err(err) => return ctx.reply(&format!("Error: {}", err)),
+        };
+
+        let time = match &args[2] {
+            "default" | "none" => None,
+            value => match parse_time_of_day(value) {
+                Ok(time) => Some(time),
+                Err(err) => return ctx.reply(&format!("Error: {}", err)),
+            },
+        };
+
+        if let Err(err) = self
+            .user_settings
+            .set_part_of_day(&ctx.event.sender, part, time)
+        {
+            error!(ctx.logger, "Failed to set time of day"; "error" => %err);
+            return ctx.reply(&format!("Error: Failed to persist time of day: {}", err));
+        }
+
+        info!(ctx.logger, "Set time of day"; "part" => ?part, "time" => ?time);
+
+        match time {
+            Some(time) => ctx.reply(&format!(
+                "The {} is now {} in your timezone",
+                name,
+                time.format("%H:%M")
+            )),
+            None => ctx.reply(&format!("Reset the {} to the default", name)),
+        }
+    }
+}
+
+/// Sets whether the user writes numeric dates day or month first.
+pub struct SetDateOrderCommand {
+    user_settings: UserSettings,
+}
+
+impl SetDateOrderCommand {
+    pub fn new(user_settings: UserSettings) -> SetDateOrderCommand {
+        SetDateOrderCommand { user_settings }
+    }
+}
+
+impl Command for SetDateOrderCommand {
+    fn name(&self) -> &'static str {
+        "set date order"
+    }
+
+    fn pattern(&self) -> &'static str {
+        r"^set\s+date\s+order\s+(\S+)\s*$"
+    }
+
+    fn usage(&self) -> &'static str {
+        "set date order dmy|mdy|none"
+    }
+
+    fn description(&self) -> &'static str {
+        "Say how you write dates, e.g. 'set date order dmy' to read 04/12 as the 4th of December"
+    }
+
+    fn handle(&self, ctx: &CommandContext, args: &Captures) -> Box<Future<Item = (), Error = ()>> {
+        let order = match &args[1] {
+            "none" | "off" => None,
+            value => match value.parse::<DateOrder>() {
+                Ok(order) => Some(order),
+                Err(err) => return ctx.reply(&format!("Error: {}", err)),
+            },
+        };
+
+        if let Err(err) = self.user_settings.set_date_order(&ctx.event.sender, order) {
+            error!(ctx.logger, "Failed to set date order"; "error" => %err);
+            return ctx.reply(&format!("Error: Failed to persist date order: {}", err));
+        }
+
+        info!(ctx.logger, "Set date order"; "order" => ?order);
+
+        match order {
+            Some(DateOrder::DayFirst) => {
+                ctx.reply("Dates like 04/12 will be read as the 4th of December")
+            }
+            Some(DateOrder::MonthFirst) => {
+                ctx.reply("Dates like 12/04 will be read as the 4th of December")
+            }
+            None => {
+                ctx.reply("You'll be asked which you mean when a date could be read either way")
+            }
+        }
+    }
+}
+
+/// Sets the language the user writes times in.
+pub struct SetLanguageCommand {
+    user_settings: UserSettings,
+}
+
+impl SetLanguageCommand {
+    pub fn new(user_settings: UserSettings) -> SetLanguageCommand {
+        SetLanguageCommand { user_settings }
+    }
+}
+
+impl Command for SetLanguageCommand {
+    fn name(&self) -> &'static str {
+        "set language"
+    }
+
+    fn pattern(&self) -> &'static str {
+        r"^set\s+language\s+(\S+)\s*$"
+    }
+
+    fn usage(&self) -> &'static str {
+        "set language en|fr|de|none"
+    }
+
+    fn description(&self) -> &'static str {
+        "Say which language you write times in, e.g. 'set language fr' for 'remind me demain à 18h to call mum'"
+    }
+
+    fn handle(&self, ctx: &CommandContext, args: &Captures) -> Box<Future<Item = (), Error = ()>> {
+        let locale = match &args[1] {
+            "none" | "off" => None,
+            value => match value.parse::<Locale>() {
+                Ok(locale) => Some(locale),
+                Err(err) => return ctx.reply(&format!("Error: {}", err)),
+            },
+        };
+
+        if let Err(err) = self.user_settings.set_locale(&ctx.event.sender, locale) {
+            error!(ctx.logger, "Failed to set language"; "error" => %err);
+            return ctx.reply(&format!("Error: Failed to persist language: {}", err));
+        }
+
+        info!(ctx.logger, "Set language"; "locale" => ?locale);
+
+        match locale {
+            Some(locale) => ctx.reply(&format!(
+                "Times will be read as {}, as well as English",
+                locale.name()
+            )),
+            None => ctx.reply("Times will be read in the room's language, or English"),
+        }
+    }
+}
+
+/// Admin command changing a setting in the room's config state event. The
+/// bot needs permission to send the event in the room.
+#[derive(Default)]
+pub struct SetRoomConfigCommand;
+
+impl SetRoomConfigCommand {
+    pub fn new() -> SetRoomConfigCommand {
+        SetRoomConfigCommand
+    }
+}
+
+impl Command for SetRoomConfigCommand {
+    fn name(&self) -> &'static str {
+        "set room"
+    }
+
+    fn pattern(&self) -> &'static str {
+        r"^set\s+room\s+(prefix|delivery|timezone|language)\s+(\S+)\s*$"
+    }
+
+    fn usage(&self) -> &'static str {
+        "set room prefix|delivery|timezone|language <value>|none"
+    }
+
+    fn description(&self) -> &'static str {
+        "Set a name the bot also answers to, or the default delivery method, timezone or language, for this room"
+    }
+
+    fn admin_only(&self) -> bool {
+        true
+    }
+
+    fn handle(&self, ctx: &CommandContext, args: &Captures) -> Box<Future<Item = (), Error = ()>> {
+        let setting = &args[1];
+        let value = &args[2];
+        let unset = value.to_lowercase() == "none";
+
+        let mut config = ctx.room_config();
+
+        match setting {
+            "prefix" if unset => config.prefix = None,
+            "prefix" => config.prefix = Some(value.to_string()),
+            "delivery" if unset => config.channel = None,
+            "delivery" => match value.to_lowercase().parse() {
+                Ok(channel) => config.channel = Some(channel),
+                Err(_) => {
+                    return ctx.reply(&format!(
+                        "Error: Unknown delivery method {}, expected 'sms', 'matrix', 'dm' or 'call'",
+                        value
+                    ));
+                }
+            },
+            "timezone" if unset => config.timezone = None,
+            "language" if unset => config.locale = None,
+            "language" => match value.parse() {
+                Ok(locale) => config.locale = Some(locale),
+                Err(err) => return ctx.reply(&format!("Error: {}", err)),
+            },
+            _ => match value.parse() {
+                Ok(tz) => config.timezone = Some(tz),
+                Err(_) => return ctx.reply(&format!("Error: Unknown timezone {}", value)),
+            },
+        }
+
+        info!(ctx.logger, "Setting room config"; "config" => ?config);
+
+        // The cached config is updated when the event comes down sync.
+        let message_sender = ctx.message_sender.clone();
+        let logger = ctx.logger.clone();
+        let room_id = ctx.room_id.to_string();
+        let event = ctx.event.clone();
+        let reply = if unset {
+            format!("Unset the room's {}", setting)
+        } else {
+            format!("Set the room's {} to {}", setting, value)
+        };
+
+        let f = ctx
+            .message_sender
+            .send_state_event(
+                ctx.room_id,
+                ROOM_CONFIG_EVENT_TYPE,
+                "",
+                room_config_content(&config),
+            )
+            .then(move |res| {
+                let text = match res {
+                    Ok(_) => reply,
+                    Err(err) => {
+                        error!(logger, "Failed to set room config"; "error" => %err);
+                        format!("Error: Failed to update room settings: {}", err)
+                    }
+                };
+
+                send_reply(&message_sender, &room_id, &event, &text)
+            });
+
+        Box::new(f)
+    }
+}
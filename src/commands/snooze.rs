@@ -0,0 +1,216 @@
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use failure::Error;
+use futures::Future;
+use regex::Captures;
+
+use date::{format_relative, parse_human_datetime, ParseSettings};
+use db::{Reminder, Reminders, TooManyReminders, UserSettings};
+
+use super::{date_error_message, get_parse_settings, get_timezone, Command, CommandContext};
+
+/// Parses a time given either as how long from now, e.g. "20m", or as when,
+/// e.g. "until tomorrow" or "at 5pm".
+fn parse_later(
+    when: &str,
+    now: DateTime<Tz>,
+    settings: &ParseSettings,
+) -> Result<DateTime<Tz>, Error> {
+    let when = if when.starts_with("until ") {
+        &when[6..]
+    } else {
+        when
+    };
+
+    parse_human_datetime(&format!("in {}", when), now, settings)
+        .or_else(|_| parse_human_datetime(when, now, settings))
+}
+
+/// Snoozes the user's most recently delivered reminder.
+pub struct SnoozeCommand {
+    reminders: Reminders,
+    user_settings: UserSettings,
+}
+
+impl SnoozeCommand {
+    pub fn new(reminders: Reminders, user_settings: UserSettings) -> SnoozeCommand {
+        SnoozeCommand {
+            reminders,
+            user_settings,
+        }
+    }
+}
+
+impl Command for SnoozeCommand {
+    fn name(&self) -> &'static str {
+        "snooze"
+    }
+
+    fn pattern(&self) -> &'static str {
+        r"^snooze\s+(.+)$"
+    }
+
+    fn usage(&self) -> &'static str {
+        "snooze <duration>|until <when>"
+    }
+
+    fn description(&self) -> &'static str {
+        "Snooze your last delivered reminder, e.g. 'snooze 20 minutes', or react to one with 💤 to snooze it for 15 minutes"
+    }
+
+    fn handle(&self, ctx: &CommandContext, args: &Captures) -> Box<Future<Item = (), Error = ()>> {
+        let logger = ctx.logger;
+
+        let reminder = match self
+            .reminders
+            .get_last_snoozable_reminder(&ctx.event.sender)
+        {
+            Ok(Some(reminder)) => reminder,
+            Ok(None) => return ctx.reply("Error: No delivered reminder to snooze"),
+            Err(err) => {
+                error!(logger, "Failed to get reminder to snooze"; "error" => %err);
+                return ctx.reply(&format!("Error: Failed to get reminder: {}", err));
+            }
+        };
+
+        let tz = get_timezone(&self.user_settings, ctx);
+        let now = Utc::now().with_timezone(&tz);
+        let settings = get_parse_settings(&self.user_settings, ctx);
+
+        // Allow both "snooze 20m" and "snooze until tomorrow"
+        let when = args[1].trim();
+
+        let due = match parse_later(when, now, &settings) {
+            Ok(date) => date,
+            Err(err) => {
+                info!(logger, "Failed to parse date {}", when);
+                return ctx.reply(&date_error_message(when, &err));
+            }
+        };
+
+        if due < now {
+            return ctx.reply(&format!("Error: Due date in past: {}", due.to_rfc2822()));
+        }
+
+        if let Err(err) = self
+            .reminders
+            .snooze_reminder(&reminder.id, &due.with_timezone(&Utc))
+        {
+            error!(logger, "Failed to snooze reminder"; "error" => %err);
+            return ctx.reply(&format!("Error: Failed to snooze reminder: {}", err));
+        }
+
+        info!(logger, "Snoozed reminder"; "reminder_id" => &reminder.id);
+
+        ctx.reply(&format!(
+            "Snoozed '{}' until '{}'",
+            reminder.text,
+            due.to_rfc2822()
+        ))
+    }
+}
+
+/// Sets a new reminder with the same text as the user's most recently
+/// delivered reminder, leaving that one as it is.
+pub struct AgainCommand {
+    reminders: Reminders,
+    user_settings: UserSettings,
+}
+
+impl AgainCommand {
+    pub fn new(reminders: Reminders, user_settings: UserSettings) -> AgainCommand {
+        AgainCommand {
+            reminders,
+            user_settings,
+        }
+    }
+}
+
+impl Command for AgainCommand {
+    fn name(&self) -> &'static str {
+        "again"
+    }
+
+    fn pattern(&self) -> &'static str {
+        r"^(?:remind\s+me\s+)?again\s+(.+)$"
+    }
+
+    fn usage(&self) -> &'static str {
+        "again <duration>|<when>"
+    }
+
+    fn description(&self) -> &'static str {
+        "Get reminded of your last delivered reminder again, e.g. 'again in 10m', without it being snoozed"
+    }
+
+    fn handle(&self, ctx: &CommandContext, args: &Captures) -> Box<Future<Item = (), Error = ()>> {
+        let logger = ctx.logger;
+
+        let reminder = match self
+            .reminders
+            .get_last_delivered_reminder(&ctx.event.sender)
+        {
+            Ok(Some(reminder)) => reminder,
+            Ok(None) => return ctx.reply("Error: No delivered reminder to repeat"),
+            Err(err) => {
+                error!(logger, "Failed to get reminder to repeat"; "error" => %err);
+                return ctx.reply(&format!("Error: Failed to get reminder: {}", err));
+            }
+        };
+
+        let tz = get_timezone(&self.user_settings, ctx);
+        let now = Utc::now().with_timezone(&tz);
+        let settings = get_parse_settings(&self.user_settings, ctx);
+
+        let when = args[1].trim();
+
+        let due = match parse_later(when, now, &settings) {
+            Ok(date) => date,
+            Err(err) => {
+                info!(logger, "Failed to parse date {}", when);
+                return ctx.reply(&date_error_message(when, &err));
+            }
+        };
+
+        if due < now {
+            return ctx.reply(&format!("Error: Due date in past: {}", due.to_rfc2822()));
+        }
+
+        // A one off copy, the original keeps any schedule it has
+        let mut again = Reminder {
+            id: String::new(),
+            due: due.with_timezone(&Utc),
+            recurrence: None,
+            repeat_until: None,
+            attempts: 0,
+            creator: ctx.event.sender.clone(),
+            created: Some(Utc::now()),
+            event_id: None,
+            timezone: Some(due.timezone()),
+            parent_id: None,
+            ..reminder.clone()
+        };
+
+        if let Err(err) = self.reminders.add_reminder(&mut again) {
+            if let Some(TooManyReminders(max)) = err.downcast_ref::<TooManyReminders>() {
+                info!(logger, "Refusing reminder over the pending limit");
+                return ctx.reply(&format!(
+                    "Error: You already have {} pending reminders, which is the most allowed. Cancel some with 'cancel <id>' first",
+                    max
+                ));
+            }
+
+            error!(logger, "Failed to repeat reminder"; "error" => %err);
+            return ctx.reply(&format!("Error: Failed to persist reminder: {}", err));
+        }
+
+        info!(logger, "Repeated reminder"; "reminder_id" => &reminder.id, "new_id" => &again.id);
+
+        ctx.reply(&format!(
+            "OK, queued reminder {} to remind you of '{}' again {}",
+            again.id,
+            again.text,
+            format_relative(due, now)
+        ))
+    }
+}
@@ -0,0 +1,84 @@
+use futures::Future;
+use regex::Captures;
+
+use db::{DeliveryStatuses, UserSettings};
+
+use super::{get_timezone, Command, CommandContext};
+
+/// Shows what Twilio reported for the texts sent for a reminder.
+pub struct StatusCommand {
+    delivery_statuses: DeliveryStatuses,
+    user_settings: UserSettings,
+}
+
+impl StatusCommand {
+    pub fn new(delivery_statuses: DeliveryStatuses, user_settings: UserSettings) -> StatusCommand {
+        StatusCommand {
+            delivery_statuses,
+            user_settings,
+        }
+    }
+}
+
+impl Command for StatusCommand {
+    fn name(&self) -> &'static str {
+        "status"
+    }
+
+    fn pattern(&self) -> &'static str {
+        r"^status\s+(\S+)\s*$"
+    }
+
+    fn usage(&self) -> &'static str {
+        "status <id>"
+    }
+
+    fn description(&self) -> &'static str {
+        "Show whether the texts for a reminder were delivered"
+    }
+
+    fn handle(&self, ctx: &CommandContext, args: &Captures) -> Box<Future<Item = (), Error = ()>> {
+        let reminder_id = &args[1];
+
+        let statuses = match self
+            .delivery_statuses
+            .get_statuses_for_reminder(reminder_id, &ctx.event.sender)
+        {
+            Ok(statuses) => statuses,
+            Err(err) => {
+                error!(ctx.logger, "Failed to get delivery statuses"; "error" => %err);
+                return ctx.reply(&format!("Error: Failed to get delivery status: {}", err));
+            }
+        };
+
+        if statuses.is_empty() {
+            return ctx.reply(&format!("No delivery status for reminder {}", reminder_id));
+        }
+
+        let tz = get_timezone(&self.user_settings, ctx);
+
+        let lines: Vec<String> = statuses
+            .iter()
+            .map(|status| {
+                let error = status
+                    .error_code
+                    .as_ref()
+                    .map(|code| format!(" (error {})", code))
+                    .unwrap_or_default();
+
+                format!(
+                    "{} at '{}'{}",
+                    status.status,
+                    status.updated.with_timezone(&tz).to_rfc2822(),
+                    error
+                )
+            })
+            .collect();
+
+        ctx.reply(&format!(
+            "Delivery status of {}:\n{}",
+            reminder_id,
+            lines.join("\n")
+        ))
+    }
+}
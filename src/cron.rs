@@ -0,0 +1,225 @@
+use chrono::{DateTime, Datelike, Duration, NaiveDateTime, NaiveTime, TimeZone, Timelike};
+use chrono_tz::Tz;
+use failure::{Error, ResultExt};
+
+use std::fmt;
+
+/// How many days ahead we search for the next matching time. This needs to
+/// cover schedules like "29th of February", which may not match for eight
+/// years around non-leap centuries.
+const MAX_SEARCH_DAYS: i64 = 8 * 366;
+
+/// A standard five field cron schedule, i.e. "minute hour day-of-month month
+/// day-of-week".
+#[derive(Debug, Clone, PartialEq)]
+pub struct CronSchedule {
+    spec: String,
+    minutes: Vec<u32>,
+    hours: Vec<u32>,
+    days_of_month: Vec<u32>,
+    months: Vec<u32>,
+    days_of_week: Vec<u32>,
+    day_of_month_restricted: bool,
+    day_of_week_restricted: bool,
+}
+
+impl CronSchedule {
+    pub fn parse(spec: &str) -> Result<CronSchedule, Error> {
+        let spec = spec.trim().to_lowercase();
+
+        let fields: Vec<&str> = spec.split_whitespace().collect();
+        if fields.len() != 5 {
+            bail!("expected 5 fields in cron schedule, got {}", fields.len());
+        }
+
+        let minutes = parse_field(fields[0], 0, 59, &[]).context("invalid minute field")?;
+        let hours = parse_field(fields[1], 0, 23, &[]).context("invalid hour field")?;
+        let days_of_month =
+            parse_field(fields[2], 1, 31, &[]).context("invalid day of month field")?;
+        let months = parse_field(fields[3], 1, 12, &MONTH_NAMES).context("invalid month field")?;
+        let mut days_of_week =
+            parse_field(fields[4], 0, 7, &DAY_NAMES).context("invalid day of week field")?;
+
+        // Both 0 and 7 mean Sunday
+        for day in &mut days_of_week {
+            if *day == 7 {
+                *day = 0;
+            }
+        }
+        days_of_week.sort();
+        days_of_week.dedup();
+
+        Ok(CronSchedule {
+            minutes,
+            hours,
+            days_of_month,
+            months,
+            days_of_week,
+            day_of_month_restricted: !fields[2].starts_with('*'),
+            day_of_week_restricted: !fields[4].starts_with('*'),
+            spec: fields.join(" "),
+        })
+    }
+
+    /// The normalized schedule, suitable for parsing again.
+    pub fn spec(&self) -> &str {
+        &self.spec
+    }
+
+    /// Finds the first time strictly after the given one that matches the
+    /// schedule, evaluated in the local time of the given timezone.
+    pub fn next_after(&self, after: DateTime<Tz>) -> Option<DateTime<Tz>> {
+        let tz = after.timezone();
+
+        let start = after.naive_local().with_second(0)?.with_nanosecond(0)?;
+        let start = start + Duration::minutes(1);
+        let start_date = start.date();
+
+        for offset in 0..MAX_SEARCH_DAYS {
+            let date = start_date + Duration::days(offset);
+
+            if !self.months.contains(&date.month()) || !self.matches_day(&date) {
+                continue;
+            }
+
+            for &hour in &self.hours {
+                for &minute in &self.minutes {
+                    let time = NaiveTime::from_hms(hour, minute, 0);
+                    let candidate = NaiveDateTime::new(date, time);
+                    if candidate < start {
+                        continue;
+                    }
+
+                    // Skip times that don't exist due to DST changes
+                    if let Some(dt) = tz.from_local_datetime(&candidate).earliest() {
+                        return Some(dt);
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    fn matches_day<D: Datelike>(&self, date: &D) -> bool {
+        let dom = self.days_of_month.contains(&date.day());
+        let dow = self
+            .days_of_week
+            .contains(&date.weekday().num_days_from_sunday());
+
+        // Standard cron semantics: if both fields are restricted then either
+        // may match.
+        match (self.day_of_month_restricted, self.day_of_week_restricted) {
+            (true, true) => dom || dow,
+            _ => dom && dow,
+        }
+    }
+}
+
+impl fmt::Display for CronSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.spec)
+    }
+}
+
+const MONTH_NAMES: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+const DAY_NAMES: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// Parses a single cron field, e.g. "*/15", "1-5" or "mon,wed,fri". Names
+/// are mapped to `min + index`.
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<Vec<u32>, Error> {
+    let mut values = Vec::new();
+
+    for part in field.split(',') {
+        let mut split = part.splitn(2, '/');
+        let range = split.next().unwrap_or("");
+        let step = match split.next() {
+            Some(step) => step.parse::<u32>().context("invalid step")?,
+            None => 1,
+        };
+
+        if step == 0 {
+            bail!("step must be positive");
+        }
+
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some(idx) = range.find('-') {
+            (
+                parse_value(&range[..idx], min, names)?,
+                parse_value(&range[idx + 1..], min, names)?,
+            )
+        } else {
+            let value = parse_value(range, min, names)?;
+            if part.contains('/') {
+                (value, max)
+            } else {
+                (value, value)
+            }
+        };
+
+        if start < min || end > max || start > end {
+            bail!("{} out of range {}-{}", part, min, max);
+        }
+
+        values.extend((start..=end).step_by(step as usize));
+    }
+
+    values.sort();
+    values.dedup();
+
+    Ok(values)
+}
+
+fn parse_value(value: &str, min: u32, names: &[&str]) -> Result<u32, Error> {
+    if let Some(idx) = names.iter().position(|name| *name == value) {
+        return Ok(min + idx as u32);
+    }
+
+    Ok(value
+        .parse::<u32>()
+        .with_context(|_| format!("invalid value {}", value))?)
+}
+
+#[test]
+fn cron_schedule_test() {
+    use chrono::Utc;
+    use chrono_tz::UTC;
+
+    // A Tuesday
+    let dt = UTC.ymd(2014, 7, 8).and_hms(9, 10, 11);
+
+    let schedule = CronSchedule::parse("0 9 * * 1-5").unwrap();
+    assert_eq!(
+        schedule.next_after(dt).unwrap(),
+        Utc.ymd(2014, 7, 9).and_hms(9, 0, 0)
+    );
+
+    let schedule = CronSchedule::parse("*/15 * * * *").unwrap();
+    assert_eq!(
+        schedule.next_after(dt).unwrap(),
+        Utc.ymd(2014, 7, 8).and_hms(9, 15, 0)
+    );
+
+    let schedule = CronSchedule::parse("30 17 * * fri").unwrap();
+    assert_eq!(
+        schedule.next_after(dt).unwrap(),
+        Utc.ymd(2014, 7, 11).and_hms(17, 30, 0)
+    );
+
+    let schedule = CronSchedule::parse("0 0 1 jan *").unwrap();
+    assert_eq!(
+        schedule.next_after(dt).unwrap(),
+        Utc.ymd(2015, 1, 1).and_hms(0, 0, 0)
+    );
+
+    assert!(CronSchedule::parse("0 9 * *").is_err());
+    assert!(CronSchedule::parse("60 9 * * *").is_err());
+    assert!(CronSchedule::parse("0 0 31 2 *")
+        .unwrap()
+        .next_after(dt)
+        .is_none());
+}
@@ -0,0 +1,354 @@
+use failure::Error;
+
+use std::str::FromStr;
+
+use super::tokenizer::{Token, TokenKind};
+
+/// Phrases in French time expressions and what they are in English, as
+/// tokens separated by spaces.
+const FRENCH: [(&str, &str); 79] = [
+    ("aujourd ' hui", "today"),
+    ("aujourd ’ hui", "today"),
+    ("après - demain", "the day after tomorrow"),
+    ("apres - demain", "the day after tomorrow"),
+    ("demain", "tomorrow"),
+    ("ce soir", "tonight"),
+    ("ce matin", "this morning"),
+    ("cet après - midi", "this afternoon"),
+    ("cet apres - midi", "this afternoon"),
+    ("après - midi", "afternoon"),
+    ("apres - midi", "afternoon"),
+    ("le matin", "in the morning"),
+    ("le soir", "in the evening"),
+    ("matin", "morning"),
+    ("soir", "evening"),
+    ("midi", "noon"),
+    ("minuit", "midnight"),
+    ("à", "at"),
+    ("dans", "in"),
+    ("un", "a"),
+    ("une", "a"),
+    ("et", "and"),
+    ("demie", "a half"),
+    ("demi", "a half"),
+    ("seconde", "second"),
+    ("secondes", "seconds"),
+    ("heure", "hour"),
+    ("heures", "hours"),
+    ("jour", "day"),
+    ("jours", "days"),
+    ("semaine", "week"),
+    ("semaines", "weeks"),
+    ("mois", "months"),
+    ("ans", "years"),
+    ("la semaine prochaine", "next week"),
+    ("semaine prochaine", "next week"),
+    ("lundi prochain", "next monday"),
+    ("mardi prochain", "next tuesday"),
+    ("mercredi prochain", "next wednesday"),
+    ("jeudi prochain", "next thursday"),
+    ("vendredi prochain", "next friday"),
+    ("samedi prochain", "next saturday"),
+    ("dimanche prochain", "next sunday"),
+    ("lundi", "monday"),
+    ("mardi", "tuesday"),
+    ("mercredi", "wednesday"),
+    ("jeudi", "thursday"),
+    ("vendredi", "friday"),
+    ("samedi", "saturday"),
+    ("dimanche", "sunday"),
+    ("lundis", "mondays"),
+    ("mardis", "tuesdays"),
+    ("mercredis", "wednesdays"),
+    ("jeudis", "thursdays"),
+    ("vendredis", "fridays"),
+    ("samedis", "saturdays"),
+    ("dimanches", "sundays"),
+    ("le", "on"),
+    ("janvier", "january"),
+    ("février", "february"),
+    ("fevrier", "february"),
+    ("mars", "march"),
+    ("avril", "april"),
+    ("mai", "may"),
+    ("juin", "june"),
+    ("juillet", "july"),
+    ("août", "august"),
+    ("aout", "august"),
+    ("septembre", "september"),
+    ("octobre", "october"),
+    ("novembre", "november"),
+    ("décembre", "december"),
+    ("decembre", "december"),
+    ("tous les jours", "every day"),
+    ("toutes les heures", "every hour"),
+    ("tous les", "every"),
+    ("toutes les", "every"),
+    ("chaque jour", "every day"),
+    ("chaque", "every"),
+];
+
+/// Phrases in German time expressions and what they are in English, as
+/// tokens separated by spaces.
+const GERMAN: [(&str, &str); 79] = [
+    ("übermorgen", "the day after tomorrow"),
+    ("uebermorgen", "the day after tomorrow"),
+    ("morgen früh", "tomorrow morning"),
+    ("morgen frueh", "tomorrow morning"),
+    ("heute morgen", "this morning"),
+    ("heute nachmittag", "this afternoon"),
+    ("heute abend", "tonight"),
+    ("morgen", "tomorrow"),
+    ("heute", "today"),
+    ("morgens", "in the morning"),
+    ("nachmittags", "in the afternoon"),
+    ("abends", "in the evening"),
+    ("nachmittag", "afternoon"),
+    ("abend", "evening"),
+    ("mittag", "noon"),
+    ("mitternacht", "midnight"),
+    ("um", "at"),
+    ("einer", "a"),
+    ("einem", "a"),
+    ("eine", "a"),
+    ("ein", "a"),
+    ("und", "and"),
+    ("sekunde", "second"),
+    ("sekunden", "seconds"),
+    ("minuten", "minutes"),
+    ("stunde", "hour"),
+    ("stunden", "hours"),
+    ("tag", "day"),
+    ("tage", "days"),
+    ("tagen", "days"),
+    ("woche", "week"),
+    ("wochen", "weeks"),
+    ("monat", "month"),
+    ("monaten", "months"),
+    ("jahr", "year"),
+    ("jahren", "years"),
+    ("nächste woche", "next week"),
+    ("naechste woche", "next week"),
+    ("nächsten", "next"),
+    ("naechsten", "next"),
+    ("nächste", "next"),
+    ("naechste", "next"),
+    ("am montag", "on monday"),
+    ("am dienstag", "on tuesday"),
+    ("am mittwoch", "on wednesday"),
+    ("am donnerstag", "on thursday"),
+    ("am freitag", "on friday"),
+    ("am samstag", "on saturday"),
+    ("am sonntag", "on sunday"),
+    ("montag", "monday"),
+    ("dienstag", "tuesday"),
+    ("mittwoch", "wednesday"),
+    ("donnerstag", "thursday"),
+    ("freitag", "friday"),
+    ("samstag", "saturday"),
+    ("sonnabend", "saturday"),
+    ("sonntag", "sunday"),
+    ("montags", "mondays"),
+    ("dienstags", "tuesdays"),
+    ("mittwochs", "wednesdays"),
+    ("donnerstags", "thursdays"),
+    ("freitags", "fridays"),
+    ("samstags", "saturdays"),
+    ("sonntags", "sundays"),
+    ("januar", "january"),
+    ("februar", "february"),
+    ("märz", "march"),
+    ("maerz", "march"),
+    ("mai", "may"),
+    ("juni", "june"),
+    ("juli", "july"),
+    ("oktober", "october"),
+    ("dezember", "december"),
+    ("jeden tag", "every day"),
+    ("jede stunde", "every hour"),
+    ("täglich", "every day"),
+    ("taeglich", "every day"),
+    ("jeden", "every"),
+    ("jede", "every"),
+];
+
+/// A language times can be written in. The language's words are read as
+/// their English equivalents, so English is understood as well.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Locale {
+    English,
+    French,
+    German,
+}
+
+impl Locale {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Locale::English => "en",
+            Locale::French => "fr",
+            Locale::German => "de",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Locale::English => "English",
+            Locale::French => "French",
+            Locale::German => "German",
+        }
+    }
+
+    fn phrases(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Locale::English => &[],
+            Locale::French => &FRENCH,
+            Locale::German => &GERMAN,
+        }
+    }
+}
+
+impl FromStr for Locale {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Locale, Error> {
+        match s.to_lowercase().as_str() {
+            "en" | "english" => Ok(Locale::English),
+            "fr" | "french" | "français" | "francais" => Ok(Locale::French),
+            "de" | "german" | "deutsch" => Ok(Locale::German),
+            _ => bail!("unknown language {}, expected en, fr or de", s),
+        }
+    }
+}
+
+/// Rewrites the tokens of a time expression into English, e.g. "demain à
+/// 18h" into "tomorrow at 18:00". The new tokens cover the same parts of
+/// the input as the ones they replace.
+pub fn translate(tokens: Vec<Token>, locale: Locale) -> Vec<Token> {
+    if locale == Locale::English {
+        return tokens;
+    }
+
+    let mut translated: Vec<Token> = Vec::with_capacity(tokens.len());
+    let mut i = 0;
+
+    while i < tokens.len() {
+        let token = &tokens[i];
+
+        let after_number = translated.last().map_or(false, |last| {
+            last.kind == TokenKind::Number && last.end == token.start
+        });
+        let after_in = translated.len() >= 2 && translated[translated.len() - 2].is_word("in");
+
+        // e.g. "18h" or "18h30", but not "dans 2h" which is a duration
+        if locale == Locale::French && token.is_word("h") && after_number && !after_in {
+            translated.push(Token {
+                kind: TokenKind::Symbol,
+                text: ":".to_string(),
+                start: token.start,
+                end: token.end,
+            });
+
+            let has_minutes = tokens.get(i + 1).map_or(false, |next| {
+                next.kind == TokenKind::Number && next.start == token.end
+            });
+            if !has_minutes {
+                translated.push(Token {
+                    kind: TokenKind::Number,
+                    text: "00".to_string(),
+                    start: token.start,
+                    end: token.end,
+                });
+            }
+
+            i += 1;
+            continue;
+        }
+
+        // e.g. "9 Uhr" is just 9
+        if locale == Locale::German && token.is_word("uhr") {
+            if let Some(last) = translated.last_mut() {
+                if last.kind == TokenKind::Number {
+                    last.end = token.end;
+                    i += 1;
+                    continue;
+                }
+            }
+        }
+
+        // The longest phrase starting here
+        let phrase = locale
+            .phrases()
+            .iter()
+            .filter_map(|&(from, to)| {
+                let words: Vec<&str> = from.split(' ').collect();
+                let matches = tokens.len() - i >= words.len()
+                    && words
+                        .iter()
+                        .zip(&tokens[i..])
+                        .all(|(word, token)| token.text == *word);
+
+                if matches {
+                    Some((words.len(), to))
+                } else {
+                    None
+                }
+            })
+            .max_by_key(|&(len, _)| len);
+
+        match phrase {
+            Some((len, to)) => {
+                let start = token.start;
+                let end = tokens[i + len - 1].end;
+
+                translated.extend(to.split(' ').map(|word| Token {
+                    kind: TokenKind::Word,
+                    text: word.to_string(),
+                    start,
+                    end,
+                }));
+
+                i += len;
+            }
+            None => {
+                translated.push(token.clone());
+                i += 1;
+            }
+        }
+    }
+
+    translated
+}
+
+#[test]
+fn translate_test() {
+    use super::tokenizer::tokenize;
+
+    let texts = |input, locale| -> Vec<String> {
+        translate(tokenize(input), locale)
+            .into_iter()
+            .map(|t| t.text)
+            .collect()
+    };
+
+    assert_eq!(
+        texts("demain à 18h", Locale::French),
+        vec!["tomorrow", "at", "18", ":", "00"]
+    );
+    assert_eq!(
+        texts("après-demain à 9h30", Locale::French),
+        vec!["the", "day", "after", "tomorrow", "at", "9", ":", "30"]
+    );
+    assert_eq!(texts("dans 2h", Locale::French), vec!["in", "2", "h"]);
+    assert_eq!(
+        texts("Morgen um 9 Uhr", Locale::German),
+        vec!["tomorrow", "at", "9"]
+    );
+    assert_eq!(texts("heute abend", Locale::German), vec!["tonight"]);
+    assert_eq!(
+        texts("demain à 18h", Locale::English),
+        vec!["demain", "à", "18", "h"]
+    );
+
+    let tokens = translate(tokenize("Morgen um 9 Uhr"), Locale::German);
+    assert_eq!((tokens[2].start, tokens[2].end), (10, 15));
+}
@@ -0,0 +1,2212 @@
+use chrono::{
+    DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Timelike, Weekday,
+};
+use chrono_tz::Tz;
+use failure::{err_msg, Error, ResultExt};
+
+use std::cmp;
+use std::fmt;
+use std::str::FromStr;
+
+use cron::CronSchedule;
+use rrule::RRule;
+
+mod locale;
+mod parser;
+mod tokenizer;
+
+pub use self::locale::Locale;
+
+use self::parser::{parse_schedule, DatePart, ParsedSchedule, Period, RepeatLimit, TimePart};
+use self::tokenizer::{tokenize, Token};
+
+/// The most times a repeating schedule can be limited to, e.g. "every hour
+/// 24 times".
+const MAX_REPEAT_TIMES: u32 = 1000;
+
+/// How a reminder repeats once it has been delivered.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Recurrence {
+    /// Repeats after a fixed amount of time, e.g. "every 2 hours".
+    Interval(Duration),
+    /// Repeats every N days at the same time of day, e.g. "every day".
+    Days(i64),
+    /// Repeats on the given days of the week, e.g. "every monday".
+    Weekdays(Vec<Weekday>),
+    /// Repeats according to a cron schedule, e.g. `cron "0 9 * * 1-5"`.
+    Cron(CronSchedule),
+    /// Repeats according to an iCal RRULE, e.g. "FREQ=WEEKLY;BYDAY=MO,WE".
+    RRule(RRule),
+}
+
+impl Recurrence {
+    /// Calculates when the reminder should next fire after the given
+    /// occurrence, if ever.
+    pub fn next_occurrence(&self, prev: DateTime<Tz>) -> Option<DateTime<Tz>> {
+        match *self {
+            Recurrence::Interval(dur) => prev.checked_add_signed(dur),
+            // Days are counted on the calendar, so that the local time stays
+            // the same when the clocks change
+            Recurrence::Days(days) => add_days(prev, days),
+            Recurrence::Weekdays(ref weekdays) => {
+                let mut days = 1;
+                while !weekdays.contains(&(prev.naive_local() + Duration::days(days)).weekday()) {
+                    days += 1;
+                }
+                add_days(prev, days)
+            }
+            Recurrence::Cron(ref schedule) => schedule.next_after(prev),
+            Recurrence::RRule(ref rule) => rule.next_after(prev),
+        }
+    }
+
+    /// Serializes the recurrence for storing in the database.
+    pub fn to_spec(&self) -> String {
+        match *self {
+            Recurrence::Interval(dur) => format!("interval:{}", dur.num_seconds()),
+            Recurrence::Days(days) => format!("days:{}", days),
+            Recurrence::Weekdays(ref weekdays) => {
+                let names: Vec<&str> = weekdays.iter().map(|d| weekday_name(*d)).collect();
+                format!("weekdays:{}", names.join(","))
+            }
+            Recurrence::Cron(ref schedule) => format!("cron:{}", schedule.spec()),
+            Recurrence::RRule(ref rule) => format!("rrule:{}", rule.spec()),
+        }
+    }
+
+    /// Parses a recurrence previously serialized with `to_spec`.
+    pub fn from_spec(spec: &str) -> Result<Recurrence, Error> {
+        let mut split = spec.splitn(2, ':');
+        let kind = split.next().unwrap_or("");
+        let value = split
+            .next()
+            .ok_or_else(|| format_err!("invalid recurrence {}", spec))?;
+
+        match kind {
+            "interval" => {
+                let secs = value.parse::<i64>().context("invalid interval")?;
+                Ok(Recurrence::Interval(Duration::seconds(secs)))
+            }
+            "days" => {
+                let days = value.parse::<i64>().context("invalid days")?;
+                Ok(Recurrence::Days(days))
+            }
+            "weekdays" => {
+                let weekdays = value
+                    .split(',')
+                    .map(|d| {
+                        d.parse::<Weekday>()
+                            .map_err(|_| format_err!("invalid weekday {}", d))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                if weekdays.is_empty() {
+                    bail!("no weekdays in recurrence");
+                }
+
+                Ok(Recurrence::Weekdays(weekdays))
+            }
+            "cron" => Ok(Recurrence::Cron(CronSchedule::parse(value)?)),
+            "rrule" => Ok(Recurrence::RRule(RRule::parse(value)?)),
+            _ => bail!("unknown recurrence type {}", kind),
+        }
+    }
+}
+
+impl fmt::Display for Recurrence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Recurrence::Interval(dur) => {
+                let secs = dur.num_seconds();
+                let (n, unit) = if secs % 3600 == 0 {
+                    (secs / 3600, "hour")
+                } else if secs % 60 == 0 {
+                    (secs / 60, "minute")
+                } else {
+                    (secs, "second")
+                };
+
+                if n == 1 {
+                    write!(f, "every {}", unit)
+                } else {
+                    write!(f, "every {} {}s", n, unit)
+                }
+            }
+            Recurrence::Days(1) => write!(f, "every day"),
+            Recurrence::Days(7) => write!(f, "every week"),
+            Recurrence::Days(days) if days % 7 == 0 => write!(f, "every {} weeks", days / 7),
+            Recurrence::Days(days) => write!(f, "every {} days", days),
+            Recurrence::Weekdays(ref weekdays) => {
+                if weekdays.len() == 5 && weekdays.iter().all(|d| is_working_day(*d)) {
+                    return write!(f, "every weekday");
+                }
+
+                let names: Vec<String> = weekdays
+                    .iter()
+                    .map(|d| format!("{}day", weekday_full_name(*d)))
+                    .collect();
+                write!(f, "every {}", names.join(", "))
+            }
+            Recurrence::Cron(ref schedule) => write!(f, "on cron schedule '{}'", schedule),
+            Recurrence::RRule(ref rule) => write!(f, "according to RRULE '{}'", rule),
+        }
+    }
+}
+
+/// A user's preferences for how dates and times are interpreted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseSettings {
+    /// What "morning" means, e.g. "tomorrow morning".
+    pub morning: NaiveTime,
+    /// What "afternoon" means, e.g. "this afternoon".
+    pub afternoon: NaiveTime,
+    /// What "evening" means, also used for "tonight".
+    pub evening: NaiveTime,
+    /// When the working day ends, for "end of day", "end of the week" (on
+    /// Friday) and "end of the month".
+    pub end_of_day: NaiveTime,
+    /// How to read dates like 04/12/2024. If unset, dates that could be
+    /// read either way are rejected.
+    pub date_order: Option<DateOrder>,
+    /// Dates named by admins, e.g. "mum's birthday", as well as the
+    /// holidays that are always understood.
+    pub named_dates: Vec<NamedDate>,
+    /// Days off that business days skip, as well as weekends.
+    pub holidays: Vec<NaiveDate>,
+    /// The language times are written in, if not English.
+    pub locale: Option<Locale>,
+    /// How far ahead reminders can be set, if there's a limit.
+    pub max_horizon: Option<Duration>,
+}
+
+impl ParseSettings {
+    fn locale(&self) -> Locale {
+        self.locale.unwrap_or(Locale::English)
+    }
+}
+
+/// A day of the year that can be referred to by name, e.g. "on mum's
+/// birthday".
+#[derive(Debug, Clone, PartialEq)]
+pub struct NamedDate {
+    pub name: String,
+    pub month: u32,
+    pub day: u32,
+}
+
+/// Whether numeric dates are written day or month first.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DateOrder {
+    /// e.g. 04/12/2024 is the 4th of December
+    DayFirst,
+    /// e.g. 12/04/2024 is the 4th of December
+    MonthFirst,
+}
+
+impl DateOrder {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DateOrder::DayFirst => "dmy",
+            DateOrder::MonthFirst => "mdy",
+        }
+    }
+}
+
+impl FromStr for DateOrder {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<DateOrder, Error> {
+        match s {
+            "dmy" => Ok(DateOrder::DayFirst),
+            "mdy" => Ok(DateOrder::MonthFirst),
+            _ => bail!("unknown date order {}, expected dmy or mdy", s),
+        }
+    }
+}
+
+/// A numeric date that could be read either day or month first, and the
+/// user hasn't said which they use.
+#[derive(Fail, Debug)]
+#[fail(
+    display = "By {} did you mean {} or {}? Use 'set date order dmy' or 'set date order mdy' to say how you write dates",
+    input, day_first, month_first
+)]
+pub struct AmbiguousDate {
+    pub input: String,
+    pub day_first: String,
+    pub month_first: String,
+}
+
+/// A time today that has already passed, e.g. "today at 9am" in the
+/// afternoon, which is more likely a mistake than meaning tomorrow.
+#[derive(Fail, Debug)]
+#[fail(display = "{} has already passed", input)]
+pub struct PastTime {
+    pub input: String,
+    /// The same time tomorrow
+    pub tomorrow: DateTime<Tz>,
+}
+
+/// A time further ahead than reminders can be set, e.g. "in 9999999 weeks".
+#[derive(Fail, Debug)]
+#[fail(
+    display = "{} is too far away, reminders can be set at most {} ahead",
+    input, max
+)]
+pub struct TooFarAway {
+    pub input: String,
+    /// The limit, e.g. "5 years"
+    pub max: String,
+}
+
+impl Default for ParseSettings {
+    fn default() -> ParseSettings {
+        ParseSettings {
+            morning: NaiveTime::from_hms(9, 30, 0),
+            afternoon: NaiveTime::from_hms(14, 0, 0),
+            evening: NaiveTime::from_hms(19, 0, 0),
+            end_of_day: NaiveTime::from_hms(17, 0, 0),
+            date_order: None,
+            named_dates: Vec::new(),
+            holidays: Vec::new(),
+            locale: None,
+            max_horizon: None,
+        }
+    }
+}
+
+/// Works out when a time expression such as "tomorrow at 5pm" or "in 2
+/// hours" is due. The whole input must be a time expression.
+pub fn parse_human_datetime(
+    input: &str,
+    now: DateTime<Tz>,
+    settings: &ParseSettings,
+) -> Result<DateTime<Tz>, Error> {
+    let schedule = parse_schedule(input, &settings.named_dates, settings.locale())?;
+    check_fully_parsed(input, &schedule)?;
+
+    if schedule.recurrence.is_some() {
+        bail!("unexpected repeating schedule");
+    }
+
+    let due = resolve_datetime(input, &schedule, now, settings)?;
+    check_horizon(input, due, now, settings)?;
+
+    Ok(due)
+}
+
+/// Parses an amount of time such as "3 days" or "1 hour 30 minutes", e.g.
+/// how long ahead of a reminder to warn. Unlike "in 3 days" it's never moved
+/// to the morning, so it doesn't depend on the time of day.
+pub fn parse_duration(
+    input: &str,
+    now: DateTime<Tz>,
+    settings: &ParseSettings,
+) -> Result<Duration, Error> {
+    let input = format!("in {}", input.trim());
+    let schedule = parse_schedule(&input, &settings.named_dates, settings.locale())?;
+    check_fully_parsed(&input, &schedule)?;
+
+    if schedule.time.is_some() || schedule.recurrence.is_some() || schedule.timezone.is_some() {
+        bail!("expected an amount of time, e.g. 2 hours");
+    }
+
+    match schedule.date {
+        Some(DatePart::Relative {
+            months, seconds, ..
+        }) => Ok(add_relative(now, months, seconds)?.signed_duration_since(now)),
+        _ => bail!("expected an amount of time, e.g. 2 hours"),
+    }
+}
+
+/// Parses a repeating schedule such as "every monday at 10:00",
+/// `cron "0 9 * * 1-5"` or "RRULE:FREQ=WEEKLY;BYDAY=MO", returning the
+/// recurrence, when it should first fire and, if it stops, the latest it can
+/// fire, e.g. for "every hour for the next 6 hours" or "every day 5 times".
+/// Returns `None` if the input isn't a recurring schedule.
+pub fn parse_recurrence(
+    input: &str,
+    now: DateTime<Tz>,
+    settings: &ParseSettings,
+) -> Result<Option<(Recurrence, DateTime<Tz>, Option<DateTime<Tz>>)>, Error> {
+    let schedule = parse_schedule(input, &settings.named_dates, settings.locale())?;
+
+    let recurrence = match schedule.recurrence {
+        Some(ref recurrence) => recurrence.clone(),
+        None => return Ok(None),
+    };
+
+    check_fully_parsed(input, &schedule)?;
+    check_interval(input, &recurrence, settings)?;
+
+    let first = resolve_first_occurrence(&recurrence, &schedule, now, settings)?;
+    check_horizon(input, first, now, settings)?;
+
+    let until = match schedule.limit {
+        Some(ref limit) => Some(resolve_repeat_limit(
+            input,
+            limit,
+            &recurrence,
+            &schedule,
+            first,
+            now,
+            settings,
+        )?),
+        None => None,
+    };
+
+    Ok(Some((recurrence, first, until)))
+}
+
+/// Splits e.g. "in 2h to go to the shops" into the time expression at the
+/// start and the text after the "to" that follows it. The split is after
+/// as much as the parser understood, so a "to" in the text doesn't matter.
+/// If the start isn't a time expression it's split at the first "to",
+/// leaving parsing the time to say what's wrong with it.
+///
+/// Also accepts the time at the end, e.g. "to go to the shops in 2h".
+pub fn split_schedule<'a>(input: &'a str, settings: &ParseSettings) -> Option<(&'a str, &'a str)> {
+    if let Ok(schedule) = parse_schedule(input, &settings.named_dates, settings.locale()) {
+        if let Some(text) = strip_to(&input[schedule.end..]) {
+            return Some((&input[schedule.start..schedule.end], text));
+        }
+    }
+
+    let tokens = tokenize(input);
+
+    if tokens.get(0).map_or(false, |token| token.is_word("to")) {
+        return split_trailing_schedule(input, &tokens, settings);
+    }
+
+    let index = tokens
+        .iter()
+        .skip(1)
+        .position(|token| token.is_word("to"))?
+        + 1;
+    let text = tokens.get(index + 1)?;
+
+    Some((input[..tokens[index].start].trim(), &input[text.start..]))
+}
+
+/// Splits e.g. "to call mum tomorrow at 6pm" into the time expression at
+/// the end and the text before it.
+fn split_trailing_schedule<'a>(
+    input: &'a str,
+    tokens: &[Token],
+    settings: &ParseSettings,
+) -> Option<(&'a str, &'a str)> {
+    let text_start = tokens.get(1)?.start;
+
+    // The earliest start that parses to the end is the longest time. It
+    // has to say when, so that e.g. a trailing "EST" alone isn't taken as
+    // the schedule.
+    for token in tokens.iter().skip(2) {
+        let at = &input[token.start..];
+        if let Ok(schedule) = parse_schedule(at, &settings.named_dates, settings.locale()) {
+            let has_when =
+                schedule.date.is_some() || schedule.time.is_some() || schedule.recurrence.is_some();
+
+            if has_when && at[schedule.end..].trim().is_empty() {
+                return Some((
+                    at[..schedule.end].trim(),
+                    input[text_start..token.start].trim(),
+                ));
+            }
+        }
+    }
+
+    None
+}
+
+/// Parses a day of the year such as "14 March" or "March 14th", e.g. for
+/// naming dates.
+pub fn parse_day_of_year(input: &str) -> Result<(u32, u32), Error> {
+    let schedule = parse_schedule(input, &[], Locale::English)?;
+    check_fully_parsed(input, &schedule)?;
+
+    if schedule.time.is_some() || schedule.recurrence.is_some() {
+        bail!("expected just a day and month, e.g. 14 March");
+    }
+
+    match schedule.date {
+        Some(DatePart::Date {
+            year: None,
+            month,
+            day,
+        }) => {
+            // A leap year, so the 29th of February is allowed
+            if NaiveDate::from_ymd_opt(2000, month, day).is_none() {
+                bail!("invalid date {:02}-{:02}", month, day);
+            }
+
+            Ok((month, day))
+        }
+        _ => bail!("expected a day and month, e.g. 14 March"),
+    }
+}
+
+/// Parses a single day such as "25 December 2024" or "next friday", e.g.
+/// for the holiday calendar.
+pub fn parse_calendar_date(
+    input: &str,
+    now: DateTime<Tz>,
+    settings: &ParseSettings,
+) -> Result<NaiveDate, Error> {
+    let schedule = parse_schedule(input, &settings.named_dates, settings.locale())?;
+    check_fully_parsed(input, &schedule)?;
+
+    if schedule.time.is_some() || schedule.recurrence.is_some() || schedule.timezone.is_some() {
+        bail!("expected just a date, e.g. 25 December 2024");
+    }
+
+    match schedule.date {
+        Some(DatePart::Relative { .. }) | None => {
+            bail!("expected a date, e.g. 25 December 2024")
+        }
+        Some(ref part) => Ok(resolve_date(part, now, settings)?.naive_local().date()),
+    }
+}
+
+/// Normalizes a date's name for comparing with others, so e.g. "Mum's
+/// Birthday" and "mums birthday" are the same.
+pub fn date_name_key(name: &str) -> String {
+    name.to_lowercase()
+        .replace('\'', "")
+        .replace('’', "")
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+/// The text after a leading "to", if there is one.
+fn strip_to(input: &str) -> Option<&str> {
+    let tokens = tokenize(input);
+    match (tokens.get(0), tokens.get(1)) {
+        (Some(to), Some(text)) if to.is_word("to") => Some(&input[text.start..]),
+        _ => None,
+    }
+}
+
+/// Fails with `TooFarAway` if the time is beyond the furthest reminders can
+/// be set.
+fn check_horizon(
+    input: &str,
+    due: DateTime<Tz>,
+    now: DateTime<Tz>,
+    settings: &ParseSettings,
+) -> Result<(), Error> {
+    let max_horizon = match settings.max_horizon {
+        Some(max_horizon) => max_horizon,
+        None => return Ok(()),
+    };
+
+    if due.signed_duration_since(now) <= max_horizon {
+        return Ok(());
+    }
+
+    Err(too_far_away(input, max_horizon))
+}
+
+/// Fails with `TooFarAway` if a repeating schedule's interval is beyond the
+/// furthest reminders can be set, as every repeat would be.
+fn check_interval(
+    input: &str,
+    recurrence: &Recurrence,
+    settings: &ParseSettings,
+) -> Result<(), Error> {
+    let max_horizon = match settings.max_horizon {
+        Some(max_horizon) => max_horizon,
+        None => return Ok(()),
+    };
+
+    let interval = match *recurrence {
+        Recurrence::Interval(dur) => dur,
+        Recurrence::Days(days) => Duration::days(days),
+        _ => return Ok(()),
+    };
+
+    if interval <= max_horizon {
+        return Ok(());
+    }
+
+    Err(too_far_away(input, max_horizon))
+}
+
+fn too_far_away(input: &str, max_horizon: Duration) -> Error {
+    let days = max_horizon.num_days();
+    let max = if days >= 365 && days % 365 == 0 {
+        plural(days / 365, "year")
+    } else {
+        plural(days, "day")
+    };
+
+    TooFarAway {
+        input: input.trim().to_string(),
+        max,
+    }
+    .into()
+}
+
+fn check_fully_parsed(input: &str, schedule: &ParsedSchedule) -> Result<(), Error> {
+    let rest = input[schedule.end..].trim();
+    if !rest.is_empty() {
+        bail!("couldn't parse '{}'", rest);
+    }
+
+    Ok(())
+}
+
+/// Works out when a (non-repeating) schedule is due.
+fn resolve_datetime(
+    input: &str,
+    schedule: &ParsedSchedule,
+    now: DateTime<Tz>,
+    settings: &ParseSettings,
+) -> Result<DateTime<Tz>, Error> {
+    // A timezone given with the time, e.g. "at 5pm Europe/Paris", overrides
+    // the user's
+    let now = match schedule.timezone {
+        Some(tz) => now.with_timezone(&tz),
+        None => now,
+    };
+
+    let mut date = match schedule.date {
+        Some(DatePart::DaysAhead(0)) if schedule.time.is_none() => {
+            bail!("couldn't parse duration")
+        }
+        Some(ref part) => resolve_date(part, now, settings)?,
+        None => now,
+    };
+
+    if let Some(time) = schedule.time {
+        let time = match time {
+            TimePart::TwelveHour(time) if schedule.date.is_none() => next_twelve_hour(now, time),
+            time => resolve_time(time, settings),
+        };
+        date = set_time(date, time)?;
+    }
+
+    if date < now {
+        if schedule.date == Some(DatePart::DaysAhead(0)) {
+            return Err(PastTime {
+                input: input.trim().to_string(),
+                tomorrow: add_days(date, 1).ok_or_else(|| err_msg("date out of range"))?,
+            }
+            .into());
+        }
+
+        match schedule.date {
+            // A day of the year that's today but has already passed, e.g.
+            // "on 4 December" on the 4th after the morning, comes around
+            // again next year.
+            Some(DatePart::Date { year: None, .. })
+            | Some(DatePart::Numeric { year: None, .. }) => {
+                let next =
+                    next_date_at_morning(date + Duration::days(1), date.month(), date.day())?;
+                date = set_time(next, date.time())?;
+            }
+            // Uh oh, we've gone backwards. This is probably because we just
+            // said "at 10:00" when we meant at 10:00 tomorrow, so lets just
+            // add a day.
+            _ => date = add_days(date, 1).ok_or_else(|| err_msg("date out of range"))?,
+        }
+    }
+
+    if date == now {
+        bail!("couldn't parse duration");
+    }
+
+    Ok(date)
+}
+
+/// Works out when a repeating schedule should first fire.
+fn resolve_first_occurrence(
+    recurrence: &Recurrence,
+    schedule: &ParsedSchedule,
+    now: DateTime<Tz>,
+    settings: &ParseSettings,
+) -> Result<DateTime<Tz>, Error> {
+    let now = match schedule.timezone {
+        Some(tz) => now.with_timezone(&tz),
+        None => now,
+    };
+
+    if schedule.date.is_some() {
+        bail!("a repeating schedule can't start on a date");
+    }
+
+    let first = match *recurrence {
+        Recurrence::Interval(dur) => {
+            if dur < Duration::minutes(1) {
+                bail!("recurrence interval too short");
+            }
+
+            now.checked_add_signed(dur)
+                .ok_or_else(|| err_msg("recurrence interval too large"))?
+        }
+        Recurrence::Days(_) | Recurrence::Weekdays(_) => {
+            let mut date = set_to_morning(now);
+            if let Some(time) = schedule.time {
+                date = set_time(date, resolve_time(time, settings))?;
+            }
+            if date < now {
+                date = add_days(date, 1).ok_or_else(|| err_msg("date out of range"))?;
+            }
+
+            if let Recurrence::Weekdays(ref weekdays) = *recurrence {
+                while !weekdays.contains(&date.weekday()) {
+                    date = add_days(date, 1).ok_or_else(|| err_msg("date out of range"))?;
+                }
+            }
+
+            date
+        }
+        Recurrence::Cron(ref cron) => cron
+            .next_after(now)
+            .ok_or_else(|| err_msg("cron schedule never fires"))?,
+        Recurrence::RRule(ref rule) => rule
+            .next_after(now)
+            .ok_or_else(|| err_msg("rule never fires"))?,
+    };
+
+    Ok(first)
+}
+
+/// Works out the latest a repeating schedule can fire, with a number of
+/// times counted from the first occurrence.
+fn resolve_repeat_limit(
+    input: &str,
+    limit: &RepeatLimit,
+    recurrence: &Recurrence,
+    schedule: &ParsedSchedule,
+    first: DateTime<Tz>,
+    now: DateTime<Tz>,
+    settings: &ParseSettings,
+) -> Result<DateTime<Tz>, Error> {
+    let now = match schedule.timezone {
+        Some(tz) => now.with_timezone(&tz),
+        None => now,
+    };
+
+    let until = match *limit {
+        RepeatLimit::For { months, seconds } => add_relative(now, months, seconds)?,
+        RepeatLimit::Until { ref date, time } => {
+            // "until friday" includes friday
+            let time = time.or_else(|| {
+                date.as_ref()
+                    .map(|_| TimePart::Clock(NaiveTime::from_hms(23, 59, 59)))
+            });
+
+            let until = ParsedSchedule {
+                date: date.clone(),
+                time,
+                timezone: schedule.timezone,
+                ..ParsedSchedule::default()
+            };
+            resolve_datetime(input, &until, now, settings)?
+        }
+        RepeatLimit::Times(times) => {
+            if times == 0 || times > MAX_REPEAT_TIMES {
+                bail!("can only repeat between 1 and {} times", MAX_REPEAT_TIMES);
+            }
+
+            let mut last = first;
+            for _ in 1..times {
+                last = recurrence
+                    .next_occurrence(last)
+                    .ok_or_else(|| err_msg("schedule doesn't fire that many times"))?;
+            }
+            last
+        }
+    };
+
+    if until < first {
+        bail!("schedule ends before it first fires");
+    }
+
+    Ok(until)
+}
+
+fn resolve_date(
+    part: &DatePart,
+    now: DateTime<Tz>,
+    settings: &ParseSettings,
+) -> Result<DateTime<Tz>, Error> {
+    let date = match *part {
+        DatePart::Relative {
+            months,
+            seconds,
+            has_time_units,
+        } => {
+            let date = add_relative(now, months, seconds)?;
+
+            // Unless a time was given, e.g. "in 2 days 3 hours", reminders
+            // more than a couple of days away go off in the morning.
+            if !has_time_units && now + Duration::hours(48) < date {
+                set_to_morning(date)
+            } else {
+                date
+            }
+        }
+        DatePart::DaysAhead(days) => set_to_morning(now + Duration::days(days)),
+        DatePart::BusinessDays(days) => {
+            let today = now.naive_local().date();
+            let days = business_days_ahead(today, days, &settings.holidays)?;
+            set_to_morning(now + Duration::days(days))
+        }
+        DatePart::NextWeek => {
+            let days = 7 - now.weekday().number_from_monday() + 1;
+            set_to_morning(now + Duration::days(i64::from(days)))
+        }
+        DatePart::Weekday { weekday, next } => {
+            let today = i64::from(now.weekday().num_days_from_monday());
+            let target = i64::from(weekday.num_days_from_monday());
+
+            let days = if next {
+                // "next friday" is the friday of next week, even if this
+                // week's is still to come
+                7 - today + target
+            } else if target > today {
+                target - today
+            } else {
+                // Today's weekday means the one next week, as does one that
+                // has already passed this week
+                7 - today + target
+            };
+
+            set_to_morning(now + Duration::days(days))
+        }
+        DatePart::Date {
+            year: Some(year),
+            month,
+            day,
+        } => date_at_morning(now, year, month, day)?,
+        DatePart::Date {
+            year: None,
+            month,
+            day,
+        } => next_date_at_morning(now, month, day)?,
+        DatePart::Numeric {
+            first,
+            second,
+            year,
+            ref text,
+        } => resolve_numeric_date(now, first, second, year, text, settings)?,
+        DatePart::DayOfMonth { day, months_ahead } => resolve_day_of_month(now, day, months_ahead)?,
+        DatePart::EndOf(period) => resolve_end_of(now, period, settings)?,
+    };
+
+    Ok(date)
+}
+
+fn resolve_time(time: TimePart, settings: &ParseSettings) -> NaiveTime {
+    match time {
+        TimePart::Clock(time) => time,
+        // On a given day "half past five" is more likely the afternoon than
+        // the early morning
+        TimePart::TwelveHour(time) if time.hour() < 7 => time + Duration::hours(12),
+        TimePart::TwelveHour(time) => time,
+        TimePart::Morning => settings.morning,
+        TimePart::Afternoon => settings.afternoon,
+        TimePart::Evening => settings.evening,
+    }
+}
+
+/// Whichever of the morning or afternoon time, e.g. for "half past five",
+/// comes next.
+fn next_twelve_hour(now: DateTime<Tz>, morning: NaiveTime) -> NaiveTime {
+    let afternoon = morning + Duration::hours(12);
+
+    if morning > now.time() {
+        morning
+    } else if afternoon > now.time() {
+        afternoon
+    } else {
+        // Tomorrow morning, as the time has passed today
+        morning
+    }
+}
+
+/// A numeric date such as 04/12/2024, read according to the user's date
+/// order when it could be either way round.
+fn resolve_numeric_date(
+    now: DateTime<Tz>,
+    first: u32,
+    second: u32,
+    year: Option<i32>,
+    text: &str,
+    settings: &ParseSettings,
+) -> Result<DateTime<Tz>, Error> {
+    // Only one way round is a valid month, so there's no need to ask
+    let order = if first > 12 {
+        DateOrder::DayFirst
+    } else if second > 12 {
+        DateOrder::MonthFirst
+    } else if first == second {
+        DateOrder::DayFirst
+    } else if let Some(order) = settings.date_order {
+        order
+    } else {
+        // Both numbers are at most 12, so either way round is a real date
+        let describe = |day, month| {
+            let date = NaiveDate::from_ymd(year.unwrap_or(2000), month, day);
+            match year {
+                Some(_) => date.format("%-d %B %Y").to_string(),
+                None => date.format("%-d %B").to_string(),
+            }
+        };
+
+        return Err(AmbiguousDate {
+            input: text.to_string(),
+            day_first: describe(first, second),
+            month_first: describe(second, first),
+        }
+        .into());
+    };
+
+    let (day, month) = match order {
+        DateOrder::DayFirst => (first, second),
+        DateOrder::MonthFirst => (second, first),
+    };
+
+    match year {
+        Some(year) => date_at_morning(now, year, month, day),
+        None => next_date_at_morning(now, month, day),
+    }
+}
+
+/// e.g. "the 15th" or "the 1st of next month". Without a month it's the next
+/// time that day of the month comes around.
+fn resolve_day_of_month(
+    now: DateTime<Tz>,
+    day: u32,
+    months_ahead: Option<i32>,
+) -> Result<DateTime<Tz>, Error> {
+    let this_month = now.year() * 12 + now.month0() as i32;
+    let year_month = |total: i32| (total / 12, (total % 12) as u32 + 1);
+
+    if let Some(months_ahead) = months_ahead {
+        let (year, month) = year_month(this_month + months_ahead);
+        return date_at_morning(now, year, month, day);
+    }
+
+    // Skip months that are too short, e.g. for "the 31st"
+    for offset in 0..12 {
+        let (year, month) = year_month(this_month + offset);
+        if day > days_in_month(year, month)? {
+            continue;
+        }
+
+        let date = date_at_morning(now, year, month, day)?;
+        if date > now {
+            return Ok(date);
+        }
+    }
+
+    bail!("invalid day {}", day)
+}
+
+/// e.g. "by end of day", "end of the week" or "eom". If that time has
+/// already passed it's the end of the next day, week or month.
+fn resolve_end_of(
+    now: DateTime<Tz>,
+    period: Period,
+    settings: &ParseSettings,
+) -> Result<DateTime<Tz>, Error> {
+    let today = now.date().naive_local();
+
+    let end = match period {
+        Period::Day => today,
+        Period::Week => {
+            // The working week ends on Friday
+            let days = 4 - i64::from(now.weekday().num_days_from_monday());
+            today + Duration::days(if days < 0 { days + 7 } else { days })
+        }
+        Period::Month => NaiveDate::from_ymd_opt(
+            today.year(),
+            today.month(),
+            days_in_month(today.year(), today.month())?,
+        )
+        .ok_or_else(|| err_msg("date out of range"))?,
+    };
+
+    let date = localize(now.timezone(), end.and_time(settings.end_of_day))?;
+    if date > now {
+        return Ok(date);
+    }
+
+    let next = match period {
+        Period::Day => end + Duration::days(1),
+        Period::Week => end + Duration::weeks(1),
+        Period::Month => {
+            let (year, month) = if end.month() == 12 {
+                (end.year() + 1, 1)
+            } else {
+                (end.year(), end.month() + 1)
+            };
+            NaiveDate::from_ymd_opt(year, month, days_in_month(year, month)?)
+                .ok_or_else(|| err_msg("date out of range"))?
+        }
+    };
+
+    localize(now.timezone(), next.and_time(settings.end_of_day))
+}
+
+/// Sets the time of day, keeping the date. A time the clocks skip or repeat
+/// that day is moved as by `localize`.
+fn set_time(date: DateTime<Tz>, time: NaiveTime) -> Result<DateTime<Tz>, Error> {
+    let time = NaiveTime::from_hms(time.hour(), time.minute(), 0);
+    localize(date.timezone(), date.naive_local().date().and_time(time))
+}
+
+/// Parses a time of day such as "08:30" or "8".
+pub fn parse_time_of_day(input: &str) -> Result<NaiveTime, Error> {
+    let input = input.trim();
+
+    NaiveTime::parse_from_str(input, "%H:%M")
+        .or_else(|_| NaiveTime::parse_from_str(&format!("{}:00", input), "%H:%M"))
+        .map_err(|_| format_err!("invalid time {}", input))
+}
+
+/// Describes when something is due relative to now, along with the local
+/// time, e.g. "in 3 hours, at 18:00 Tue".
+pub fn format_relative(due: DateTime<Tz>, now: DateTime<Tz>) -> String {
+    let diff = due.signed_duration_since(now);
+
+    let relative = if diff < Duration::minutes(1) {
+        "in less than a minute".to_string()
+    } else if diff < Duration::hours(1) {
+        format!("in {}", plural(diff.num_minutes(), "minute"))
+    } else if diff < Duration::days(1) {
+        let minutes = diff.num_minutes() % 60;
+        if minutes == 0 {
+            format!("in {}", plural(diff.num_hours(), "hour"))
+        } else {
+            format!(
+                "in {} {}",
+                plural(diff.num_hours(), "hour"),
+                plural(minutes, "minute")
+            )
+        }
+    } else if diff < Duration::weeks(2) {
+        format!("in {}", plural(diff.num_days(), "day"))
+    } else {
+        format!("in {}", plural(diff.num_weeks(), "week"))
+    };
+
+    let absolute = if diff < Duration::weeks(1) {
+        due.format("%H:%M %a")
+    } else if due.year() == now.year() {
+        due.format("%H:%M %a %-d %b")
+    } else {
+        due.format("%H:%M %a %-d %b %Y")
+    };
+
+    format!("{}, at {}", relative, absolute)
+}
+
+/// Describes how a reminder due at the given time repeats, e.g. "every day
+/// 3 times, until 09:00 Thu 10 Jul 2014". Series that stop say how many
+/// times are left, so that "every day 3 times" reads back as it was set.
+pub fn format_repeat(
+    recurrence: &Recurrence,
+    due: DateTime<Tz>,
+    until: Option<DateTime<Tz>>,
+) -> String {
+    let until = match until {
+        Some(until) => until,
+        None => return recurrence.to_string(),
+    };
+
+    let mut times = 0;
+    let mut next = Some(due);
+    while let Some(date) = next {
+        if date > until || times > MAX_REPEAT_TIMES {
+            break;
+        }
+        times += 1;
+        next = recurrence.next_occurrence(date);
+    }
+
+    let until = until.format("%H:%M %a %-d %b %Y");
+
+    if times <= MAX_REPEAT_TIMES {
+        format!(
+            "{} {}, until {}",
+            recurrence,
+            plural(times.into(), "time"),
+            until
+        )
+    } else {
+        format!("{}, until {}", recurrence, until)
+    }
+}
+
+fn plural(count: i64, unit: &str) -> String {
+    if count == 1 {
+        format!("1 {}", unit)
+    } else {
+        format!("{} {}s", count, unit)
+    }
+}
+
+/// The same day at the time reminders go off in the morning. That time can
+/// be skipped or repeated when the clocks change, in which case it's the
+/// earliest time it exists, or the hour after if it doesn't.
+fn set_to_morning(n: DateTime<Tz>) -> DateTime<Tz> {
+    let naive = n.date().naive_local().and_hms(9, 30, 0);
+    localize(n.timezone(), naive).unwrap_or(n)
+}
+
+/// The given date in the same timezone as `now`, at the time reminders go
+/// off in the morning.
+fn date_at_morning(
+    now: DateTime<Tz>,
+    year: i32,
+    month: u32,
+    day: u32,
+) -> Result<DateTime<Tz>, Error> {
+    let naive = NaiveDate::from_ymd_opt(year, month, day)
+        .ok_or_else(|| format_err!("invalid date {}-{:02}-{:02}", year, month, day))?
+        .and_hms(9, 30, 0);
+
+    localize(now.timezone(), naive)
+}
+
+/// The next time the given day of the year comes around, including today,
+/// at the time reminders go off in the morning.
+fn next_date_at_morning(now: DateTime<Tz>, month: u32, day: u32) -> Result<DateTime<Tz>, Error> {
+    // The 29th of February may not be this year
+    for year in now.year()..now.year() + 8 {
+        if NaiveDate::from_ymd_opt(year, month, day).is_none() {
+            continue;
+        }
+
+        let date = date_at_morning(now, year, month, day)?;
+        if date.date() >= now.date() {
+            return Ok(date);
+        }
+    }
+
+    bail!("invalid date {:02}-{:02}", month, day)
+}
+
+/// Converts a local time to the timezone. If the time doesn't exist that
+/// day, due to the clocks going forward, uses the hour after.
+fn localize(tz: Tz, naive: NaiveDateTime) -> Result<DateTime<Tz>, Error> {
+    tz.from_local_datetime(&naive)
+        .earliest()
+        .or_else(|| {
+            tz.from_local_datetime(&(naive + Duration::hours(1)))
+                .earliest()
+        })
+        .ok_or_else(|| err_msg("invalid local time"))
+}
+
+/// Adds calendar days to the date, keeping the local time of day.
+fn add_days(date: DateTime<Tz>, days: i64) -> Option<DateTime<Tz>> {
+    let naive = date
+        .naive_local()
+        .checked_add_signed(Duration::days(days))?;
+    localize(date.timezone(), naive).ok()
+}
+
+fn weekday_name(weekday: Weekday) -> &'static str {
+    match weekday {
+        Weekday::Mon => "mon",
+        Weekday::Tue => "tue",
+        Weekday::Wed => "wed",
+        Weekday::Thu => "thu",
+        Weekday::Fri => "fri",
+        Weekday::Sat => "sat",
+        Weekday::Sun => "sun",
+    }
+}
+
+fn weekday_full_name(weekday: Weekday) -> &'static str {
+    match weekday {
+        Weekday::Mon => "mon",
+        Weekday::Tue => "tues",
+        Weekday::Wed => "wednes",
+        Weekday::Thu => "thurs",
+        Weekday::Fri => "fri",
+        Weekday::Sat => "satur",
+        Weekday::Sun => "sun",
+    }
+}
+
+fn is_working_day(weekday: Weekday) -> bool {
+    match weekday {
+        Weekday::Sat | Weekday::Sun => false,
+        _ => true,
+    }
+}
+
+/// How many calendar days ahead of the given date it is the given number of
+/// business days ahead, skipping weekends and holidays.
+fn business_days_ahead(from: NaiveDate, days: i64, holidays: &[NaiveDate]) -> Result<i64, Error> {
+    if days < 1 {
+        bail!("expected at least one business day");
+    }
+    if days > 10_000 {
+        bail!("duration too large");
+    }
+
+    let mut date = from;
+    let mut remaining = days;
+    while remaining > 0 {
+        date = date.succ();
+        if is_working_day(date.weekday()) && !holidays.contains(&date) {
+            remaining -= 1;
+        }
+    }
+
+    Ok((date - from).num_days())
+}
+
+fn get_duration_from_string(s: &str) -> Duration {
+    match s {
+        "s" | "sec" | "secs" | "second" | "seconds" => Duration::seconds(1),
+        "m" | "min" | "mins" | "minute" | "minutes" => Duration::minutes(1),
+        "h" | "hr" | "hrs" | "hour" | "hours" => Duration::hours(1),
+        "d" | "day" | "days" => Duration::days(1),
+        "w" | "week" | "weeks" => Duration::weeks(1),
+        "fortnight" | "fortnights" => Duration::weeks(2),
+        _ => panic!("unrecognized type"),
+    }
+}
+
+/// Adds calendar months to the date, keeping the time of day. If the day of
+/// the month doesn't exist in the new month it's moved back to the last day,
+/// e.g. a month after 31st January is 28th (or 29th) February.
+fn add_months(date: DateTime<Tz>, months: i32) -> Result<DateTime<Tz>, Error> {
+    let total = date.year() * 12 + date.month0() as i32 + months;
+    let (year, month) = (total / 12, (total % 12) as u32 + 1);
+
+    let day = cmp::min(date.day(), days_in_month(year, month)?);
+
+    let naive = NaiveDate::from_ymd_opt(year, month, day)
+        .ok_or_else(|| err_msg("date out of range"))?
+        .and_time(date.time());
+
+    localize(date.timezone(), naive)
+}
+
+/// Like `add_months`, but the months can be fractional, e.g. "half a
+/// month". The fraction is of the length of the month it falls in.
+/// Adds an amount of time such as "1 month and 2 days" to the date, with the
+/// months counted on the calendar.
+fn add_relative(date: DateTime<Tz>, months: f64, seconds: f64) -> Result<DateTime<Tz>, Error> {
+    add_fractional_months(date, months)?
+        .checked_add_signed(Duration::seconds(seconds as i64))
+        .ok_or_else(|| err_msg("duration too large"))
+}
+
+fn add_fractional_months(date: DateTime<Tz>, months: f64) -> Result<DateTime<Tz>, Error> {
+    let whole = months.trunc();
+    let date = add_months(date, whole as i32)?;
+
+    let fraction = months - whole;
+    if fraction > 0.0 {
+        let month_secs = i64::from(days_in_month(date.year(), date.month())?) * 24 * 60 * 60;
+        return Ok(date + Duration::seconds((month_secs as f64 * fraction) as i64));
+    }
+
+    Ok(date)
+}
+
+fn days_in_month(year: i32, month: u32) -> Result<u32, Error> {
+    let (next_year, next_month) = if month == 12 {
+        (year + 1, 1)
+    } else {
+        (year, month + 1)
+    };
+
+    let first_of_next = NaiveDate::from_ymd_opt(next_year, next_month, 1)
+        .ok_or_else(|| err_msg("date out of range"))?;
+
+    Ok(first_of_next.pred().day())
+}
+
+#[test]
+fn date_parse_test() {
+    use chrono::{TimeZone, Utc};
+    use chrono_tz::UTC;
+
+    let dt = UTC.ymd(2014, 7, 8).and_hms(9, 10, 11);
+    let settings = ParseSettings::default();
+
+    assert_eq!(
+        parse_human_datetime("at 1800", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 8).and_hms(18, 00, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("tomorrow", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 9).and_hms(9, 30, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("tomorrow at 1800", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 9).and_hms(18, 00, 0)
+    );
+
+    assert!(parse_human_datetime("tomorrow at 9900", dt, &settings).is_err());
+
+    assert_eq!(
+        parse_human_datetime("in 1 week", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 15).and_hms(9, 30, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("in 1 week at 10:00", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 15).and_hms(10, 00, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("in 2 months", dt, &settings).unwrap(),
+        Utc.ymd(2014, 9, 8).and_hms(9, 30, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("in 1 year at 10:00", dt, &settings).unwrap(),
+        Utc.ymd(2015, 7, 8).and_hms(10, 00, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("in 1h30m", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 8).and_hms(10, 40, 11)
+    );
+
+    assert_eq!(
+        parse_human_datetime("in 2 hours and 15 minutes", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 8).and_hms(11, 25, 11)
+    );
+
+    assert_eq!(
+        parse_human_datetime("in an hour and a half", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 8).and_hms(10, 40, 11)
+    );
+
+    assert_eq!(
+        parse_human_datetime("in 3 days, 2 hrs", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 11).and_hms(11, 10, 11)
+    );
+
+    assert_eq!(
+        parse_human_datetime("in 1 week 2 days", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 17).and_hms(9, 30, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("in a few hours", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 8).and_hms(12, 10, 11)
+    );
+
+    assert_eq!(
+        parse_human_datetime("in an hour", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 8).and_hms(10, 10, 11)
+    );
+
+    assert_eq!(
+        parse_human_datetime("in a minute", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 8).and_hms(9, 11, 11)
+    );
+
+    assert_eq!(
+        parse_human_datetime("in a day", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 9).and_hms(9, 10, 11)
+    );
+
+    assert_eq!(
+        parse_human_datetime("in a fortnight", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 22).and_hms(9, 30, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("in 2 fortnights", dt, &settings).unwrap(),
+        Utc.ymd(2014, 8, 5).and_hms(9, 30, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("in half an hour", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 8).and_hms(9, 40, 11)
+    );
+
+    assert_eq!(
+        parse_human_datetime("wed", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 9).and_hms(9, 30, 00)
+    );
+
+    assert_eq!(
+        parse_human_datetime("thurs", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 10).and_hms(9, 30, 00)
+    );
+
+    assert_eq!(
+        parse_human_datetime("on monday", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 14).and_hms(9, 30, 00)
+    );
+
+    // dt is a Tuesday
+    assert_eq!(
+        parse_human_datetime("on tuesday", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 15).and_hms(9, 30, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("friday", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 11).and_hms(9, 30, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("next friday", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 18).and_hms(9, 30, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("next monday at 1800", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 14).and_hms(18, 0, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("by end of day", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 8).and_hms(17, 0, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("end of the week", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 11).and_hms(17, 0, 0)
+    );
+
+    let limited = ParseSettings {
+        max_horizon: Some(Duration::days(5 * 365)),
+        ..ParseSettings::default()
+    };
+    assert_eq!(
+        parse_human_datetime("in 4 years", dt, &limited).unwrap(),
+        Utc.ymd(2018, 7, 8).and_hms(9, 30, 0)
+    );
+    let err = parse_human_datetime("in 9999999 weeks", dt, &limited).unwrap_err();
+    let err = err.downcast_ref::<TooFarAway>().unwrap();
+    assert_eq!(
+        err.to_string(),
+        "in 9999999 weeks is too far away, reminders can be set at most 5 years ahead"
+    );
+
+    let french = ParseSettings {
+        locale: Some(Locale::French),
+        ..ParseSettings::default()
+    };
+    assert_eq!(
+        parse_human_datetime("demain à 18h", dt, &french).unwrap(),
+        Utc.ymd(2014, 7, 9).and_hms(18, 0, 0)
+    );
+    assert_eq!(
+        parse_human_datetime("dans 2 heures", dt, &french).unwrap(),
+        Utc.ymd(2014, 7, 8).and_hms(11, 10, 11)
+    );
+
+    let german = ParseSettings {
+        locale: Some(Locale::German),
+        ..ParseSettings::default()
+    };
+    assert_eq!(
+        parse_human_datetime("morgen um 9 Uhr", dt, &german).unwrap(),
+        Utc.ymd(2014, 7, 9).and_hms(9, 0, 0)
+    );
+    assert!(parse_human_datetime("morgen um 9", dt, &settings).is_err());
+
+    assert_eq!(
+        parse_human_datetime("tmrw at 5pm", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 9).and_hms(17, 0, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("tomorow", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 9).and_hms(9, 30, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("in 5 mins", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 8).and_hms(9, 15, 11)
+    );
+
+    assert_eq!(
+        parse_human_datetime("in 2 wks", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 22).and_hms(9, 30, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("at half past five", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 8).and_hms(17, 30, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("at quarter to 10", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 8).and_hms(9, 45, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("quarter to 9", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 8).and_hms(20, 45, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("tomorrow at twenty past 3", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 9).and_hms(15, 20, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("at half past 7am", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 9).and_hms(7, 30, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("on 2024-06-01T14:30", dt, &settings).unwrap(),
+        Utc.ymd(2024, 6, 1).and_hms(14, 30, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("2024-06-01 14:30", dt, &settings).unwrap(),
+        Utc.ymd(2024, 6, 1).and_hms(14, 30, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("next business day", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 9).and_hms(9, 30, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("in 3 working days at 5pm", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 11).and_hms(17, 0, 0)
+    );
+
+    let holidays = ParseSettings {
+        holidays: vec![NaiveDate::from_ymd(2014, 7, 10)],
+        ..ParseSettings::default()
+    };
+    assert_eq!(
+        parse_human_datetime("in 3 working days", dt, &holidays).unwrap(),
+        Utc.ymd(2014, 7, 14).and_hms(9, 30, 0)
+    );
+
+    assert_eq!(
+        parse_calendar_date("25 december 2014", dt, &settings).unwrap(),
+        NaiveDate::from_ymd(2014, 12, 25)
+    );
+    assert!(parse_calendar_date("in 2 hours", dt, &settings).is_err());
+
+    assert_eq!(
+        parse_human_datetime("on christmas", dt, &settings).unwrap(),
+        Utc.ymd(2014, 12, 25).and_hms(9, 30, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("new year's day at 10am", dt, &settings).unwrap(),
+        Utc.ymd(2015, 1, 1).and_hms(10, 0, 0)
+    );
+
+    let named = ParseSettings {
+        named_dates: vec![NamedDate {
+            name: "Mum's birthday".to_string(),
+            month: 3,
+            day: 14,
+        }],
+        ..ParseSettings::default()
+    };
+    assert_eq!(
+        parse_human_datetime("on mums birthday", dt, &named).unwrap(),
+        Utc.ymd(2015, 3, 14).and_hms(9, 30, 0)
+    );
+
+    assert_eq!(parse_day_of_year("14 march").unwrap(), (3, 14));
+    assert!(parse_day_of_year("14 march 2015").is_err());
+    assert!(parse_day_of_year("30 feb").is_err());
+
+    assert_eq!(
+        parse_human_datetime("eom", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 31).and_hms(17, 0, 0)
+    );
+
+    // The end of today has passed, so it's the end of tomorrow
+    assert_eq!(
+        parse_human_datetime(
+            "end of the day",
+            UTC.ymd(2014, 7, 8).and_hms(18, 0, 0),
+            &settings
+        )
+        .unwrap(),
+        Utc.ymd(2014, 7, 9).and_hms(17, 0, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime(
+            "end of the month",
+            UTC.ymd(2014, 12, 31).and_hms(18, 0, 0),
+            &settings
+        )
+        .unwrap(),
+        Utc.ymd(2015, 1, 31).and_hms(17, 0, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("on the 15th", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 15).and_hms(9, 30, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("on the 2nd", dt, &settings).unwrap(),
+        Utc.ymd(2014, 8, 2).and_hms(9, 30, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("on the 1st of next month at 1800", dt, &settings).unwrap(),
+        Utc.ymd(2014, 8, 1).and_hms(18, 0, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime(
+            "on the 31st",
+            UTC.ymd(2014, 8, 31).and_hms(12, 0, 0),
+            &settings
+        )
+        .unwrap(),
+        Utc.ymd(2014, 10, 31).and_hms(9, 30, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("on 2017-12-04", dt, &settings).unwrap(),
+        Utc.ymd(2017, 12, 04).and_hms(9, 30, 00)
+    );
+
+    assert_eq!(
+        parse_human_datetime("on 4 December", dt, &settings).unwrap(),
+        Utc.ymd(2014, 12, 4).and_hms(9, 30, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("on Dec 4th at 1800", dt, &settings).unwrap(),
+        Utc.ymd(2014, 12, 4).and_hms(18, 0, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("on the 1st of march", dt, &settings).unwrap(),
+        Utc.ymd(2015, 3, 1).and_hms(9, 30, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("on July 8", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 8).and_hms(9, 30, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("on 29 Feb 2016", dt, &settings).unwrap(),
+        Utc.ymd(2016, 2, 29).and_hms(9, 30, 0)
+    );
+
+    assert!(parse_human_datetime("on 30 feb", dt, &settings).is_err());
+
+    assert_eq!(
+        parse_human_datetime("on 25/12/2024", dt, &settings).unwrap(),
+        Utc.ymd(2024, 12, 25).and_hms(9, 30, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("on 12/25", dt, &settings).unwrap(),
+        Utc.ymd(2014, 12, 25).and_hms(9, 30, 0)
+    );
+
+    let err = parse_human_datetime("on 04/12/2024", dt, &settings).unwrap_err();
+    let err = err.downcast_ref::<AmbiguousDate>().unwrap();
+    assert_eq!(err.day_first, "4 December 2024");
+    assert_eq!(err.month_first, "12 April 2024");
+
+    assert!(parse_human_datetime("on 02/30", dt, &settings).is_err());
+
+    assert_eq!(
+        parse_human_datetime("today at 5pm", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 8).and_hms(17, 0, 0)
+    );
+
+    let err = parse_human_datetime("today at 9am", dt, &settings).unwrap_err();
+    let err = err.downcast_ref::<PastTime>().unwrap();
+    assert_eq!(err.input, "today at 9am");
+    assert_eq!(err.tomorrow, Utc.ymd(2014, 7, 9).and_hms(9, 0, 0));
+
+    let day_first = ParseSettings {
+        date_order: Some(DateOrder::DayFirst),
+        ..ParseSettings::default()
+    };
+    assert_eq!(
+        parse_human_datetime("on 04/12/24", dt, &day_first).unwrap(),
+        Utc.ymd(2024, 12, 4).and_hms(9, 30, 0)
+    );
+
+    let month_first = ParseSettings {
+        date_order: Some(DateOrder::MonthFirst),
+        ..ParseSettings::default()
+    };
+    assert_eq!(
+        parse_human_datetime("on 04/12/2024 at 1800", dt, &month_first).unwrap(),
+        Utc.ymd(2024, 4, 12).and_hms(18, 0, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("at 5:30pm", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 8).and_hms(17, 30, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("tomorrow at 8am", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 9).and_hms(8, 0, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("at 12pm", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 8).and_hms(12, 0, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("at 12:15am", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 9).and_hms(0, 15, 0)
+    );
+
+    assert!(parse_human_datetime("at 13pm", dt, &settings).is_err());
+
+    assert_eq!(
+        parse_human_datetime("at 5pm Europe/Paris", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 8).and_hms(15, 0, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("tomorrow at 9am est", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 9).and_hms(13, 0, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("at 18:00 america/new_york", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 8).and_hms(22, 0, 0)
+    );
+
+    assert!(parse_human_datetime("at 5pm Nowhere/Special", dt, &settings).is_err());
+
+    assert_eq!(
+        parse_human_datetime("at noon", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 8).and_hms(12, 0, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("at midnight", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 9).and_hms(0, 0, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("tomorrow evening", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 9).and_hms(19, 0, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("tonight", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 8).and_hms(19, 0, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("this afternoon", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 8).and_hms(14, 0, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("wednesday", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 9).and_hms(9, 30, 0)
+    );
+
+    let early = ParseSettings {
+        morning: NaiveTime::from_hms(7, 0, 0),
+        ..ParseSettings::default()
+    };
+    assert_eq!(
+        parse_human_datetime("friday morning", dt, &early).unwrap(),
+        Utc.ymd(2014, 7, 11).and_hms(7, 0, 0)
+    );
+}
+
+#[test]
+fn split_schedule_test() {
+    let settings = ParseSettings::default();
+
+    assert_eq!(
+        split_schedule("in 2h to go to the shops", &settings),
+        Some(("in 2h", "go to the shops"))
+    );
+    assert_eq!(
+        split_schedule("tomorrow at 5pm to call mum", &settings),
+        Some(("tomorrow at 5pm", "call mum"))
+    );
+    assert_eq!(
+        split_schedule("every monday at 9am to file timesheet", &settings),
+        Some(("every monday at 9am", "file timesheet"))
+    );
+
+    // Left for parsing the time to complain about
+    assert_eq!(
+        split_schedule("at 13pm to go to bed", &settings),
+        Some(("at 13pm", "go to bed"))
+    );
+    assert_eq!(
+        split_schedule("whenever to go to bed", &settings),
+        Some(("whenever", "go to bed"))
+    );
+
+    assert_eq!(split_schedule("tomorrow", &settings), None);
+
+    assert_eq!(
+        split_schedule("to call mum tomorrow at 6pm", &settings),
+        Some(("tomorrow at 6pm", "call mum"))
+    );
+    assert_eq!(
+        split_schedule("to go to the shops in 2h", &settings),
+        Some(("in 2h", "go to the shops"))
+    );
+    assert_eq!(
+        split_schedule("to take my pills every day at 9am", &settings),
+        Some(("every day at 9am", "take my pills"))
+    );
+    assert_eq!(
+        split_schedule("to email the EST team on friday", &settings),
+        Some(("on friday", "email the EST team"))
+    );
+    assert_eq!(split_schedule("to go to the shops", &settings), None);
+    assert_eq!(split_schedule("to call bob EST", &settings), None);
+    assert_eq!(
+        split_schedule("to call bob at 9am EST", &settings),
+        Some(("at 9am EST", "call bob"))
+    );
+
+    assert_eq!(
+        split_schedule("at quarter to 9 to call mum", &settings),
+        Some(("at quarter to 9", "call mum"))
+    );
+    assert_eq!(
+        split_schedule("to call mum at quarter to 9", &settings),
+        Some(("at quarter to 9", "call mum"))
+    );
+
+    let french = ParseSettings {
+        locale: Some(Locale::French),
+        ..ParseSettings::default()
+    };
+    assert_eq!(
+        split_schedule("demain à 18h to call mum", &french),
+        Some(("demain à 18h", "call mum"))
+    );
+}
+
+#[test]
+fn business_days_ahead_test() {
+    // A Friday
+    let friday = NaiveDate::from_ymd(2014, 7, 11);
+
+    assert_eq!(business_days_ahead(friday, 1, &[]).unwrap(), 3);
+    assert_eq!(business_days_ahead(friday, 5, &[]).unwrap(), 7);
+    assert_eq!(
+        business_days_ahead(friday, 1, &[NaiveDate::from_ymd(2014, 7, 14)]).unwrap(),
+        4
+    );
+    assert!(business_days_ahead(friday, 0, &[]).is_err());
+}
+
+#[test]
+fn add_months_test() {
+    use chrono::{TimeZone, Utc};
+    use chrono_tz::UTC;
+
+    let dt = UTC.ymd(2014, 1, 31).and_hms(9, 10, 11);
+    assert_eq!(
+        add_months(dt, 1).unwrap(),
+        Utc.ymd(2014, 2, 28).and_hms(9, 10, 11)
+    );
+    assert_eq!(
+        add_months(dt, 3).unwrap(),
+        Utc.ymd(2014, 4, 30).and_hms(9, 10, 11)
+    );
+    assert_eq!(
+        add_months(dt, 11).unwrap(),
+        Utc.ymd(2014, 12, 31).and_hms(9, 10, 11)
+    );
+    assert_eq!(
+        add_months(dt, 12).unwrap(),
+        Utc.ymd(2015, 1, 31).and_hms(9, 10, 11)
+    );
+    assert_eq!(
+        add_months(dt, 25).unwrap(),
+        Utc.ymd(2016, 2, 29).and_hms(9, 10, 11)
+    );
+
+    let leap_day = UTC.ymd(2016, 2, 29).and_hms(9, 10, 11);
+    assert_eq!(
+        add_months(leap_day, 12).unwrap(),
+        Utc.ymd(2017, 2, 28).and_hms(9, 10, 11)
+    );
+
+    // Half of August
+    let dt = UTC.ymd(2014, 7, 8).and_hms(9, 10, 11);
+    assert_eq!(
+        add_fractional_months(dt, 1.5).unwrap(),
+        Utc.ymd(2014, 8, 23).and_hms(21, 10, 11)
+    );
+}
+
+#[test]
+fn date_parse_same_day_test() {
+    use chrono::{TimeZone, Utc};
+    use chrono_tz::UTC;
+
+    // After the morning on 4 December
+    let dt = UTC.ymd(2014, 12, 4).and_hms(10, 0, 0);
+    let settings = ParseSettings {
+        date_order: Some(DateOrder::DayFirst),
+        ..ParseSettings::default()
+    };
+
+    assert_eq!(
+        parse_human_datetime("on 4 december", dt, &settings).unwrap(),
+        Utc.ymd(2015, 12, 4).and_hms(9, 30, 0)
+    );
+    assert_eq!(
+        parse_human_datetime("on 4 december at 9am", dt, &settings).unwrap(),
+        Utc.ymd(2015, 12, 4).and_hms(9, 0, 0)
+    );
+    assert_eq!(
+        parse_human_datetime("on 04/12", dt, &settings).unwrap(),
+        Utc.ymd(2015, 12, 4).and_hms(9, 30, 0)
+    );
+
+    // Later today is still today
+    assert_eq!(
+        parse_human_datetime("on 4 december at 5pm", dt, &settings).unwrap(),
+        Utc.ymd(2014, 12, 4).and_hms(17, 0, 0)
+    );
+
+    let named = ParseSettings {
+        named_dates: vec![NamedDate {
+            name: "Mum's birthday".to_string(),
+            month: 12,
+            day: 4,
+        }],
+        ..ParseSettings::default()
+    };
+    assert_eq!(
+        parse_human_datetime("on mums birthday", dt, &named).unwrap(),
+        Utc.ymd(2015, 12, 4).and_hms(9, 30, 0)
+    );
+}
+
+#[test]
+fn date_parse_timezone_test() {
+    use chrono::{TimeZone, Utc};
+    use chrono_tz::Europe::London;
+
+    // 10:10 BST
+    let dt = Utc.ymd(2014, 7, 8).and_hms(9, 10, 11).with_timezone(&London);
+    let settings = ParseSettings::default();
+
+    assert_eq!(
+        parse_human_datetime("at 1800", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 8).and_hms(17, 00, 0)
+    );
+
+    assert_eq!(
+        parse_human_datetime("tomorrow", dt, &settings).unwrap(),
+        Utc.ymd(2014, 7, 9).and_hms(8, 30, 0)
+    );
+
+    // The clocks go forward overnight, so 9am is 23 hours after 10pm
+    let dt = London.ymd(2014, 3, 29).and_hms(22, 0, 0);
+    assert_eq!(
+        parse_human_datetime("at 9am", dt, &settings).unwrap(),
+        Utc.ymd(2014, 3, 30).and_hms(8, 0, 0)
+    );
+
+    // 01:30 is skipped that night, so it's the hour after
+    assert_eq!(
+        set_time(dt + Duration::days(1), NaiveTime::from_hms(1, 30, 0)).unwrap(),
+        Utc.ymd(2014, 3, 30).and_hms(1, 30, 0)
+    );
+}
+
+#[test]
+fn recurrence_parse_test() {
+    use chrono::{TimeZone, Utc};
+    use chrono_tz::UTC;
+
+    // A Tuesday
+    let dt = UTC.ymd(2014, 7, 8).and_hms(9, 10, 11);
+    let settings = ParseSettings::default();
+
+    let (recurrence, first, _) = parse_recurrence("every monday at 10:00", dt, &settings)
+        .unwrap()
+        .unwrap();
+    assert_eq!(recurrence, Recurrence::Weekdays(vec![Weekday::Mon]));
+    assert_eq!(first, Utc.ymd(2014, 7, 14).and_hms(10, 0, 0));
+    assert_eq!(
+        recurrence.next_occurrence(first).unwrap(),
+        Utc.ymd(2014, 7, 21).and_hms(10, 0, 0)
+    );
+
+    let (recurrence, first, _) = parse_recurrence("every day at 0800", dt, &settings)
+        .unwrap()
+        .unwrap();
+    assert_eq!(recurrence, Recurrence::Days(1));
+    assert_eq!(first, Utc.ymd(2014, 7, 9).and_hms(8, 0, 0));
+
+    let (recurrence, first, _) = parse_recurrence("every weekday", dt, &settings)
+        .unwrap()
+        .unwrap();
+    assert_eq!(first, Utc.ymd(2014, 7, 8).and_hms(9, 30, 0));
+    assert_eq!(
+        recurrence
+            .next_occurrence(Utc.ymd(2014, 7, 11).and_hms(9, 30, 0).with_timezone(&UTC))
+            .unwrap(),
+        Utc.ymd(2014, 7, 14).and_hms(9, 30, 0)
+    );
+
+    let (recurrence, first, _) = parse_recurrence("every 2 hours", dt, &settings)
+        .unwrap()
+        .unwrap();
+    assert_eq!(recurrence, Recurrence::Interval(Duration::hours(2)));
+    assert_eq!(first, Utc.ymd(2014, 7, 8).and_hms(11, 10, 11));
+
+    assert_eq!(
+        Recurrence::from_spec(&recurrence.to_spec()).unwrap(),
+        recurrence
+    );
+
+    let (recurrence, first, _) = parse_recurrence("cron \"0 9 * * 1-5\"", dt, &settings)
+        .unwrap()
+        .unwrap();
+    assert_eq!(first, Utc.ymd(2014, 7, 9).and_hms(9, 0, 0));
+    assert_eq!(
+        Recurrence::from_spec(&recurrence.to_spec()).unwrap(),
+        recurrence
+    );
+
+    let (recurrence, first, _) =
+        parse_recurrence("rrule \"FREQ=WEEKLY;BYDAY=MO,WE\"", dt, &settings)
+            .unwrap()
+            .unwrap();
+    assert_eq!(first, Utc.ymd(2014, 7, 9).and_hms(9, 10, 11));
+    assert_eq!(
+        Recurrence::from_spec(&recurrence.to_spec()).unwrap(),
+        recurrence
+    );
+
+    assert!(parse_recurrence("tomorrow", dt, &settings)
+        .unwrap()
+        .is_none());
+    assert!(parse_recurrence("every 10 seconds", dt, &settings).is_err());
+
+    let (_, first, until) = parse_recurrence("every hour for the next 6 hours", dt, &settings)
+        .unwrap()
+        .unwrap();
+    assert_eq!(first, Utc.ymd(2014, 7, 8).and_hms(10, 10, 11));
+    assert_eq!(
+        until,
+        Some(Utc.ymd(2014, 7, 8).and_hms(15, 10, 11).with_timezone(&UTC))
+    );
+
+    let (_, _, until) = parse_recurrence("every day at 8am 5 times", dt, &settings)
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        until,
+        Some(Utc.ymd(2014, 7, 13).and_hms(8, 0, 0).with_timezone(&UTC))
+    );
+
+    let (_, _, until) = parse_recurrence("every weekday at 9am until friday 5pm", dt, &settings)
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        until,
+        Some(Utc.ymd(2014, 7, 11).and_hms(17, 0, 0).with_timezone(&UTC))
+    );
+
+    let (_, _, until) = parse_recurrence("every day at 6pm until thursday", dt, &settings)
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        until,
+        Some(Utc.ymd(2014, 7, 10).and_hms(23, 59, 59).with_timezone(&UTC))
+    );
+
+    let (_, _, until) = parse_recurrence("every day at 8am for 3 days", dt, &settings)
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        until,
+        Some(Utc.ymd(2014, 7, 11).and_hms(9, 10, 11).with_timezone(&UTC))
+    );
+
+    assert!(parse_recurrence("every day 0 times", dt, &settings).is_err());
+    assert!(parse_recurrence("every monday until tomorrow", dt, &settings).is_err());
+}
+
+#[test]
+fn recurrence_dst_test() {
+    use chrono::{TimeZone, Utc};
+    use chrono_tz::Europe::London;
+
+    // The clocks go forward overnight, so a day later is 23 hours later
+    let now = London.ymd(2014, 3, 29).and_hms(22, 0, 0);
+    let settings = ParseSettings::default();
+    let (_, first, _) = parse_recurrence("every day at 9am", now, &settings)
+        .unwrap()
+        .unwrap();
+    assert_eq!(first, Utc.ymd(2014, 3, 30).and_hms(8, 0, 0));
+
+    let (_, first, _) = parse_recurrence("every sunday at 9am", now, &settings)
+        .unwrap()
+        .unwrap();
+    assert_eq!(first, Utc.ymd(2014, 3, 30).and_hms(8, 0, 0));
+
+    let prev = London.ymd(2014, 3, 29).and_hms(9, 0, 0);
+    assert_eq!(
+        Recurrence::Days(1).next_occurrence(prev).unwrap(),
+        Utc.ymd(2014, 3, 30).and_hms(8, 0, 0)
+    );
+
+    // A Friday, so the next weekday is after the clocks change
+    let prev = London.ymd(2014, 3, 28).and_hms(9, 0, 0);
+    assert_eq!(
+        Recurrence::Weekdays(vec![Weekday::Mon])
+            .next_occurrence(prev)
+            .unwrap(),
+        Utc.ymd(2014, 3, 31).and_hms(8, 0, 0)
+    );
+
+    // Fixed intervals don't care about the local time
+    assert_eq!(
+        Recurrence::Interval(Duration::hours(24))
+            .next_occurrence(prev)
+            .unwrap(),
+        Utc.ymd(2014, 3, 29).and_hms(9, 0, 0)
+    );
+}
+
+#[test]
+fn parse_duration_test() {
+    use chrono::TimeZone;
+    use chrono_tz::UTC;
+
+    // In the evening, so that "in 3 days" would be moved to the morning
+    let dt = UTC.ymd(2014, 7, 8).and_hms(20, 0, 0);
+    let settings = ParseSettings::default();
+
+    assert_eq!(
+        parse_duration("1 hour", dt, &settings).unwrap(),
+        Duration::hours(1)
+    );
+    assert_eq!(
+        parse_duration("3 days", dt, &settings).unwrap(),
+        Duration::days(3)
+    );
+    assert_eq!(
+        parse_duration("1 week", dt, &settings).unwrap(),
+        Duration::weeks(1)
+    );
+    assert!(parse_duration("tomorrow", dt, &settings).is_err());
+    assert!(parse_duration("3 days at 5pm", dt, &settings).is_err());
+}
+
+#[test]
+fn recurrence_overflow_test() {
+    use chrono::TimeZone;
+    use chrono_tz::UTC;
+
+    let dt = UTC.ymd(2014, 7, 8).and_hms(9, 10, 11);
+    let settings = ParseSettings::default();
+
+    assert!(parse_recurrence("every 4294967295 hours", dt, &settings).is_err());
+    assert!(parse_recurrence("every 4294967295 weeks", dt, &settings).is_err());
+
+    let limited = ParseSettings {
+        max_horizon: Some(Duration::days(5 * 365)),
+        ..ParseSettings::default()
+    };
+    let err = parse_recurrence("every 9999999 hours", dt, &limited).unwrap_err();
+    assert!(err.downcast_ref::<TooFarAway>().is_some());
+    let err = parse_recurrence("every 9999999 weeks", dt, &limited).unwrap_err();
+    assert!(err.downcast_ref::<TooFarAway>().is_some());
+
+    // Stored schedules past the end of time stop rather than panic
+    assert_eq!(Recurrence::Days(4_294_967_295 * 7).next_occurrence(dt), None);
+    assert_eq!(
+        Recurrence::Interval(Duration::weeks(4_294_967_295)).next_occurrence(dt),
+        None
+    );
+}
+
+#[test]
+fn format_relative_test() {
+    use chrono::TimeZone;
+    use chrono_tz::UTC;
+
+    // A Tuesday
+    let now = UTC.ymd(2014, 7, 8).and_hms(15, 0, 0);
+
+    assert_eq!(
+        format_relative(UTC.ymd(2014, 7, 8).and_hms(15, 0, 30), now),
+        "in less than a minute, at 15:00 Tue"
+    );
+    assert_eq!(
+        format_relative(UTC.ymd(2014, 7, 8).and_hms(15, 1, 0), now),
+        "in 1 minute, at 15:01 Tue"
+    );
+    assert_eq!(
+        format_relative(UTC.ymd(2014, 7, 8).and_hms(18, 0, 0), now),
+        "in 3 hours, at 18:00 Tue"
+    );
+    assert_eq!(
+        format_relative(UTC.ymd(2014, 7, 8).and_hms(17, 30, 0), now),
+        "in 2 hours 30 minutes, at 17:30 Tue"
+    );
+    assert_eq!(
+        format_relative(UTC.ymd(2014, 7, 11).and_hms(9, 0, 0), now),
+        "in 2 days, at 09:00 Fri"
+    );
+    assert_eq!(
+        format_relative(UTC.ymd(2014, 8, 8).and_hms(9, 0, 0), now),
+        "in 4 weeks, at 09:00 Fri 8 Aug"
+    );
+    assert_eq!(
+        format_relative(UTC.ymd(2015, 1, 1).and_hms(9, 0, 0), now),
+        "in 25 weeks, at 09:00 Thu 1 Jan 2015"
+    );
+}
+
+#[test]
+fn format_repeat_test() {
+    use chrono::TimeZone;
+    use chrono_tz::UTC;
+
+    let due = UTC.ymd(2014, 7, 8).and_hms(9, 0, 0);
+    let daily = Recurrence::Days(1);
+
+    assert_eq!(format_repeat(&daily, due, None), "every day");
+    assert_eq!(
+        format_repeat(&daily, due, Some(UTC.ymd(2014, 7, 10).and_hms(9, 0, 0))),
+        "every day 3 times, until 09:00 Thu 10 Jul 2014"
+    );
+    assert_eq!(
+        format_repeat(&daily, due, Some(due)),
+        "every day 1 time, until 09:00 Tue 8 Jul 2014"
+    );
+    assert_eq!(
+        format_repeat(
+            &Recurrence::Interval(Duration::minutes(1)),
+            due,
+            Some(UTC.ymd(2015, 7, 8).and_hms(9, 0, 0))
+        ),
+        "every minute, until 09:00 Wed 8 Jul 2015"
+    );
+}
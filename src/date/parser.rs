@@ -0,0 +1,1611 @@
+use chrono::{Duration, NaiveTime, Weekday};
+use chrono_tz::{America, Asia, Australia, Europe, Tz, UTC};
+use failure::{err_msg, Error};
+
+use std::cmp;
+
+use super::locale::{translate, Locale};
+use super::tokenizer::{tokenize, Token, TokenKind};
+use super::{date_name_key, get_duration_from_string, NamedDate, Recurrence};
+use cron::CronSchedule;
+use rrule::RRule;
+
+const MONTH_NAMES: [&str; 12] = [
+    "january",
+    "february",
+    "march",
+    "april",
+    "may",
+    "june",
+    "july",
+    "august",
+    "september",
+    "october",
+    "november",
+    "december",
+];
+
+/// Holidays that are always understood, by `date_name_key`.
+const HOLIDAYS: [(&str, u32, u32); 16] = [
+    ("new year", 1, 1),
+    ("new years", 1, 1),
+    ("new years day", 1, 1),
+    ("valentines", 2, 14),
+    ("valentines day", 2, 14),
+    ("st patricks day", 3, 17),
+    ("halloween", 10, 31),
+    ("bonfire night", 11, 5),
+    ("christmas eve", 12, 24),
+    ("christmas", 12, 25),
+    ("christmas day", 12, 25),
+    ("xmas", 12, 25),
+    ("boxing day", 12, 26),
+    ("new years eve", 12, 31),
+    ("nye", 12, 31),
+    ("hogmanay", 12, 31),
+];
+
+/// The most words a date's name can be.
+const MAX_NAME_TOKENS: usize = 8;
+
+/// A time expression such as "every monday at 5pm" broken down into its
+/// parts, before it's worked out relative to the current time.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParsedSchedule {
+    pub date: Option<DatePart>,
+    pub time: Option<TimePart>,
+    pub recurrence: Option<Recurrence>,
+    /// When a repeating schedule stops, e.g. "for the next 6 hours"
+    pub limit: Option<RepeatLimit>,
+    /// A timezone given along with the time, e.g. "at 9am EST"
+    pub timezone: Option<Tz>,
+    /// Byte offset of the start of the expression in the input
+    pub start: usize,
+    /// Byte offset of the end of the expression in the input
+    pub end: usize,
+}
+
+/// Which day something is due, e.g. "tomorrow" or "on the 15th".
+#[derive(Debug, Clone, PartialEq)]
+pub enum DatePart {
+    /// An amount of time from now, e.g. "in 2 hours and 15 minutes"
+    Relative {
+        months: f64,
+        seconds: f64,
+        /// Whether any units were shorter than a day
+        has_time_units: bool,
+    },
+    /// e.g. "tomorrow" is 1
+    DaysAhead(i64),
+    /// Days that aren't weekends or holidays, e.g. "next business day" is 1
+    /// and "in 3 working days" is 3
+    BusinessDays(i64),
+    /// Monday of next week
+    NextWeek,
+    /// e.g. "friday", or "next friday" which is always in the next week
+    Weekday { weekday: Weekday, next: bool },
+    /// e.g. "4 December" or "2017-12-04"
+    Date {
+        year: Option<i32>,
+        month: u32,
+        day: u32,
+    },
+    /// e.g. "04/12", which needs the user's date order to make sense of
+    Numeric {
+        first: u32,
+        second: u32,
+        year: Option<i32>,
+        text: String,
+    },
+    /// e.g. "the 15th", or "the 1st of next month"
+    DayOfMonth { day: u32, months_ahead: Option<i32> },
+    /// e.g. "end of the week"
+    EndOf(Period),
+}
+
+/// When a repeating schedule stops.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RepeatLimit {
+    /// e.g. "for the next 6 hours", as an amount of time from now
+    For { months: f64, seconds: f64 },
+    /// e.g. "until friday at 5pm"
+    Until {
+        date: Option<DatePart>,
+        time: Option<TimePart>,
+    },
+    /// e.g. "6 times"
+    Times(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Period {
+    Day,
+    Week,
+    Month,
+}
+
+/// What time of day something is due.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimePart {
+    /// e.g. "at 17:30", "at 5:30pm" or "at noon"
+    Clock(NaiveTime),
+    /// e.g. "half past five", which could be in the morning or afternoon.
+    /// The time is the morning one.
+    TwelveHour(NaiveTime),
+    Morning,
+    Afternoon,
+    /// Also "tonight"
+    Evening,
+}
+
+/// Parses as much of the input as makes up a time expression, starting at
+/// the beginning. The end of the returned schedule says how far it got.
+/// Dates can be referred to by the given names as well as by holidays, and
+/// the expression can be in the given language as well as English.
+pub fn parse_schedule(
+    input: &str,
+    named_dates: &[NamedDate],
+    locale: Locale,
+) -> Result<ParsedSchedule, Error> {
+    let mut parser = Parser {
+        input,
+        tokens: translate(tokenize(input), locale),
+        pos: 0,
+        named_dates,
+    };
+
+    parser.parse()
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    tokens: Vec<Token>,
+    pos: usize,
+    named_dates: &'a [NamedDate],
+}
+
+impl<'a> Parser<'a> {
+    fn parse(&mut self) -> Result<ParsedSchedule, Error> {
+        let mut schedule = ParsedSchedule::default();
+
+        schedule.recurrence = self.parse_recurrence()?;
+
+        while self.parse_clause(&mut schedule)? {}
+
+        if self.pos == 0 {
+            bail!("couldn't parse date");
+        }
+
+        schedule.start = self.tokens[0].start;
+        schedule.end = self.tokens[self.pos - 1].end;
+
+        Ok(schedule)
+    }
+
+    /// Parses the next part of the expression into the schedule, returning
+    /// false if there isn't one.
+    fn parse_clause(&mut self, schedule: &mut ParsedSchedule) -> Result<bool, Error> {
+        let start = self.pos;
+
+        // e.g. "friday, 5pm"
+        self.eat_symbol(',');
+
+        if schedule.recurrence.is_some() && schedule.limit.is_none() {
+            if let Some(limit) = self.parse_repeat_limit()? {
+                schedule.limit = Some(limit);
+                return Ok(true);
+            }
+        }
+
+        if let Some(date) = self.parse_date()? {
+            if schedule.date.is_none() {
+                schedule.date = Some(date);
+                return Ok(true);
+            }
+        } else if let Some(time) = self.parse_time()? {
+            if schedule.time.is_none() {
+                schedule.time = Some(time);
+                return Ok(true);
+            }
+        } else if let Some(tz) = self.parse_timezone()? {
+            if schedule.timezone.is_none() {
+                schedule.timezone = Some(tz);
+                return Ok(true);
+            }
+        }
+
+        // Either nothing matched or it's a second date or time, which is
+        // something else
+        self.pos = start;
+        Ok(false)
+    }
+
+    /// Parses e.g. "every monday", `cron "0 9 * * 1-5"` or
+    /// "RRULE:FREQ=WEEKLY;BYDAY=MO" at the start of the input.
+    fn parse_recurrence(&mut self) -> Result<Option<Recurrence>, Error> {
+        if self.eat_word("cron") {
+            let spec = self
+                .parse_quoted()
+                .ok_or_else(|| err_msg("expected a quoted cron schedule"))?;
+            return Ok(Some(Recurrence::Cron(CronSchedule::parse(&spec)?)));
+        }
+
+        if let Some(spec) = self.parse_rrule_spec() {
+            return Ok(Some(Recurrence::RRule(RRule::parse(&spec)?)));
+        }
+
+        if !self.eat_word("every") {
+            return Ok(None);
+        }
+
+        if self.eat_word("weekday") || self.eat_word("weekdays") {
+            let weekdays = vec![
+                Weekday::Mon,
+                Weekday::Tue,
+                Weekday::Wed,
+                Weekday::Thu,
+                Weekday::Fri,
+            ];
+            return Ok(Some(Recurrence::Weekdays(weekdays)));
+        }
+
+        // e.g. "every monday, wednesday and friday"
+        if let Some(first) = self.peek_word(0).and_then(weekday_from_word) {
+            self.pos += 1;
+
+            let mut weekdays = vec![first];
+            loop {
+                let before = self.pos;
+                if !self.eat_symbol(',') && !self.eat_word("and") {
+                    break;
+                }
+                self.eat_word("and");
+
+                match self.peek_word(0).and_then(weekday_from_word) {
+                    Some(weekday) => {
+                        self.pos += 1;
+                        weekdays.push(weekday);
+                    }
+                    None => {
+                        self.pos = before;
+                        break;
+                    }
+                }
+            }
+
+            return Ok(Some(Recurrence::Weekdays(weekdays)));
+        }
+
+        // e.g. "every 2 hours" or "every day"
+        let number = match self.number_token(0) {
+            Some((number, _)) => {
+                self.pos += 1;
+                i64::from(number)
+            }
+            None => 1,
+        };
+
+        let unit = self
+            .peek_word(0)
+            .and_then(|word| {
+                if is_duration_unit(word) {
+                    Some(word.to_string())
+                } else {
+                    None
+                }
+            })
+            .ok_or_else(|| err_msg("couldn't parse recurrence"))?;
+
+        if number <= 0 {
+            bail!("invalid recurrence interval");
+        }
+        if number > 10_000_000 {
+            bail!("recurrence interval too large");
+        }
+
+        let recurrence = match unit.as_str() {
+            "month" | "months" | "year" | "years" => bail!("couldn't parse recurrence"),
+            unit => {
+                let dur = get_duration_from_string(unit);
+                if dur < Duration::days(1) {
+                    Recurrence::Interval(Duration::seconds(dur.num_seconds() * number))
+                } else {
+                    Recurrence::Days(dur.num_days() * number)
+                }
+            }
+        };
+        self.pos += 1;
+
+        Ok(Some(recurrence))
+    }
+
+    /// Parses e.g. "for the next 6 hours", "until friday at 5pm" or "6
+    /// times", which end a repeating schedule.
+    fn parse_repeat_limit(&mut self) -> Result<Option<RepeatLimit>, Error> {
+        let start = self.pos;
+
+        if self.eat_word("for") {
+            self.eat_word("the");
+            self.eat_word("next");
+
+            if let Some(DatePart::Relative {
+                months, seconds, ..
+            }) = self.parse_duration()?
+            {
+                return Ok(Some(RepeatLimit::For { months, seconds }));
+            }
+        } else if self.eat_word("until") || self.eat_word("till") || self.eat_word("til") {
+            let mut date = self.parse_date()?;
+            let time = self.parse_time()?;
+            if date.is_none() {
+                date = self.parse_date()?;
+            }
+
+            if date.is_some() || time.is_some() {
+                return Ok(Some(RepeatLimit::Until { date, time }));
+            }
+        } else if let Some((number, _)) = self.number_token(0) {
+            if self.peek_word(1) == Some("times") {
+                self.pos += 2;
+                return Ok(Some(RepeatLimit::Times(number)));
+            }
+        } else if self.eat_word("twice") {
+            return Ok(Some(RepeatLimit::Times(2)));
+        }
+
+        self.pos = start;
+        Ok(None)
+    }
+
+    /// Parses an RRULE, optionally quoted and prefixed with "RRULE:",
+    /// returning its text.
+    fn parse_rrule_spec(&mut self) -> Option<String> {
+        let start = self.pos;
+
+        if self.eat_word("rrule") {
+            self.eat_symbol(':');
+        }
+        let quoted = self.eat_quote();
+        if quoted && self.eat_word("rrule") {
+            self.eat_symbol(':');
+        }
+
+        if !self.peek(0).map_or(false, |t| t.is_word("freq")) {
+            self.pos = start;
+            return None;
+        }
+
+        // The rule runs until a space or closing quote
+        let mut end = self.pos;
+        while let Some(token) = self.tokens.get(end + 1) {
+            if token.start != self.tokens[end].end || is_quote(token) {
+                break;
+            }
+            end += 1;
+        }
+
+        let spec = self.input[self.tokens[self.pos].start..self.tokens[end].end].to_lowercase();
+        self.pos = end + 1;
+
+        if quoted {
+            self.eat_quote();
+        }
+
+        Some(spec)
+    }
+
+    /// Parses a quoted string, returning what's between the quotes.
+    fn parse_quoted(&mut self) -> Option<String> {
+        let start = self.pos;
+        if !self.eat_quote() {
+            return None;
+        }
+
+        let close = match (self.pos..self.tokens.len()).find(|&i| is_quote(&self.tokens[i])) {
+            Some(close) => close,
+            None => {
+                self.pos = start;
+                return None;
+            }
+        };
+
+        let text = self.input[self.tokens[start].end..self.tokens[close].start].to_lowercase();
+        self.pos = close + 1;
+
+        Some(text)
+    }
+
+    fn parse_date(&mut self) -> Result<Option<DatePart>, Error> {
+        let start = self.pos;
+
+        if let Some(part) = self.parse_business_days() {
+            return Ok(Some(part));
+        }
+        if let Some(part) = self.parse_in_duration()? {
+            return Ok(Some(part));
+        }
+        if let Some(part) = self.parse_day_word() {
+            return Ok(Some(part));
+        }
+        if let Some(part) = self.parse_end_of() {
+            return Ok(Some(part));
+        }
+        if self.eat_word("next") && self.eat_word("week") {
+            return Ok(Some(DatePart::NextWeek));
+        }
+        self.pos = start;
+
+        if let Some(part) = self.parse_weekday() {
+            return Ok(Some(part));
+        }
+
+        self.eat_word("on");
+
+        if let Some(part) = self.parse_named_date() {
+            return Ok(Some(part));
+        }
+        if let Some(part) = self.parse_iso_date() {
+            return Ok(Some(part));
+        }
+        if let Some(part) = self.parse_numeric_date()? {
+            return Ok(Some(part));
+        }
+        if let Some(part) = self.parse_day_month() {
+            return Ok(Some(part));
+        }
+        if let Some(part) = self.parse_month_day() {
+            return Ok(Some(part));
+        }
+        if let Some(part) = self.parse_day_of_month()? {
+            return Ok(Some(part));
+        }
+
+        self.pos = start;
+        Ok(None)
+    }
+
+    /// Parses "next business day" or e.g. "in 3 working days".
+    fn parse_business_days(&mut self) -> Option<DatePart> {
+        let start = self.pos;
+
+        let days = if self.eat_word("next") {
+            1
+        } else if self.eat_word("in") {
+            if let Some((days, _)) = self.number_token(0) {
+                self.pos += 1;
+                i64::from(days)
+            } else if self.eat_word("a") || self.eat_word("one") {
+                1
+            } else {
+                self.pos = start;
+                return None;
+            }
+        } else {
+            return None;
+        };
+
+        let business = self.eat_word("business") || self.eat_word("working");
+        if !business || !(self.eat_word("day") || self.eat_word("days")) {
+            self.pos = start;
+            return None;
+        }
+
+        Some(DatePart::BusinessDays(days))
+    }
+
+    /// Parses e.g. "in 2 hours and 15 minutes", "in 1h30m" or "in a day and
+    /// a half".
+    fn parse_in_duration(&mut self) -> Result<Option<DatePart>, Error> {
+        let start = self.pos;
+        if !self.eat_word("in") {
+            return Ok(None);
+        }
+
+        let part = self.parse_duration()?;
+        if part.is_none() {
+            self.pos = start;
+        }
+
+        Ok(part)
+    }
+
+    /// Parses e.g. "2 hours and 15 minutes" as an amount of time from now.
+    fn parse_duration(&mut self) -> Result<Option<DatePart>, Error> {
+        let start = self.pos;
+
+        let mut months = 0.0;
+        let mut seconds = 0.0;
+        let mut has_time_units = false;
+        let mut last_unit: Option<String> = None;
+
+        loop {
+            let segment_start = self.pos;
+            if last_unit.is_some() {
+                self.eat_symbol(',');
+                self.eat_word("and");
+            }
+
+            let (number, unit) = match self.parse_duration_segment(last_unit.as_ref())? {
+                Some(segment) => segment,
+                None => {
+                    self.pos = segment_start;
+                    break;
+                }
+            };
+
+            if number > 10_000_000.0 {
+                bail!("duration too large");
+            }
+
+            // Months vary in length, so they're added by the calendar
+            match unit.as_str() {
+                "month" | "months" => months += number,
+                "year" | "years" => months += number * 12.0,
+                unit => {
+                    let dur = get_duration_from_string(unit);
+                    if dur < Duration::days(1) {
+                        has_time_units = true;
+                    }
+                    seconds += dur.num_seconds() as f64 * number;
+                }
+            }
+
+            last_unit = Some(unit);
+        }
+
+        if last_unit.is_none() {
+            self.pos = start;
+            return Ok(None);
+        }
+
+        Ok(Some(DatePart::Relative {
+            months,
+            seconds,
+            has_time_units,
+        }))
+    }
+
+    /// Parses e.g. "2 hours", "a few days" or "half an hour".
+    fn parse_duration_segment(
+        &mut self,
+        last_unit: Option<&String>,
+    ) -> Result<Option<(f64, String)>, Error> {
+        let start = self.pos;
+
+        // "and a half" is half of whatever came before
+        if self.peek_word(0) == Some("a") && self.peek_word(1) == Some("half") {
+            if let Some(unit) = last_unit {
+                self.pos += 2;
+                return Ok(Some((0.5, unit.clone())));
+            }
+        }
+
+        let number = if let Some(number) = self.parse_decimal() {
+            number
+        } else if self.eat_word("half") {
+            if !self.eat_word("a") && !self.eat_word("an") {
+                self.pos = start;
+                return Ok(None);
+            }
+            0.5
+        } else if self.eat_word("a") || self.eat_word("an") {
+            if self.eat_word("couple") {
+                if !self.eat_word("of") {
+                    self.pos = start;
+                    return Ok(None);
+                }
+                2.0
+            } else if self.eat_word("few") {
+                3.0
+            } else {
+                1.0
+            }
+        } else {
+            return Ok(None);
+        };
+
+        let unit = self.peek_word(0).and_then(|word| {
+            if is_duration_unit(word) {
+                Some(word.to_string())
+            } else {
+                None
+            }
+        });
+
+        match unit {
+            Some(unit) => {
+                self.pos += 1;
+                Ok(Some((number, unit)))
+            }
+            None => {
+                self.pos = start;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Parses e.g. "2" or "1.5".
+    fn parse_decimal(&mut self) -> Option<f64> {
+        let mut text = match self.peek(0) {
+            Some(token) if token.kind == TokenKind::Number => token.text.clone(),
+            _ => return None,
+        };
+        self.pos += 1;
+
+        let fraction = self.joined(0)
+            && self.symbol_at(0, '.')
+            && self.joined(1)
+            && self.number_token(1).is_some();
+        if fraction {
+            text.push('.');
+            text.push_str(&self.tokens[self.pos + 1].text);
+            self.pos += 2;
+        }
+
+        text.parse().ok()
+    }
+
+    /// Parses "today", "tomorrow" or "(the) day after tomorrow".
+    fn parse_day_word(&mut self) -> Option<DatePart> {
+        let start = self.pos;
+
+        self.eat_word("the");
+        if self.eat_word("day") && self.eat_word("after") && self.eat_word("tomorrow") {
+            return Some(DatePart::DaysAhead(2));
+        }
+        self.pos = start;
+
+        if self.eat_word("tomorrow") {
+            Some(DatePart::DaysAhead(1))
+        } else if self.eat_word("today") {
+            Some(DatePart::DaysAhead(0))
+        } else {
+            None
+        }
+    }
+
+    /// Parses e.g. "by end of day", "end of the week" or "eom".
+    fn parse_end_of(&mut self) -> Option<DatePart> {
+        let start = self.pos;
+
+        self.eat_word("by");
+
+        let period = match self.peek_word(0) {
+            Some("eod") => Some(Period::Day),
+            Some("eow") => Some(Period::Week),
+            Some("eom") => Some(Period::Month),
+            _ => None,
+        };
+        if let Some(period) = period {
+            self.pos += 1;
+            return Some(DatePart::EndOf(period));
+        }
+
+        self.eat_word("the");
+        if !self.eat_word("end") || !self.eat_word("of") {
+            self.pos = start;
+            return None;
+        }
+        self.eat_word("the");
+
+        let period = match self.peek_word(0) {
+            Some("day") => Some(Period::Day),
+            Some("week") => Some(Period::Week),
+            Some("month") => Some(Period::Month),
+            _ => None,
+        };
+
+        match period {
+            Some(period) => {
+                self.pos += 1;
+                Some(DatePart::EndOf(period))
+            }
+            None => {
+                self.pos = start;
+                None
+            }
+        }
+    }
+
+    /// Parses e.g. "wed", "on monday" or "next friday".
+    fn parse_weekday(&mut self) -> Option<DatePart> {
+        let start = self.pos;
+
+        let next = self.eat_word("next");
+        if !next && !self.eat_word("on") {
+            self.eat_word("this");
+        }
+
+        match self.peek_word(0).and_then(weekday_from_word) {
+            Some(weekday) => {
+                self.pos += 1;
+                Some(DatePart::Weekday { weekday, next })
+            }
+            None => {
+                self.pos = start;
+                None
+            }
+        }
+    }
+
+    /// Parses a holiday such as "christmas eve", or a date named by an
+    /// admin such as "mum's birthday", optionally followed by a year.
+    fn parse_named_date(&mut self) -> Option<DatePart> {
+        let mut found = None;
+
+        // The longest name that matches, so that "new year's eve" isn't
+        // taken as "new year"
+        for len in 1..=cmp::min(MAX_NAME_TOKENS, self.tokens.len() - self.pos) {
+            let text =
+                &self.input[self.tokens[self.pos].start..self.tokens[self.pos + len - 1].end];
+            let key = date_name_key(text);
+
+            let named = self
+                .named_dates
+                .iter()
+                .find(|named| date_name_key(&named.name) == key)
+                .map(|named| (named.month, named.day));
+            let holiday = HOLIDAYS
+                .iter()
+                .find(|&&(name, _, _)| name == key)
+                .map(|&(_, month, day)| (month, day));
+
+            if let Some((month, day)) = named.or(holiday) {
+                found = Some((len, month, day));
+            }
+        }
+
+        let (len, month, day) = found?;
+        self.pos += len;
+
+        let year = self.parse_year();
+
+        Some(DatePart::Date { year, month, day })
+    }
+
+    /// Parses e.g. "2017-12-04".
+    fn parse_iso_date(&mut self) -> Option<DatePart> {
+        let (year, digits) = self.number_token(0)?;
+        if digits != 4 || !self.symbol_at(1, '-') || !self.symbol_at(3, '-') {
+            return None;
+        }
+
+        let (month, _) = self.number_token(2)?;
+        let (day, _) = self.number_token(4)?;
+        self.pos += 5;
+
+        // e.g. "2024-06-01T14:30", leaving the time to be parsed next
+        let time_follows = self.peek(0).map_or(false, |token| token.is_word("t"))
+            && self.joined(0)
+            && self.number_token(1).is_some()
+            && self.joined(1);
+        if time_follows {
+            self.pos += 1;
+        }
+
+        Some(DatePart::Date {
+            year: Some(year as i32),
+            month,
+            day,
+        })
+    }
+
+    /// Parses e.g. "04/12", "04/12/24" or "04/12/2024".
+    fn parse_numeric_date(&mut self) -> Result<Option<DatePart>, Error> {
+        let (first, first_digits) = match self.number_token(0) {
+            Some(number) => number,
+            None => return Ok(None),
+        };
+        let (second, second_digits) = match self.number_token(2) {
+            Some(number) => number,
+            None => return Ok(None),
+        };
+        if first_digits > 2 || second_digits > 2 || !self.symbol_at(1, '/') {
+            return Ok(None);
+        }
+
+        let mut len = 3;
+        let mut year = None;
+        if self.symbol_at(3, '/') {
+            if let Some((number, digits)) = self.number_token(4) {
+                year = match digits {
+                    2 => Some(2000 + number as i32),
+                    4 => Some(number as i32),
+                    _ => return Ok(None),
+                };
+                len = 5;
+            }
+        }
+
+        let text = self.input[self.tokens[self.pos].start..self.tokens[self.pos + len - 1].end]
+            .to_string();
+
+        if first == 0 || second == 0 {
+            bail!("invalid date {}", text);
+        }
+
+        self.pos += len;
+
+        Ok(Some(DatePart::Numeric {
+            first,
+            second,
+            year,
+            text,
+        }))
+    }
+
+    /// Parses e.g. "4 December", "the 4th of dec" or "4 dec 2019".
+    fn parse_day_month(&mut self) -> Option<DatePart> {
+        let start = self.pos;
+
+        self.eat_word("the");
+
+        let day = match self.number_token(0) {
+            Some((day, digits)) if digits <= 2 => day,
+            _ => {
+                self.pos = start;
+                return None;
+            }
+        };
+        self.pos += 1;
+        self.eat_ordinal();
+        self.eat_word("of");
+
+        let month = match self.peek_word(0).and_then(month_from_word) {
+            Some(month) => month,
+            None => {
+                self.pos = start;
+                return None;
+            }
+        };
+        self.pos += 1;
+
+        if self.joined(0) {
+            self.eat_symbol('.');
+        }
+
+        let year = self.parse_year();
+
+        Some(DatePart::Date { year, month, day })
+    }
+
+    /// Parses e.g. "December 4", "dec 4th" or "Dec 4th, 2019".
+    fn parse_month_day(&mut self) -> Option<DatePart> {
+        let start = self.pos;
+
+        let month = self.peek_word(0).and_then(month_from_word)?;
+        self.pos += 1;
+
+        if self.joined(0) {
+            self.eat_symbol('.');
+        }
+
+        let day = match self.number_token(0) {
+            Some((day, digits)) if digits <= 2 => day,
+            _ => {
+                self.pos = start;
+                return None;
+            }
+        };
+        self.pos += 1;
+        self.eat_ordinal();
+
+        let year = self.parse_year();
+
+        Some(DatePart::Date { year, month, day })
+    }
+
+    /// Parses the year after a date, e.g. ", 2019".
+    fn parse_year(&mut self) -> Option<i32> {
+        let start = self.pos;
+
+        self.eat_symbol(',');
+        match self.number_token(0) {
+            Some((year, 4)) => {
+                self.pos += 1;
+                Some(year as i32)
+            }
+            _ => {
+                self.pos = start;
+                None
+            }
+        }
+    }
+
+    /// Parses e.g. "the 15th" or "the 1st of next month".
+    fn parse_day_of_month(&mut self) -> Result<Option<DatePart>, Error> {
+        let start = self.pos;
+
+        if !self.eat_word("the") {
+            return Ok(None);
+        }
+
+        let day = match self.number_token(0) {
+            Some((day, digits)) if digits <= 2 => day,
+            _ => {
+                self.pos = start;
+                return Ok(None);
+            }
+        };
+        self.pos += 1;
+
+        if !self.eat_ordinal() {
+            self.pos = start;
+            return Ok(None);
+        }
+
+        let before_month = self.pos;
+        let months_ahead = if self.eat_word("of") {
+            let months_ahead = if self.eat_word("this") {
+                Some(0)
+            } else if self.eat_word("next") {
+                Some(1)
+            } else {
+                None
+            };
+
+            if months_ahead.is_some() && self.eat_word("month") {
+                months_ahead
+            } else {
+                self.pos = before_month;
+                None
+            }
+        } else {
+            None
+        };
+
+        if day == 0 || day > 31 {
+            bail!("invalid day {}", day);
+        }
+
+        Ok(Some(DatePart::DayOfMonth { day, months_ahead }))
+    }
+
+    /// Parses e.g. "at 17:30", "5:30pm", "at noon" or "tomorrow evening".
+    fn parse_time(&mut self) -> Result<Option<TimePart>, Error> {
+        let start = self.pos;
+        let at = self.eat_word("at");
+
+        if let Some(time) = self.parse_past_to()? {
+            return Ok(Some(time));
+        }
+        if let Some(time) = self.parse_clock(at)? {
+            return Ok(Some(TimePart::Clock(time)));
+        }
+
+        let time = match self.peek_word(0) {
+            Some("noon") | Some("midday") => Some(TimePart::Clock(NaiveTime::from_hms(12, 0, 0))),
+            Some("midnight") => Some(TimePart::Clock(NaiveTime::from_hms(0, 0, 0))),
+            Some("tonight") if !at => Some(TimePart::Evening),
+            _ => None,
+        };
+        if let Some(time) = time {
+            self.pos += 1;
+            return Ok(Some(time));
+        }
+
+        // e.g. "this evening", "in the morning" or "at night"
+        if !at {
+            if self.eat_word("in") {
+                if !self.eat_word("the") {
+                    self.pos = start;
+                    return Ok(None);
+                }
+            } else {
+                self.eat_word("this");
+            }
+        }
+
+        let time = match self.peek_word(0) {
+            Some("morning") => Some(TimePart::Morning),
+            Some("afternoon") => Some(TimePart::Afternoon),
+            Some("evening") | Some("night") => Some(TimePart::Evening),
+            _ => None,
+        };
+
+        match time {
+            Some(time) => {
+                self.pos += 1;
+                Ok(Some(time))
+            }
+            None => {
+                self.pos = start;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Parses e.g. "half past five", "quarter to 9" or "10 past 3pm".
+    fn parse_past_to(&mut self) -> Result<Option<TimePart>, Error> {
+        let start = self.pos;
+
+        self.eat_word("a");
+        let minutes = if self.eat_word("half") {
+            30
+        } else if self.eat_word("quarter") {
+            15
+        } else if let Some(minutes) = self.parse_small_number() {
+            let _ = self.eat_word("minutes") || self.eat_word("minute") || self.eat_word("mins");
+            minutes
+        } else {
+            self.pos = start;
+            return Ok(None);
+        };
+
+        let to = if self.eat_word("past") || self.eat_word("after") {
+            false
+        } else if self.eat_word("to") || self.eat_word("before") {
+            true
+        } else {
+            self.pos = start;
+            return Ok(None);
+        };
+
+        let hour = match self.parse_small_number() {
+            Some(hour) => hour,
+            None => {
+                self.pos = start;
+                return Ok(None);
+            }
+        };
+
+        let pm = match self.peek_word(0) {
+            Some("am") => Some(false),
+            Some("pm") => Some(true),
+            _ => None,
+        };
+        if pm.is_some() {
+            self.pos += 1;
+        }
+
+        if minutes == 0 || minutes >= 60 {
+            bail!("invalid minutes {}", minutes);
+        }
+        if hour > 23 || (pm.is_some() && (hour == 0 || hour > 12)) {
+            bail!("invalid hour {}", hour);
+        }
+
+        // 12am is midnight and 12pm is midday
+        let hour = match pm {
+            Some(true) => hour % 12 + 12,
+            Some(false) => hour % 12,
+            None => hour,
+        };
+
+        // Minutes into the day, e.g. "quarter to 12" is 11:45
+        let total = if to {
+            (hour * 60 + 24 * 60 - minutes) % (24 * 60)
+        } else {
+            hour * 60 + minutes
+        };
+
+        // Without am or pm only the 24 hour clock is certain
+        if pm.is_some() || hour == 0 || hour > 12 {
+            let time = NaiveTime::from_hms(total / 60, total % 60, 0);
+            return Ok(Some(TimePart::Clock(time)));
+        }
+
+        let total = total % (12 * 60);
+        Ok(Some(TimePart::TwelveHour(NaiveTime::from_hms(
+            total / 60,
+            total % 60,
+            0,
+        ))))
+    }
+
+    /// Parses a number up to 59, either in digits or e.g. "five".
+    fn parse_small_number(&mut self) -> Option<u32> {
+        let number = match self.number_token(0) {
+            Some((number, digits)) if digits <= 2 => Some(number),
+            Some(_) => None,
+            None => self.peek_word(0).and_then(number_from_word),
+        };
+
+        if number.is_some() {
+            self.pos += 1;
+        }
+
+        number
+    }
+
+    /// Parses e.g. "5pm", "5:30 pm", "17:30" or "1730". A number on its own
+    /// is only a time if it came after "at".
+    fn parse_clock(&mut self, at: bool) -> Result<Option<NaiveTime>, Error> {
+        let start = self.pos;
+
+        let (number, digits) = match self.number_token(0) {
+            Some(number) => number,
+            None => return Ok(None),
+        };
+        self.pos += 1;
+
+        let colon = digits <= 2
+            && self.symbol_at(0, ':')
+            && self
+                .number_token(1)
+                .map_or(false, |(_, digits)| digits == 2);
+
+        let (hour, minute) = if colon {
+            let (minute, _) = self.number_token(1).unwrap_or((0, 0));
+            self.pos += 2;
+
+            // Seconds, e.g. in a pasted timestamp, are ignored as reminders
+            // go off on the minute
+            let seconds = self.symbol_at(0, ':')
+                && self.joined(0)
+                && self
+                    .number_token(1)
+                    .map_or(false, |(_, digits)| digits == 2);
+            if seconds {
+                self.pos += 2;
+            }
+
+            (number, minute)
+        } else if digits == 3 || digits == 4 {
+            (number / 100, number % 100)
+        } else if digits <= 2 {
+            (number, 0)
+        } else {
+            self.pos = start;
+            return Ok(None);
+        };
+
+        let pm = match self.peek_word(0) {
+            Some("am") => Some(false),
+            Some("pm") => Some(true),
+            _ => None,
+        };
+
+        let hour = if let Some(pm) = pm {
+            self.pos += 1;
+
+            if hour == 0 || hour > 12 {
+                bail!("invalid hour {}", hour);
+            }
+
+            // 12am is midnight and 12pm is midday
+            if pm {
+                hour % 12 + 12
+            } else {
+                hour % 12
+            }
+        } else if at || colon {
+            hour
+        } else {
+            self.pos = start;
+            return Ok(None);
+        };
+
+        match NaiveTime::from_hms_opt(hour, minute, 0) {
+            Some(time) => Ok(Some(time)),
+            None => bail!("invalid time {:02}:{:02}", hour, minute),
+        }
+    }
+
+    /// Parses a timezone name or common abbreviation, e.g. "Europe/Paris" or
+    /// "EST".
+    fn parse_timezone(&mut self) -> Result<Option<Tz>, Error> {
+        let word = match self.peek_word(0) {
+            Some(word) => word.to_string(),
+            None => return Ok(None),
+        };
+
+        // The "Z" ending a timestamp such as "2024-06-01T14:30Z"
+        if word == "z" {
+            if !self.joined(0) {
+                return Ok(None);
+            }
+            self.pos += 1;
+            return Ok(Some(UTC));
+        }
+
+        if !(self.symbol_at(1, '/') && self.joined(1)) {
+            return match timezone_from_abbreviation(&word) {
+                Some(tz) => {
+                    self.pos += 1;
+                    Ok(Some(tz))
+                }
+                None => Ok(None),
+            };
+        }
+
+        // Names can contain e.g. "America/Argentina/Buenos_Aires" or
+        // "Etc/GMT+5"
+        let mut end = self.pos + 1;
+        while let Some(token) = self.tokens.get(end) {
+            let part_of_name = token.kind != TokenKind::Symbol
+                || token.is_symbol('/')
+                || token.is_symbol('+')
+                || token.is_symbol('-');
+            if token.start != self.tokens[end - 1].end || !part_of_name {
+                break;
+            }
+            end += 1;
+        }
+
+        let name = &self.input[self.tokens[self.pos].start..self.tokens[end - 1].end];
+        let tz = timezone_from_name(name)?;
+        self.pos = end;
+
+        Ok(Some(tz))
+    }
+
+    fn peek(&self, offset: usize) -> Option<&Token> {
+        self.tokens.get(self.pos + offset)
+    }
+
+    fn peek_word(&self, offset: usize) -> Option<&str> {
+        self.peek(offset).and_then(|token| {
+            if token.kind == TokenKind::Word {
+                Some(token.text.as_str())
+            } else {
+                None
+            }
+        })
+    }
+
+    /// The value and number of digits of a number token.
+    fn number_token(&self, offset: usize) -> Option<(u32, usize)> {
+        self.peek(offset)
+            .and_then(|token| token.number().map(|number| (number, token.text.len())))
+    }
+
+    fn symbol_at(&self, offset: usize, symbol: char) -> bool {
+        self.peek(offset)
+            .map_or(false, |token| token.is_symbol(symbol))
+    }
+
+    /// Whether the token directly follows the one before, without a space.
+    fn joined(&self, offset: usize) -> bool {
+        let index = self.pos + offset;
+        match (index.checked_sub(1), self.tokens.get(index)) {
+            (Some(prev), Some(token)) => self.tokens[prev].end == token.start,
+            _ => false,
+        }
+    }
+
+    fn eat_word(&mut self, word: &str) -> bool {
+        if self.peek(0).map_or(false, |token| token.is_word(word)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn eat_symbol(&mut self, symbol: char) -> bool {
+        if self.symbol_at(0, symbol) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn eat_quote(&mut self) -> bool {
+        if self.peek(0).map_or(false, is_quote) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Eats the suffix of e.g. "4th".
+    fn eat_ordinal(&mut self) -> bool {
+        let ordinal = self.joined(0)
+            && match self.peek_word(0) {
+                Some("st") | Some("nd") | Some("rd") | Some("th") => true,
+                _ => false,
+            };
+
+        if ordinal {
+            self.pos += 1;
+        }
+
+        ordinal
+    }
+}
+
+fn is_quote(token: &Token) -> bool {
+    token.is_symbol('"') || token.is_symbol('“') || token.is_symbol('”')
+}
+
+fn is_duration_unit(word: &str) -> bool {
+    match word {
+        "s" | "sec" | "secs" | "second" | "seconds" => true,
+        "m" | "min" | "mins" | "minute" | "minutes" => true,
+        "h" | "hr" | "hrs" | "hour" | "hours" => true,
+        "d" | "day" | "days" => true,
+        "w" | "week" | "weeks" | "fortnight" | "fortnights" => true,
+        "month" | "months" | "year" | "years" => true,
+        _ => false,
+    }
+}
+
+/// The value of e.g. "five" or "twenty".
+fn number_from_word(word: &str) -> Option<u32> {
+    let number = match word {
+        "one" => 1,
+        "two" => 2,
+        "three" => 3,
+        "four" => 4,
+        "five" => 5,
+        "six" => 6,
+        "seven" => 7,
+        "eight" => 8,
+        "nine" => 9,
+        "ten" => 10,
+        "eleven" => 11,
+        "twelve" => 12,
+        "twenty" => 20,
+        _ => return None,
+    };
+
+    Some(number)
+}
+
+/// The weekday of e.g. "wed", "thurs" or "fridays".
+fn weekday_from_word(word: &str) -> Option<Weekday> {
+    let weekday = match word {
+        "mon" | "monday" => Weekday::Mon,
+        "tue" | "tues" | "tuesday" => Weekday::Tue,
+        "wed" | "weds" | "wednesday" => Weekday::Wed,
+        "thu" | "thur" | "thurs" | "thursday" => Weekday::Thu,
+        "fri" | "friday" => Weekday::Fri,
+        "sat" | "saturday" => Weekday::Sat,
+        "sun" | "sunday" => Weekday::Sun,
+        _ if word.len() > 3 && word.ends_with('s') => {
+            return weekday_from_word(&word[..word.len() - 1]);
+        }
+        _ => return None,
+    };
+
+    Some(weekday)
+}
+
+/// The month number of e.g. "dec", "sept" or "december".
+fn month_from_word(word: &str) -> Option<u32> {
+    if word.len() < 3 {
+        return None;
+    }
+
+    MONTH_NAMES
+        .iter()
+        .position(|name| name.starts_with(word))
+        .map(|index| index as u32 + 1)
+}
+
+/// Looks up a timezone such as "Europe/Paris", allowing e.g. "europe/paris".
+fn timezone_from_name(name: &str) -> Result<Tz, Error> {
+    let title_case: String = name
+        .split('/')
+        .map(|part| {
+            part.split('_')
+                .map(|word| {
+                    let mut chars = word.chars();
+                    match chars.next() {
+                        Some(c) => c.to_uppercase().chain(chars).collect(),
+                        None => String::new(),
+                    }
+                })
+                .collect::<Vec<String>>()
+                .join("_")
+        })
+        .collect::<Vec<String>>()
+        .join("/");
+
+    name.parse::<Tz>()
+        .or_else(|_| title_case.parse::<Tz>())
+        .map_err(|_| format_err!("unknown timezone {}", name))
+}
+
+/// The timezone usually meant by an abbreviation. Summer and winter
+/// abbreviations both give the zone rather than a fixed offset, since people
+/// often say e.g. "EST" all year round.
+fn timezone_from_abbreviation(abbreviation: &str) -> Option<Tz> {
+    let tz = match abbreviation.to_uppercase().as_str() {
+        "UTC" | "GMT" => UTC,
+        "BST" => Europe::London,
+        "CET" | "CEST" => Europe::Paris,
+        "EET" | "EEST" => Europe::Athens,
+        "EST" | "EDT" => America::New_York,
+        "CST" | "CDT" => America::Chicago,
+        "MST" | "MDT" => America::Denver,
+        "PST" | "PDT" => America::Los_Angeles,
+        "IST" => Asia::Kolkata,
+        "JST" => Asia::Tokyo,
+        "AEST" | "AEDT" => Australia::Sydney,
+        _ => return None,
+    };
+
+    Some(tz)
+}
+
+#[test]
+fn parse_schedule_test() {
+    let schedule = parse_schedule("tomorrow at 5:30pm", &[], Locale::English).unwrap();
+    assert_eq!(schedule.date, Some(DatePart::DaysAhead(1)));
+    assert_eq!(
+        schedule.time,
+        Some(TimePart::Clock(NaiveTime::from_hms(17, 30, 0)))
+    );
+    assert_eq!((schedule.start, schedule.end), (0, 18));
+
+    let schedule = parse_schedule(
+        "in 2 hours and 15 minutes to go to the shops",
+        &[],
+        Locale::English,
+    )
+    .unwrap();
+    assert_eq!(
+        schedule.date,
+        Some(DatePart::Relative {
+            months: 0.0,
+            seconds: 8100.0,
+            has_time_units: true,
+        })
+    );
+    assert_eq!(schedule.end, 25);
+
+    let schedule = parse_schedule("next friday evening EST", &[], Locale::English).unwrap();
+    assert_eq!(
+        schedule.date,
+        Some(DatePart::Weekday {
+            weekday: Weekday::Fri,
+            next: true,
+        })
+    );
+    assert_eq!(schedule.time, Some(TimePart::Evening));
+    assert_eq!(schedule.timezone, Some(America::New_York));
+
+    let schedule = parse_schedule("on 2024-06-01T14:30:15Z", &[], Locale::English).unwrap();
+    assert_eq!(
+        schedule.date,
+        Some(DatePart::Date {
+            year: Some(2024),
+            month: 6,
+            day: 1,
+        })
+    );
+    assert_eq!(
+        schedule.time,
+        Some(TimePart::Clock(NaiveTime::from_hms(14, 30, 0)))
+    );
+    assert_eq!(schedule.timezone, Some(UTC));
+    assert_eq!(schedule.end, 23);
+
+    let schedule = parse_schedule("at a quarter past twelve", &[], Locale::English).unwrap();
+    assert_eq!(
+        schedule.time,
+        Some(TimePart::TwelveHour(NaiveTime::from_hms(0, 15, 0)))
+    );
+
+    let schedule = parse_schedule("at quarter to 1pm", &[], Locale::English).unwrap();
+    assert_eq!(
+        schedule.time,
+        Some(TimePart::Clock(NaiveTime::from_hms(12, 45, 0)))
+    );
+
+    let schedule = parse_schedule("at 10 to 18", &[], Locale::English).unwrap();
+    assert_eq!(
+        schedule.time,
+        Some(TimePart::Clock(NaiveTime::from_hms(17, 50, 0)))
+    );
+
+    let schedule = parse_schedule("at 5 to call mum", &[], Locale::English).unwrap();
+    assert_eq!(
+        schedule.time,
+        Some(TimePart::Clock(NaiveTime::from_hms(5, 0, 0)))
+    );
+    assert_eq!(schedule.end, 4);
+
+    let schedule = parse_schedule("next business day at 9am", &[], Locale::English).unwrap();
+    assert_eq!(schedule.date, Some(DatePart::BusinessDays(1)));
+    assert_eq!(
+        schedule.time,
+        Some(TimePart::Clock(NaiveTime::from_hms(9, 0, 0)))
+    );
+
+    let schedule = parse_schedule(
+        "in 3 working days to send the invoice",
+        &[],
+        Locale::English,
+    )
+    .unwrap();
+    assert_eq!(schedule.date, Some(DatePart::BusinessDays(3)));
+    assert_eq!(schedule.end, 20);
+
+    let schedule = parse_schedule("on the 4th of Dec. 2019", &[], Locale::English).unwrap();
+    assert_eq!(
+        schedule.date,
+        Some(DatePart::Date {
+            year: Some(2019),
+            month: 12,
+            day: 4,
+        })
+    );
+
+    let schedule = parse_schedule("every mon, wed and fri at 0900", &[], Locale::English).unwrap();
+    assert_eq!(
+        schedule.recurrence,
+        Some(Recurrence::Weekdays(vec![
+            Weekday::Mon,
+            Weekday::Wed,
+            Weekday::Fri,
+        ]))
+    );
+    assert_eq!(
+        schedule.time,
+        Some(TimePart::Clock(NaiveTime::from_hms(9, 0, 0)))
+    );
+
+    let schedule = parse_schedule(
+        "every hour for the next 6 hours to drink water",
+        &[],
+        Locale::English,
+    )
+    .unwrap();
+    assert_eq!(
+        schedule.limit,
+        Some(RepeatLimit::For {
+            months: 0.0,
+            seconds: 21600.0,
+        })
+    );
+    assert_eq!(schedule.end, 31);
+
+    let schedule = parse_schedule("every day at 9am until friday", &[], Locale::English).unwrap();
+    assert_eq!(
+        schedule.limit,
+        Some(RepeatLimit::Until {
+            date: Some(DatePart::Weekday {
+                weekday: Weekday::Fri,
+                next: false,
+            }),
+            time: None,
+        })
+    );
+
+    let schedule = parse_schedule("every 2 hours 3 times", &[], Locale::English).unwrap();
+    assert_eq!(schedule.limit, Some(RepeatLimit::Times(3)));
+
+    // Only repeating schedules have an end
+    let schedule = parse_schedule("tomorrow for 2 hours", &[], Locale::English).unwrap();
+    assert_eq!(schedule.limit, None);
+    assert_eq!(schedule.end, 8);
+
+    // A second date isn't part of the expression
+    let schedule = parse_schedule("friday tomorrow", &[], Locale::English).unwrap();
+    assert_eq!(schedule.end, 6);
+
+    let named_dates = vec![NamedDate {
+        name: "Mum's birthday".to_string(),
+        month: 3,
+        day: 14,
+    }];
+    let schedule =
+        parse_schedule("on mums birthday at 9am", &named_dates, Locale::English).unwrap();
+    assert_eq!(
+        schedule.date,
+        Some(DatePart::Date {
+            year: None,
+            month: 3,
+            day: 14,
+        })
+    );
+    assert!(schedule.time.is_some());
+
+    let schedule = parse_schedule("new year’s eve 2019", &[], Locale::English).unwrap();
+    assert_eq!(
+        schedule.date,
+        Some(DatePart::Date {
+            year: Some(2019),
+            month: 12,
+            day: 31,
+        })
+    );
+
+    assert!(parse_schedule("call mum", &[], Locale::English).is_err());
+    assert!(parse_schedule("at 25:00", &[], Locale::English).is_err());
+}
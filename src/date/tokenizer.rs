@@ -0,0 +1,249 @@
+/// Abbreviations and common misspellings of words in time expressions,
+/// and the word they're read as.
+const ALIASES: [(&str, &str); 20] = [
+    ("tmrw", "tomorrow"),
+    ("tmrow", "tomorrow"),
+    ("tmr", "tomorrow"),
+    ("tmw", "tomorrow"),
+    ("tomoz", "tomorrow"),
+    ("tommorow", "tomorrow"),
+    ("tommorrow", "tomorrow"),
+    ("tdy", "today"),
+    ("tonite", "tonight"),
+    ("morn", "morning"),
+    ("arvo", "afternoon"),
+    ("wk", "week"),
+    ("wks", "weeks"),
+    ("mth", "month"),
+    ("mths", "months"),
+    ("yr", "year"),
+    ("yrs", "years"),
+    ("minuite", "minute"),
+    ("minuites", "minutes"),
+    ("mnths", "months"),
+];
+
+/// Words that are still understood with a letter missing, added, changed or
+/// swapped with its neighbour. Short words aren't included, as too many
+/// ordinary words are a letter away from them.
+const SPELLED_WORDS: [&str; 20] = [
+    "tomorrow",
+    "tonight",
+    "minute",
+    "minutes",
+    "second",
+    "seconds",
+    "morning",
+    "evening",
+    "afternoon",
+    "midnight",
+    "fortnight",
+    "tuesday",
+    "wednesday",
+    "thursday",
+    "saturday",
+    "february",
+    "september",
+    "october",
+    "november",
+    "december",
+];
+
+/// The shortest word that's checked for misspellings.
+const MIN_MISSPELLED_LEN: usize = 6;
+
+/// What sort of characters a token is made of.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenKind {
+    /// A run of digits, e.g. "1800"
+    Number,
+    /// A run of letters, e.g. "tomorrow" or "new_york"
+    Word,
+    /// Any other single character, e.g. ":" or "/"
+    Symbol,
+}
+
+/// A piece of a time expression such as "tomorrow at 5:30pm".
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    /// The text of the token, lowercased
+    pub text: String,
+    /// Byte offset of the start of the token in the input
+    pub start: usize,
+    /// Byte offset of the end of the token in the input
+    pub end: usize,
+}
+
+impl Token {
+    pub fn is_word(&self, word: &str) -> bool {
+        self.kind == TokenKind::Word && self.text == word
+    }
+
+    pub fn is_symbol(&self, symbol: char) -> bool {
+        self.kind == TokenKind::Symbol && self.text.starts_with(symbol)
+    }
+
+    /// The value of a number token, if it fits.
+    pub fn number(&self) -> Option<u32> {
+        if self.kind != TokenKind::Number {
+            return None;
+        }
+
+        self.text.parse().ok()
+    }
+}
+
+/// Splits the input into numbers, words and symbols, dropping whitespace.
+/// Letters and digits next to each other are separate tokens, so "1h30m" is
+/// four tokens.
+pub fn tokenize(input: &str) -> Vec<Token> {
+    let mut tokens: Vec<Token> = Vec::new();
+
+    for (start, c) in input.char_indices() {
+        let end = start + c.len_utf8();
+
+        let kind = if c.is_whitespace() {
+            continue;
+        } else if c.is_ascii_digit() {
+            TokenKind::Number
+        } else if c.is_alphabetic() || c == '_' {
+            TokenKind::Word
+        } else {
+            TokenKind::Symbol
+        };
+
+        if kind != TokenKind::Symbol {
+            if let Some(last) = tokens.last_mut() {
+                if last.kind == kind && last.end == start {
+                    last.text.extend(c.to_lowercase());
+                    last.end = end;
+                    continue;
+                }
+            }
+        }
+
+        tokens.push(Token {
+            kind,
+            text: c.to_lowercase().collect(),
+            start,
+            end,
+        });
+    }
+
+    for token in &mut tokens {
+        if token.kind != TokenKind::Word {
+            continue;
+        }
+
+        if let Some(word) = correct_word(&token.text) {
+            token.text = word.to_string();
+        }
+    }
+
+    tokens
+}
+
+/// The word meant by an abbreviation or misspelling, e.g. "tmrw" or
+/// "tomorow" for "tomorrow".
+fn correct_word(word: &str) -> Option<&'static str> {
+    if let Some(&(_, alias)) = ALIASES.iter().find(|&&(from, _)| from == word) {
+        return Some(alias);
+    }
+
+    if word.chars().count() < MIN_MISSPELLED_LEN
+        || SPELLED_WORDS.iter().any(|&spelled| spelled == word)
+    {
+        return None;
+    }
+
+    SPELLED_WORDS
+        .iter()
+        .find(|spelled| within_one_edit(word, spelled))
+        .cloned()
+}
+
+/// Whether the words differ by at most one letter being added, removed,
+/// changed or swapped with its neighbour.
+fn within_one_edit(a: &str, b: &str) -> bool {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+
+    let (shorter, longer) = if a.len() <= b.len() {
+        (&a, &b)
+    } else {
+        (&b, &a)
+    };
+
+    match longer.len() - shorter.len() {
+        0 => {
+            let diffs: Vec<usize> = (0..a.len()).filter(|&i| a[i] != b[i]).collect();
+            match diffs.len() {
+                0 | 1 => true,
+                2 => {
+                    let (i, j) = (diffs[0], diffs[1]);
+                    j == i + 1 && a[i] == b[j] && a[j] == b[i]
+                }
+                _ => false,
+            }
+        }
+        1 => {
+            // The first difference must be the added letter
+            let skip = (0..shorter.len())
+                .find(|&i| shorter[i] != longer[i])
+                .unwrap_or_else(|| shorter.len());
+            shorter[skip..] == longer[skip + 1..]
+        }
+        _ => false,
+    }
+}
+
+#[test]
+fn tokenize_test() {
+    let tokens = tokenize("Tomorrow at 5:30pm");
+    let texts: Vec<&str> = tokens.iter().map(|t| t.text.as_str()).collect();
+    assert_eq!(texts, vec!["tomorrow", "at", "5", ":", "30", "pm"]);
+
+    assert_eq!(tokens[0].kind, TokenKind::Word);
+    assert_eq!(tokens[2].number(), Some(5));
+    assert!(tokens[3].is_symbol(':'));
+    assert_eq!((tokens[4].start, tokens[4].end), (14, 16));
+
+    let texts: Vec<String> = tokenize("in 1h30m, America/New_York")
+        .into_iter()
+        .map(|t| t.text)
+        .collect();
+    assert_eq!(
+        texts,
+        vec!["in", "1", "h", "30", "m", ",", "america", "/", "new_york"]
+    );
+
+    let texts: Vec<String> = tokenize("Tmrw tomorow wendesday 2 minuets 3 hrs sundae")
+        .into_iter()
+        .map(|t| t.text)
+        .collect();
+    assert_eq!(
+        texts,
+        vec![
+            "tomorrow",
+            "tomorrow",
+            "wednesday",
+            "2",
+            "minutes",
+            "3",
+            "hrs",
+            "sundae"
+        ]
+    );
+}
+
+#[test]
+fn within_one_edit_test() {
+    assert!(within_one_edit("tomorrow", "tomorrow"));
+    assert!(within_one_edit("tomorow", "tomorrow"));
+    assert!(within_one_edit("tomorrrow", "tomorrow"));
+    assert!(within_one_edit("tomorriw", "tomorrow"));
+    assert!(within_one_edit("tomorrwo", "tomorrow"));
+    assert!(!within_one_edit("tommorow", "tomorrow"));
+    assert!(!within_one_edit("tomor", "tomorrow"));
+}
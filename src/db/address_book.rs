@@ -1,15 +1,47 @@
 use std::sync::Arc;
 
+use chrono::{DateTime, Utc};
 use failure::{Error, ResultExt};
 use rusqlite::Connection;
 
+use super::add_column_if_missing;
+
 const ADDRESS_BOOK_SCHEMA: &str = r"
     CREATE TABLE IF NOT EXISTS address_book (
         user_id TEXT PRIMARY KEY,
-        msisdn TEXT NOT NULL
+        msisdn TEXT NOT NULL,
+        opted_out BOOL NOT NULL DEFAULT 0
+    );
+
+    CREATE TABLE IF NOT EXISTS msisdn_verifications (
+        user_id TEXT PRIMARY KEY,
+        msisdn TEXT NOT NULL,
+        code TEXT NOT NULL,
+        expiry_ts BIGINT NOT NULL,
+        failed_attempts INTEGER NOT NULL DEFAULT 0
     );
+
+    CREATE TABLE IF NOT EXISTS verification_sends (
+        user_id TEXT NOT NULL,
+        msisdn TEXT NOT NULL,
+        sent_ts BIGINT NOT NULL
+    );
+
+    CREATE INDEX IF NOT EXISTS verification_sends_ts ON verification_sends(sent_ts);
 ";
 
+/// The outcome of trying a verification code.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verification {
+    /// The number has been set
+    Verified(String),
+    /// The code was wrong, and this many more tries are allowed
+    WrongCode(u32),
+    /// There's no code to try, as none was sent, it has expired or there
+    /// have been too many wrong tries
+    NotPending,
+}
+
 #[derive(Debug, Clone)]
 pub struct AddressBook {
     conn: Arc<Connection>,
@@ -20,6 +52,19 @@ impl AddressBook {
         conn.execute_batch(ADDRESS_BOOK_SCHEMA)
             .context("failed to create address book schema")?;
 
+        add_column_if_missing(
+            &conn,
+            "address_book",
+            "opted_out",
+            "BOOL NOT NULL DEFAULT 0",
+        )?;
+        add_column_if_missing(
+            &conn,
+            "msisdn_verifications",
+            "failed_attempts",
+            "INTEGER NOT NULL DEFAULT 0",
+        )?;
+
         Ok(AddressBook { conn })
     }
 
@@ -37,4 +82,353 @@ impl AddressBook {
 
         Ok(None)
     }
+
+    /// Sets the user's phone number, replacing any existing one.
+    pub fn set_msisdn_for_user(&self, user_id: &str, msisdn: &str) -> Result<(), Error> {
+        let msisdn = &normalize_msisdn(msisdn)?;
+
+        self.conn
+            .prepare_cached("INSERT OR IGNORE INTO address_book (user_id, msisdn) VALUES (?, ?)")
+            .context("failed to create insert statement")?
+            .execute(&[&user_id, &msisdn])
+            .context("failed to insert msisdn")?;
+
+        self.conn
+            .prepare_cached("UPDATE address_book SET msisdn = ? WHERE user_id = ?")
+            .context("failed to create update statement")?
+            .execute(&[&msisdn, &user_id])
+            .context("failed to update msisdn")?;
+
+        Ok(())
+    }
+
+    /// Records a code we've sent to a new number, which the user needs to
+    /// confirm before we start using the number. Replaces any previous
+    /// pending verification.
+    pub fn start_verification(
+        &self,
+        user_id: &str,
+        msisdn: &str,
+        code: &str,
+        now: &DateTime<Utc>,
+        expiry: &DateTime<Utc>,
+    ) -> Result<(), Error> {
+        let msisdn = &normalize_msisdn(msisdn)?;
+
+        self.conn
+            .prepare_cached(
+                "INSERT OR REPLACE INTO msisdn_verifications (user_id, msisdn, code, expiry_ts) VALUES (?, ?, ?, ?)",
+            )
+            .context("failed to create insert statement")?
+            .execute(&[&user_id, &msisdn, &code, &expiry.timestamp()])
+            .context("failed to insert verification")?;
+
+        self.conn
+            .prepare_cached(
+                "INSERT INTO verification_sends (user_id, msisdn, sent_ts) VALUES (?, ?, ?)",
+            )
+            .context("failed to create insert statement")?
+            .execute(&[&user_id, &msisdn, &now.timestamp()])
+            .context("failed to record verification send")?;
+
+        Ok(())
+    }
+
+    /// Counts the verification codes sent since the given time to the user,
+    /// and to the number whoever asked for them. Older sends are forgotten.
+    pub fn count_verification_sends(
+        &self,
+        user_id: &str,
+        msisdn: &str,
+        since: &DateTime<Utc>,
+    ) -> Result<(u32, u32), Error> {
+        let msisdn = &normalize_msisdn(msisdn)?;
+
+        self.conn
+            .prepare_cached("DELETE FROM verification_sends WHERE sent_ts < ?")
+            .context("failed to create delete statement")?
+            .execute(&[&since.timestamp()])
+            .context("failed to prune verification sends")?;
+
+        let (by_user, to_msisdn): (i64, i64) = self
+            .conn
+            .prepare_cached(
+                "SELECT COALESCE(SUM(user_id = ?), 0), COALESCE(SUM(msisdn = ?), 0) FROM verification_sends",
+            )
+            .context("failed to create select statement")?
+            .query_row(&[&user_id, &msisdn], |row| (row.get(0), row.get(1)))
+            .context("failed to count verification sends")?;
+
+        Ok((by_user as u32, to_msisdn as u32))
+    }
+
+    /// Checks the code against the user's pending verification, and if it
+    /// matches sets their phone number. After `max_attempts` wrong codes the
+    /// verification is dropped, so that codes can't be guessed.
+    pub fn complete_verification(
+        &self,
+        user_id: &str,
+        code: &str,
+        now: &DateTime<Utc>,
+        max_attempts: u32,
+    ) -> Result<Verification, Error> {
+        let pending: Option<(String, String, i64)> = {
+            let mut stmt = self
+                .conn
+                .prepare_cached(
+                    "SELECT msisdn, code, failed_attempts FROM msisdn_verifications WHERE user_id = ? AND expiry_ts > ?",
+                )
+                .context("failed to create select statement")?;
+
+            let mut rows = stmt.query_map(&[&user_id, &now.timestamp()], |row| {
+                (row.get(0), row.get(1), row.get(2))
+            })?;
+
+            match rows.next() {
+                Some(row) => Some(row?),
+                None => None,
+            }
+        };
+
+        let (msisdn, expected, failed_attempts) = match pending {
+            Some(pending) => pending,
+            None => return Ok(Verification::NotPending),
+        };
+
+        if code != expected {
+            let failed_attempts = failed_attempts as u32 + 1;
+
+            if failed_attempts >= max_attempts {
+                self.delete_verification(user_id)?;
+                return Ok(Verification::NotPending);
+            }
+
+            self.conn
+                .prepare_cached(
+                    "UPDATE msisdn_verifications SET failed_attempts = ? WHERE user_id = ?",
+                )
+                .context("failed to create update statement")?
+                .execute(&[&failed_attempts, &user_id])
+                .context("failed to record failed verification")?;
+
+            return Ok(Verification::WrongCode(max_attempts - failed_attempts));
+        }
+
+        self.set_msisdn_for_user(user_id, &msisdn)?;
+        self.delete_verification(user_id)?;
+
+        Ok(Verification::Verified(msisdn))
+    }
+
+    fn delete_verification(&self, user_id: &str) -> Result<(), Error> {
+        self.conn
+            .prepare_cached("DELETE FROM msisdn_verifications WHERE user_id = ?")
+            .context("failed to create delete statement")?
+            .execute(&[&user_id])
+            .context("failed to delete verification")?;
+
+        Ok(())
+    }
+
+    /// Removes the user's phone number. Returns whether they had one.
+    pub fn forget_msisdn_for_user(&self, user_id: &str) -> Result<bool, Error> {
+        let changed = self
+            .conn
+            .prepare_cached("DELETE FROM address_book WHERE user_id = ?")
+            .context("failed to create delete statement")?
+            .execute(&[&user_id])
+            .context("failed to delete msisdn")?;
+
+        self.delete_verification(user_id)?;
+
+        Ok(changed > 0)
+    }
+
+    /// Finds the user a phone number belongs to, e.g. for inbound SMS.
+    pub fn get_user_for_msisdn(&self, msisdn: &str) -> Result<Option<String>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT user_id FROM address_book WHERE msisdn = ?")
+            .context("failed to create select statement")?;
+
+        let rows = stmt.query_map(&[&msisdn], |row| row.get(0))?;
+
+        for row in rows {
+            return Ok(Some(row?));
+        }
+
+        Ok(None)
+    }
+
+    /// Whether the user has asked us to stop texting or calling them.
+    pub fn is_opted_out(&self, user_id: &str) -> Result<bool, Error> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT opted_out FROM address_book WHERE user_id = ?")
+            .context("failed to create select statement")?;
+
+        let rows = stmt.query_map(&[&user_id], |row| row.get(0))?;
+
+        for row in rows {
+            return Ok(row?);
+        }
+
+        Ok(false)
+    }
+
+    pub fn set_opted_out(&self, user_id: &str, opted_out: bool) -> Result<(), Error> {
+        self.conn
+            .prepare_cached("UPDATE address_book SET opted_out = ? WHERE user_id = ?")
+            .context("failed to create update statement")?
+            .execute(&[&opted_out, &user_id])
+            .context("failed to update opt out")?;
+
+        Ok(())
+    }
+}
+
+/// Hides all but the last few digits of a phone number, for logging, e.g.
+/// "+447700900123" to "*********0123".
+pub fn redact_msisdn(msisdn: &str) -> String {
+    let chars: Vec<char> = msisdn.chars().collect();
+    let shown = chars.len().saturating_sub(4);
+
+    chars
+        .iter()
+        .enumerate()
+        .map(|(i, &c)| if i < shown { '*' } else { c })
+        .collect()
+}
+
+/// Normalizes a phone number to E.164, e.g. "+44 (0)7700 900-123" to
+/// "+447700900123".
+pub fn normalize_msisdn(input: &str) -> Result<String, Error> {
+    // Numbers are often written with the trunk prefix in brackets after the
+    // country code, which isn't dialled internationally.
+    let input = input.trim().replace("(0)", "");
+
+    let (international, rest) = if input.starts_with('+') {
+        (true, &input[1..])
+    } else if input.starts_with("00") {
+        (true, &input[2..])
+    } else {
+        (false, &input[..])
+    };
+
+    if !international {
+        bail!("phone number must start with + and a country code, e.g. +447700900123");
+    }
+
+    let mut digits = String::new();
+    for c in rest.chars() {
+        match c {
+            '0'..='9' => digits.push(c),
+            ' ' | '-' | '.' | '(' | ')' => {}
+            _ => bail!("invalid character '{}' in phone number", c),
+        }
+    }
+
+    if digits.starts_with('0') {
+        bail!("country code can't start with 0");
+    }
+
+    if digits.len() < 8 || digits.len() > 15 {
+        bail!("phone number must have between 8 and 15 digits including the country code");
+    }
+
+    Ok(format!("+{}", digits))
+}
+
+#[test]
+fn redact_msisdn_test() {
+    assert_eq!(redact_msisdn("+447700900123"), "*********0123");
+    assert_eq!(redact_msisdn("123"), "123");
+}
+
+#[test]
+fn normalize_msisdn_test() {
+    assert_eq!(normalize_msisdn("+447700900123").unwrap(), "+447700900123");
+    assert_eq!(
+        normalize_msisdn(" +44 7700 900-123 ").unwrap(),
+        "+447700900123"
+    );
+    assert_eq!(
+        normalize_msisdn("+44 (0)7700 900123").unwrap(),
+        "+447700900123"
+    );
+    assert_eq!(normalize_msisdn("00447700900123").unwrap(), "+447700900123");
+    assert_eq!(
+        normalize_msisdn("+1 (555) 010-0199").unwrap(),
+        "+15550100199"
+    );
+
+    assert!(normalize_msisdn("07700900123").is_err());
+    assert!(normalize_msisdn("+0447700900123").is_err());
+    assert!(normalize_msisdn("+44770").is_err());
+    assert!(normalize_msisdn("+4477009001231234").is_err());
+    assert!(normalize_msisdn("+44 7700 abc").is_err());
+    assert!(normalize_msisdn("+44+7700900123").is_err());
+}
+
+#[test]
+fn verification_attempts_test() {
+    use chrono::Duration;
+
+    let conn = Arc::new(Connection::open_in_memory().unwrap());
+    let address_book = AddressBook::with_connection(conn).unwrap();
+
+    let now = Utc::now();
+    let expiry = now + Duration::minutes(15);
+    let since = now - Duration::minutes(60);
+
+    address_book
+        .start_verification(
+            "@alice:example.com",
+            "+447700900123",
+            "123456",
+            &now,
+            &expiry,
+        )
+        .unwrap();
+    address_book
+        .start_verification("@bob:example.com", "+447700900123", "654321", &now, &expiry)
+        .unwrap();
+    assert_eq!(
+        address_book
+            .count_verification_sends("@alice:example.com", "+447700900123", &since)
+            .unwrap(),
+        (1, 2)
+    );
+
+    assert_eq!(
+        address_book
+            .complete_verification("@alice:example.com", "000000", &now, 2)
+            .unwrap(),
+        Verification::WrongCode(1)
+    );
+    assert_eq!(
+        address_book
+            .complete_verification("@alice:example.com", "000000", &now, 2)
+            .unwrap(),
+        Verification::NotPending
+    );
+    assert_eq!(
+        address_book
+            .complete_verification("@alice:example.com", "123456", &now, 2)
+            .unwrap(),
+        Verification::NotPending
+    );
+
+    assert_eq!(
+        address_book
+            .complete_verification("@bob:example.com", "654321", &now, 2)
+            .unwrap(),
+        Verification::Verified("+447700900123".to_string())
+    );
+    assert_eq!(
+        address_book
+            .get_msisdn_for_user("@bob:example.com")
+            .unwrap(),
+        Some("+447700900123".to_string())
+    );
 }
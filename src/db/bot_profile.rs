@@ -0,0 +1,78 @@
+use std::sync::Arc;
+
+use failure::{Error, ResultExt};
+use rusqlite::Connection;
+
+use super::migrate_schema;
+
+const BOT_PROFILE_SCHEMA: &str = r"
+    CREATE TABLE IF NOT EXISTS bot_profiles (
+        user_id TEXT PRIMARY KEY,
+        avatar_hash TEXT NOT NULL
+    );
+";
+
+// The hash used to be stored for a single account only. Forgetting it just
+// means uploading the avatar again.
+const BOT_PROFILE_MIGRATION_1: &str = "DROP TABLE IF EXISTS bot_profile";
+
+/// Remembers which avatar we last set on each of the bot's accounts, so
+/// that it's only uploaded again when the image changes.
+#[derive(Debug, Clone)]
+pub struct BotProfile {
+    conn: Arc<Connection>,
+}
+
+impl BotProfile {
+    pub fn with_connection(conn: Arc<Connection>) -> Result<BotProfile, Error> {
+        conn.execute_batch(BOT_PROFILE_SCHEMA)
+            .context("failed to create bot profile schema")?;
+
+        migrate_schema(&conn, "bot_profile", 1, BOT_PROFILE_MIGRATION_1)?;
+
+        Ok(BotProfile { conn })
+    }
+
+    pub fn get_avatar_hash(&self, user_id: &str) -> Result<Option<String>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT avatar_hash FROM bot_profiles WHERE user_id = ?")
+            .context("failed to create select statement")?;
+
+        let rows = stmt.query_map(&[&user_id], |row| row.get(0))?;
+
+        for row in rows {
+            return Ok(Some(row?));
+        }
+
+        Ok(None)
+    }
+
+    pub fn set_avatar_hash(&self, user_id: &str, avatar_hash: &str) -> Result<(), Error> {
+        self.conn
+            .prepare_cached(
+                "INSERT OR REPLACE INTO bot_profiles (user_id, avatar_hash) VALUES (?, ?)",
+            )
+            .context("failed to create insert statement")?
+            .execute(&[&user_id, &avatar_hash])
+            .context("failed to store avatar hash")?;
+
+        Ok(())
+    }
+}
+
+#[test]
+fn bot_profile_survives_restart_test() {
+    let conn = Arc::new(Connection::open_in_memory().unwrap());
+
+    let bot_profile = BotProfile::with_connection(conn.clone()).unwrap();
+    bot_profile
+        .set_avatar_hash("@bot:example.com", "abc123")
+        .unwrap();
+
+    let bot_profile = BotProfile::with_connection(conn).unwrap();
+    assert_eq!(
+        bot_profile.get_avatar_hash("@bot:example.com").unwrap(),
+        Some("abc123".to_string())
+    );
+}
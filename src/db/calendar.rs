@@ -0,0 +1,146 @@
+use std::sync::Arc;
+
+use chrono::NaiveDate;
+use failure::{Error, ResultExt};
+use rusqlite::Connection;
+
+use date::{date_name_key, NamedDate};
+
+/// Dates admins have named for everyone, keyed by `date_name_key` so
+/// different spellings of the name are the same.
+const NAMED_DATES_SCHEMA: &str = r"
+    CREATE TABLE IF NOT EXISTS named_dates (
+        key TEXT PRIMARY KEY,
+        name TEXT NOT NULL,
+        month INTEGER NOT NULL,
+        day INTEGER NOT NULL
+    );
+";
+
+/// Days off, besides weekends, that business days skip, stored as
+/// e.g. "2024-12-25".
+const HOLIDAYS_SCHEMA: &str = r"
+    CREATE TABLE IF NOT EXISTS holidays (
+        date TEXT PRIMARY KEY
+    );
+";
+
+/// The dates shared by everyone: the names admins have given to dates and
+/// the holiday calendar that business days skip.
+#[derive(Debug, Clone)]
+pub struct Calendar {
+    conn: Arc<Connection>,
+}
+
+impl Calendar {
+    pub fn with_connection(conn: Arc<Connection>) -> Result<Calendar, Error> {
+        conn.execute_batch(NAMED_DATES_SCHEMA)
+            .context("failed to create named dates schema")?;
+        conn.execute_batch(HOLIDAYS_SCHEMA)
+            .context("failed to create holidays schema")?;
+
+        Ok(Calendar { conn })
+    }
+
+    /// The dates admins have named, in order of name.
+    pub fn get_named_dates(&self) -> Result<Vec<NamedDate>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT name, month, day FROM named_dates ORDER BY key")
+            .context("failed to create select statement")?;
+
+        let rows = stmt
+            .query_map(&[], |row| NamedDate {
+                name: row.get(0),
+                month: row.get::<_, i64>(1) as u32,
+                day: row.get::<_, i64>(2) as u32,
+            })
+            .context("failed to execute select query")?;
+
+        let mut named_dates = Vec::new();
+        for row in rows {
+            named_dates.push(row.context("failed to read results of select query")?);
+        }
+
+        Ok(named_dates)
+    }
+
+    /// Names a date for everyone, replacing any date with the same name.
+    pub fn set_named_date(&self, named_date: &NamedDate) -> Result<(), Error> {
+        self.conn
+            .prepare_cached(
+                "INSERT OR REPLACE INTO named_dates (key, name, month, day) VALUES (?, ?, ?, ?)",
+            )
+            .context("failed to create insert statement")?
+            .execute(&[
+                &date_name_key(&named_date.name),
+                &named_date.name,
+                &i64::from(named_date.month),
+                &i64::from(named_date.day),
+            ])
+            .context("failed to insert named date")?;
+
+        Ok(())
+    }
+
+    /// Removes a named date, returning false if there's none by that name.
+    pub fn remove_named_date(&self, name: &str) -> Result<bool, Error> {
+        let removed = self
+            .conn
+            .prepare_cached("DELETE FROM named_dates WHERE key = ?")
+            .context("failed to create delete statement")?
+            .execute(&[&date_name_key(name)])
+            .context("failed to delete named date")?;
+
+        Ok(removed > 0)
+    }
+
+    /// The days in the holiday calendar, in order.
+    pub fn get_holidays(&self) -> Result<Vec<NaiveDate>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT date FROM holidays ORDER BY date")
+            .context("failed to create select statement")?;
+
+        let rows = stmt
+            .query_map(&[], |row| row.get::<_, String>(0))
+            .context("failed to execute select query")?;
+
+        let mut holidays = Vec::new();
+        for row in rows {
+            let date = row.context("failed to read results of select query")?;
+            holidays.push(
+                NaiveDate::parse_from_str(&date, "%Y-%m-%d")
+                    .with_context(|_| format!("invalid holiday {}", date))?,
+            );
+        }
+
+        Ok(holidays)
+    }
+
+    /// Adds a day to the holiday calendar, returning false if it was
+    /// already there.
+    pub fn add_holiday(&self, date: NaiveDate) -> Result<bool, Error> {
+        let added = self
+            .conn
+            .prepare_cached("INSERT OR IGNORE INTO holidays (date) VALUES (?)")
+            .context("failed to create insert statement")?
+            .execute(&[&date.format("%Y-%m-%d").to_string()])
+            .context("failed to insert holiday")?;
+
+        Ok(added > 0)
+    }
+
+    /// Removes a day from the holiday calendar, returning false if it
+    /// wasn't there.
+    pub fn remove_holiday(&self, date: NaiveDate) -> Result<bool, Error> {
+        let removed = self
+            .conn
+            .prepare_cached("DELETE FROM holidays WHERE date = ?")
+            .context("failed to create delete statement")?
+            .execute(&[&date.format("%Y-%m-%d").to_string()])
+            .context("failed to delete holiday")?;
+
+        Ok(removed > 0)
+    }
+}
@@ -0,0 +1,100 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, TimeZone, Utc};
+use failure::{Error, ResultExt};
+use rusqlite::Connection;
+
+use super::reminders::normalize_reminder_id;
+
+const DELIVERY_STATUSES_SCHEMA: &str = r"
+    CREATE TABLE IF NOT EXISTS delivery_status (
+        message_sid TEXT PRIMARY KEY,
+        reminder_id TEXT NOT NULL,
+        status TEXT NOT NULL,
+        error_code TEXT,
+        updated_ts BIGINT NOT NULL
+    );
+
+    CREATE INDEX IF NOT EXISTS delivery_status_reminder_id ON delivery_status(reminder_id);
+";
+
+/// The latest status Twilio reported for a message sent for a reminder.
+#[derive(Debug, Clone)]
+pub struct DeliveryStatus {
+    pub message_sid: String,
+    pub reminder_id: String,
+    /// e.g. "delivered", "undelivered" or "failed"
+    pub status: String,
+    pub error_code: Option<String>,
+    pub updated: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DeliveryStatuses {
+    conn: Arc<Connection>,
+}
+
+impl DeliveryStatuses {
+    pub fn with_connection(conn: Arc<Connection>) -> Result<DeliveryStatuses, Error> {
+        conn.execute_batch(DELIVERY_STATUSES_SCHEMA)
+            .context("failed to create delivery status schema")?;
+
+        Ok(DeliveryStatuses { conn })
+    }
+
+    /// Records the status of a message, replacing any previous status.
+    pub fn set_status(&self, status: &DeliveryStatus) -> Result<(), Error> {
+        self.conn
+            .prepare_cached(
+                "INSERT OR REPLACE INTO delivery_status (message_sid, reminder_id, status, error_code, updated_ts) VALUES (?,?,?,?,?)",
+            )
+            .context("failed to create insert statement")?
+            .execute(&[
+                &status.message_sid,
+                &status.reminder_id,
+                &status.status,
+                &status.error_code,
+                &status.updated.timestamp(),
+            ])
+            .context("failed to insert delivery status")?;
+
+        Ok(())
+    }
+
+    /// Gets the statuses of messages sent for the user's reminder, newest
+    /// first.
+    pub fn get_statuses_for_reminder(
+        &self,
+        reminder_id: &str,
+        owner: &str,
+    ) -> Result<Vec<DeliveryStatus>, Error> {
+        let reminder_id = normalize_reminder_id(reminder_id);
+
+        let mut stmt = self
+            .conn
+            .prepare_cached(
+                r"
+                SELECT s.message_sid, s.reminder_id, s.status, s.error_code, s.updated_ts
+                FROM delivery_status AS s
+                INNER JOIN reminders AS r ON r.id = s.reminder_id
+                WHERE s.reminder_id = ? AND r.destination = ?
+                ORDER BY s.updated_ts DESC
+                ",
+            )
+            .context("failed to create select statement")?;
+
+        let vec = stmt
+            .query_map(&[&reminder_id, &owner], |row| DeliveryStatus {
+                message_sid: row.get(0),
+                reminder_id: row.get(1),
+                status: row.get(2),
+                error_code: row.get(3),
+                updated: Utc.timestamp(row.get(4), 0),
+            })
+            .context("failed to execute select query")?
+            .collect::<Result<_, _>>()
+            .context("failed to read results of select query")?;
+
+        Ok(vec)
+    }
+}
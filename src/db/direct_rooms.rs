@@ -0,0 +1,64 @@
+use std::sync::Arc;
+
+use failure::{Error, ResultExt};
+use rusqlite::Connection;
+
+const DIRECT_ROOMS_SCHEMA: &str = r"
+    CREATE TABLE IF NOT EXISTS direct_rooms (
+        user_id TEXT PRIMARY KEY,
+        room_id TEXT NOT NULL
+    );
+";
+
+/// Caches the 1:1 rooms the bot has created for delivering reminders to
+/// users.
+#[derive(Debug, Clone)]
+pub struct DirectRooms {
+    conn: Arc<Connection>,
+}
+
+impl DirectRooms {
+    pub fn with_connection(conn: Arc<Connection>) -> Result<DirectRooms, Error> {
+        conn.execute_batch(DIRECT_ROOMS_SCHEMA)
+            .context("failed to create direct rooms schema")?;
+
+        Ok(DirectRooms { conn })
+    }
+
+    pub fn get_room_for_user(&self, user_id: &str) -> Result<Option<String>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT room_id FROM direct_rooms WHERE user_id = ?")
+            .context("failed to create select statement")?;
+
+        let rows = stmt.query_map(&[&user_id], |row| row.get(0))?;
+
+        for row in rows {
+            return Ok(Some(row?));
+        }
+
+        Ok(None)
+    }
+
+    /// Whether the room is one we deliver reminders to someone in.
+    pub fn is_direct_room(&self, room_id: &str) -> Result<bool, Error> {
+        let count: i64 = self
+            .conn
+            .prepare_cached("SELECT COUNT(*) FROM direct_rooms WHERE room_id = ?")
+            .context("failed to create select statement")?
+            .query_row(&[&room_id], |row| row.get(0))
+            .context("failed to count direct rooms")?;
+
+        Ok(count > 0)
+    }
+
+    pub fn set_room_for_user(&self, user_id: &str, room_id: &str) -> Result<(), Error> {
+        self.conn
+            .prepare_cached("INSERT OR REPLACE INTO direct_rooms (user_id, room_id) VALUES (?, ?)")
+            .context("failed to create insert statement")?
+            .execute(&[&user_id, &room_id])
+            .context("failed to insert direct room")?;
+
+        Ok(())
+    }
+}
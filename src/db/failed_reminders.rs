@@ -0,0 +1,98 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, TimeZone, Utc};
+use failure::{Error, ResultExt};
+use rusqlite::Connection;
+
+const FAILED_REMINDERS_SCHEMA: &str = r"
+    CREATE TABLE IF NOT EXISTS failed_reminders (
+        reminder_id TEXT NOT NULL,
+        due_ts BIGINT NOT NULL,
+        destination TEXT NOT NULL,
+        text TEXT NOT NULL,
+        channel TEXT NOT NULL,
+        attempts INTEGER NOT NULL,
+        error TEXT NOT NULL,
+        failed_ts BIGINT NOT NULL
+    );
+
+    CREATE INDEX IF NOT EXISTS failed_reminders_failed_ts ON failed_reminders(failed_ts);
+";
+
+/// A reminder (or occurrence of a recurring reminder) that we gave up trying
+/// to deliver.
+#[derive(Debug, Clone)]
+pub struct FailedReminder {
+    pub reminder_id: String,
+    pub due: DateTime<Utc>,
+    pub destination: String,
+    pub text: String,
+    pub channel: String,
+    pub attempts: u32,
+    /// The error from the last delivery attempt
+    pub error: String,
+    pub failed_at: DateTime<Utc>,
+}
+
+/// Dead letter queue of reminders that couldn't be delivered.
+#[derive(Debug, Clone)]
+pub struct FailedReminders {
+    conn: Arc<Connection>,
+}
+
+impl FailedReminders {
+    pub fn with_connection(conn: Arc<Connection>) -> Result<FailedReminders, Error> {
+        conn.execute_batch(FAILED_REMINDERS_SCHEMA)
+            .context("failed to create failed reminders schema")?;
+
+        Ok(FailedReminders { conn })
+    }
+
+    pub fn add_failed_reminder(&self, failed: &FailedReminder) -> Result<(), Error> {
+        self.conn
+            .prepare_cached(
+                "INSERT INTO failed_reminders (reminder_id, due_ts, destination, text, channel, attempts, error, failed_ts) VALUES (?,?,?,?,?,?,?,?)",
+            )
+            .context("failed to create insert statement")?
+            .execute(&[
+                &failed.reminder_id,
+                &failed.due.timestamp(),
+                &failed.destination,
+                &failed.text,
+                &failed.channel,
+                &i64::from(failed.attempts),
+                &failed.error,
+                &failed.failed_at.timestamp(),
+            ])
+            .context("failed to insert failed reminder")?;
+
+        Ok(())
+    }
+
+    /// Gets the most recent failures, newest first.
+    pub fn get_recent_failures(&self, limit: u32) -> Result<Vec<FailedReminder>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare_cached(
+                "SELECT reminder_id, due_ts, destination, text, channel, attempts, error, failed_ts FROM failed_reminders ORDER BY failed_ts DESC LIMIT ?",
+            )
+            .context("failed to create select statement")?;
+
+        let vec = stmt
+            .query_map(&[&i64::from(limit)], |row| FailedReminder {
+                reminder_id: row.get(0),
+                due: Utc.timestamp(row.get(1), 0),
+                destination: row.get(2),
+                text: row.get(3),
+                channel: row.get(4),
+                attempts: row.get::<_, i64>(5) as u32,
+                error: row.get(6),
+                failed_at: Utc.timestamp(row.get(7), 0),
+            })
+            .context("failed to execute select query")?
+            .collect::<Result<_, _>>()
+            .context("failed to read results of select query")?;
+
+        Ok(vec)
+    }
+}
@@ -1,5 +1,91 @@
+use failure::{Error, ResultExt};
+use rusqlite::Connection;
+
 mod address_book;
+mod bot_profile;
+mod calendar;
+mod delivery_statuses;
+mod direct_rooms;
+mod failed_reminders;
+mod processed_events;
 mod reminders;
+mod room_state;
+mod rooms;
+mod sessions;
+mod sync_tokens;
+mod user_settings;
+
+pub use self::address_book::{normalize_msisdn, redact_msisdn, AddressBook, Verification};
+pub use self::bot_profile::BotProfile;
+pub use self::calendar::Calendar;
+pub use self::delivery_statuses::{DeliveryStatus, DeliveryStatuses};
+pub use self::direct_rooms::DirectRooms;
+pub use self::failed_reminders::{FailedReminder, FailedReminders};
+pub use self::processed_events::ProcessedEvents;
+pub use self::reminders::{
+    Channel, Reminder, ReminderImage, Reminders, TooManyReminders, DIGEST_ID,
+};
+pub use self::room_state::{RoomConfig, RoomState};
+pub use self::rooms::Rooms;
+pub use self::sessions::Sessions;
+pub use self::sync_tokens::SyncTokens;
+pub use self::user_settings::{DigestSettings, PartOfDay, QuietHours, UserSettings};
+
+/// Adds a column to an existing table if it isn't already there, so that
+/// databases created by older versions pick up new columns.
+fn add_column_if_missing(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<(), Error> {
+    let mut stmt = conn
+        .prepare(&format!("PRAGMA table_info({})", table))
+        .context("failed to create table info statement")?;
+
+    let columns: Vec<String> = stmt
+        .query_map(&[], |row| row.get(1))
+        .context("failed to execute table info query")?
+        .collect::<Result<_, _>>()
+        .context("failed to read results of table info query")?;
+
+    if !columns.iter().any(|c| c == column) {
+        conn.execute_batch(&format!(
+            "ALTER TABLE {} ADD COLUMN {} {}",
+            table, column, definition
+        ))
+        .with_context(|_| format!("failed to add column {} to {}", column, table))?;
+    }
+
+    Ok(())
+}
+
+/// Runs a one off migration for the named part of the schema, if the
+/// database hasn't already been migrated to the given version.
+fn migrate_schema(conn: &Connection, name: &str, version: i64, sql: &str) -> Result<(), Error> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS schema_versions (name TEXT PRIMARY KEY, version BIGINT NOT NULL)",
+    )
+    .context("failed to create schema versions table")?;
+
+    let current: i64 = conn
+        .query_row(
+            "SELECT COALESCE(MAX(version), 0) FROM schema_versions WHERE name = ?",
+            &[&name],
+            |row| row.get(0),
+        )
+        .context("failed to get schema version")?;
+
+    if current < version {
+        conn.execute_batch(sql)
+            .with_context(|_| format!("failed to migrate {} to version {}", name, version))?;
+
+        conn.execute(
+            "INSERT OR REPLACE INTO schema_versions (name, version) VALUES (?, ?)",
+            &[&name, &version],
+        )
+        .context("failed to update schema version")?;
+    }
 
-pub use self::address_book::AddressBook;
-pub use self::reminders::{Reminder, Reminders};
+    Ok(())
+}
@@ -0,0 +1,57 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use failure::{Error, ResultExt};
+use rusqlite::Connection;
+
+const PROCESSED_EVENTS_SCHEMA: &str = r"
+    CREATE TABLE IF NOT EXISTS processed_events (
+        event_id TEXT PRIMARY KEY,
+        processed_ts BIGINT NOT NULL
+    );
+
+    CREATE INDEX IF NOT EXISTS processed_events_processed_ts ON processed_events(processed_ts);
+";
+
+/// Records the Matrix events the bot has already handled, so that events
+/// sent to us again (e.g. after a restart) don't run commands twice.
+#[derive(Debug, Clone)]
+pub struct ProcessedEvents {
+    conn: Arc<Connection>,
+}
+
+impl ProcessedEvents {
+    pub fn with_connection(conn: Arc<Connection>) -> Result<ProcessedEvents, Error> {
+        conn.execute_batch(PROCESSED_EVENTS_SCHEMA)
+            .context("failed to create processed events schema")?;
+
+        Ok(ProcessedEvents { conn })
+    }
+
+    /// Records the event as processed, returning false if it already was.
+    pub fn mark_processed(&self, event_id: &str, now: &DateTime<Utc>) -> Result<bool, Error> {
+        let inserted = self
+            .conn
+            .prepare_cached(
+                "INSERT OR IGNORE INTO processed_events (event_id, processed_ts) VALUES (?, ?)",
+            )
+            .context("failed to create insert statement")?
+            .execute(&[&event_id, &now.timestamp()])
+            .context("failed to insert processed event")?;
+
+        Ok(inserted > 0)
+    }
+
+    /// Forgets events processed before the given time, returning how many
+    /// were removed.
+    pub fn prune_before(&self, before: &DateTime<Utc>) -> Result<usize, Error> {
+        let deleted = self
+            .conn
+            .prepare_cached("DELETE FROM processed_events WHERE processed_ts < ?")
+            .context("failed to create delete statement")?
+            .execute(&[&before.timestamp()])
+            .context("failed to delete processed events")?;
+
+        Ok(deleted as usize)
+    }
+}
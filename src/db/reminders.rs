@@ -1,8 +1,75 @@
+use std::str::FromStr;
 use std::sync::Arc;
 
-use chrono::{DateTime, TimeZone, Utc};
-use failure::{Error, ResultExt};
-use rusqlite::Connection;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use chrono_tz::Tz;
+use failure::{Error, Fail, ResultExt};
+use rand::{thread_rng, Rng};
+use rusqlite::types::ToSql;
+use rusqlite::{self, ffi, Connection, Row};
+
+use super::add_column_if_missing;
+use date::Recurrence;
+
+/// Builds a query selecting the columns expected by `reminder_from_row`.
+macro_rules! select_reminders {
+    ($clause:expr) => {
+        concat!(
+            "SELECT id, due_ts, destination, text, recurrence, room_id, channel, attempts, creator, created_ts, event_id, image_url, image_name, image_mimetype, timezone, nag_interval_secs, parent_id, repeat_until_ts FROM reminders ",
+            $clause
+        )
+    };
+}
+
+/// Characters reminder IDs are made of, leaving out ones that are easily
+/// confused with others (i, l, o and u).
+const ID_ALPHABET: &[u8] = b"0123456789abcdefghjkmnpqrstvwxyz";
+
+const ID_LENGTH: usize = 6;
+
+/// The ID used for the digest when it's delivered like a reminder. It can't
+/// clash with a real reminder, as "i" isn't in the ID alphabet.
+pub const DIGEST_ID: &str = "digest";
+
+/// How many random IDs we try before giving up on finding an unused one.
+const MAX_ID_ATTEMPTS: usize = 10;
+
+/// How a reminder gets delivered to the user.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Channel {
+    Sms,
+    /// Delivered to the room the reminder was created in
+    Matrix,
+    /// Delivered to a 1:1 room with the user
+    Direct,
+    /// Read out in a voice call
+    Call,
+}
+
+impl Channel {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Channel::Sms => "sms",
+            Channel::Matrix => "matrix",
+            Channel::Direct => "dm",
+            Channel::Call => "call",
+        }
+    }
+}
+
+impl FromStr for Channel {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Channel, Error> {
+        match s {
+            "sms" | "text" => Ok(Channel::Sms),
+            "matrix" => Ok(Channel::Matrix),
+            "dm" | "direct" => Ok(Channel::Direct),
+            "call" | "phone" => Ok(Channel::Call),
+            _ => bail!("unknown delivery channel {}", s),
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Reminder {
@@ -10,25 +77,163 @@ pub struct Reminder {
     pub due: DateTime<Utc>,
     pub destination: String,
     pub text: String,
+    pub recurrence: Option<Recurrence>,
+    /// The last time a recurring reminder can fire, e.g. for "every hour for
+    /// the next 6 hours"
+    pub repeat_until: Option<DateTime<Utc>>,
+    /// The room the reminder was created in
+    pub room_id: Option<String>,
+    pub channel: Channel,
+    /// Number of failed delivery attempts for the current occurrence
+    pub attempts: u32,
+    /// The user who created the reminder, which may differ from the
+    /// destination. They're the one who can list, edit and cancel it.
+    pub creator: String,
+    /// None for reminders created before we started recording this
+    pub created: Option<DateTime<Utc>>,
+    /// The message the reminder was created by, so that edits to the
+    /// message can replace it
+    pub event_id: Option<String>,
+    /// Sent along with the reminder when it's delivered in Matrix
+    pub image: Option<ReminderImage>,
+    /// The timezone the reminder was set in, so that repeats keep the same
+    /// local time across DST changes. None for reminders created before we
+    /// started recording this
+    pub timezone: Option<Tz>,
+    /// How often the reminder is sent again after delivery until the user
+    /// says it's done, if at all
+    pub nag_interval: Option<Duration>,
+    /// For a heads up delivered ahead of another reminder, the reminder it's
+    /// for
+    pub parent_id: Option<String>,
+}
+
+/// An image uploaded to the homeserver.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReminderImage {
+    /// The `mxc://` URL of the upload
+    pub url: String,
+    /// Usually the image's file name
+    pub name: String,
+    pub mimetype: Option<String>,
 }
 
 #[derive(Debug, Clone)]
 pub struct Reminders {
     conn: Arc<Connection>,
+    max_pending: u32,
+    /// Whether SQLite has FTS5, otherwise searches fall back to LIKE
+    fts: bool,
 }
 
+/// Returned when a user already has as many pending reminders as they're
+/// allowed.
+#[derive(Fail, Debug)]
+#[fail(display = "too many pending reminders, the limit is {}", _0)]
+pub struct TooManyReminders(pub u32);
+
 impl Reminders {
-    pub fn with_connection(conn: Arc<Connection>) -> Result<Reminders, Error> {
+    /// Opens the reminders table, limiting each user to `max_pending` pending
+    /// reminders.
+    pub fn with_connection(conn: Arc<Connection>, max_pending: u32) -> Result<Reminders, Error> {
         conn.execute_batch(REMINDERS_SCHEMA)
             .context("failed to create reminders schema")?;
 
-        Ok(Reminders { conn })
+        add_column_if_missing(&conn, "reminders", "snoozable", "BOOL NOT NULL DEFAULT 0")?;
+        add_column_if_missing(&conn, "reminders", "recurrence", "TEXT")?;
+        add_column_if_missing(&conn, "reminders", "room_id", "TEXT")?;
+        add_column_if_missing(&conn, "reminders", "channel", "TEXT NOT NULL DEFAULT 'sms'")?;
+        add_column_if_missing(&conn, "reminders", "attempts", "INTEGER NOT NULL DEFAULT 0")?;
+        add_column_if_missing(&conn, "reminders", "retry_ts", "BIGINT")?;
+        add_column_if_missing(&conn, "reminders", "in_flight", "BOOL NOT NULL DEFAULT 0")?;
+        add_column_if_missing(&conn, "reminders", "creator", "TEXT")?;
+        add_column_if_missing(&conn, "reminders", "created_ts", "BIGINT")?;
+        add_column_if_missing(&conn, "reminders", "event_id", "TEXT")?;
+        add_column_if_missing(&conn, "reminders", "image_url", "TEXT")?;
+        add_column_if_missing(&conn, "reminders", "image_name", "TEXT")?;
+        add_column_if_missing(&conn, "reminders", "image_mimetype", "TEXT")?;
+        add_column_if_missing(&conn, "reminders", "timezone", "TEXT")?;
+        add_column_if_missing(&conn, "reminders", "nag_interval_secs", "INTEGER")?;
+        add_column_if_missing(&conn, "reminders", "nagging", "BOOL NOT NULL DEFAULT 0")?;
+        add_column_if_missing(&conn, "reminders", "nags", "INTEGER NOT NULL DEFAULT 0")?;
+        add_column_if_missing(&conn, "reminders", "sent_ts", "BIGINT")?;
+        add_column_if_missing(&conn, "reminders", "completed_ts", "BIGINT")?;
+        add_column_if_missing(&conn, "reminders", "parent_id", "TEXT")?;
+        add_column_if_missing(&conn, "reminders", "repeat_until_ts", "BIGINT")?;
+        add_column_if_missing(&conn, "reminders", "delivered_event_id", "TEXT")?;
+
+        // Until now reminders could only be created for yourself
+        conn.execute_batch("UPDATE reminders SET creator = destination WHERE creator IS NULL")
+            .context("failed to backfill reminder creators")?;
+
+        // Heads ups used to copy the recurrence of the reminder they're for,
+        // but are now queued again whenever it repeats.
+        conn.execute_batch(
+            "UPDATE reminders SET recurrence = NULL, repeat_until_ts = NULL WHERE parent_id IS NOT NULL",
+        )
+        .context("failed to clear heads up recurrences")?;
+
+        // The search index is rebuilt on startup, which picks up any existing
+        // reminders and fixes up rowids that may have changed on VACUUM. Not
+        // every SQLite is built with FTS5, in which case the triggers keeping
+        // the index up to date have to go, or they'd break every write.
+        let fts = conn
+            .execute_batch("CREATE VIRTUAL TABLE temp.fts5_check USING fts5(text); DROP TABLE temp.fts5_check;")
+            .is_ok();
+
+        if fts {
+            conn.execute_batch(REMINDERS_FTS_SCHEMA)
+                .context("failed to create reminders search index")?;
+            conn.execute_batch("INSERT INTO reminders_fts(reminders_fts) VALUES ('rebuild')")
+                .context("failed to rebuild reminders search index")?;
+        } else {
+            conn.execute_batch(DROP_REMINDERS_FTS_TRIGGERS)
+                .context("failed to drop reminders search index triggers")?;
+        }
+
+        Ok(Reminders {
+            conn,
+            max_pending,
+            fts,
+        })
     }
 
-    pub fn add_reminder(&self, reminder: &Reminder) -> Result<(), Error> {
-        self.conn
+    /// Stores a new reminder, assigning it an unused short ID. Fails with
+    /// `TooManyReminders` if the creator already has too many pending.
+    pub fn add_reminder(&self, reminder: &mut Reminder) -> Result<(), Error> {
+        if self.count_pending_reminders(&reminder.creator)? >= self.max_pending {
+            return Err(TooManyReminders(self.max_pending).into());
+        }
+
+        for _ in 0..MAX_ID_ATTEMPTS {
+            reminder.id = generate_reminder_id();
+
+            if self.insert_reminder(reminder)? {
+                return Ok(());
+            }
+        }
+
+        bail!("failed to find an unused reminder ID")
+    }
+
+    /// Counts the unsent reminders the user has created.
+    fn count_pending_reminders(&self, creator: &str) -> Result<u32, Error> {
+        let count: i64 = self
+            .conn
+            .prepare_cached("SELECT COUNT(*) FROM reminders WHERE creator = ? AND NOT sent")
+            .context("failed to create count statement")?
+            .query_row(&[&creator], |row| row.get(0))
+            .context("failed to count reminders")?;
+
+        Ok(count as u32)
+    }
+
+    /// Inserts the reminder, returning false if its ID is already taken.
+    fn insert_reminder(&self, reminder: &Reminder) -> Result<bool, Error> {
+        let res = self
+            .conn
             .prepare_cached(
-                "INSERT INTO reminders (id, due_ts, destination, text, sent) VALUES (?,?,?,?,?)",
+                "INSERT INTO reminders (id, due_ts, destination, text, sent, recurrence, room_id, channel, creator, created_ts, event_id, image_url, image_name, image_mimetype, timezone, nag_interval_secs, parent_id, repeat_until_ts) VALUES (?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?)",
             )
             .context("failed to create insert statement")?
             .execute(&[
@@ -37,38 +242,790 @@ impl Reminders {
                 &reminder.destination,
                 &reminder.text,
                 &false,
-            ])
-            .context("failed to insert query")?;
+                &reminder.recurrence.as_ref().map(Recurrence::to_spec),
+                &reminder.room_id,
+                &reminder.channel.as_str(),
+                &reminder.creator,
+                &reminder.created.map(|created| created.timestamp()),
+                &reminder.event_id,
+                &reminder.image.as_ref().map(|image| &image.url),
+                &reminder.image.as_ref().map(|image| &image.name),
+                &reminder.image.as_ref().and_then(|image| image.mimetype.as_ref()),
+                &reminder.timezone.map(|tz| tz.name()),
+                &reminder.nag_interval.map(|interval| interval.num_seconds()),
+                &reminder.parent_id,
+                &reminder.repeat_until.map(|until| until.timestamp()),
+            ]);
 
-        Ok(())
+        match res {
+            Ok(_) => Ok(true),
+            Err(rusqlite::Error::SqliteFailure(ref err, _))
+                if err.extended_code == ffi::SQLITE_CONSTRAINT_PRIMARYKEY =>
+            {
+                Ok(false)
+            }
+            Err(err) => Err(err.context("failed to insert query").into()),
+        }
     }
 
+    /// Gets the reminders that are ready to be delivered. Reminders for
+    /// users who have paused delivery are held back until they resume, and
+    /// those for users who get a daily digest are left for the digest (unless
+    /// they're for a room, or were created too late for today's digest).
     pub fn get_reminders_before(&self, now: &DateTime<Utc>) -> Result<Vec<Reminder>, Error> {
-        let mut stmt = self.conn
-            .prepare_cached("SELECT id, due_ts, destination, text FROM reminders WHERE due_ts <= ? AND NOT sent")
+        let mut stmt = self
+            .conn
+            .prepare_cached(select_reminders!(
+                r"
+                WHERE COALESCE(retry_ts, due_ts) <= ? AND NOT sent AND NOT in_flight
+                AND destination NOT IN (SELECT user_id FROM user_settings WHERE paused)
+                AND (
+                    channel = 'matrix'
+                    OR destination NOT IN (
+                        SELECT user_id FROM user_settings WHERE digest_time IS NOT NULL
+                        AND NOT (
+                            reminders.created_ts > last_digest_ts
+                            AND reminders.due_ts < last_digest_until_ts
+                        )
+                    )
+                )
+                "
+            ))
             .context("failed to create select statement")?;
 
-        let vec =
-            stmt.query_map(&[&now.timestamp()], |row| Reminder {
-                id: row.get(0),
-                due: Utc.timestamp(row.get(1), 0),
-                destination: row.get(2),
-                text: row.get(3),
-            }).context("failed to execute select query")?
-                .collect::<Result<_, _>>()
-                .context("failed to read results of query")?;
+        let vec = stmt
+            .query_map(&[&now.timestamp()], reminder_from_row)
+            .context("failed to execute select query")?
+            .collect::<Result<_, _>>()
+            .context("failed to read results of query")?;
 
         Ok(vec)
     }
 
+    /// Gets the user's pending reminders due before the given time that
+    /// should go in their digest, oldest first.
+    pub fn get_digest_reminders(
+        &self,
+        user_id: &str,
+        before: &DateTime<Utc>,
+    ) -> Result<Vec<Reminder>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare_cached(select_reminders!(
+                "WHERE destination = ? AND due_ts < ? AND NOT sent AND NOT in_flight AND channel != 'matrix' ORDER BY due_ts"
+            ))
+            .context("failed to create select statement")?;
+
+        let vec = stmt
+            .query_map(&[&user_id, &before.timestamp()], reminder_from_row)
+            .context("failed to execute select query")?
+            .collect::<Result<_, _>>()
+            .context("failed to read results of query")?;
+
+        Ok(vec)
+    }
+
+    pub fn get_reminders_for_user(&self, user_id: &str) -> Result<Vec<Reminder>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare_cached(select_reminders!(
+                "WHERE creator = ? AND NOT sent ORDER BY due_ts"
+            ))
+            .context("failed to create select statement")?;
+
+        let vec = stmt
+            .query_map(&[&user_id], reminder_from_row)
+            .context("failed to execute select query")?
+            .collect::<Result<_, _>>()
+            .context("failed to read results of query")?;
+
+        Ok(vec)
+    }
+
+    /// Gets every user's pending reminders, soonest first.
+    pub fn get_all_pending_reminders(&self) -> Result<Vec<Reminder>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare_cached(select_reminders!("WHERE NOT sent ORDER BY due_ts"))
+            .context("failed to create select statement")?;
+
+        let vec = stmt
+            .query_map(&[], reminder_from_row)
+            .context("failed to execute select query")?
+            .collect::<Result<_, _>>()
+            .context("failed to read results of query")?;
+
+        Ok(vec)
+    }
+
+    /// Finds the user's pending reminders containing words starting with
+    /// each of the words in the query.
+    pub fn search_reminders(&self, user_id: &str, query: &str) -> Result<Vec<Reminder>, Error> {
+        if !self.fts {
+            return self.search_reminders_like(user_id, query);
+        }
+
+        let mut stmt = self
+            .conn
+            .prepare_cached(select_reminders!(
+                r"
+                WHERE creator = ? AND NOT sent AND rowid IN (
+                    SELECT rowid FROM reminders_fts WHERE reminders_fts MATCH ?
+                )
+                ORDER BY due_ts
+                "
+            ))
+            .context("failed to create select statement")?;
+
+        let vec = stmt
+            .query_map(&[&user_id, &fts_query(query)], reminder_from_row)
+            .context("failed to execute select query")?
+            .collect::<Result<_, _>>()
+            .context("failed to read results of query")?;
+
+        Ok(vec)
+    }
+
+    /// Finds the user's pending reminders containing each of the words in
+    /// the query anywhere, for when there's no search index.
+    fn search_reminders_like(&self, user_id: &str, query: &str) -> Result<Vec<Reminder>, Error> {
+        let patterns = like_patterns(query);
+
+        let mut sql = select_reminders!("WHERE creator = ? AND NOT sent").to_string();
+        for _ in &patterns {
+            sql.push_str(r" AND text LIKE ? ESCAPE '\'");
+        }
+        sql.push_str(" ORDER BY due_ts");
+
+        let mut params = vec![&user_id as &ToSql];
+        params.extend(patterns.iter().map(|pattern| pattern as &ToSql));
+
+        let mut stmt = self
+            .conn
+            .prepare(&sql)
+            .context("failed to create select statement")?;
+
+        let vec = stmt
+            .query_map(&params, reminder_from_row)
+            .context("failed to execute select query")?
+            .collect::<Result<_, _>>()
+            .context("failed to read results of query")?;
+
+        Ok(vec)
+    }
+
+    /// Cancels a pending reminder along with its heads up, returning false if
+    /// no pending reminder with that ID belongs to the owner.
+    pub fn cancel_reminder(&self, id: &str, owner: &str) -> Result<bool, Error> {
+        let id = normalize_reminder_id(id);
+
+        let changed = self
+            .conn
+            .prepare_cached(
+                "DELETE FROM reminders WHERE (id = ? OR parent_id = ?) AND creator = ? AND NOT sent",
+            )
+            .context("failed to create delete statement")?
+            .execute(&[&id, &id, &owner])
+            .context("failed to delete reminder")?;
+
+        Ok(changed > 0)
+    }
+
+    /// Updates the text of a pending reminder, returning false if no pending
+    /// reminder with that ID belongs to the owner.
+    pub fn update_reminder_text(&self, id: &str, owner: &str, text: &str) -> Result<bool, Error> {
+        let id = normalize_reminder_id(id);
+
+        let changed = self
+            .conn
+            .prepare_cached(
+                "UPDATE reminders SET text = ? WHERE id = ? AND creator = ? AND NOT sent",
+            )
+            .context("failed to create update statement")?
+            .execute(&[&text, &id, &owner])
+            .context("failed to update reminder")?;
+
+        Ok(changed > 0)
+    }
+
+    /// Changes when a pending reminder is due, returning false if no pending
+    /// reminder with that ID belongs to the owner. Its heads up moves along
+    /// with it.
+    pub fn reschedule_reminder(
+        &self,
+        id: &str,
+        owner: &str,
+        due: &DateTime<Utc>,
+    ) -> Result<bool, Error> {
+        let id = normalize_reminder_id(id);
+
+        if self.get_pending_reminder(&id, owner)?.is_none() {
+            return Ok(false);
+        }
+
+        self.shift_heads_ups(&id, due)?;
+
+        let changed = self
+            .conn
+            .prepare_cached(
+                "UPDATE reminders SET due_ts = ?, retry_ts = NULL, nagging = 0, nags = 0 WHERE id = ? AND creator = ? AND NOT sent",
+            )
+            .context("failed to create update statement")?
+            .execute(&[&due.timestamp(), &id, &owner])
+            .context("failed to update reminder")?;
+
+        Ok(changed > 0)
+    }
+
+    /// Cancels all of the owner's pending reminders, returning how many were
+    /// removed.
+    pub fn cancel_all_reminders(&self, owner: &str) -> Result<usize, Error> {
+        let changed = self
+            .conn
+            .prepare_cached("DELETE FROM reminders WHERE creator = ? AND NOT sent")
+            .context("failed to create delete statement")?
+            .execute(&[&owner])
+            .context("failed to delete reminders")?;
+
+        Ok(changed as usize)
+    }
+
+    /// Marks the reminder as sent. The row is kept around so that the
+    /// reminder can be snoozed after delivery.
     pub fn delete_reminder(&self, id: &str) -> Result<(), Error> {
         self.conn
-            .prepare_cached("UPDATE reminders SET sent = ? WHERE id = ?")
+            .prepare_cached(
+                "UPDATE reminders SET sent = ?, snoozable = ?, attempts = 0, retry_ts = NULL, in_flight = 0, nagging = 0, nags = 0 WHERE id = ?",
+            )
             .context("failed to create delete statement")?
-            .execute(&[&true, &id])?;
+            .execute(&[&true, &true, &id])?;
+
+        Ok(())
+    }
+
+    /// Moves a recurring reminder on to its next occurrence.
+    pub fn reschedule_recurring_reminder(
+        &self,
+        id: &str,
+        due: &DateTime<Utc>,
+    ) -> Result<(), Error> {
+        self.shift_heads_ups(id, due)?;
+
+        self.conn
+            .prepare_cached(
+                "UPDATE reminders SET due_ts = ?, attempts = 0, retry_ts = NULL, in_flight = 0, nagging = 0, nags = 0 WHERE id = ?",
+            )
+            .context("failed to create update statement")?
+            .execute(&[&due.timestamp(), &id])
+            .context("failed to reschedule reminder")?;
+
+        Ok(())
+    }
+
+    /// Queues a delivered reminder to be sent again at `nag_at` as it hasn't
+    /// been acknowledged yet. Recurring reminders move on to their next
+    /// occurrence at the same time, which acknowledging them leaves them at.
+    /// Returns false, without changing anything, if the reminder has already
+    /// nagged `max_nags` times.
+    pub fn nag_reminder(
+        &self,
+        id: &str,
+        next_due: Option<DateTime<Utc>>,
+        nag_at: &DateTime<Utc>,
+        max_nags: u32,
+    ) -> Result<bool, Error> {
+        let nags: i64 = self
+            .conn
+            .prepare_cached("SELECT nags FROM reminders WHERE id = ?")
+            .context("failed to create select statement")?
+            .query_row(&[&id], |row| row.get(0))
+            .context("failed to get nag count")?;
+
+        if nags >= i64::from(max_nags) {
+            return Ok(false);
+        }
+
+        if let Some(ref next_due) = next_due {
+            self.shift_heads_ups(id, next_due)?;
+        }
+
+        self.conn
+            .prepare_cached(
+                "UPDATE reminders SET due_ts = COALESCE(?, due_ts), nagging = 1, nags = nags + 1, attempts = 0, retry_ts = ?, in_flight = 0 WHERE id = ?",
+            )
+            .context("failed to create update statement")?
+            .execute(&[
+                &next_due.map(|due| due.timestamp()),
+                &nag_at.timestamp(),
+                &id,
+            ])
+            .context("failed to queue nag")?;
+
+        Ok(true)
+    }
+
+    /// Moves the heads ups for a reminder that's about to move to `due` by
+    /// the same amount, so they stay the same time ahead of it. Heads ups
+    /// that have been sent are queued again for the new time, e.g. for a
+    /// recurring reminder's next occurrence, unless that's already passed.
+    ///
+    /// This needs to be called before the reminder itself is updated.
+    fn shift_heads_ups(&self, id: &str, due: &DateTime<Utc>) -> Result<(), Error> {
+        self.conn
+            .prepare_cached(
+                r"
+                UPDATE reminders SET
+                    sent = due_ts + ?1 - (SELECT due_ts FROM reminders WHERE id = ?2) <= ?3,
+                    due_ts = due_ts + ?1 - (SELECT due_ts FROM reminders WHERE id = ?2),
+                    attempts = 0, retry_ts = NULL, in_flight = 0
+                WHERE parent_id = ?2
+                ",
+            )
+            .context("failed to create update statement")?
+            .execute(&[&due.timestamp(), &id, &Utc::now().timestamp()])
+            .context("failed to reschedule heads up")?;
+
+        Ok(())
+    }
+
+    /// Records when the reminder was last delivered, which it can be marked
+    /// as done after.
+    pub fn mark_delivered(&self, id: &str, at: &DateTime<Utc>) -> Result<(), Error> {
+        self.conn
+            .prepare_cached("UPDATE reminders SET sent_ts = ? WHERE id = ?")
+            .context("failed to create update statement")?
+            .execute(&[&at.timestamp(), &id])
+            .context("failed to mark reminder delivered")?;
+
+        Ok(())
+    }
+
+    /// Marks the owner's last delivery of the reminder as done, returning
+    /// false if it hasn't been delivered since it was last marked. This also
+    /// stops a nagging reminder from being sent again, after which one off
+    /// reminders count as sent and recurring ones stay scheduled for their
+    /// next occurrence.
+    pub fn mark_reminder_done(
+        &self,
+        id: &str,
+        owner: &str,
+        at: &DateTime<Utc>,
+    ) -> Result<bool, Error> {
+        let id = normalize_reminder_id(id);
+
+        let changed = self
+            .conn
+            .prepare_cached(
+                r"
+                UPDATE reminders SET
+                    completed_ts = ?,
+                    sent = sent OR (nagging AND recurrence IS NULL),
+                    snoozable = 0,
+                    attempts = CASE WHEN nagging THEN 0 ELSE attempts END,
+                    retry_ts = CASE WHEN nagging THEN NULL ELSE retry_ts END,
+                    nagging = 0,
+                    nags = 0
+                WHERE id = ? AND destination = ? AND sent_ts IS NOT NULL
+                AND (completed_ts IS NULL OR completed_ts < sent_ts)
+                ",
+            )
+            .context("failed to create update statement")?
+            .execute(&[&at.timestamp(), &id, &owner])
+            .context("failed to mark reminder done")?;
+
+        Ok(changed > 0)
+    }
+
+    /// Gets the user's reminders delivered since the given time that they
+    /// haven't marked as done, oldest first.
+    pub fn get_undone_reminders(
+        &self,
+        user_id: &str,
+        since: &DateTime<Utc>,
+    ) -> Result<Vec<Reminder>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare_cached(select_reminders!(
+                "WHERE destination = ? AND sent_ts >= ? AND (completed_ts IS NULL OR completed_ts < sent_ts) ORDER BY sent_ts"
+            ))
+            .context("failed to create select statement")?;
+
+        let vec = stmt
+            .query_map(&[&user_id, &since.timestamp()], reminder_from_row)
+            .context("failed to execute select query")?
+            .collect::<Result<_, _>>()
+            .context("failed to read results of query")?;
+
+        Ok(vec)
+    }
+
+    /// Marks a reminder as currently being delivered, so that it isn't
+    /// picked up again until the attempt completes.
+    pub fn mark_in_flight(&self, id: &str) -> Result<(), Error> {
+        self.conn
+            .prepare_cached("UPDATE reminders SET in_flight = 1 WHERE id = ?")
+            .context("failed to create update statement")?
+            .execute(&[&id])
+            .context("failed to mark reminder in flight")?;
 
         Ok(())
     }
+
+    /// Requeues any reminders left in flight, e.g. due to crashing mid
+    /// delivery. Returns the number of reminders requeued.
+    pub fn recover_in_flight(&self) -> Result<usize, Error> {
+        let changed = self
+            .conn
+            .prepare_cached("UPDATE reminders SET in_flight = 0 WHERE in_flight")
+            .context("failed to create update statement")?
+            .execute(&[])
+            .context("failed to recover in flight reminders")?;
+
+        Ok(changed as usize)
+    }
+
+    /// Records a failed delivery attempt, queuing the reminder to be retried
+    /// at the given time.
+    pub fn retry_reminder(
+        &self,
+        id: &str,
+        attempts: u32,
+        retry_at: &DateTime<Utc>,
+    ) -> Result<(), Error> {
+        self.conn
+            .prepare_cached(
+                "UPDATE reminders SET sent = ?, snoozable = ?, attempts = ?, retry_ts = ?, in_flight = 0 WHERE id = ?",
+            )
+            .context("failed to create update statement")?
+            .execute(&[
+                &false,
+                &false,
+                &i64::from(attempts),
+                &retry_at.timestamp(),
+                &id,
+            ])
+            .context("failed to record delivery attempt")?;
+
+        Ok(())
+    }
+
+    /// Holds back a reminder until the given time without counting it as a
+    /// failed attempt, e.g. during the user's quiet hours.
+    pub fn defer_reminder(&self, id: &str, until: &DateTime<Utc>) -> Result<(), Error> {
+        self.conn
+            .prepare_cached("UPDATE reminders SET retry_ts = ?, in_flight = 0 WHERE id = ?")
+            .context("failed to create update statement")?
+            .execute(&[&until.timestamp(), &id])
+            .context("failed to defer reminder")?;
+
+        Ok(())
+    }
+
+    /// Removes a reminder entirely along with its heads up, e.g. once it has
+    /// been moved to the failed reminders table.
+    pub fn remove_reminder(&self, id: &str) -> Result<(), Error> {
+        self.conn
+            .prepare_cached("DELETE FROM reminders WHERE id = ? OR parent_id = ?")
+            .context("failed to create delete statement")?
+            .execute(&[&id, &id])
+            .context("failed to remove reminder")?;
+
+        Ok(())
+    }
+
+    /// Gets the most recently delivered reminder for the user that can still
+    /// be snoozed.
+    pub fn get_last_snoozable_reminder(&self, user_id: &str) -> Result<Option<Reminder>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare_cached(select_reminders!(
+                "WHERE destination = ? AND sent AND snoozable ORDER BY due_ts DESC LIMIT 1"
+            ))
+            .context("failed to create select statement")?;
+
+        let rows = stmt
+            .query_map(&[&user_id], reminder_from_row)
+            .context("failed to execute select query")?;
+
+        for row in rows {
+            return Ok(Some(row?));
+        }
+
+        Ok(None)
+    }
+
+    /// Records the Matrix message the reminder was delivered in, so that
+    /// reactions to it can be matched up with the reminder.
+    pub fn set_delivered_event(&self, id: &str, event_id: &str) -> Result<(), Error> {
+        self.conn
+            .prepare_cached("UPDATE reminders SET delivered_event_id = ? WHERE id = ?")
+            .context("failed to create update statement")?
+            .execute(&[&event_id, &id])
+            .context("failed to set delivered event")?;
+
+        Ok(())
+    }
+
+    /// Gets the user's reminder that was delivered in the given message, if
+    /// it can still be snoozed.
+    pub fn get_snoozable_reminder_for_event(
+        &self,
+        event_id: &str,
+        user_id: &str,
+    ) -> Result<Option<Reminder>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare_cached(select_reminders!(
+                "WHERE delivered_event_id = ? AND destination = ? AND sent AND snoozable"
+            ))
+            .context("failed to create select statement")?;
+
+        let mut rows = stmt
+            .query_map(&[&event_id, &user_id], reminder_from_row)
+            .context("failed to execute select query")?;
+
+        match rows.next() {
+            Some(row) => Ok(Some(row?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Gets the reminder most recently delivered to the user, whether or not
+    /// it's done.
+    pub fn get_last_delivered_reminder(&self, user_id: &str) -> Result<Option<Reminder>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare_cached(select_reminders!(
+                "WHERE destination = ? AND sent_ts IS NOT NULL ORDER BY sent_ts DESC LIMIT 1"
+            ))
+            .context("failed to create select statement")?;
+
+        let rows = stmt
+            .query_map(&[&user_id], reminder_from_row)
+            .context("failed to execute select query")?;
+
+        for row in rows {
+            return Ok(Some(row?));
+        }
+
+        Ok(None)
+    }
+
+    /// Gets the most recent reminder the user created since the given time,
+    /// if it hasn't been sent yet.
+    pub fn get_last_created_reminder(
+        &self,
+        creator: &str,
+        since: &DateTime<Utc>,
+    ) -> Result<Option<Reminder>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare_cached(select_reminders!(
+                "WHERE creator = ? AND created_ts >= ? AND NOT sent ORDER BY created_ts DESC LIMIT 1"
+            ))
+            .context("failed to create select statement")?;
+
+        let rows = stmt
+            .query_map(&[&creator, &since.timestamp()], reminder_from_row)
+            .context("failed to execute select query")?;
+
+        for row in rows {
+            return Ok(Some(row?));
+        }
+
+        Ok(None)
+    }
+
+    /// Gets the pending reminder created by the given message.
+    pub fn get_reminder_for_event(&self, event_id: &str) -> Result<Option<Reminder>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare_cached(select_reminders!("WHERE event_id = ? AND NOT sent"))
+            .context("failed to create select statement")?;
+
+        let rows = stmt
+            .query_map(&[&event_id], reminder_from_row)
+            .context("failed to execute select query")?;
+
+        for row in rows {
+            return Ok(Some(row?));
+        }
+
+        Ok(None)
+    }
+
+    /// Gets the reminder created by the event, if it has already been sent.
+    pub fn get_sent_reminder_for_event(&self, event_id: &str) -> Result<Option<Reminder>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare_cached(select_reminders!("WHERE event_id = ? AND sent"))
+            .context("failed to create select statement")?;
+
+        let rows = stmt
+            .query_map(&[&event_id], reminder_from_row)
+            .context("failed to execute select query")?;
+
+        for row in rows {
+            return Ok(Some(row?));
+        }
+
+        Ok(None)
+    }
+
+    /// Runs `f` in a transaction, so none of its changes are kept if it
+    /// fails.
+    pub fn in_transaction<T, F>(&self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce() -> Result<T, Error>,
+    {
+        // A savepoint rather than a `Transaction`, as that needs a mutable
+        // connection.
+        self.conn
+            .execute_batch("SAVEPOINT reminders_txn")
+            .context("failed to start transaction")?;
+
+        match f() {
+            Ok(value) => {
+                self.conn
+                    .execute_batch("RELEASE reminders_txn")
+                    .context("failed to commit transaction")?;
+                Ok(value)
+            }
+            Err(err) => {
+                self.conn
+                    .execute_batch("ROLLBACK TO reminders_txn; RELEASE reminders_txn")
+                    .context("failed to roll back transaction")?;
+                Err(err)
+            }
+        }
+    }
+
+    /// Gets one of the owner's pending reminders by its ID.
+    pub fn get_pending_reminder(&self, id: &str, owner: &str) -> Result<Option<Reminder>, Error> {
+        let id = normalize_reminder_id(id);
+
+        let mut stmt = self
+            .conn
+            .prepare_cached(select_reminders!(
+                "WHERE id = ? AND creator = ? AND NOT sent"
+            ))
+            .context("failed to create select statement")?;
+
+        let rows = stmt
+            .query_map(&[&id, &owner], reminder_from_row)
+            .context("failed to execute select query")?;
+
+        for row in rows {
+            return Ok(Some(row?));
+        }
+
+        Ok(None)
+    }
+
+    /// Attaches the image to the reminder, replacing any it already had.
+    pub fn set_image(&self, id: &str, image: &ReminderImage) -> Result<(), Error> {
+        self.conn
+            .prepare_cached(
+                "UPDATE reminders SET image_url = ?, image_name = ?, image_mimetype = ? WHERE id = ?",
+            )
+            .context("failed to create update statement")?
+            .execute(&[&image.url, &image.name, &image.mimetype, &id])
+            .context("failed to set reminder image")?;
+
+        Ok(())
+    }
+
+    /// Requeues a delivered reminder to be sent again at the given time.
+    pub fn snooze_reminder(&self, id: &str, due: &DateTime<Utc>) -> Result<(), Error> {
+        self.conn
+            .prepare_cached(
+                "UPDATE reminders SET due_ts = ?, sent = ?, snoozable = ? WHERE id = ? AND snoozable",
+            )
+            .context("failed to create update statement")?
+            .execute(&[&due.timestamp(), &false, &false, &id])
+            .context("failed to snooze reminder")?;
+
+        Ok(())
+    }
+}
+
+/// Turns a user's search into an FTS query matching each word as a prefix,
+/// quoting them so that FTS syntax in the search is treated literally.
+fn fts_query(input: &str) -> String {
+    let terms: Vec<String> = input
+        .split_whitespace()
+        .map(|word| format!("\"{}\"*", word.replace('"', "\"\"")))
+        .collect();
+
+    terms.join(" ")
+}
+
+/// Turns a user's search into LIKE patterns matching each word anywhere,
+/// escaping LIKE's wildcards.
+fn like_patterns(input: &str) -> Vec<String> {
+    input
+        .split_whitespace()
+        .map(|word| {
+            let escaped = word
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_");
+            format!("%{}%", escaped)
+        })
+        .collect()
+}
+
+fn generate_reminder_id() -> String {
+    let mut rng = thread_rng();
+
+    (0..ID_LENGTH)
+        .map(|_| ID_ALPHABET[rng.gen_range(0, ID_ALPHABET.len())] as char)
+        .collect()
+}
+
+/// Normalizes a reminder ID typed by a user, e.g. "AB1O2L" to "ab1021".
+/// Older reminders have longer, case sensitive IDs, which are left alone.
+pub fn normalize_reminder_id(id: &str) -> String {
+    if id.len() != ID_LENGTH {
+        return id.to_string();
+    }
+
+    id.chars()
+        .map(|c| match c.to_ascii_lowercase() {
+            'o' => '0',
+            'i' | 'l' => '1',
+            c => c,
+        })
+        .collect()
+}
+
+fn reminder_from_row(row: &Row) -> Reminder {
+    Reminder {
+        id: row.get(0),
+        due: Utc.timestamp(row.get(1), 0),
+        destination: row.get(2),
+        text: row.get(3),
+        recurrence: row
+            .get::<_, Option<String>>(4)
+            .and_then(|spec| Recurrence::from_spec(&spec).ok()),
+        room_id: row.get(5),
+        channel: row.get::<_, String>(6).parse().unwrap_or(Channel::Sms),
+        attempts: row.get::<_, i64>(7) as u32,
+        creator: row.get(8),
+        created: row.get::<_, Option<i64>>(9).map(|ts| Utc.timestamp(ts, 0)),
+        event_id: row.get(10),
+        image: row.get::<_, Option<String>>(11).map(|url| ReminderImage {
+            url,
+            name: row.get::<_, Option<String>>(12).unwrap_or_default(),
+            mimetype: row.get(13),
+        }),
+        timezone: row
+            .get::<_, Option<String>>(14)
+            .and_then(|tz| tz.parse::<Tz>().ok()),
+        nag_interval: row.get::<_, Option<i64>>(15).map(Duration::seconds),
+        parent_id: row.get(16),
+        repeat_until: row.get::<_, Option<i64>>(17).map(|ts| Utc.timestamp(ts, 0)),
+    }
 }
 
 const REMINDERS_SCHEMA: &str = r"
@@ -77,8 +1034,360 @@ const REMINDERS_SCHEMA: &str = r"
         due_ts BIGINT NOT NULL,
         destination TEXT NOT NULL,
         text NOT NULL,
-        sent BOOL NOT NULL
+        sent BOOL NOT NULL,
+        snoozable BOOL NOT NULL DEFAULT 0,
+        recurrence TEXT,
+        room_id TEXT,
+        channel TEXT NOT NULL DEFAULT 'sms',
+        attempts INTEGER NOT NULL DEFAULT 0,
+        retry_ts BIGINT,
+        in_flight BOOL NOT NULL DEFAULT 0,
+        creator TEXT,
+        created_ts BIGINT,
+        event_id TEXT,
+        image_url TEXT,
+        image_name TEXT,
+        image_mimetype TEXT,
+        timezone TEXT,
+        nag_interval_secs INTEGER,
+        nagging BOOL NOT NULL DEFAULT 0,
+        nags INTEGER NOT NULL DEFAULT 0,
+        sent_ts BIGINT,
+        completed_ts BIGINT,
+        parent_id TEXT,
+        repeat_until_ts BIGINT,
+        delivered_event_id TEXT
     );
 
     CREATE INDEX IF NOT EXISTS reminders_ts ON reminders (due_ts, sent);
 ";
+
+/// Full text index of reminder text, kept in sync with the reminders table
+/// by triggers.
+const REMINDERS_FTS_SCHEMA: &str = r"
+    CREATE VIRTUAL TABLE IF NOT EXISTS reminders_fts USING fts5(
+        text,
+        content = 'reminders',
+        content_rowid = 'rowid'
+    );
+
+    CREATE TRIGGER IF NOT EXISTS reminders_fts_insert AFTER INSERT ON reminders BEGIN
+        INSERT INTO reminders_fts (rowid, text) VALUES (new.rowid, new.text);
+    END;
+
+    CREATE TRIGGER IF NOT EXISTS reminders_fts_delete AFTER DELETE ON reminders BEGIN
+        INSERT INTO reminders_fts (reminders_fts, rowid, text) VALUES ('delete', old.rowid, old.text);
+    END;
+
+    CREATE TRIGGER IF NOT EXISTS reminders_fts_update AFTER UPDATE OF text ON reminders BEGIN
+        INSERT INTO reminders_fts (reminders_fts, rowid, text) VALUES ('delete', old.rowid, old.text);
+        INSERT INTO reminders_fts (rowid, text) VALUES (new.rowid, new.text);
+    END;
+";
+
+const DROP_REMINDERS_FTS_TRIGGERS: &str = r"
+    DROP TRIGGER IF EXISTS reminders_fts_insert;
+    DROP TRIGGER IF EXISTS reminders_fts_delete;
+    DROP TRIGGER IF EXISTS reminders_fts_update;
+";
+
+#[test]
+fn reminder_id_test() {
+    let id = generate_reminder_id();
+    assert_eq!(id.len(), ID_LENGTH);
+    assert!(id.bytes().all(|c| ID_ALPHABET.contains(&c)));
+
+    assert_eq!(normalize_reminder_id("AB1O2L"), "ab1021");
+    assert_eq!(normalize_reminder_id("x7k2m9"), "x7k2m9");
+    assert_eq!(
+        normalize_reminder_id("Ab3dEf7hIjKlMnOpQrSt"),
+        "Ab3dEf7hIjKlMnOpQrSt"
+    );
+}
+
+#[test]
+fn fts_query_test() {
+    assert_eq!(fts_query("groceries"), r#""groceries"*"#);
+    assert_eq!(fts_query(" buy  milk "), r#""buy"* "milk"*"#);
+    assert_eq!(fts_query(r#"say "hi" OR"#), r#""say"* """hi"""* "OR"*"#);
+}
+
+#[test]
+fn like_patterns_test() {
+    assert_eq!(like_patterns("groceries"), vec!["%groceries%"]);
+    assert_eq!(like_patterns(" buy  milk "), vec!["%buy%", "%milk%"]);
+    assert_eq!(like_patterns(r"50% off_\"), vec![r"%50\%%", r"%off\_\\%"]);
+}
+
+#[test]
+fn recurring_heads_up_test() {
+    let conn = Arc::new(Connection::open_in_memory().unwrap());
+    let reminders = Reminders::with_connection(conn, 10).unwrap();
+
+    // Every week, with a heads up a day before
+    let first_due = Utc::now() + Duration::days(2);
+    let week = Duration::weeks(1);
+
+    let mut reminder = Reminder {
+        id: String::new(),
+        due: first_due,
+        destination: "@alice:example.com".to_string(),
+        text: "Bins".to_string(),
+        recurrence: Some(Recurrence::Interval(week)),
+        repeat_until: None,
+        room_id: None,
+        channel: Channel::Matrix,
+        attempts: 0,
+        creator: "@alice:example.com".to_string(),
+        created: Some(Utc::now()),
+        event_id: None,
+        image: None,
+        timezone: None,
+        nag_interval: None,
+        parent_id: None,
+    };
+    reminders.add_reminder(&mut reminder).unwrap();
+
+    let mut heads_up = Reminder {
+        due: first_due - Duration::days(1),
+        recurrence: None,
+        parent_id: Some(reminder.id.clone()),
+        ..reminder.clone()
+    };
+    reminders.add_reminder(&mut heads_up).unwrap();
+
+    for occurrence in 1..3 {
+        // The heads up goes off, then the reminder itself
+        reminders.delete_reminder(&heads_up.id).unwrap();
+        assert!(reminders
+            .get_pending_reminder(&heads_up.id, &heads_up.creator)
+            .unwrap()
+            .is_none());
+
+        let next_due = first_due + week * occurrence;
+        reminders
+            .reschedule_recurring_reminder(&reminder.id, &next_due)
+            .unwrap();
+
+        let pending = reminders
+            .get_pending_reminder(&heads_up.id, &heads_up.creator)
+            .unwrap()
+            .expect("heads up should be queued again");
+        assert_eq!(
+            pending.due.timestamp(),
+            (next_due - Duration::days(1)).timestamp()
+        );
+        assert!(pending.recurrence.is_none());
+    }
+}
+
+#[test]
+fn mark_reminder_done_test() {
+    let conn = Arc::new(Connection::open_in_memory().unwrap());
+    let reminders = Reminders::with_connection(conn, 10).unwrap();
+
+    let start = Utc.ymd(2014, 7, 8).and_hms(9, 0, 0);
+    let owner = "@alice:example.com";
+
+    let mut reminder = Reminder {
+        id: String::new(),
+        due: start,
+        destination: owner.to_string(),
+        text: "Water the plants".to_string(),
+        recurrence: Some(Recurrence::Days(1)),
+        repeat_until: None,
+        room_id: None,
+        channel: Channel::Matrix,
+        attempts: 0,
+        creator: owner.to_string(),
+        created: Some(start),
+        event_id: None,
+        image: None,
+        timezone: None,
+        nag_interval: None,
+        parent_id: None,
+    };
+    reminders.add_reminder(&mut reminder).unwrap();
+
+    let undone_ids = |since| -> Vec<String> {
+        reminders
+            .get_undone_reminders(owner, &since)
+            .unwrap()
+            .into_iter()
+            .map(|reminder| reminder.id)
+            .collect()
+    };
+
+    // It can't be done before it has been delivered
+    assert!(!reminders
+        .mark_reminder_done(&reminder.id, owner, &start)
+        .unwrap());
+    assert!(undone_ids(start).is_empty());
+
+    reminders
+        .mark_delivered(&reminder.id, &(start + Duration::minutes(1)))
+        .unwrap();
+    assert_eq!(undone_ids(start), vec![reminder.id.clone()]);
+
+    // Only its owner can mark it done, and only once per delivery
+    assert!(!reminders
+        .mark_reminder_done(
+            &reminder.id,
+            "@bob:example.com",
+            &(start + Duration::minutes(2))
+        )
+        .unwrap());
+    assert!(reminders
+        .mark_reminder_done(&reminder.id, owner, &(start + Duration::minutes(2)))
+        .unwrap());
+    assert!(!reminders
+        .mark_reminder_done(&reminder.id, owner, &(start + Duration::minutes(3)))
+        .unwrap());
+    assert!(undone_ids(start).is_empty());
+
+    // The next day's delivery needs marking done again
+    let tomorrow = start + Duration::days(1);
+    reminders.mark_delivered(&reminder.id, &tomorrow).unwrap();
+    assert_eq!(undone_ids(start), vec![reminder.id.clone()]);
+    assert!(undone_ids(tomorrow + Duration::minutes(1)).is_empty());
+
+    assert!(reminders
+        .mark_reminder_done(&reminder.id, owner, &(tomorrow + Duration::minutes(1)))
+        .unwrap());
+    assert!(undone_ids(start).is_empty());
+}
+
+#[test]
+fn digest_reminders_test() {
+    use chrono::NaiveTime;
+    use db::UserSettings;
+
+    let conn = Arc::new(Connection::open_in_memory().unwrap());
+    let user_settings = UserSettings::with_connection(conn.clone(), None).unwrap();
+    let reminders = Reminders::with_connection(conn, 10).unwrap();
+
+    let digest_at = Utc.ymd(2014, 7, 8).and_hms(8, 0, 0);
+    let end_of_day = Utc.ymd(2014, 7, 9).and_hms(0, 0, 0);
+    let owner = "@alice:example.com";
+
+    user_settings
+        .set_digest_time(owner, Some(NaiveTime::from_hms(8, 0, 0)))
+        .unwrap();
+    user_settings
+        .set_last_digest(owner, &digest_at, &end_of_day)
+        .unwrap();
+
+    let add = |created, due| {
+        let mut reminder = Reminder {
+            id: String::new(),
+            due,
+            destination: owner.to_string(),
+            text: "Water the plants".to_string(),
+            recurrence: None,
+            repeat_until: None,
+            room_id: None,
+            channel: Channel::Sms,
+            attempts: 0,
+            creator: owner.to_string(),
+            created: Some(created),
+            event_id: None,
+            image: None,
+            timezone: None,
+            nag_interval: None,
+            parent_id: None,
+        };
+        reminders.add_reminder(&mut reminder).unwrap();
+        reminder.id
+    };
+
+    // Left for the digest, which will have included it
+    add(
+        digest_at - Duration::hours(1),
+        digest_at + Duration::hours(2),
+    );
+    // Created after the digest, so it's delivered directly
+    let late = add(
+        digest_at + Duration::hours(1),
+        digest_at + Duration::hours(2),
+    );
+    // Due tomorrow, so it'll go in tomorrow's digest
+    add(
+        digest_at + Duration::hours(1),
+        end_of_day + Duration::hours(2),
+    );
+
+    let due: Vec<_> = reminders
+        .get_reminders_before(&(end_of_day + Duration::hours(3)))
+        .unwrap()
+        .into_iter()
+        .map(|reminder| reminder.id)
+        .collect();
+    assert_eq!(due, vec![late]);
+}
+
+#[test]
+fn insert_taken_id_test() {
+    let conn = Arc::new(Connection::open_in_memory().unwrap());
+    let reminders = Reminders::with_connection(conn, 10).unwrap();
+
+    let mut reminder = Reminder {
+        id: String::new(),
+        due: Utc::now(),
+        destination: "@alice:example.com".to_string(),
+        text: "Bins".to_string(),
+        recurrence: None,
+        repeat_until: None,
+        room_id: None,
+        channel: Channel::Matrix,
+        attempts: 0,
+        creator: "@alice:example.com".to_string(),
+        created: Some(Utc::now()),
+        event_id: None,
+        image: None,
+        timezone: None,
+        nag_interval: None,
+        parent_id: None,
+    };
+    reminders.add_reminder(&mut reminder).unwrap();
+
+    assert!(!reminders.insert_reminder(&reminder).unwrap());
+
+    reminder.id = "abc123".to_string();
+    assert!(reminders.insert_reminder(&reminder).unwrap());
+}
+
+#[test]
+fn cancel_reminder_for_other_user_test() {
+    let conn = Arc::new(Connection::open_in_memory().unwrap());
+    let reminders = Reminders::with_connection(conn, 10).unwrap();
+
+    let alice = "@alice:example.com";
+    let bob = "@bob:example.com";
+
+    let mut reminder = Reminder {
+        id: String::new(),
+        due: Utc::now() + Duration::days(1),
+        destination: bob.to_string(),
+        text: "Call mum".to_string(),
+        recurrence: None,
+        repeat_until: None,
+        room_id: None,
+        channel: Channel::Matrix,
+        attempts: 0,
+        creator: alice.to_string(),
+        created: Some(Utc::now()),
+        event_id: None,
+        image: None,
+        timezone: None,
+        nag_interval: None,
+        parent_id: None,
+    };
+    reminders.add_reminder(&mut reminder).unwrap();
+
+    // The reminder is Alice's, even though it goes to Bob
+    assert_eq!(reminders.get_reminders_for_user(alice).unwrap().len(), 1);
+    assert!(reminders.get_reminders_for_user(bob).unwrap().is_empty());
+    assert!(!reminders.cancel_reminder(&reminder.id, bob).unwrap());
+    assert!(reminders.cancel_reminder(&reminder.id, alice).unwrap());
+    assert!(reminders.get_reminders_for_user(alice).unwrap().is_empty());
+}
@@ -0,0 +1,248 @@
+use std::sync::Arc;
+
+use chrono_tz::Tz;
+use failure::{Error, ResultExt};
+use rusqlite::Connection;
+
+use super::{add_column_if_missing, Channel};
+use date::Locale;
+
+const ROOM_STATE_SCHEMA: &str = r"
+    CREATE TABLE IF NOT EXISTS room_names (
+        room_id TEXT PRIMARY KEY,
+        name TEXT NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS room_members (
+        room_id TEXT NOT NULL,
+        user_id TEXT NOT NULL,
+        display_name TEXT,
+        PRIMARY KEY (room_id, user_id)
+    );
+
+    CREATE TABLE IF NOT EXISTS room_power_levels (
+        room_id TEXT PRIMARY KEY,
+        content TEXT NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS room_configs (
+        room_id TEXT PRIMARY KEY,
+        prefix TEXT,
+        channel TEXT,
+        timezone TEXT,
+        locale TEXT
+    );
+";
+
+/// Settings for a room, from its `org.reminderbot.config` state event.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RoomConfig {
+    /// Another name the bot responds to in the room
+    pub prefix: Option<String>,
+    /// How reminders are delivered for users who haven't picked a way
+    pub channel: Option<Channel>,
+    /// The timezone for users who haven't set one
+    pub timezone: Option<Tz>,
+    /// The language times are written in, for users who haven't set one
+    pub locale: Option<Locale>,
+}
+
+/// The names and settings of rooms and the names of the people in them, as
+/// last seen in sync.
+#[derive(Debug, Clone)]
+pub struct RoomState {
+    conn: Arc<Connection>,
+}
+
+impl RoomState {
+    pub fn with_connection(conn: Arc<Connection>) -> Result<RoomState, Error> {
+        conn.execute_batch(ROOM_STATE_SCHEMA)
+            .context("failed to create room state schema")?;
+
+        add_column_if_missing(&conn, "room_configs", "locale", "TEXT")?;
+
+        Ok(RoomState { conn })
+    }
+
+    pub fn get_room_name(&self, room_id: &str) -> Result<Option<String>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT name FROM room_names WHERE room_id = ?")
+            .context("failed to create select statement")?;
+
+        let rows = stmt.query_map(&[&room_id], |row| row.get(0))?;
+
+        for row in rows {
+            return Ok(Some(row?));
+        }
+
+        Ok(None)
+    }
+
+    /// Sets the room's name, or forgets it if None.
+    pub fn set_room_name(&self, room_id: &str, name: Option<&str>) -> Result<(), Error> {
+        if let Some(name) = name {
+            self.conn
+                .prepare_cached("INSERT OR REPLACE INTO room_names (room_id, name) VALUES (?, ?)")
+                .context("failed to create insert statement")?
+                .execute(&[&room_id, &name])
+                .context("failed to store room name")?;
+        } else {
+            self.conn
+                .prepare_cached("DELETE FROM room_names WHERE room_id = ?")
+                .context("failed to create delete statement")?
+                .execute(&[&room_id])
+                .context("failed to delete room name")?;
+        }
+
+        Ok(())
+    }
+
+    /// Gets the display name of a member of the room. Returns None if we
+    /// don't know they're in the room, and Some(None) if they are but don't
+    /// have a display name.
+    pub fn get_member_name(
+        &self,
+        room_id: &str,
+        user_id: &str,
+    ) -> Result<Option<Option<String>>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare_cached(
+                "SELECT display_name FROM room_members WHERE room_id = ? AND user_id = ?",
+            )
+            .context("failed to create select statement")?;
+
+        let rows = stmt.query_map(&[&room_id, &user_id], |row| row.get(0))?;
+
+        for row in rows {
+            return Ok(Some(row?));
+        }
+
+        Ok(None)
+    }
+
+    pub fn set_member(
+        &self,
+        room_id: &str,
+        user_id: &str,
+        display_name: Option<&str>,
+    ) -> Result<(), Error> {
+        self.conn
+            .prepare_cached(
+                "INSERT OR REPLACE INTO room_members (room_id, user_id, display_name) VALUES (?, ?, ?)",
+            )
+            .context("failed to create insert statement")?
+            .execute(&[&room_id, &user_id, &display_name])
+            .context("failed to store room member")?;
+
+        Ok(())
+    }
+
+    pub fn remove_member(&self, room_id: &str, user_id: &str) -> Result<(), Error> {
+        self.conn
+            .prepare_cached("DELETE FROM room_members WHERE room_id = ? AND user_id = ?")
+            .context("failed to create delete statement")?
+            .execute(&[&room_id, &user_id])
+            .context("failed to delete room member")?;
+
+        Ok(())
+    }
+
+    /// The JSON content of the room's `m.room.power_levels` event, if we've
+    /// seen one.
+    pub fn get_power_levels(&self, room_id: &str) -> Result<Option<String>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT content FROM room_power_levels WHERE room_id = ?")
+            .context("failed to create select statement")?;
+
+        let rows = stmt.query_map(&[&room_id], |row| row.get(0))?;
+
+        for row in rows {
+            return Ok(Some(row?));
+        }
+
+        Ok(None)
+    }
+
+    pub fn set_power_levels(&self, room_id: &str, content: &str) -> Result<(), Error> {
+        self.conn
+            .prepare_cached(
+                "INSERT OR REPLACE INTO room_power_levels (room_id, content) VALUES (?, ?)",
+            )
+            .context("failed to create insert statement")?
+            .execute(&[&room_id, &content])
+            .context("failed to store power levels")?;
+
+        Ok(())
+    }
+
+    pub fn get_room_config(&self, room_id: &str) -> Result<RoomConfig, Error> {
+        let mut stmt = self
+            .conn
+            .prepare_cached(
+                "SELECT prefix, channel, timezone, locale FROM room_configs WHERE room_id = ?",
+            )
+            .context("failed to create select statement")?;
+
+        let rows = stmt.query_map(&[&room_id], |row| {
+            (
+                row.get::<_, Option<String>>(0),
+                row.get::<_, Option<String>>(1),
+                row.get::<_, Option<String>>(2),
+                row.get::<_, Option<String>>(3),
+            )
+        })?;
+
+        for row in rows {
+            let (prefix, channel, timezone, locale) = row?;
+
+            let channel = match channel {
+                Some(channel) => Some(channel.parse()?),
+                None => None,
+            };
+
+            let timezone = match timezone {
+                Some(timezone) => Some(
+                    timezone
+                        .parse::<Tz>()
+                        .map_err(|e| format_err!("invalid timezone in database: {}", e))?,
+                ),
+                None => None,
+            };
+
+            let locale = match locale {
+                Some(locale) => Some(locale.parse()?),
+                None => None,
+            };
+
+            return Ok(RoomConfig {
+                prefix,
+                channel,
+                timezone,
+                locale,
+            });
+        }
+
+        Ok(RoomConfig::default())
+    }
+
+    pub fn set_room_config(&self, room_id: &str, config: &RoomConfig) -> Result<(), Error> {
+        self.conn
+            .prepare_cached(
+                "INSERT OR REPLACE INTO room_configs (room_id, prefix, channel, timezone, locale) VALUES (?, ?, ?, ?, ?)",
+            )
+            .context("failed to create insert statement")?
+            .execute(&[
+                &room_id,
+                &config.prefix,
+                &config.channel.map(|channel| channel.as_str()),
+                &config.timezone.map(|tz| tz.name()),
+                &config.locale.map(|locale| locale.as_str()),
+            ])
+            .context("failed to store room config")?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,136 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use failure::{Error, ResultExt};
+use rusqlite::Connection;
+
+use super::migrate_schema;
+
+const ROOMS_SCHEMA: &str = r"
+    CREATE TABLE IF NOT EXISTS joined_rooms (
+        user_id TEXT NOT NULL,
+        room_id TEXT NOT NULL,
+        last_active_ts BIGINT NOT NULL,
+        PRIMARY KEY (user_id, room_id)
+    );
+
+    CREATE INDEX IF NOT EXISTS joined_rooms_room_id ON joined_rooms(room_id);
+";
+
+// Rooms used to be tracked for a single account only. We can't tell which
+// account those were for, but they're added again on the next sync anyway.
+const ROOMS_MIGRATION_1: &str = "DROP TABLE IF EXISTS rooms";
+
+/// The rooms each of the bot's accounts is in, and when each was last used.
+#[derive(Debug, Clone)]
+pub struct Rooms {
+    conn: Arc<Connection>,
+}
+
+impl Rooms {
+    pub fn with_connection(conn: Arc<Connection>) -> Result<Rooms, Error> {
+        conn.execute_batch(ROOMS_SCHEMA)
+            .context("failed to create rooms schema")?;
+
+        migrate_schema(&conn, "rooms", 1, ROOMS_MIGRATION_1)?;
+
+        Ok(Rooms { conn })
+    }
+
+    /// Starts tracking the room if we aren't already, counting it as active
+    /// from now.
+    pub fn add_room(&self, user_id: &str, room_id: &str, now: &DateTime<Utc>) -> Result<(), Error> {
+        self.conn
+            .prepare_cached(
+                "INSERT OR IGNORE INTO joined_rooms (user_id, room_id, last_active_ts) VALUES (?, ?, ?)",
+            )
+            .context("failed to create insert statement")?
+            .execute(&[&user_id, &room_id, &now.timestamp()])
+            .context("failed to insert room")?;
+
+        Ok(())
+    }
+
+    pub fn record_activity(
+        &self,
+        user_id: &str,
+        room_id: &str,
+        now: &DateTime<Utc>,
+    ) -> Result<(), Error> {
+        self.conn
+            .prepare_cached(
+                "INSERT OR REPLACE INTO joined_rooms (user_id, room_id, last_active_ts) VALUES (?, ?, ?)",
+            )
+            .context("failed to create insert statement")?
+            .execute(&[&user_id, &room_id, &now.timestamp()])
+            .context("failed to record room activity")?;
+
+        Ok(())
+    }
+
+    /// Gets the accounts that are in the room.
+    pub fn get_users_in_room(&self, room_id: &str) -> Result<Vec<String>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT user_id FROM joined_rooms WHERE room_id = ?")
+            .context("failed to create select statement")?;
+
+        let vec = stmt
+            .query_map(&[&room_id], |row| row.get(0))
+            .context("failed to execute select query")?
+            .collect::<Result<_, _>>()
+            .context("failed to read results of query")?;
+
+        Ok(vec)
+    }
+
+    /// Gets the user's rooms that haven't been used since the given time.
+    /// Rooms with pending reminders, or that we use to message a user
+    /// directly, are still needed so are left out.
+    pub fn get_inactive_rooms(
+        &self,
+        user_id: &str,
+        before: &DateTime<Utc>,
+    ) -> Result<Vec<String>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare_cached(
+                r"
+                SELECT room_id FROM joined_rooms
+                WHERE user_id = ?
+                    AND last_active_ts < ?
+                    AND room_id NOT IN (
+                        SELECT room_id FROM reminders WHERE NOT sent AND room_id IS NOT NULL
+                    )
+                    AND room_id NOT IN (SELECT room_id FROM direct_rooms)
+                ",
+            )
+            .context("failed to create select statement")?;
+
+        let vec = stmt
+            .query_map(&[&user_id, &before.timestamp()], |row| row.get(0))
+            .context("failed to execute select query")?
+            .collect::<Result<_, _>>()
+            .context("failed to read results of query")?;
+
+        Ok(vec)
+    }
+
+    /// Stops tracking a room the user has left, and forgets about using it
+    /// to message anyone directly.
+    pub fn remove_room(&self, user_id: &str, room_id: &str) -> Result<(), Error> {
+        self.conn
+            .prepare_cached("DELETE FROM joined_rooms WHERE user_id = ? AND room_id = ?")
+            .context("failed to create delete statement")?
+            .execute(&[&user_id, &room_id])
+            .context("failed to delete room")?;
+
+        self.conn
+            .prepare_cached("DELETE FROM direct_rooms WHERE room_id = ?")
+            .context("failed to create delete statement")?
+            .execute(&[&room_id])
+            .context("failed to delete direct room")?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,119 @@
+use std::sync::Arc;
+
+use failure::{Error, ResultExt};
+use rusqlite::Connection;
+
+use super::add_column_if_missing;
+
+const SESSIONS_SCHEMA: &str = r"
+    CREATE TABLE IF NOT EXISTS matrix_sessions (
+        id INTEGER PRIMARY KEY CHECK (id = 1),
+        user_id TEXT NOT NULL,
+        access_token TEXT NOT NULL,
+        device_id TEXT NOT NULL,
+        refresh_token TEXT
+    );
+
+    CREATE TABLE IF NOT EXISTS account_sessions (
+        user_id TEXT PRIMARY KEY,
+        access_token TEXT NOT NULL,
+        device_id TEXT NOT NULL,
+        refresh_token TEXT
+    );
+";
+
+/// Stores the access tokens we got from logging in with a password, one per
+/// account, so that we don't create a new device every time we start.
+#[derive(Debug, Clone)]
+pub struct Sessions {
+    conn: Arc<Connection>,
+}
+
+impl Sessions {
+    pub fn with_connection(conn: Arc<Connection>) -> Result<Sessions, Error> {
+        conn.execute_batch(SESSIONS_SCHEMA)
+            .context("failed to create sessions schema")?;
+
+        add_column_if_missing(&conn, "matrix_sessions", "refresh_token", "TEXT")?;
+
+        // Sessions used to be limited to a single account, so move any
+        // stored then over to the table that allows several.
+        conn.execute_batch(
+            r"
+            INSERT OR IGNORE INTO account_sessions (user_id, access_token, device_id, refresh_token)
+            SELECT user_id, access_token, device_id, refresh_token FROM matrix_sessions;
+
+            DELETE FROM matrix_sessions;
+            ",
+        )
+        .context("failed to move sessions")?;
+
+        Ok(Sessions { conn })
+    }
+
+    /// Gets the access and refresh tokens of the stored session, if it's for
+    /// the user.
+    pub fn get_session(&self, user_id: &str) -> Result<Option<(String, Option<String>)>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare_cached(
+                "SELECT access_token, refresh_token FROM account_sessions WHERE user_id = ?",
+            )
+            .context("failed to create select statement")?;
+
+        let rows = stmt.query_map(&[&user_id], |row| (row.get(0), row.get(1)))?;
+
+        for row in rows {
+            return Ok(Some(row?));
+        }
+
+        Ok(None)
+    }
+
+    pub fn set_session(
+        &self,
+        user_id: &str,
+        access_token: &str,
+        refresh_token: Option<&str>,
+        device_id: &str,
+    ) -> Result<(), Error> {
+        self.conn
+            .prepare_cached(
+                "INSERT OR REPLACE INTO account_sessions (user_id, access_token, refresh_token, device_id) VALUES (?, ?, ?, ?)",
+            )
+            .context("failed to create insert statement")?
+            .execute(&[&user_id, &access_token, &refresh_token, &device_id])
+            .context("failed to store session")?;
+
+        Ok(())
+    }
+
+    /// Replaces the user's stored tokens after they've been refreshed.
+    pub fn update_tokens(
+        &self,
+        user_id: &str,
+        access_token: &str,
+        refresh_token: Option<&str>,
+    ) -> Result<(), Error> {
+        self.conn
+            .prepare_cached(
+                "UPDATE account_sessions SET access_token = ?, refresh_token = ? WHERE user_id = ?",
+            )
+            .context("failed to create update statement")?
+            .execute(&[&access_token, &refresh_token, &user_id])
+            .context("failed to update session")?;
+
+        Ok(())
+    }
+
+    /// Forgets the user's session, e.g. after it's been logged out.
+    pub fn delete_session(&self, user_id: &str) -> Result<(), Error> {
+        self.conn
+            .prepare_cached("DELETE FROM account_sessions WHERE user_id = ?")
+            .context("failed to create delete statement")?
+            .execute(&[&user_id])
+            .context("failed to delete session")?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,82 @@
+use std::sync::Arc;
+
+use failure::{Error, ResultExt};
+use rusqlite::Connection;
+
+const SYNC_TOKENS_SCHEMA: &str = r"
+    CREATE TABLE IF NOT EXISTS sync_tokens (
+        id INTEGER PRIMARY KEY CHECK (id = 1),
+        next_batch TEXT NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS account_sync_tokens (
+        user_id TEXT PRIMARY KEY,
+        next_batch TEXT NOT NULL
+    );
+";
+
+/// Stores where each account got up to in the Matrix sync stream, so that
+/// after a restart we can pick up any messages sent while we were down.
+#[derive(Debug, Clone)]
+pub struct SyncTokens {
+    conn: Arc<Connection>,
+}
+
+impl SyncTokens {
+    pub fn with_connection(conn: Arc<Connection>) -> Result<SyncTokens, Error> {
+        conn.execute_batch(SYNC_TOKENS_SCHEMA)
+            .context("failed to create sync tokens schema")?;
+
+        Ok(SyncTokens { conn })
+    }
+
+    /// Moves the token stored before we supported several accounts, which
+    /// doesn't say whose it is, to the given account.
+    pub fn adopt_legacy_token(&self, user_id: &str) -> Result<(), Error> {
+        self.conn
+            .prepare_cached(
+                r"
+                INSERT OR IGNORE INTO account_sync_tokens (user_id, next_batch)
+                SELECT ?, next_batch FROM sync_tokens WHERE id = 1
+                ",
+            )
+            .context("failed to create insert statement")?
+            .execute(&[&user_id])
+            .context("failed to move legacy sync token")?;
+
+        self.conn
+            .prepare_cached("DELETE FROM sync_tokens")
+            .context("failed to create delete statement")?
+            .execute(&[])
+            .context("failed to delete legacy sync token")?;
+
+        Ok(())
+    }
+
+    pub fn get_next_batch(&self, user_id: &str) -> Result<Option<String>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT next_batch FROM account_sync_tokens WHERE user_id = ?")
+            .context("failed to create select statement")?;
+
+        let rows = stmt.query_map(&[&user_id], |row| row.get(0))?;
+
+        for row in rows {
+            return Ok(Some(row?));
+        }
+
+        Ok(None)
+    }
+
+    pub fn set_next_batch(&self, user_id: &str, next_batch: &str) -> Result<(), Error> {
+        self.conn
+            .prepare_cached(
+                "INSERT OR REPLACE INTO account_sync_tokens (user_id, next_batch) VALUES (?, ?)",
+            )
+            .context("failed to create insert statement")?
+            .execute(&[&user_id, &next_batch])
+            .context("failed to store sync token")?;
+
+        Ok(())
+    }
+}
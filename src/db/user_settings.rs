@@ -0,0 +1,599 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use failure::{Error, ResultExt};
+use rusqlite::Connection;
+
+use super::{add_column_if_missing, Calendar, Channel};
+use date::{parse_time_of_day, DateOrder, Locale, ParseSettings};
+
+const USER_SETTINGS_SCHEMA: &str = r"
+    CREATE TABLE IF NOT EXISTS user_settings (
+        user_id TEXT PRIMARY KEY,
+        timezone TEXT,
+        channel TEXT,
+        allow_others BOOL NOT NULL DEFAULT 0,
+        paused BOOL NOT NULL DEFAULT 0,
+        quiet_hours TEXT,
+        digest_time TEXT,
+        last_digest_ts BIGINT,
+        last_digest_until_ts BIGINT,
+        react_confirmations BOOL NOT NULL DEFAULT 0,
+        morning_time TEXT,
+        afternoon_time TEXT,
+        evening_time TEXT,
+        end_of_day_time TEXT,
+        date_order TEXT,
+        locale TEXT
+    );
+";
+
+/// A user who gets their reminders as a daily digest.
+#[derive(Debug, Clone)]
+pub struct DigestSettings {
+    pub user_id: String,
+    /// The local time the digest is sent at
+    pub time: NaiveTime,
+    pub timezone: Option<Tz>,
+    pub last_digest: Option<DateTime<Utc>>,
+}
+
+/// A daily window, in the user's local time, during which they don't want
+/// to be texted or called. The window may wrap past midnight.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuietHours {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl QuietHours {
+    /// If the given time is within quiet hours, returns when they end.
+    pub fn end_after(&self, now: DateTime<Tz>) -> Option<DateTime<Tz>> {
+        let time = now.time();
+
+        let quiet = if self.start <= self.end {
+            time >= self.start && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        };
+
+        if !quiet {
+            return None;
+        }
+
+        let mut date = now.date().naive_local();
+        if time >= self.end {
+            date = date.succ();
+        }
+
+        let end = date.and_time(self.end);
+
+        // If the local time doesn't exist due to DST then shift it forward
+        now.timezone()
+            .from_local_datetime(&end)
+            .earliest()
+            .or_else(|| {
+                now.timezone()
+                    .from_local_datetime(&(end + Duration::hours(1)))
+                    .earliest()
+            })
+    }
+}
+
+impl FromStr for QuietHours {
+    type Err = Error;
+
+    /// Parses e.g. "22:00-08:00" or "22-8".
+    fn from_str(s: &str) -> Result<QuietHours, Error> {
+        let mut split = s.splitn(2, '-');
+        let start = split.next().unwrap_or("");
+        let end = split
+            .next()
+            .ok_or_else(|| format_err!("expected <start>-<end>, e.g. 22:00-08:00"))?;
+
+        Ok(QuietHours {
+            start: parse_time_of_day(start)?,
+            end: parse_time_of_day(end)?,
+        })
+    }
+}
+
+impl fmt::Display for QuietHours {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}-{}",
+            self.start.format("%H:%M"),
+            self.end.format("%H:%M")
+        )
+    }
+}
+
+/// A named time of day whose meaning users can change, e.g. "tomorrow
+/// morning" or "end of day".
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PartOfDay {
+    Morning,
+    Afternoon,
+    Evening,
+    EndOfDay,
+}
+
+impl PartOfDay {
+    fn column(self) -> &'static str {
+        match self {
+            PartOfDay::Morning => "morning_time",
+            PartOfDay::Afternoon => "afternoon_time",
+            PartOfDay::Evening => "evening_time",
+            PartOfDay::EndOfDay => "end_of_day_time",
+        }
+    }
+}
+
+impl FromStr for PartOfDay {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<PartOfDay, Error> {
+        match s {
+            "morning" => Ok(PartOfDay::Morning),
+            "afternoon" => Ok(PartOfDay::Afternoon),
+            "evening" => Ok(PartOfDay::Evening),
+            "end of day" => Ok(PartOfDay::EndOfDay),
+            _ => bail!("unknown time of day {}", s),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct UserSettings {
+    conn: Arc<Connection>,
+    calendar: Calendar,
+    max_horizon: Option<Duration>,
+}
+
+impl UserSettings {
+    /// Opens the user settings tables. Times further ahead than
+    /// `max_horizon`, if given, are rejected when parsed.
+    pub fn with_connection(
+        conn: Arc<Connection>,
+        max_horizon: Option<Duration>,
+    ) -> Result<UserSettings, Error> {
+        conn.execute_batch(USER_SETTINGS_SCHEMA)
+            .context("failed to create user settings schema")?;
+
+        add_column_if_missing(&conn, "user_settings", "channel", "TEXT")?;
+        add_column_if_missing(
+            &conn,
+            "user_settings",
+            "allow_others",
+            "BOOL NOT NULL DEFAULT 0",
+        )?;
+        add_column_if_missing(&conn, "user_settings", "paused", "BOOL NOT NULL DEFAULT 0")?;
+        add_column_if_missing(&conn, "user_settings", "quiet_hours", "TEXT")?;
+        add_column_if_missing(&conn, "user_settings", "digest_time", "TEXT")?;
+        add_column_if_missing(&conn, "user_settings", "last_digest_ts", "BIGINT")?;
+        add_column_if_missing(&conn, "user_settings", "last_digest_until_ts", "BIGINT")?;
+        add_column_if_missing(
+            &conn,
+            "user_settings",
+            "react_confirmations",
+            "BOOL NOT NULL DEFAULT 0",
+        )?;
+        add_column_if_missing(&conn, "user_settings", "morning_time", "TEXT")?;
+        add_column_if_missing(&conn, "user_settings", "afternoon_time", "TEXT")?;
+        add_column_if_missing(&conn, "user_settings", "evening_time", "TEXT")?;
+        add_column_if_missing(&conn, "user_settings", "date_order", "TEXT")?;
+        add_column_if_missing(&conn, "user_settings", "end_of_day_time", "TEXT")?;
+        add_column_if_missing(&conn, "user_settings", "locale", "TEXT")?;
+
+        let calendar = Calendar::with_connection(conn.clone())?;
+
+        Ok(UserSettings {
+            conn,
+            calendar,
+            max_horizon,
+        })
+    }
+
+    pub fn get_timezone(&self, user_id: &str) -> Result<Option<Tz>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT timezone FROM user_settings WHERE user_id = ?")
+            .context("failed to create select statement")?;
+
+        let rows = stmt.query_map(&[&user_id], |row| row.get::<_, Option<String>>(0))?;
+
+        for row in rows {
+            return match row? {
+                Some(tz) => Ok(Some(
+                    tz.parse::<Tz>()
+                        .map_err(|e| format_err!("invalid timezone in database: {}", e))?,
+                )),
+                None => Ok(None),
+            };
+        }
+
+        Ok(None)
+    }
+
+    pub fn set_timezone(&self, user_id: &str, tz: Tz) -> Result<(), Error> {
+        self.ensure_user(user_id)?;
+
+        self.conn
+            .prepare_cached("UPDATE user_settings SET timezone = ? WHERE user_id = ?")
+            .context("failed to create update statement")?
+            .execute(&[&tz.name(), &user_id])
+            .context("failed to update timezone")?;
+
+        Ok(())
+    }
+
+    /// Gets the user's preferred delivery channel, if they have set one.
+    pub fn get_channel(&self, user_id: &str) -> Result<Option<Channel>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT channel FROM user_settings WHERE user_id = ?")
+            .context("failed to create select statement")?;
+
+        let rows = stmt.query_map(&[&user_id], |row| row.get::<_, Option<String>>(0))?;
+
+        for row in rows {
+            if let Some(channel) = row? {
+                return Ok(Some(channel.parse()?));
+            }
+        }
+
+        Ok(None)
+    }
+
+    pub fn set_channel(&self, user_id: &str, channel: Channel) -> Result<(), Error> {
+        self.ensure_user(user_id)?;
+
+        self.conn
+            .prepare_cached("UPDATE user_settings SET channel = ? WHERE user_id = ?")
+            .context("failed to create update statement")?
+            .execute(&[&channel.as_str(), &user_id])
+            .context("failed to update channel")?;
+
+        Ok(())
+    }
+
+    /// Whether the user has opted in to other users setting reminders for
+    /// them.
+    pub fn get_allow_others(&self, user_id: &str) -> Result<bool, Error> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT allow_others FROM user_settings WHERE user_id = ?")
+            .context("failed to create select statement")?;
+
+        let rows = stmt.query_map(&[&user_id], |row| row.get(0))?;
+
+        for row in rows {
+            return Ok(row?);
+        }
+
+        Ok(false)
+    }
+
+    pub fn set_allow_others(&self, user_id: &str, allow: bool) -> Result<(), Error> {
+        self.ensure_user(user_id)?;
+
+        self.conn
+            .prepare_cached("UPDATE user_settings SET allow_others = ? WHERE user_id = ?")
+            .context("failed to create update statement")?
+            .execute(&[&allow, &user_id])
+            .context("failed to update allow_others")?;
+
+        Ok(())
+    }
+
+    /// Whether the user wants new reminders acknowledged with a reaction
+    /// rather than a message.
+    pub fn get_react_confirmations(&self, user_id: &str) -> Result<bool, Error> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT react_confirmations FROM user_settings WHERE user_id = ?")
+            .context("failed to create select statement")?;
+
+        let rows = stmt.query_map(&[&user_id], |row| row.get(0))?;
+
+        for row in rows {
+            return Ok(row?);
+        }
+
+        Ok(false)
+    }
+
+    pub fn set_react_confirmations(&self, user_id: &str, react: bool) -> Result<(), Error> {
+        self.ensure_user(user_id)?;
+
+        self.conn
+            .prepare_cached("UPDATE user_settings SET react_confirmations = ? WHERE user_id = ?")
+            .context("failed to create update statement")?
+            .execute(&[&react, &user_id])
+            .context("failed to update react_confirmations")?;
+
+        Ok(())
+    }
+
+    /// Gets how the user wants dates and times interpreted, falling back
+    /// to the defaults for anything they haven't set.
+    pub fn get_parse_settings(&self, user_id: &str) -> Result<ParseSettings, Error> {
+        let mut stmt = self
+            .conn
+            .prepare_cached(
+                "SELECT morning_time, afternoon_time, evening_time, date_order, end_of_day_time, locale FROM user_settings WHERE user_id = ?",
+            )
+            .context("failed to create select statement")?;
+
+        let rows = stmt.query_map(&[&user_id], |row| {
+            (
+                row.get::<_, Option<String>>(0),
+                row.get::<_, Option<String>>(1),
+                row.get::<_, Option<String>>(2),
+                row.get::<_, Option<String>>(3),
+                row.get::<_, Option<String>>(4),
+                row.get::<_, Option<String>>(5),
+            )
+        })?;
+
+        let mut settings = ParseSettings::default();
+        for row in rows {
+            let (morning, afternoon, evening, date_order, end_of_day, locale) = row?;
+
+            if let Some(morning) = morning {
+                settings.morning = parse_time_of_day(&morning)?;
+            }
+            if let Some(afternoon) = afternoon {
+                settings.afternoon = parse_time_of_day(&afternoon)?;
+            }
+            if let Some(evening) = evening {
+                settings.evening = parse_time_of_day(&evening)?;
+            }
+            if let Some(end_of_day) = end_of_day {
+                settings.end_of_day = parse_time_of_day(&end_of_day)?;
+            }
+            if let Some(date_order) = date_order {
+                settings.date_order = Some(date_order.parse()?);
+            }
+            if let Some(locale) = locale {
+                settings.locale = Some(locale.parse()?);
+            }
+        }
+
+        settings.named_dates = self.calendar.get_named_dates()?;
+        settings.holidays = self.calendar.get_holidays()?;
+        settings.max_horizon = self.max_horizon;
+
+        Ok(settings)
+    }
+
+    /// Sets what the user means by e.g. "morning", or resets it to the
+    /// default if None.
+    pub fn set_part_of_day(
+        &self,
+        user_id: &str,
+        part: PartOfDay,
+        time: Option<NaiveTime>,
+    ) -> Result<(), Error> {
+        self.ensure_user(user_id)?;
+
+        let sql = format!(
+            "UPDATE user_settings SET {} = ? WHERE user_id = ?",
+            part.column()
+        );
+
+        self.conn
+            .prepare_cached(&sql)
+            .context("failed to create update statement")?
+            .execute(&[&time.map(|t| t.format("%H:%M").to_string()), &user_id])
+            .context("failed to update time of day")?;
+
+        Ok(())
+    }
+
+    /// Sets whether the user writes numeric dates day or month first, or
+    /// clears it if None.
+    pub fn set_date_order(&self, user_id: &str, order: Option<DateOrder>) -> Result<(), Error> {
+        self.ensure_user(user_id)?;
+
+        self.conn
+            .prepare_cached("UPDATE user_settings SET date_order = ? WHERE user_id = ?")
+            .context("failed to create update statement")?
+            .execute(&[&order.map(|o| o.as_str()), &user_id])
+            .context("failed to update date order")?;
+
+        Ok(())
+    }
+
+    /// Sets the language the user writes times in, or clears it if None.
+    pub fn set_locale(&self, user_id: &str, locale: Option<Locale>) -> Result<(), Error> {
+        self.ensure_user(user_id)?;
+
+        self.conn
+            .prepare_cached("UPDATE user_settings SET locale = ? WHERE user_id = ?")
+            .context("failed to create update statement")?
+            .execute(&[&locale.map(|l| l.as_str()), &user_id])
+            .context("failed to update locale")?;
+
+        Ok(())
+    }
+
+    /// Pauses or resumes delivery of the user's reminders. Reminders that
+    /// come due while paused are delivered on resume.
+    pub fn set_paused(&self, user_id: &str, paused: bool) -> Result<(), Error> {
+        self.ensure_user(user_id)?;
+
+        self.conn
+            .prepare_cached("UPDATE user_settings SET paused = ? WHERE user_id = ?")
+            .context("failed to create update statement")?
+            .execute(&[&paused, &user_id])
+            .context("failed to update paused")?;
+
+        Ok(())
+    }
+
+    pub fn get_quiet_hours(&self, user_id: &str) -> Result<Option<QuietHours>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT quiet_hours FROM user_settings WHERE user_id = ?")
+            .context("failed to create select statement")?;
+
+        let rows = stmt.query_map(&[&user_id], |row| row.get::<_, Option<String>>(0))?;
+
+        for row in rows {
+            if let Some(quiet_hours) = row? {
+                return Ok(Some(quiet_hours.parse()?));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Sets the user's quiet hours, or clears them if None.
+    pub fn set_quiet_hours(
+        &self,
+        user_id: &str,
+        quiet_hours: Option<QuietHours>,
+    ) -> Result<(), Error> {
+        self.ensure_user(user_id)?;
+
+        self.conn
+            .prepare_cached("UPDATE user_settings SET quiet_hours = ? WHERE user_id = ?")
+            .context("failed to create update statement")?
+            .execute(&[&quiet_hours.map(|q| q.to_string()), &user_id])
+            .context("failed to update quiet hours")?;
+
+        Ok(())
+    }
+
+    /// Turns on the daily digest at the given local time, or turns it off if
+    /// None.
+    pub fn set_digest_time(&self, user_id: &str, time: Option<NaiveTime>) -> Result<(), Error> {
+        self.ensure_user(user_id)?;
+
+        self.conn
+            .prepare_cached("UPDATE user_settings SET digest_time = ? WHERE user_id = ?")
+            .context("failed to create update statement")?
+            .execute(&[&time.map(|t| t.format("%H:%M").to_string()), &user_id])
+            .context("failed to update digest time")?;
+
+        Ok(())
+    }
+
+    /// Gets all the users who have turned on the daily digest, other than
+    /// those who have paused their reminders.
+    pub fn get_digest_users(&self) -> Result<Vec<DigestSettings>, Error> {
+        let mut stmt = self
+            .conn
+            .prepare_cached(
+                "SELECT user_id, digest_time, timezone, last_digest_ts FROM user_settings WHERE digest_time IS NOT NULL AND NOT paused",
+            )
+            .context("failed to create select statement")?;
+
+        let rows = stmt
+            .query_map(&[], |row| {
+                (
+                    row.get::<_, String>(0),
+                    row.get::<_, String>(1),
+                    row.get::<_, Option<String>>(2),
+                    row.get::<_, Option<i64>>(3),
+                )
+            })
+            .context("failed to execute select query")?;
+
+        let mut users = Vec::new();
+        for row in rows {
+            let (user_id, time, timezone, last_digest) =
+                row.context("failed to read results of select query")?;
+
+            let timezone = match timezone {
+                Some(tz) => Some(
+                    tz.parse::<Tz>()
+                        .map_err(|e| format_err!("invalid timezone in database: {}", e))?,
+                ),
+                None => None,
+            };
+
+            users.push(DigestSettings {
+                user_id,
+                time: parse_time_of_day(&time)?,
+                timezone,
+                last_digest: last_digest.map(|ts| Utc.timestamp(ts, 0)),
+            });
+        }
+
+        Ok(users)
+    }
+
+    /// Records that a digest was sent, covering reminders due up until the
+    /// given time.
+    pub fn set_last_digest(
+        &self,
+        user_id: &str,
+        sent_at: &DateTime<Utc>,
+        until: &DateTime<Utc>,
+    ) -> Result<(), Error> {
+        self.conn
+            .prepare_cached(
+                "UPDATE user_settings SET last_digest_ts = ?, last_digest_until_ts = ? WHERE user_id = ?",
+            )
+            .context("failed to create update statement")?
+            .execute(&[&sent_at.timestamp(), &until.timestamp(), &user_id])
+            .context("failed to update last digest")?;
+
+        Ok(())
+    }
+
+    fn ensure_user(&self, user_id: &str) -> Result<(), Error> {
+        self.conn
+            .prepare_cached("INSERT OR IGNORE INTO user_settings (user_id) VALUES (?)")
+            .context("failed to create insert statement")?
+            .execute(&[&user_id])
+            .context("failed to insert user")?;
+
+        Ok(())
+    }
+}
+
+#[test]
+fn quiet_hours_test() {
+    use chrono_tz::Europe::London;
+
+    let quiet: QuietHours = "22:00-08:00".parse().unwrap();
+    assert_eq!(quiet, "22-8".parse::<QuietHours>().unwrap());
+    assert_eq!(quiet.to_string(), "22:00-08:00");
+
+    assert_eq!(
+        quiet.end_after(London.ymd(2014, 7, 8).and_hms(23, 0, 0)),
+        Some(London.ymd(2014, 7, 9).and_hms(8, 0, 0))
+    );
+    assert_eq!(
+        quiet.end_after(London.ymd(2014, 7, 8).and_hms(3, 0, 0)),
+        Some(London.ymd(2014, 7, 8).and_hms(8, 0, 0))
+    );
+    assert_eq!(
+        quiet.end_after(London.ymd(2014, 7, 8).and_hms(8, 0, 0)),
+        None
+    );
+    assert_eq!(
+        quiet.end_after(London.ymd(2014, 7, 8).and_hms(12, 0, 0)),
+        None
+    );
+
+    let quiet: QuietHours = "13:00-14:30".parse().unwrap();
+    assert_eq!(
+        quiet.end_after(London.ymd(2014, 7, 8).and_hms(13, 30, 0)),
+        Some(London.ymd(2014, 7, 8).and_hms(14, 30, 0))
+    );
+    assert_eq!(
+        quiet.end_after(London.ymd(2014, 7, 8).and_hms(22, 0, 0)),
+        None
+    );
+
+    assert!("22:00".parse::<QuietHours>().is_err());
+    assert!("25:00-08:00".parse::<QuietHours>().is_err());
+}
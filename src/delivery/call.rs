@@ -0,0 +1,86 @@
+use failure::Error;
+use futures::{future, Future};
+use slog::Logger;
+use twilio_rust::calls::{CallFrom, Calls, OutboundCallBuilder};
+use twilio_rust::Client;
+use url::Url;
+
+use db::{AddressBook, Reminder};
+
+use super::{get_msisdn, DeliveryChannel, PermanentFailure};
+
+/// Delivers reminders by placing a voice call via Twilio that reads out the
+/// reminder text.
+pub struct CallChannel {
+    client: Client,
+    from_num: String,
+    address_book: AddressBook,
+    /// Our webhook URL returning TwiML that reads out the `message` query
+    /// param, if webhooks are set up.
+    twiml_url: Option<String>,
+}
+
+impl CallChannel {
+    pub fn new(
+        client: Client,
+        from_num: String,
+        address_book: AddressBook,
+        twiml_url: Option<String>,
+    ) -> CallChannel {
+        CallChannel {
+            client,
+            from_num,
+            address_book,
+            twiml_url,
+        }
+    }
+}
+
+impl DeliveryChannel for CallChannel {
+    fn deliver(
+        &self,
+        logger: Logger,
+        reminder: &Reminder,
+    ) -> Box<Future<Item = (), Error = Error>> {
+        let msisdn = match get_msisdn(&self.address_book, reminder) {
+            Ok(msisdn) => msisdn,
+            Err(err) => return Box::new(future::err(err)),
+        };
+
+        let twiml_url = match self.twiml_url {
+            Some(ref twiml_url) => twiml_url,
+            None => {
+                return Box::new(future::err(
+                    PermanentFailure("calls need webhooks to be configured".to_string()).into(),
+                ))
+            }
+        };
+
+        let message = format!("This is your reminder. {}", reminder.text);
+        let url = match Url::parse_with_params(twiml_url, &[("message", &message)]) {
+            Ok(url) => url,
+            Err(err) => {
+                return Box::new(future::err(format_err!(
+                    "invalid TwiML URL {}: {}",
+                    twiml_url,
+                    err
+                )))
+            }
+        };
+
+        let calls = Calls::new(&self.client);
+
+        let outbound_call =
+            OutboundCallBuilder::new(CallFrom::From(&self.from_num), &msisdn, &url).build();
+
+        let f = calls.make_call(&outbound_call).then(move |res| match res {
+            Ok(call) => {
+                info!(logger, "Call placed"; "status" => ?call.status);
+                Ok(())
+            }
+            Err(err) => Err(format_err!("error placing call: {:?}", err)),
+        });
+
+        Box::new(f)
+    }
+}
@@ -0,0 +1,221 @@
+use failure::{err_msg, Error, ResultExt};
+use futures::{future, Future};
+use slog::Logger;
+
+use std::rc::Rc;
+
+use db::{DirectRooms, Reminder, Reminders, Rooms};
+use matrix::{EventId, MessageSender};
+
+use super::{DeliveryChannel, PermanentFailure};
+
+/// Delivers reminders into the room they were created in, from whichever of
+/// the bot's accounts is in it.
+pub struct MatrixRoomChannel {
+    /// The senders for each account by user ID, the first being used for
+    /// rooms we don't know the members of
+    message_senders: Vec<(String, Rc<MessageSender>)>,
+    rooms: Rooms,
+    reminders: Reminders,
+}
+
+impl MatrixRoomChannel {
+    pub fn new(
+        message_senders: Vec<(String, Rc<MessageSender>)>,
+        rooms: Rooms,
+        reminders: Reminders,
+    ) -> MatrixRoomChannel {
+        MatrixRoomChannel {
+            message_senders,
+            rooms,
+            reminders,
+        }
+    }
+
+    fn message_sender_for_room(&self, room_id: &str) -> Result<&Rc<MessageSender>, Error> {
+        let users = self
+            .rooms
+            .get_users_in_room(room_id)
+            .context("failed to get room's accounts from DB")?;
+
+        let sender = self
+            .message_senders
+            .iter()
+            .find(|(user_id, _)| users.contains(user_id))
+            .or_else(|| self.message_senders.first())
+            .map(|(_, sender)| sender)
+            .ok_or_else(|| err_msg("no matrix accounts to send from"))?;
+
+        Ok(sender)
+    }
+}
+
+impl DeliveryChannel for MatrixRoomChannel {
+    fn deliver(
+        &self,
+        logger: Logger,
+        reminder: &Reminder,
+    ) -> Box<Future<Item = (), Error = Error>> {
+        let room_id = if let Some(ref room_id) = reminder.room_id {
+            room_id
+        } else {
+            return Box::new(future::err(
+                PermanentFailure(format!(
+                    "no room to deliver reminder to for {}",
+                    reminder.destination
+                ))
+                .into(),
+            ));
+        };
+
+        let message_sender = match self.message_sender_for_room(room_id) {
+            Ok(message_sender) => message_sender,
+            Err(err) => return Box::new(future::err(err)),
+        };
+
+        let f = send_reminder(
+            logger,
+            message_sender,
+            &self.reminders,
+            reminder,
+            room_id,
+            &format!("{}: {}", reminder.destination, reminder.text),
+        )
+        .map_err(|err| format_err!("failed to send message to room: {}", err));
+
+        Box::new(f)
+    }
+}
+
+/// Delivers reminders into a 1:1 room with the user, creating one if
+/// necessary.
+pub struct DirectMessageChannel {
+    message_sender: Rc<MessageSender>,
+    direct_rooms: DirectRooms,
+    reminders: Reminders,
+}
+
+impl DirectMessageChannel {
+    pub fn new(
+        message_sender: Rc<MessageSender>,
+        direct_rooms: DirectRooms,
+        reminders: Reminders,
+    ) -> DirectMessageChannel {
+        DirectMessageChannel {
+            message_sender,
+            direct_rooms,
+            reminders,
+        }
+    }
+}
+
+impl DeliveryChannel for DirectMessageChannel {
+    fn deliver(
+        &self,
+        logger: Logger,
+        reminder: &Reminder,
+    ) -> Box<Future<Item = (), Error = Error>> {
+        let room_res = self
+            .direct_rooms
+            .get_room_for_user(&reminder.destination)
+            .context("failed to get direct room from DB");
+
+        match room_res {
+            Ok(Some(room_id)) => {
+                let f = send_reminder(
+                    logger,
+                    &self.message_sender,
+                    &self.reminders,
+                    reminder,
+                    &room_id,
+                    &reminder.text,
+                )
+                .map_err(|err| format_err!("failed to send direct message: {}", err));
+                return Box::new(f);
+            }
+            Ok(None) => {}
+            Err(err) => return Box::new(future::err(err.into())),
+        }
+
+        // We don't have a room with the user yet, so create one and
+        // remember it for next time.
+        let direct_rooms = self.direct_rooms.clone();
+        let message_sender = self.message_sender.clone();
+        let reminders = self.reminders.clone();
+        let reminder = reminder.clone();
+        let destination = reminder.destination.clone();
+
+        let f = self
+            .message_sender
+            .create_direct_room(&reminder.destination)
+            .map_err(|()| err_msg("failed to create direct room"))
+            .and_then(move |room_id| {
+                if let Err(err) = direct_rooms.set_room_for_user(&destination, &room_id) {
+                    error!(logger, "Failed to persist direct room"; "err" => %err);
+                }
+
+                send_reminder(
+                    logger,
+                    &message_sender,
+                    &reminders,
+                    &reminder,
+                    &room_id,
+                    &reminder.text,
+                )
+                .map_err(|err| format_err!("failed to send direct message: {}", err))
+            });
+
+        Box::new(f)
+    }
+}
+
+/// Sends the reminder's text, followed by its image if it has one. Failing
+/// to send the image is only logged, as retrying would repeat the text. The
+/// message is recorded against the reminder, so it can be snoozed by
+/// reacting to it.
+fn send_reminder(
+    logger: Logger,
+    message_sender: &Rc<MessageSender>,
+    reminders: &Reminders,
+    reminder: &Reminder,
+    room_id: &str,
+    text: &str,
+) -> Box<Future<Item = (), Error = Error>> {
+    let message_sender = message_sender.clone();
+    let reminders = reminders.clone();
+    let reminder_id = reminder.id.clone();
+    let image = reminder.image.clone();
+    let room_id = room_id.to_string();
+
+    let send_text = message_sender.send_text_message(&room_id, text);
+
+    let f = send_text.and_then(
+        move |event_id: EventId| -> Box<Future<Item = (), Error = Error>> {
+            if let Err(err) = reminders.set_delivered_event(&reminder_id, &event_id) {
+                error!(logger, "Failed to record delivered message"; "error" => %err);
+            }
+
+            let image = match image {
+                Some(image) => image,
+                None => return Box::new(future::ok(())),
+            };
+
+            let f = message_sender
+                .send_image(
+                    &room_id,
+                    &image.url,
+                    &image.name,
+                    image.mimetype.as_ref().map(String::as_str),
+                )
+                .map(|_| ())
+                .or_else(move |err| {
+                    error!(logger, "Failed to send reminder image"; "error" => %err);
+                    Ok::<_, Error>(())
+                });
+
+            Box::new(f)
+        },
+    );
+
+    Box::new(f)
+}
@@ -0,0 +1,63 @@
+use failure::{Error, ResultExt};
+use futures::Future;
+use slog::Logger;
+
+use std::collections::BTreeMap;
+
+use db::{AddressBook, Reminder};
+
+mod call;
+mod matrix;
+mod sms;
+
+pub use self::call::CallChannel;
+pub use self::matrix::{DirectMessageChannel, MatrixRoomChannel};
+pub use self::sms::{SmsChannel, SmsSender};
+
+/// A delivery failure that won't be fixed by retrying, e.g. the user not
+/// having a phone number.
+#[derive(Fail, Debug)]
+#[fail(display = "{}", _0)]
+pub struct PermanentFailure(pub String);
+
+/// A way of delivering a due reminder to a user.
+pub trait DeliveryChannel {
+    /// Attempts to deliver the reminder, failing if it should be retried.
+    fn deliver(&self, logger: Logger, reminder: &Reminder)
+        -> Box<Future<Item = (), Error = Error>>;
+}
+
+/// The set of available delivery channels, keyed by channel name.
+#[derive(Default)]
+pub struct DeliveryChannels {
+    channels: BTreeMap<String, Box<DeliveryChannel>>,
+}
+
+impl DeliveryChannels {
+    pub fn new() -> DeliveryChannels {
+        DeliveryChannels::default()
+    }
+
+    pub fn register<D: DeliveryChannel + 'static>(&mut self, name: &str, channel: D) {
+        self.channels.insert(name.to_string(), Box::new(channel));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&DeliveryChannel> {
+        self.channels.get(name).map(|channel| &**channel)
+    }
+}
+
+/// Looks up the phone number for the reminder's destination, unless they
+/// have opted out of texts and calls.
+fn get_msisdn(address_book: &AddressBook, reminder: &Reminder) -> Result<String, Error> {
+    if address_book.is_opted_out(&reminder.destination)? {
+        return Err(PermanentFailure(format!("{} has opted out", reminder.destination)).into());
+    }
+
+    let msisdn = address_book
+        .get_msisdn_for_user(&reminder.destination)
+        .context("failed to get msisdn from DB")?
+        .ok_or_else(|| PermanentFailure(format!("no msisdn for {}", reminder.destination)))?;
+
+    Ok(msisdn)
+}
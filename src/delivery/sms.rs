@@ -0,0 +1,115 @@
+use failure::Error;
+use futures::{future, Future};
+use slog::Logger;
+use twilio_rust::messages::{MessageFrom, Messages, OutboundMessageBuilder};
+use twilio_rust::Client;
+use url::Url;
+
+use std::rc::Rc;
+
+use db::{AddressBook, Reminder, DIGEST_ID};
+
+use super::{get_msisdn, DeliveryChannel};
+
+/// Sends texts via Twilio.
+pub struct SmsSender {
+    client: Client,
+    from_num: String,
+}
+
+impl SmsSender {
+    pub fn new(client: Client, from_num: String) -> SmsSender {
+        SmsSender { client, from_num }
+    }
+
+    /// Sends a text, optionally asking Twilio to post status updates to the
+    /// given URL.
+    pub fn send(
+        &self,
+        logger: Logger,
+        to: &str,
+        body: &str,
+        status_callback: Option<&Url>,
+    ) -> Box<Future<Item = (), Error = Error>> {
+        let messages = Messages::new(&self.client);
+
+        let mut builder =
+            OutboundMessageBuilder::new_sms(MessageFrom::From(&self.from_num), to, body);
+        if let Some(url) = status_callback {
+            builder = builder.with_status_callback(url.as_str());
+        }
+        let outbound_sms = builder.build();
+
+        let f = messages
+            .send_message(&outbound_sms)
+            .then(move |res| match res {
+                Ok(msg) => {
+                    if let Some(error) = msg.error_message {
+                        Err(format_err!("error from twilio: {}", error))
+                    } else {
+                        info!(logger, "Message sent"; "sid" => &msg.sid, "status" => ?msg.status);
+                        Ok(())
+                    }
+                }
+                Err(err) => Err(format_err!("error sending sms: {:?}", err)),
+            });
+
+        Box::new(f)
+    }
+}
+
+/// Delivers reminders by SMS via Twilio, to the number in the user's
+/// address book entry.
+pub struct SmsChannel {
+    sender: Rc<SmsSender>,
+    address_book: AddressBook,
+    /// URL Twilio should post message status updates to, if any.
+    status_callback_url: Option<String>,
+}
+
+impl SmsChannel {
+    pub fn new(
+        sender: Rc<SmsSender>,
+        address_book: AddressBook,
+        status_callback_url: Option<String>,
+    ) -> SmsChannel {
+        SmsChannel {
+            sender,
+            address_book,
+            status_callback_url,
+        }
+    }
+}
+
+impl DeliveryChannel for SmsChannel {
+    fn deliver(
+        &self,
+        logger: Logger,
+        reminder: &Reminder,
+    ) -> Box<Future<Item = (), Error = Error>> {
+        let msisdn = match get_msisdn(&self.address_book, reminder) {
+            Ok(msisdn) => msisdn,
+            Err(err) => return Box::new(future::err(err)),
+        };
+
+        // Tag the callback with the reminder so we know what the status
+        // update is for. Digests aren't a reminder, so aren't tracked.
+        let status_callback = match self.status_callback_url {
+            Some(_) if reminder.id == DIGEST_ID => None,
+            Some(ref url) => match Url::parse_with_params(url, &[("reminder_id", &reminder.id)]) {
+                Ok(url) => Some(url),
+                Err(err) => {
+                    return Box::new(future::err(format_err!(
+                        "invalid status callback URL {}: {}",
+                        url,
+                        err
+                    )))
+                }
+            },
+            None => None,
+        };
+
+        self.sender
+            .send(logger, &msisdn, &reminder.text, status_callback.as_ref())
+    }
+}
@@ -1,5 +1,5 @@
-use chrono;
-use db::{Reminder, Reminders};
+use chrono::{DateTime, Duration, Utc};
+use chrono_tz::UTC;
 use futures::{future, Future, Stream};
 use hyper::client::connect::Connect;
 use rand::distributions::Alphanumeric;
@@ -7,29 +7,145 @@ use rand::{thread_rng, Rng, ThreadRng};
 use regex::Regex;
 use slog::Logger;
 use tokio_core::reactor::Handle;
+use url::percent_encoding::percent_decode;
 
-use date::parse_human_datetime;
+use std::rc::Rc;
+
+use commands::{event_image, CommandContext, Commands};
+use db::{ProcessedEvents, ReminderImage, Reminders, SyncTokens};
 use matrix::types::Event;
-use matrix::{MessageSender, Syncer};
+use matrix::{is_unknown_token, MessageSender, Syncer};
+use room_state::RoomStateCache;
+use room_tracker::RoomTracker;
+
+/// Reacting to a delivered reminder with one of these snoozes it.
+const SNOOZE_REACTIONS: &[&str] = &["💤", "⏰"];
+
+/// How long reacting to a reminder snoozes it for.
+const REACTION_SNOOZE_MINS: i64 = 15;
+
+/// How long we remember which events we've processed. Events redelivered
+/// after this long are handled again.
+const PROCESSED_EVENT_TTL_DAYS: i64 = 7;
+
+/// Who is allowed to use the bot, and who can use admin commands.
+pub struct AccessControl {
+    admins: Vec<String>,
+    allowed_users: Vec<String>,
+    allowed_rooms: Vec<String>,
+    /// Senders whose messages are always ignored, e.g. other bots
+    ignored_senders: Vec<Regex>,
+    /// User IDs or server names we accept room invites from
+    invite_allowlist: Vec<String>,
+}
+
+impl AccessControl {
+    /// If both `allowed_users` and `allowed_rooms` are empty then anyone can
+    /// use the bot, otherwise only the listed users or people in the listed
+    /// rooms can. Admins can always use it, unless they match one of the
+    /// `ignored_senders`. Invites are accepted from anyone if
+    /// `invite_allowlist` is empty.
+    pub fn new(
+        admins: Vec<String>,
+        allowed_users: Vec<String>,
+        allowed_rooms: Vec<String>,
+        ignored_senders: Vec<Regex>,
+        invite_allowlist: Vec<String>,
+    ) -> AccessControl {
+        AccessControl {
+            admins,
+            allowed_users,
+            allowed_rooms,
+            ignored_senders,
+            invite_allowlist,
+        }
+    }
+
+    pub fn is_admin(&self, user_id: &str) -> bool {
+        self.admins.iter().any(|admin| admin == user_id)
+    }
+
+    /// Whether the user can send the bot commands from the given room.
+    pub fn is_allowed(&self, user_id: &str, room_id: &str) -> bool {
+        if self.ignored_senders.iter().any(|re| re.is_match(user_id)) {
+            return false;
+        }
+
+        if self.allowed_users.is_empty() && self.allowed_rooms.is_empty() {
+            return true;
+        }
+
+        self.is_admin(user_id)
+            || self.allowed_users.iter().any(|user| user == user_id)
+            || self.allowed_rooms.iter().any(|room| room == room_id)
+    }
+
+    /// Whether we should join rooms the user invites us to.
+    pub fn can_invite(&self, inviter: &str) -> bool {
+        if self.invite_allowlist.is_empty() || self.is_admin(inviter) {
+            return true;
+        }
+
+        let server_name = inviter.splitn(2, ':').nth(1).unwrap_or("");
+
+        self.invite_allowlist
+            .iter()
+            .any(|allowed| allowed == inviter || allowed == server_name)
+    }
+}
 
 pub struct EventHandler {
     logger: Logger,
-    reminders: Reminders,
+    commands: Commands,
+    access: AccessControl,
+    /// The bot's own user ID
+    user_id: String,
+    /// Lowercased names the bot responds to, e.g. "testbot"
+    prefixes: Vec<String>,
+    /// Lowercased mentions of the bot, e.g. "@testbot:example.com"
+    mentions: Vec<String>,
     rng: ThreadRng,
-    message_sender: Box<MessageSender>,
+    message_sender: Rc<MessageSender>,
+    processed_events: ProcessedEvents,
+    sync_tokens: SyncTokens,
+    room_tracker: RoomTracker,
+    room_state: RoomStateCache,
+    reminders: Reminders,
+    /// Messages older than this are ignored, e.g. after the bot was down
+    max_event_age: Duration,
 }
 
 impl EventHandler {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         logger: Logger,
+        commands: Commands,
+        access: AccessControl,
+        user_id: String,
+        prefixes: Vec<String>,
+        message_sender: Rc<MessageSender>,
+        processed_events: ProcessedEvents,
+        sync_tokens: SyncTokens,
+        room_tracker: RoomTracker,
+        room_state: RoomStateCache,
         reminders: Reminders,
-        message_sender: Box<MessageSender>,
+        max_event_age: Duration,
     ) -> EventHandler {
         EventHandler {
             logger,
-            reminders,
+            commands,
+            access,
+            prefixes: prefixes.iter().map(|p| p.to_lowercase()).collect(),
+            mentions: bot_mentions(&user_id),
+            user_id,
             rng: thread_rng(),
             message_sender,
+            processed_events,
+            sync_tokens,
+            room_tracker,
+            room_state,
+            reminders,
+            max_event_age,
         }
     }
 
@@ -41,34 +157,241 @@ impl EventHandler {
         syncer.run().for_each(move |res| {
             match res {
                 Ok(resp) => {
+                    let expired = Utc::now() - Duration::days(PROCESSED_EVENT_TTL_DAYS);
+                    if let Err(err) = self.processed_events.prune_before(&expired) {
+                        error!(self.logger, "Failed to prune processed events"; "error" => %err);
+                    }
+
+                    // Pending invites are included in the initial sync too,
+                    // so we handle them whether or not we're live.
+                    for (room_id, inviter) in resp.sync_response.invites(&self.user_id) {
+                        self.handle_invite(&handle, room_id, inviter);
+                    }
+
+                    self.room_state.handle_sync(&resp.sync_response);
+
+                    self.room_tracker
+                        .handle_sync(&handle, &resp.sync_response, Utc::now());
+
                     if resp.is_live {
                         for (room_id, event) in resp.sync_response.events() {
-                            handle.spawn(self.handle_event(room_id, event))
+                            handle.spawn(self.handle_event(&handle, room_id, event))
                         }
                     }
+
+                    if let Err(err) = self
+                        .sync_tokens
+                        .set_next_batch(&self.user_id, &resp.sync_response.next_batch)
+                    {
+                        error!(self.logger, "Failed to store sync token"; "error" => %err);
+                    }
+                }
+                Err(err) => {
+                    // Retrying won't help, so stop rather than quietly
+                    // failing forever.
+                    if is_unknown_token(&err) {
+                        crit!(
+                            self.logger,
+                            "Matrix access token is invalid, it may have been logged out"
+                        );
+                        return Err(());
+                    }
+
+                    error!(self.logger, "Error"; "err" => %err)
                 }
-                Err(err) => error!(self.logger, "Error"; "err" => %err),
             }
 
             Ok(())
         })
     }
 
-    fn handle_event(&mut self, room_id: &str, event: &Event) -> Box<Future<Item = (), Error = ()>> {
+    fn handle_invite(&self, handle: &Handle, room_id: &str, inviter: Option<&str>) {
+        let inviter = inviter.unwrap_or("");
+
+        if !self.access.can_invite(inviter) {
+            info!(self.logger, "Ignoring invite"; "room_id" => room_id, "inviter" => inviter);
+            return;
+        }
+
+        info!(self.logger, "Accepting invite"; "room_id" => room_id, "inviter" => inviter);
+
+        handle.spawn(self.message_sender.join_room(room_id));
+    }
+
+    /// Records that we've handled the event, returning false if we already
+    /// had. If that can't be recorded we carry on anyway.
+    fn mark_processed(&self, logger: &Logger, event: &Event) -> bool {
+        match self
+            .processed_events
+            .mark_processed(&event.event_id, &Utc::now())
+        {
+            Ok(true) => true,
+            Ok(false) => {
+                info!(logger, "Ignoring already processed event"; "event_id" => &event.event_id);
+                false
+            }
+            Err(err) => {
+                error!(logger, "Failed to record processed event"; "error" => %err);
+                true
+            }
+        }
+    }
+
+    /// Adds the image to the reminder created by the message it's a reply
+    /// to, if there is one and it was created by the image's sender.
+    fn attach_image(
+        &self,
+        logger: &Logger,
+        room_id: &str,
+        event: &Event,
+        image: ReminderImage,
+    ) -> Box<Future<Item = (), Error = ()>> {
+        let original = match event.in_reply_to() {
+            Some(original) => original,
+            None => return Box::new(future::ok(())),
+        };
+
+        let reminder = match self.reminders.get_reminder_for_event(original) {
+            Ok(Some(reminder)) => reminder,
+            Ok(None) => return Box::new(future::ok(())),
+            Err(err) => {
+                error!(logger, "Failed to get reminder for image"; "error" => %err);
+                return Box::new(future::ok(()));
+            }
+        };
+
+        if reminder.creator != event.sender {
+            info!(logger, "Ignoring image for another user's reminder"; "id" => &reminder.id);
+            return Box::new(future::ok(()));
+        }
+
+        if !self.mark_processed(logger, event) {
+            return Box::new(future::ok(()));
+        }
+
+        self.room_tracker.record_activity(room_id);
+
+        let text = match self.reminders.set_image(&reminder.id, &image) {
+            Ok(()) => {
+                info!(logger, "Attached image to reminder"; "id" => &reminder.id);
+                format!("Added the image to reminder {}", reminder.id)
+            }
+            Err(err) => {
+                error!(logger, "Failed to attach image"; "error" => %err);
+                format!("Error: Failed to add the image: {}", err)
+            }
+        };
+
+        let f = self
+            .message_sender
+            .send_reply(room_id, event, &text)
+            .map(|_| ())
+            .map_err(|_| ());
+
+        Box::new(f)
+    }
+
+    /// Snoozes a delivered reminder when its owner reacts to it with a
+    /// snooze emoji.
+    fn handle_reaction(
+        &self,
+        logger: &Logger,
+        room_id: &str,
+        event: &Event,
+        target: &str,
+        key: &str,
+    ) -> Box<Future<Item = (), Error = ()>> {
+        if !SNOOZE_REACTIONS.contains(&key) {
+            return Box::new(future::ok(()));
+        }
+
+        let reminder = match self
+            .reminders
+            .get_snoozable_reminder_for_event(target, &event.sender)
+        {
+            Ok(Some(reminder)) => reminder,
+            Ok(None) => return Box::new(future::ok(())),
+            Err(err) => {
+                error!(logger, "Failed to get reminder to snooze"; "error" => %err);
+                return Box::new(future::ok(()));
+            }
+        };
+
+        if !self.mark_processed(logger, event) {
+            return Box::new(future::ok(()));
+        }
+
+        let due = Utc::now() + Duration::minutes(REACTION_SNOOZE_MINS);
+
+        let text = match self.reminders.snooze_reminder(&reminder.id, &due) {
+            Ok(()) => {
+                info!(logger, "Snoozed reminder by reaction"; "reminder_id" => &reminder.id);
+                let tz = reminder.timezone.unwrap_or(UTC);
+                format!(
+                    "Snoozed '{}' until '{}'",
+                    reminder.text,
+                    due.with_timezone(&tz).to_rfc2822()
+                )
+            }
+            Err(err) => {
+                error!(logger, "Failed to snooze reminder"; "error" => %err);
+                format!("Error: Failed to snooze reminder: {}", err)
+            }
+        };
+
+        let f = self
+            .message_sender
+            .send_text_message(room_id, &text)
+            .map(|_| ())
+            .map_err(|_| ());
+
+        Box::new(f)
+    }
+
+    fn handle_event(
+        &mut self,
+        handle: &Handle,
+        room_id: &str,
+        event: &Event,
+    ) -> Box<Future<Item = (), Error = ()>> {
         let id: String = self.rng.sample_iter(&Alphanumeric).take(20).collect();
 
-        let logger = self.logger.new(o!("id" => id.clone()));
+        let logger = self.logger.new(o!("id" => id));
 
         info!(logger, "Got event";
             "room" => room_id,
             "sender" => &event.sender,
         );
 
-        if event.etype != "m.room.message" {
+        if event.etype != "m.room.message" && event.etype != "m.reaction" {
             return Box::new(future::ok(()));
         }
 
-        let body_opt = event.content.get("body").and_then(|value| value.as_str());
+        // Never react to our own messages, which could end up in a loop
+        if event.sender == self.user_id {
+            return Box::new(future::ok(()));
+        }
+
+        // Even live events can be old if we've been unable to sync for a
+        // while, and acting on them then would be confusing.
+        let age = event_age(event.origin_server_ts, event.unsigned.age, Utc::now());
+        if age > self.max_event_age {
+            info!(logger, "Ignoring stale event"; "age_secs" => age.num_seconds());
+            return Box::new(future::ok(()));
+        }
+
+        // Messages from people who aren't allowed to use the bot are ignored
+        // without a reply, so the bot can sit in busy rooms quietly.
+        if !self.access.is_allowed(&event.sender, room_id) {
+            debug!(logger, "Ignoring message from user who isn't allowed");
+            return Box::new(future::ok(()));
+        }
+
+        if let Some((target, key)) = event.reaction() {
+            return self.handle_reaction(&logger, room_id, event, target, key);
+        }
+
+        let body_opt = event.content_str("body");
 
         let body = if let Some(body) = body_opt {
             body
@@ -76,66 +399,308 @@ impl EventHandler {
             return Box::new(future::ok(()));
         };
 
-        if !body.starts_with("testbot:") {
-            return Box::new(future::ok(()));
+        // Clients insert a pill with the bot's current display name when
+        // autocompleting a mention, so accept whatever text it has.
+        let mut mentions = self.mentions.clone();
+        if let Some(formatted_body) = event.content_str("formatted_body") {
+            if let Some(text) = leading_pill_text(formatted_body, &self.user_id) {
+                mentions.push(text.to_lowercase());
+            }
         }
 
-        let reminder_regex =
-            Regex::new(r"^testbot:\s+remind\s*me\s+(.*)\s+to\s+(.*)$").expect("invalid regex");
-        if let Some(capt) = reminder_regex.captures(body) {
-            let at = &capt[1];
-            let text = &capt[2];
-
-            let now = chrono::Utc::now();
-            let due = match parse_human_datetime(at, now) {
-                Ok(date) => date,
-                Err(_) => {
-                    info!(logger, "Failed to parse date {}", at);
-                    return self
-                        .message_sender
-                        .send_text_message(room_id, &format!("Error: Failed to parse date {}", at));
-                }
-            };
+        let mut prefixes = self.prefixes.clone();
+        if let Some(prefix) = self.room_state.room_config(room_id).prefix {
+            prefixes.push(prefix.to_lowercase());
+        }
 
-            if due < now {
-                info!(logger, "Due date in past: {}", due);
-                return self.message_sender.send_text_message(
-                    room_id,
-                    &format!("Error: Due date in past: {}", due.to_rfc2822()),
-                );
+        let body = if let Some(body) = strip_command_prefix(&prefixes, &mentions, body) {
+            body
+        } else {
+            // Images sent in reply to the message that created a reminder
+            // are added to it.
+            if let Some(image) = event_image(event) {
+                return self.attach_image(&logger, room_id, event, image);
             }
 
-            info!(
-                logger,
-                "Queuing message to be sent at '{}'",
-                due.to_rfc2822(),
-            );
-
-            let res = self.reminders.add_reminder(&Reminder {
-                id,
-                due,
-                text: String::from(text),
-                destination: event.sender.clone(),
-            });
-
-            if let Err(err) = res {
-                error!(logger, "Failed to handle reminder"; "error" => %err);
-                return self.message_sender.send_text_message(
-                    room_id,
-                    &format!("Error: Failed to persist reminder: {}", err),
-                );
-            } else {
-                return self.message_sender.send_text_message(
-                    room_id,
-                    &format!("Queuing message to be sent at '{}'", due.to_rfc2822()),
-                );
-            }
+            return Box::new(future::ok(()));
+        };
 
-        // TODO: persist.
+        let (command, capt) = if let Some(matched) = self.commands.find(body) {
+            matched
         } else {
             info!(logger, "Unrecognized command");
+            return Box::new(future::ok(()));
+        };
+
+        if event.replaces().is_some() && !command.handles_edits() {
+            info!(logger, "Ignoring edited command"; "command" => command.name());
+            return Box::new(future::ok(()));
+        }
+
+        // The same event can turn up again after a restart or if the server
+        // resends it, which mustn't e.g. create the reminder twice. This is
+        // only checked once we know it's a command we'll act on, so that
+        // other messages don't each cost a write, and so that when several
+        // of our accounts are in the room only the first answers a name they
+        // share, without stopping the others seeing their own.
+        if !self.mark_processed(&logger, event) {
+            return Box::new(future::ok(()));
         }
 
-        Box::new(future::ok(()))
+        self.room_tracker.record_activity(room_id);
+
+        let is_admin = self.access.is_admin(&event.sender);
+
+        if command.admin_only() && !is_admin {
+            info!(logger, "Non-admin tried to use admin command"; "command" => command.name());
+            let f = self
+                .message_sender
+                .send_reply(
+                    room_id,
+                    event,
+                    &format!("Error: Only admins can use '{}'", command.name()),
+                )
+                .map(|_| ())
+                .map_err(|_| ());
+
+            return Box::new(f);
+        }
+
+        let ctx = CommandContext {
+            logger: &logger,
+            room_id,
+            event,
+            is_admin,
+            commands: &self.commands,
+            message_sender: &self.message_sender,
+            room_state: &self.room_state,
+        };
+
+        // Show that we're working on it while the command is parsed,
+        // stored and any slower parts of it, e.g. texting a verification
+        // code, run.
+        handle.spawn(self.message_sender.set_typing(room_id, &self.user_id, true));
+
+        let result = command.handle(&ctx, &capt);
+
+        // Lets people see the bot has seen the command, and stops unread
+        // messages piling up for the bot.
+        let receipt = self
+            .message_sender
+            .send_read_receipt(room_id, &event.event_id)
+            .then(|_| Ok(()));
+
+        let message_sender = self.message_sender.clone();
+        let room_id = room_id.to_string();
+        let user_id = self.user_id.clone();
+
+        let f = receipt
+            .join(result)
+            .then(move |_| message_sender.set_typing(&room_id, &user_id, false));
+
+        Box::new(f)
+    }
+}
+
+/// How long ago the event was sent. The server's idea of the age is used if
+/// given, as our clock may not match the sender's server.
+fn event_age(origin_server_ts: u64, unsigned_age: Option<u64>, now: DateTime<Utc>) -> Duration {
+    match unsigned_age {
+        Some(age) => Duration::milliseconds(age as i64),
+        None => Duration::milliseconds(now.timestamp_millis() - origin_server_ts as i64),
     }
 }
+
+/// Strips a leading "<prefix>:" (or "<prefix>,") or mention of the bot from
+/// the message, returning the rest of the message if it was addressed to the
+/// bot. Mentions don't need to be followed by a colon.
+fn strip_command_prefix<'a>(
+    prefixes: &[String],
+    mentions: &[String],
+    body: &'a str,
+) -> Option<&'a str> {
+    let body = body.trim();
+
+    let candidates = prefixes
+        .iter()
+        .map(|p| (p, true))
+        .chain(mentions.iter().map(|m| (m, false)));
+
+    for (prefix, needs_separator) in candidates {
+        let rest = match body.get(..prefix.len()) {
+            Some(start) if start.to_lowercase() == *prefix => &body[prefix.len()..],
+            _ => continue,
+        };
+
+        let rest = if rest.starts_with(':') || rest.starts_with(',') {
+            &rest[1..]
+        } else if needs_separator {
+            continue;
+        } else {
+            rest
+        };
+
+        if rest.is_empty() || rest.starts_with(char::is_whitespace) {
+            return Some(rest.trim());
+        }
+    }
+
+    None
+}
+
+/// The ways users might mention the bot in a plain text message, i.e. the
+/// full user ID or just "@localpart".
+fn bot_mentions(user_id: &str) -> Vec<String> {
+    let user_id = user_id.to_lowercase();
+    let localpart = user_id.split(':').next().unwrap_or("").to_string();
+
+    vec![user_id, localpart]
+}
+
+/// If the HTML body starts with a pill linking to the given user, returns
+/// the text of the pill, which will also be at the start of the plain body.
+fn leading_pill_text<'a>(formatted_body: &'a str, user_id: &str) -> Option<&'a str> {
+    let pill_regex =
+        Regex::new(r#"^\s*<a\s+href=["']https://matrix\.to/#/([^"'?]+)[^"']*["']\s*>([^<]*)</a>"#)
+            .expect("invalid regex");
+
+    let capt = pill_regex.captures(formatted_body)?;
+
+    let target = percent_decode(capt[1].as_bytes()).decode_utf8().ok()?;
+    if target != user_id {
+        return None;
+    }
+
+    capt.get(2).map(|m| m.as_str())
+}
+
+#[test]
+fn strip_command_prefix_test() {
+    let prefixes = vec!["testbot".to_string(), "reminder bot".to_string()];
+    let mentions = vec!["@testbot:example.com".to_string(), "@testbot".to_string()];
+
+    assert_eq!(
+        strip_command_prefix(&prefixes, &mentions, "testbot: list"),
+        Some("list")
+    );
+    assert_eq!(
+        strip_command_prefix(&prefixes, &mentions, "TestBot:   cancel all "),
+        Some("cancel all")
+    );
+    assert_eq!(
+        strip_command_prefix(&prefixes, &mentions, "Reminder Bot, list"),
+        Some("list")
+    );
+    assert_eq!(
+        strip_command_prefix(&prefixes, &mentions, "@testbot:example.com: list"),
+        Some("list")
+    );
+    assert_eq!(
+        strip_command_prefix(&prefixes, &mentions, "@testbot list"),
+        Some("list")
+    );
+    assert_eq!(
+        strip_command_prefix(&prefixes, &mentions, "testbot list"),
+        None
+    );
+    assert_eq!(
+        strip_command_prefix(&prefixes, &mentions, "testbot:list"),
+        None
+    );
+    assert_eq!(
+        strip_command_prefix(&prefixes, &mentions, "testbotty: list"),
+        None
+    );
+    assert_eq!(
+        strip_command_prefix(&prefixes, &mentions, "@testbotty list"),
+        None
+    );
+    assert_eq!(
+        strip_command_prefix(&prefixes, &mentions, "hello testbot: list"),
+        None
+    );
+}
+
+#[test]
+fn leading_pill_text_test() {
+    let user_id = "@testbot:example.com";
+
+    assert_eq!(
+        leading_pill_text(
+            r#"<a href="https://matrix.to/#/@testbot:example.com">Test Bot</a>: list"#,
+            user_id
+        ),
+        Some("Test Bot")
+    );
+    assert_eq!(
+        leading_pill_text(
+            r#"<a href="https://matrix.to/#/%40testbot%3Aexample.com">Test Bot</a>: list"#,
+            user_id
+        ),
+        Some("Test Bot")
+    );
+    assert_eq!(
+        leading_pill_text(
+            r#"<a href="https://matrix.to/#/@other:example.com">Other</a>: list"#,
+            user_id
+        ),
+        None
+    );
+    assert_eq!(
+        leading_pill_text(
+            r#"hi <a href="https://matrix.to/#/@testbot:example.com">Test Bot</a>"#,
+            user_id
+        ),
+        None
+    );
+}
+
+#[test]
+fn access_control_test() {
+    let open = AccessControl::new(
+        vec!["@admin:example.com".to_string()],
+        vec![],
+        vec![],
+        vec![Regex::new(r"^@\w+bot:example\.com$").unwrap()],
+        vec![],
+    );
+    assert!(open.is_allowed("@anyone:example.com", "!room:example.com"));
+    assert!(open.is_admin("@admin:example.com"));
+    assert!(!open.is_admin("@anyone:example.com"));
+    assert!(!open.is_allowed("@otherbot:example.com", "!room:example.com"));
+
+    let restricted = AccessControl::new(
+        vec!["@admin:example.com".to_string()],
+        vec!["@alice:example.com".to_string()],
+        vec!["!team:example.com".to_string()],
+        vec![],
+        vec!["@alice:example.com".to_string(), "trusted.org".to_string()],
+    );
+    assert!(restricted.is_allowed("@alice:example.com", "!other:example.com"));
+    assert!(restricted.is_allowed("@bob:example.com", "!team:example.com"));
+    assert!(restricted.is_allowed("@admin:example.com", "!other:example.com"));
+    assert!(!restricted.is_allowed("@bob:example.com", "!other:example.com"));
+
+    assert!(open.can_invite("@anyone:example.com"));
+    assert!(restricted.can_invite("@alice:example.com"));
+    assert!(restricted.can_invite("@carol:trusted.org"));
+    assert!(restricted.can_invite("@admin:example.com"));
+    assert!(!restricted.can_invite("@bob:example.com"));
+    assert!(!restricted.can_invite(""));
+}
+
+#[test]
+fn event_age_test() {
+    use chrono::TimeZone;
+
+    let now = Utc.timestamp(1_532_000_000, 0);
+
+    assert_eq!(
+        event_age(1_531_999_940_000, None, now),
+        Duration::seconds(60)
+    );
+    assert_eq!(
+        event_age(1_531_999_940_000, Some(5000), now),
+        Duration::seconds(5)
+    );
+}
@@ -0,0 +1,125 @@
+use base64;
+use failure::{Error, ResultExt};
+use futures::{future, Future, Stream};
+use hyper;
+use hyper::client::connect::Connect;
+use serde_json;
+use slog::Logger;
+
+use db::redact_msisdn;
+
+/// What kind of line a phone number is for.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LineType {
+    Mobile,
+    Landline,
+    Voip,
+    Unknown,
+}
+
+pub trait NumberLookup {
+    /// Looks up the line type of a number, returning None if the number
+    /// doesn't exist.
+    fn lookup(&self, msisdn: &str) -> Box<Future<Item = Option<LineType>, Error = Error>>;
+}
+
+#[derive(Debug, Deserialize)]
+struct LookupResponse {
+    carrier: Option<Carrier>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Carrier {
+    #[serde(rename = "type")]
+    line_type: Option<String>,
+}
+
+/// Looks up numbers using the Twilio Lookup API, which is billed per
+/// request.
+pub struct TwilioLookup<C: Connect + 'static> {
+    client: hyper::Client<C>,
+    account_sid: String,
+    auth_token: String,
+    logger: Logger,
+}
+
+impl<C> TwilioLookup<C>
+where
+    C: Connect + 'static,
+{
+    pub fn new(
+        client: hyper::Client<C>,
+        account_sid: String,
+        auth_token: String,
+        logger: Logger,
+    ) -> TwilioLookup<C> {
+        TwilioLookup {
+            client,
+            account_sid,
+            auth_token,
+            logger,
+        }
+    }
+}
+
+impl<C> NumberLookup for TwilioLookup<C>
+where
+    C: Connect + 'static,
+{
+    fn lookup(&self, msisdn: &str) -> Box<Future<Item = Option<LineType>, Error = Error>> {
+        let url = format!(
+            "https://lookups.twilio.com/v1/PhoneNumbers/{}?Type=carrier",
+            msisdn.replace('+', "%2B")
+        );
+
+        info!(self.logger, "Looking up number"; "msisdn" => redact_msisdn(msisdn));
+
+        let credentials = base64::encode(&format!("{}:{}", self.account_sid, self.auth_token));
+
+        let request = hyper::Request::get(url)
+            .header("Authorization", &format!("Basic {}", credentials) as &str)
+            .body(hyper::Body::empty())
+            .expect("valid http request");
+
+        let f = self
+            .client
+            .request(request)
+            .then(|res| res.context("Failed to make lookup request"))
+            .from_err()
+            .and_then(
+                |res| -> Box<Future<Item = Option<hyper::Chunk>, Error = Error>> {
+                    if res.status() == hyper::StatusCode::NOT_FOUND {
+                        Box::new(future::ok(None))
+                    } else if res.status().is_success() {
+                        Box::new(res.into_body().concat2().map(Some).from_err())
+                    } else {
+                        Box::new(future::err(format_err!(
+                            "Got HTTP response: {}",
+                            res.status()
+                        )))
+                    }
+                },
+            )
+            .and_then(|body| {
+                let body = if let Some(body) = body {
+                    body
+                } else {
+                    return Ok(None);
+                };
+
+                let resp: LookupResponse =
+                    serde_json::from_slice(&body).context("Failed to parse lookup response")?;
+
+                let line_type = resp.carrier.and_then(|carrier| carrier.line_type);
+
+                Ok(Some(match line_type.as_ref().map(|t| t as &str) {
+                    Some("mobile") => LineType::Mobile,
+                    Some("landline") => LineType::Landline,
+                    Some("voip") => LineType::Voip,
+                    _ => LineType::Unknown,
+                }))
+            });
+
+        Box::new(f)
+    }
+}
@@ -1,7 +1,10 @@
+extern crate base64;
 extern crate chrono;
+extern crate chrono_tz;
 #[macro_use]
 extern crate failure;
 extern crate futures;
+extern crate hmac;
 extern crate hyper;
 extern crate hyper_tls;
 extern crate linear_map;
@@ -13,6 +16,7 @@ extern crate serde;
 extern crate serde_derive;
 #[macro_use]
 extern crate serde_json;
+extern crate sha1;
 #[macro_use]
 extern crate slog;
 extern crate slog_async;
@@ -22,39 +26,123 @@ extern crate tokio_signal;
 extern crate tokio_timer;
 extern crate toml;
 extern crate twilio_rust;
+extern crate url;
 
-use futures::{Future, Stream};
+use failure::ResultExt;
+use futures::{future, Future, Stream};
 use hyper::Client;
 use hyper_tls::HttpsConnector;
+use regex::Regex;
 use rusqlite::Connection;
+use serde::{Deserialize, Deserializer};
 use slog::Drain;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
 use std::fs::File;
+use std::hash::{Hash, Hasher};
 use std::io::Read;
+use std::path::Path;
+use std::rc::Rc;
 use std::sync::Arc;
 use std::time::Duration;
 
+mod commands;
+mod cron;
 mod date;
 mod db;
+mod delivery;
 mod event_handler;
 mod futures_flag;
+mod lookup;
 mod matrix;
 mod reminder_handler;
-
-use db::{AddressBook, Reminders};
-use event_handler::EventHandler;
+mod room_state;
+mod room_tracker;
+mod rrule;
+mod webhooks;
+
+use db::{
+    AddressBook, BotProfile, Calendar, Channel, DeliveryStatuses, DirectRooms, FailedReminders,
+    ProcessedEvents, Reminders, RoomState, Rooms, Sessions, SyncTokens, UserSettings,
+};
+use delivery::{
+    CallChannel, DeliveryChannels, DirectMessageChannel, MatrixRoomChannel, SmsChannel, SmsSender,
+};
+use event_handler::{AccessControl, EventHandler};
 use reminder_handler::ReminderHandler;
+use room_state::RoomStateCache;
+use room_tracker::RoomTracker;
+use webhooks::WebhookHandler;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct Config {
-    matrix: MatrixConfig,
+    /// The accounts to run the bot as. Either a single `[matrix]` section,
+    /// or one `[[matrix]]` section per account.
+    #[serde(deserialize_with = "one_or_many")]
+    matrix: Vec<MatrixConfig>,
     twilio: TwilioConfig,
     database: String,
+    /// Matrix user IDs allowed to use admin commands
+    #[serde(default)]
+    admins: Vec<String>,
+    /// Matrix user IDs allowed to use the bot. If this and `allowed_rooms`
+    /// are both empty then anyone can.
+    #[serde(default)]
+    allowed_users: Vec<String>,
+    /// Room IDs whose members are allowed to use the bot.
+    #[serde(default)]
+    allowed_rooms: Vec<String>,
+    /// Regexes matching the whole user ID of senders to ignore, e.g. other
+    /// bots, to avoid bots replying to each other.
+    #[serde(default)]
+    ignored_senders: Vec<String>,
+    /// User IDs or server names the bot accepts room invites from. If empty
+    /// the bot joins any room it's invited to.
+    #[serde(default)]
+    invite_allowlist: Vec<String>,
+    webhooks: Option<WebhooksConfig>,
+    /// Commands sent longer ago than this are ignored, e.g. if the bot was
+    /// down when they were sent.
+    #[serde(default = "default_max_command_age_mins")]
+    max_command_age_mins: i64,
+    /// Names the bot responds to, e.g. "testbot" for "testbot: list". The
+    /// bot's display name is also always accepted.
+    #[serde(default = "default_command_prefixes")]
+    command_prefixes: Vec<String>,
+    /// Path to a TOML file of user ID to phone number mappings, which are
+    /// imported into the address book on startup.
+    address_book_import: Option<String>,
+    /// For how long after creating a reminder editing the message replaces
+    /// the reminder.
+    #[serde(default = "default_edit_grace_period_mins")]
+    edit_grace_period_mins: i64,
+    /// How many pending reminders each user can have at once.
+    #[serde(default = "default_max_pending_reminders")]
+    max_pending_reminders: u32,
+    /// How many days ahead reminders can be set, e.g. to reject "in 9999999
+    /// weeks".
+    #[serde(default = "default_max_reminder_horizon_days")]
+    max_reminder_horizon_days: i64,
+    /// Leave rooms nobody has used a command in for this many days, unless
+    /// they still have pending reminders. If unset the bot stays in rooms
+    /// until it's the only member left.
+    leave_unused_rooms_after_days: Option<i64>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
 struct MatrixConfig {
+    /// Either the URL of the client API, e.g. "https://matrix.example.com",
+    /// or a server name to look it up for, e.g. "example.com"
     host: String,
-    access_token: String,
+    /// Either an access token or a user and password to log in with is
+    /// needed.
+    access_token: Option<String>,
+    user: Option<String>,
+    password: Option<String>,
+    /// Display name to give the bot, if it should be changed
+    display_name: Option<String>,
+    /// Path to a PNG, JPEG, GIF or WebP image to use as the bot's avatar
+    avatar_path: Option<String>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -63,6 +151,71 @@ struct TwilioConfig {
     auth_token: String,
     from_num: String,
     // to_num: String,
+    /// Whether to check new phone numbers can receive texts using Twilio
+    /// Lookup, which is charged per lookup.
+    #[serde(default)]
+    lookup_numbers: bool,
+}
+
+/// Config for the HTTP listener that receives callbacks from Twilio.
+#[derive(Debug, Clone, Deserialize)]
+struct WebhooksConfig {
+    /// Local address to listen on, e.g. "0.0.0.0:8088"
+    bind_address: String,
+    /// Base URL Twilio can reach the listener on, without a trailing slash.
+    /// This must match the URL configured in Twilio exactly, as request
+    /// signatures are checked against it.
+    public_url: String,
+}
+
+/// A config value that can be given either once or as a list.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+fn one_or_many<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => Ok(vec![value]),
+        OneOrMany::Many(values) => Ok(values),
+    }
+}
+
+/// A Matrix account the bot runs as, once we've logged in.
+struct Account {
+    user_id: String,
+    homeserver: String,
+    access_token: matrix::AccessToken,
+    /// Names the bot responds to on this account
+    prefixes: Vec<String>,
+    message_sender: Rc<matrix::MessageSender>,
+}
+
+fn default_command_prefixes() -> Vec<String> {
+    vec!["testbot".to_string()]
+}
+
+fn default_max_pending_reminders() -> u32 {
+    100
+}
+
+/// 5 years
+fn default_max_reminder_horizon_days() -> i64 {
+    5 * 365
+}
+
+fn default_edit_grace_period_mins() -> i64 {
+    10
+}
+
+fn default_max_command_age_mins() -> i64 {
+    60
 }
 
 fn main() {
@@ -87,9 +240,56 @@ fn main() {
 
     // Set up reminders handling
 
-    let reminders = Reminders::with_connection(database.clone()).expect("failed to open reminders");
+    let reminders = Reminders::with_connection(database.clone(), config.max_pending_reminders)
+        .expect("failed to open reminders");
 
-    let address_book = AddressBook::with_connection(database).expect("failed to open address book");
+    // Anything still marked in flight was interrupted mid delivery, so
+    // needs sending again.
+    let recovered = reminders
+        .recover_in_flight()
+        .expect("failed to recover in flight reminders");
+    if recovered > 0 {
+        warn!(logger, "Requeued interrupted reminders"; "count" => recovered);
+    }
+
+    let address_book =
+        AddressBook::with_connection(database.clone()).expect("failed to open address book");
+
+    if let Some(ref path) = config.address_book_import {
+        import_address_book(&logger, &address_book, path);
+    }
+
+    let failed_reminders = FailedReminders::with_connection(database.clone())
+        .expect("failed to open failed reminders");
+
+    let delivery_statuses = DeliveryStatuses::with_connection(database.clone())
+        .expect("failed to open delivery statuses");
+
+    let user_settings = UserSettings::with_connection(
+        database.clone(),
+        Some(chrono::Duration::days(config.max_reminder_horizon_days)),
+    )
+    .expect("failed to open user settings");
+
+    let calendar = Calendar::with_connection(database.clone()).expect("failed to open calendar");
+
+    let direct_rooms =
+        DirectRooms::with_connection(database.clone()).expect("failed to open direct rooms");
+
+    let processed_events = ProcessedEvents::with_connection(database.clone())
+        .expect("failed to open processed events");
+
+    let rooms = Rooms::with_connection(database.clone()).expect("failed to open rooms");
+
+    let room_state =
+        RoomState::with_connection(database.clone()).expect("failed to open room state");
+
+    let sync_tokens =
+        SyncTokens::with_connection(database.clone()).expect("failed to open sync tokens");
+
+    let sessions = Sessions::with_connection(database.clone()).expect("failed to open sessions");
+
+    let bot_profile = BotProfile::with_connection(database).expect("failed to open bot profile");
 
     let twilio_client = twilio_rust::Client::new(
         &config.twilio.account_sid,
@@ -97,63 +297,348 @@ fn main() {
         &handle,
     ).expect("failed to set up twilio client");
 
+    let twilio_call_client = twilio_rust::Client::new(
+        &config.twilio.account_sid,
+        &config.twilio.auth_token,
+        &handle,
+    )
+    .expect("failed to set up twilio client");
+
+    let sms_sender = Rc::new(SmsSender::new(
+        twilio_client,
+        config.twilio.from_num.clone(),
+    ));
+
+    let connector = HttpsConnector::new(4).expect("tls setup");
+    let http_client = Client::builder().build(connector);
+
+    // Log in to each of the matrix accounts
+
+    let accounts: Vec<Account> = config
+        .matrix
+        .iter()
+        .map(|matrix_config| {
+            setup_account(
+                &logger,
+                &mut core,
+                &http_client,
+                matrix_config,
+                &config.command_prefixes,
+                &sessions,
+                &bot_profile,
+            )
+        })
+        .collect();
+
+    if accounts.is_empty() {
+        panic!("config needs at least one matrix section");
+    }
+
+    // The sync token from before we supported several accounts must have
+    // been for the first one.
+    sync_tokens
+        .adopt_legacy_token(&accounts[0].user_id)
+        .expect("failed to move sync token in database");
+
+    let mut channels = DeliveryChannels::new();
+    channels.register(
+        Channel::Sms.as_str(),
+        SmsChannel::new(
+            sms_sender.clone(),
+            address_book.clone(),
+            config
+                .webhooks
+                .as_ref()
+                .map(|c| format!("{}{}", c.public_url, webhooks::STATUS_CALLBACK_PATH)),
+        ),
+    );
+    channels.register(
+        Channel::Call.as_str(),
+        CallChannel::new(
+            twilio_call_client,
+            config.twilio.from_num.clone(),
+            address_book.clone(),
+            config
+                .webhooks
+                .as_ref()
+                .map(|c| format!("{}{}", c.public_url, webhooks::CALL_TWIML_PATH)),
+        ),
+    );
+    channels.register(
+        Channel::Matrix.as_str(),
+        MatrixRoomChannel::new(
+            accounts
+                .iter()
+                .map(|account| (account.user_id.clone(), account.message_sender.clone()))
+                .collect(),
+            rooms.clone(),
+            reminders.clone(),
+        ),
+    );
+    channels.register(
+        Channel::Direct.as_str(),
+        DirectMessageChannel::new(
+            accounts[0].message_sender.clone(),
+            direct_rooms.clone(),
+            reminders.clone(),
+        ),
+    );
+
     let reminder_handler = ReminderHandler::new(
         logger.clone(),
-        twilio_client,
-        config.clone(),
+        config
+            .command_prefixes
+            .first()
+            .cloned()
+            .unwrap_or_else(|| "testbot".to_string()),
         reminders.clone(),
-        address_book,
+        failed_reminders.clone(),
+        user_settings.clone(),
+        channels,
     );
 
     let reminder_loop = spawn_reminder_loop(handle.clone(), reminder_handler);
     handle.spawn(reminder_loop);
 
-    // Set up matrix::Syncer
+    // Set up Twilio webhook handling
 
-    let connector = HttpsConnector::new(4).expect("tls setup");
-    let http_client = Client::builder().build(connector);
+    if let Some(ref webhooks_config) = config.webhooks {
+        let addr = webhooks_config
+            .bind_address
+            .parse()
+            .expect("invalid webhook bind address");
 
-    let mut stop_flag = futures_flag::Flag::new();
+        let validator = webhooks::SignatureValidator::new(
+            config.twilio.auth_token.clone(),
+            webhooks_config.public_url.clone(),
+        );
 
-    let syncer = matrix::Syncer::new(
-        http_client.clone(),
-        config.matrix.host.clone(),
-        config.matrix.access_token.clone(),
-        logger.clone(),
-        stop_flag.clone(),
-    );
+        let webhook_stream = webhooks::listen(&handle, &addr, validator, logger.clone())
+            .expect("failed to start webhooks");
 
-    // Set up graceful shutdown
+        let webhook_handler = WebhookHandler::new(
+            logger.clone(),
+            delivery_statuses.clone(),
+            reminders.clone(),
+            address_book.clone(),
+            user_settings.clone(),
+        );
 
-    let ctrl_c = tokio_signal::ctrl_c()
-        .flatten_stream()
-        .for_each(move |()| {
-            // We got a SIGINT, lets stop things gracefully.
-            stop_flag.set();
+        handle.spawn(webhook_stream.for_each(move |webhook| {
+            webhook_handler.handle_webhook(webhook);
             Ok(())
-        })
-        .map_err(|_| ());
-    handle.spawn(ctrl_c);
-
-    // Set up matrix message sender
-
-    let message_sender = matrix::MessageSenderHyper::new(
-        http_client,
-        config.matrix.host.clone(),
-        config.matrix.access_token.clone(),
-        logger.clone(),
-    );
+        }));
+    }
 
-    // Set up main event handling code
+    // Set up graceful shutdown
 
-    let event_handler =
-        EventHandler::new(logger.clone(), reminders.clone(), Box::new(message_sender));
+    let stop_flag = futures_flag::Flag::new();
+
+    {
+        let mut stop_flag = stop_flag.clone();
+        let ctrl_c = tokio_signal::ctrl_c()
+            .flatten_stream()
+            .for_each(move |()| {
+                // We got a SIGINT, lets stop things gracefully.
+                stop_flag.set();
+                Ok(())
+            })
+            .map_err(|_| ());
+        handle.spawn(ctrl_c);
+    }
+
+    // Set up phone number lookups
+
+    let number_lookup: Option<Rc<lookup::NumberLookup>> = if config.twilio.lookup_numbers {
+        Some(Rc::new(lookup::TwilioLookup::new(
+            http_client.clone(),
+            config.twilio.account_sid.clone(),
+            config.twilio.auth_token.clone(),
+            logger.clone(),
+        )))
+    } else {
+        None
+    };
+
+    let ignored_senders = config
+        .ignored_senders
+        .iter()
+        .map(|pattern| Regex::new(&format!("^(?:{})$", pattern)))
+        .collect::<Result<Vec<_>, _>>()
+        .expect("invalid ignored sender pattern");
+
+    // Room names are the same whichever account sees them, so all accounts
+    // share the cache.
+    let room_state = RoomStateCache::new(logger.clone(), room_state);
+
+    // Set up a matrix::Syncer and event handling for each account
+
+    let mut syncs = Vec::new();
+
+    for account in accounts {
+        let logger = logger.new(o!("user_id" => account.user_id.clone()));
+
+        let next_batch = sync_tokens
+            .get_next_batch(&account.user_id)
+            .expect("failed to get sync token from database");
+        if next_batch.is_some() {
+            info!(logger, "Resuming sync from previous run");
+        }
+
+        let syncer = matrix::Syncer::new(
+            http_client.clone(),
+            account.homeserver.clone(),
+            account.access_token.clone(),
+            logger.clone(),
+            stop_flag.clone(),
+            next_batch,
+        );
+
+        let room_tracker = RoomTracker::new(
+            logger.clone(),
+            account.user_id.clone(),
+            rooms.clone(),
+            direct_rooms.clone(),
+            account.message_sender.clone(),
+            config
+                .leave_unused_rooms_after_days
+                .map(chrono::Duration::days),
+        );
+
+        // Set up the commands the bot understands, in the order they're tried
+
+        let pending_reminders = commands::PendingReminders::new();
+
+        // The name to show in examples of commands
+        let help_prefix = account
+            .prefixes
+            .first()
+            .cloned()
+            .unwrap_or_else(|| "testbot".to_string());
+
+        let mut commands = commands::Commands::new();
+        commands.register(commands::RemindCommand::new(
+            reminders.clone(),
+            user_settings.clone(),
+            chrono::Duration::minutes(config.edit_grace_period_mins),
+            pending_reminders.clone(),
+            help_prefix.clone(),
+        ));
+        commands.register(commands::ConfirmCommand::new(
+            reminders.clone(),
+            user_settings.clone(),
+            pending_reminders,
+            help_prefix.clone(),
+        ));
+        commands.register(commands::SetDeliveryCommand::new(user_settings.clone()));
+        commands.register(commands::SetTimezoneCommand::new(user_settings.clone()));
+        commands.register(commands::AllowOthersCommand::new(user_settings.clone()));
+        commands.register(commands::PauseCommand::new(user_settings.clone()));
+        commands.register(commands::SetQuietHoursCommand::new(user_settings.clone()));
+        commands.register(commands::SetDigestCommand::new(user_settings.clone()));
+        commands.register(commands::SetPartOfDayCommand::new(user_settings.clone()));
+        commands.register(commands::SetDateOrderCommand::new(user_settings.clone()));
+        commands.register(commands::SetLanguageCommand::new(user_settings.clone()));
+        commands.register(commands::SetConfirmationsCommand::new(
+            user_settings.clone(),
+        ));
+        commands.register(commands::ListCommand::new(
+            reminders.clone(),
+            user_settings.clone(),
+        ));
+        commands.register(commands::FindCommand::new(
+            reminders.clone(),
+            user_settings.clone(),
+        ));
+        commands.register(commands::SnoozeCommand::new(
+            reminders.clone(),
+            user_settings.clone(),
+        ));
+        commands.register(commands::AgainCommand::new(
+            reminders.clone(),
+            user_settings.clone(),
+        ));
+        commands.register(commands::EditCommand::new(reminders.clone()));
+        commands.register(commands::RescheduleCommand::new(
+            reminders.clone(),
+            user_settings.clone(),
+        ));
+        commands.register(commands::SkipCommand::new(
+            reminders.clone(),
+            user_settings.clone(),
+        ));
+        commands.register(commands::CancelAllCommand::new(reminders.clone()));
+        commands.register(commands::CancelCommand::new(reminders.clone()));
+        commands.register(commands::UndoCommand::new(reminders.clone()));
+        commands.register(commands::DoneCommand::new(reminders.clone()));
+        commands.register(commands::SetPhoneCommand::new(
+            address_book.clone(),
+            sms_sender.clone(),
+            number_lookup.clone(),
+        ));
+        commands.register(commands::VerifyCommand::new(address_book.clone()));
+        commands.register(commands::ForgetPhoneCommand::new(address_book.clone()));
+        commands.register(commands::StatusCommand::new(
+            delivery_statuses.clone(),
+            user_settings.clone(),
+        ));
+        commands.register(commands::FailedCommand::new(failed_reminders.clone()));
+        commands.register(commands::ListAllCommand::new(
+            reminders.clone(),
+            room_state.clone(),
+            direct_rooms.clone(),
+        ));
+        commands.register(commands::PurgeCommand::new(reminders.clone()));
+        commands.register(commands::AddNamedDateCommand::new(calendar.clone()));
+        commands.register(commands::RemoveNamedDateCommand::new(calendar.clone()));
+        commands.register(commands::ListNamedDatesCommand::new(calendar.clone()));
+        commands.register(commands::AddHolidayCommand::new(
+            user_settings.clone(),
+            calendar.clone(),
+        ));
+        commands.register(commands::RemoveHolidayCommand::new(
+            user_settings.clone(),
+            calendar.clone(),
+        ));
+        commands.register(commands::ListHolidaysCommand::new(
+            user_settings.clone(),
+            calendar.clone(),
+        ));
+        commands.register(commands::SetRoomConfigCommand::new());
+        commands.register(commands::BroadcastCommand::new());
+        commands.register(commands::HelpCommand::new(help_prefix));
+
+        // Set up main event handling code
+
+        let event_handler = EventHandler::new(
+            logger.clone(),
+            commands,
+            AccessControl::new(
+                config.admins.clone(),
+                config.allowed_users.clone(),
+                config.allowed_rooms.clone(),
+                ignored_senders.clone(),
+                config.invite_allowlist.clone(),
+            ),
+            account.user_id,
+            account.prefixes,
+            account.message_sender,
+            processed_events.clone(),
+            sync_tokens.clone(),
+            room_tracker,
+            room_state.clone(),
+            reminders.clone(),
+            chrono::Duration::minutes(config.max_command_age_mins),
+        );
+
+        syncs.push(event_handler.start_from_sync(handle.clone(), syncer));
+    }
 
     // Actually start syncing from matrix
 
-    info!(logger, "Starting");
+    info!(logger, "Starting"; "accounts" => syncs.len());
 
-    core.run(event_handler.start_from_sync(handle, syncer))
+    core.run(future::join_all(syncs))
         .expect("sync stream failed");
 }
 
@@ -174,6 +659,301 @@ fn parse_config() -> Config {
     toml::from_str(&s).expect("failed to parse config")
 }
 
+/// Imports phone numbers from a TOML file of the form:
+///
+/// ```toml
+/// "@alice:example.com" = "+447700900123"
+/// ```
+///
+/// Invalid entries are logged and skipped.
+fn import_address_book(logger: &slog::Logger, address_book: &AddressBook, path: &str) {
+    let mut f = File::open(path).expect("couldn't find address book import file");
+    let mut s = String::new();
+    f.read_to_string(&mut s)
+        .expect("failed to read address book import file");
+
+    let entries: BTreeMap<String, String> =
+        toml::from_str(&s).expect("failed to parse address book import file");
+
+    let mut imported = 0;
+    for (user_id, msisdn) in &entries {
+        match address_book.set_msisdn_for_user(user_id, msisdn) {
+            Ok(()) => imported += 1,
+            Err(err) => {
+                warn!(logger, "Failed to import phone number"; "user_id" => user_id, "error" => %err)
+            }
+        }
+    }
+
+    info!(logger, "Imported address book"; "imported" => imported, "total" => entries.len());
+}
+
+/// Finds the account's homeserver, logs in and sets up its profile.
+#[allow(clippy::too_many_arguments)]
+fn setup_account<C: hyper::client::connect::Connect + 'static>(
+    logger: &slog::Logger,
+    core: &mut tokio_core::reactor::Core,
+    client: &Client<C>,
+    config: &MatrixConfig,
+    command_prefixes: &[String],
+    sessions: &Sessions,
+    bot_profile: &BotProfile,
+) -> Account {
+    let homeserver = core
+        .run(matrix::discover_homeserver(client, &config.host))
+        .expect("failed to find homeserver");
+
+    info!(logger, "Using homeserver"; "url" => &homeserver);
+
+    let access_token = get_access_token(logger, core, client, &homeserver, config, sessions);
+
+    // Work out what names the bot should respond to
+
+    let (user_id, display_name) = core
+        .run(matrix::get_own_profile(client, &homeserver, &access_token))
+        .expect("failed to get bot's profile");
+
+    info!(logger, "Got bot profile"; "user_id" => &user_id, "display_name" => ?display_name);
+
+    let display_name = setup_profile(
+        logger,
+        core,
+        client,
+        &homeserver,
+        &access_token,
+        &user_id,
+        display_name,
+        config,
+        bot_profile,
+    );
+
+    let mut prefixes = command_prefixes.to_vec();
+    prefixes.extend(display_name);
+
+    let message_sender: Rc<matrix::MessageSender> = Rc::new(matrix::MessageSenderHyper::new(
+        client.clone(),
+        homeserver.clone(),
+        access_token.clone(),
+        logger.clone(),
+    ));
+
+    Account {
+        user_id,
+        homeserver,
+        access_token,
+        prefixes,
+        message_sender,
+    }
+}
+
+/// Gets the access token from the config, or otherwise logs in with the
+/// configured user and password. The tokens from logging in are stored and
+/// reused on later runs, updated whenever they're refreshed, and replaced by
+/// logging in again if the session is logged out.
+fn get_access_token<C: hyper::client::connect::Connect + 'static>(
+    logger: &slog::Logger,
+    core: &mut tokio_core::reactor::Core,
+    client: &Client<C>,
+    homeserver: &str,
+    config: &MatrixConfig,
+    sessions: &Sessions,
+) -> matrix::AccessToken {
+    if let Some(ref access_token) = config.access_token {
+        return matrix::AccessToken::new(access_token.clone(), None, |_, _| {});
+    }
+
+    let (user, password) = match (&config.user, &config.password) {
+        (Some(user), Some(password)) => (user, password),
+        _ => panic!("config needs either matrix.access_token or matrix.user and matrix.password"),
+    };
+
+    let on_refresh = {
+        let logger = logger.clone();
+        let sessions = sessions.clone();
+        let user = user.clone();
+        move |access_token: &str, refresh_token: Option<&str>| {
+            info!(logger, "Refreshed access token"; "user" => &user);
+            if let Err(err) = sessions.update_tokens(&user, access_token, refresh_token) {
+                error!(logger, "Failed to store refreshed access token"; "error" => %err);
+            }
+        }
+    };
+
+    // If the stored session gets logged out, we replace it by logging in
+    // again, or forget it if we can't.
+    let on_login = {
+        let logger = logger.clone();
+        let sessions = sessions.clone();
+        let user = user.clone();
+        move |res: Result<&matrix::types::LoginResponse, &failure::Error>| match res {
+            Ok(resp) => {
+                info!(logger, "Logged in again"; "user_id" => &resp.user_id, "device_id" => &resp.device_id);
+                let res = sessions.set_session(
+                    &user,
+                    &resp.access_token,
+                    resp.refresh_token.as_ref().map(String::as_str),
+                    &resp.device_id,
+                );
+                if let Err(err) = res {
+                    error!(logger, "Failed to store session"; "error" => %err);
+                }
+            }
+            Err(err) => {
+                error!(logger, "Failed to log in again"; "user" => &user, "error" => %err);
+                if let Err(err) = sessions.delete_session(&user) {
+                    error!(logger, "Failed to delete session"; "error" => %err);
+                }
+            }
+        }
+    };
+
+    if let Some((access_token, refresh_token)) = sessions
+        .get_session(user)
+        .expect("failed to get session from database")
+    {
+        return matrix::AccessToken::new(access_token, refresh_token, on_refresh).with_credentials(
+            user.clone(),
+            password.clone(),
+            on_login,
+        );
+    }
+
+    let resp = core
+        .run(matrix::login(client, homeserver, user, password))
+        .expect("failed to log in");
+
+    info!(logger, "Logged in"; "user_id" => &resp.user_id, "device_id" => &resp.device_id);
+
+    // Store the session under the user from the config, rather than the
+    // full user ID we get back, so that we find it again next time.
+    sessions
+        .set_session(
+            user,
+            &resp.access_token,
+            resp.refresh_token.as_ref().map(String::as_str),
+            &resp.device_id,
+        )
+        .expect("failed to store session in database");
+
+    matrix::AccessToken::new(resp.access_token, resp.refresh_token, on_refresh).with_credentials(
+        user.clone(),
+        password.clone(),
+        on_login,
+    )
+}
+
+/// Sets the display name and avatar from the config on the bot's profile,
+/// if they've changed, returning the display name the bot now has. Failures
+/// are logged rather than stopping the bot.
+#[allow(clippy::too_many_arguments)]
+fn setup_profile<C: hyper::client::connect::Connect + 'static>(
+    logger: &slog::Logger,
+    core: &mut tokio_core::reactor::Core,
+    client: &Client<C>,
+    homeserver: &str,
+    access_token: &matrix::AccessToken,
+    user_id: &str,
+    mut display_name: Option<String>,
+    config: &MatrixConfig,
+    bot_profile: &BotProfile,
+) -> Option<String> {
+    if let Some(ref wanted) = config.display_name {
+        if display_name.as_ref() != Some(wanted) {
+            match core.run(matrix::set_display_name(
+                client,
+                homeserver,
+                access_token,
+                user_id,
+                wanted,
+            )) {
+                Ok(()) => {
+                    info!(logger, "Set display name"; "display_name" => wanted);
+                    display_name = Some(wanted.clone());
+                }
+                Err(err) => error!(logger, "Failed to set display name"; "error" => %err),
+            }
+        }
+    }
+
+    if let Some(ref path) = config.avatar_path {
+        if let Err(err) = set_avatar(
+            logger,
+            core,
+            client,
+            homeserver,
+            access_token,
+            user_id,
+            path,
+            bot_profile,
+        ) {
+            error!(logger, "Failed to set avatar"; "path" => path, "error" => %err);
+        }
+    }
+
+    display_name
+}
+
+/// Uploads the image as the bot's avatar, unless it's the one we last set.
+#[allow(clippy::too_many_arguments)]
+fn set_avatar<C: hyper::client::connect::Connect + 'static>(
+    logger: &slog::Logger,
+    core: &mut tokio_core::reactor::Core,
+    client: &Client<C>,
+    homeserver: &str,
+    access_token: &matrix::AccessToken,
+    user_id: &str,
+    path: &str,
+    bot_profile: &BotProfile,
+) -> Result<(), failure::Error> {
+    let content_type = match image_content_type(path) {
+        Some(content_type) => content_type,
+        None => bail!("unsupported image type, use PNG, JPEG, GIF or WebP"),
+    };
+
+    let mut image = Vec::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_end(&mut image))
+        .context("failed to read avatar")?;
+
+    // Only used to spot changes, so it doesn't matter if the hash changes
+    // between Rust versions, that just means uploading it again.
+    let mut hasher = DefaultHasher::new();
+    image.hash(&mut hasher);
+    let hash = format!("{:016x}", hasher.finish());
+
+    if bot_profile.get_avatar_hash(user_id)?.as_ref() == Some(&hash) {
+        return Ok(());
+    }
+
+    core.run(matrix::set_avatar(
+        client,
+        homeserver,
+        access_token,
+        user_id,
+        content_type,
+        image,
+    ))?;
+
+    bot_profile.set_avatar_hash(user_id, &hash)?;
+
+    info!(logger, "Set avatar"; "path" => path);
+
+    Ok(())
+}
+
+/// The MIME type of the image, going by its file extension.
+fn image_content_type(path: &str) -> Option<&'static str> {
+    let extension = Path::new(path).extension()?.to_str()?.to_lowercase();
+
+    match &extension as &str {
+        "png" => Some("image/png"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "gif" => Some("image/gif"),
+        "webp" => Some("image/webp"),
+        _ => None,
+    }
+}
+
 fn spawn_reminder_loop(
     handle: tokio_core::reactor::Handle,
     handler: ReminderHandler,
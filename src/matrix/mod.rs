@@ -2,9 +2,16 @@ use failure::{Error, ResultExt};
 use futures::{future, stream, Future, Stream};
 use hyper;
 use hyper::client::connect::Connect;
+use hyper::header::RETRY_AFTER;
+use hyper::StatusCode;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use serde::de::DeserializeOwned;
 use serde_json;
 use slog::Logger;
 use tokio_timer::sleep;
+use url::form_urlencoded;
+use url::percent_encoding::{utf8_percent_encode, PATH_SEGMENT_ENCODE_SET, USERINFO_ENCODE_SET};
 
 use std::cell::RefCell;
 use std::rc::Rc;
@@ -12,17 +19,69 @@ use std::time::Duration;
 
 use futures_flag::{Flag, FutureExt};
 
+mod send_queue;
+mod token;
 pub mod types;
 
-use self::types::{SyncResponse, SyncStreamItem};
+use self::send_queue::SendQueue;
+use self::token::authed_request;
+pub use self::token::AccessToken;
+
+use self::types::{
+    CreateRoomResponse, DisplayNameResponse, ErrorResponse, Event, JoinedRoomsResponse,
+    LoginResponse, MessagesResponse, SendEventResponse, SyncResponse, SyncStreamItem,
+    UploadResponse, WellKnownResponse, WhoamiResponse,
+};
+
+/// The ID of an event, e.g. one we've sent.
+pub type EventId = String;
+
+/// The most events we fetch for a room whose timeline had a gap in it.
+const BACKFILL_LIMIT: u32 = 100;
+
+/// The state event type rooms are configured with.
+pub const ROOM_CONFIG_EVENT_TYPE: &str = "org.reminderbot.config";
+
+/// How long the typing indicator shows for if we don't clear it.
+const TYPING_TIMEOUT_MS: u64 = 30 * 1000;
+
+/// The delay before retrying a failed sync, doubled after each further
+/// failure.
+const BASE_SYNC_RETRY_MS: u64 = 1000;
+
+/// The longest we wait between failed syncs.
+const MAX_SYNC_RETRY_MS: u64 = 5 * 60 * 1000;
 
 #[derive(Fail, Debug)]
 #[fail(display = "Syncer was stopped")]
 struct StopError;
 
+/// The homeserver no longer accepts our access token, and we couldn't get a
+/// new one, so syncing won't work until it's replaced.
+#[derive(Fail, Debug)]
+#[fail(display = "Access token is not valid")]
+pub struct UnknownToken;
+
+/// Whether the error was caused by the access token being invalid, even if
+/// it has since had context added.
+pub fn is_unknown_token(err: &Error) -> bool {
+    err.causes()
+        .any(|cause| cause.downcast_ref::<UnknownToken>().is_some())
+}
+
+/// The homeserver asked us to slow down, optionally saying for how long.
+#[derive(Fail, Debug)]
+#[fail(display = "Rate limited by homeserver")]
+struct RateLimited {
+    retry_after: Option<Duration>,
+}
+
 #[derive(Debug, Clone, Default)]
 struct SyncState {
-    errored: bool,
+    /// How many syncs in a row have failed
+    failures: u32,
+    /// How long the homeserver asked us to wait after the last failure
+    retry_after: Option<Duration>,
     is_live: bool,
     next_batch: Option<String>,
 }
@@ -32,8 +91,10 @@ pub struct Syncer<C: Connect + 'static> {
     client: hyper::Client<C>,
     stop_flag: Flag,
     base_host: String,
-    access_token: String,
+    access_token: AccessToken,
     logger: Logger,
+    /// URL encoded filter limiting the sync to what we use
+    filter: String,
 }
 
 impl<C> Syncer<C>
@@ -43,84 +104,133 @@ where
     pub fn new(
         client: hyper::Client<C>,
         base_host: String,
-        access_token: String,
+        access_token: AccessToken,
         logger: Logger,
         stop_flag: Flag,
+        next_batch: Option<String>,
     ) -> Syncer<C> {
+        // If we're resuming from a previous run then everything from the
+        // first sync on is new.
+        let state = SyncState {
+            is_live: next_batch.is_some(),
+            next_batch,
+            ..SyncState::default()
+        };
+
+        let filter =
+            utf8_percent_encode(&sync_filter().to_string(), USERINFO_ENCODE_SET).to_string();
+
         Syncer {
-            state: Rc::default(),
+            state: Rc::new(RefCell::new(state)),
             client,
             stop_flag,
             base_host,
             access_token,
             logger,
+            filter,
         }
     }
 
-    fn create_request(&self) -> hyper::Request<hyper::Body> {
+    fn sync_url(&self) -> String {
         let url = if let Some(ref nb) = self.state.borrow().next_batch {
             format!(
-                "{}/_matrix/client/r0/sync?since={}&timeout=60000",
-                self.base_host, nb
+                "{}/_matrix/client/r0/sync?since={}&timeout=60000&filter={}",
+                self.base_host, nb, self.filter
             )
         } else {
-            format!("{}/_matrix/client/r0/sync", self.base_host)
+            format!(
+                "{}/_matrix/client/r0/sync?filter={}",
+                self.base_host, self.filter
+            )
         };
 
         trace!(self.logger, "Using url: {}", url);
 
-        hyper::Request::get(url)
-            .header(
-                "Authorization",
-                &format!("Bearer {}", &self.access_token) as &str,
-            )
-            .body(hyper::Body::empty())
-            .expect("valid http request")
+        url
     }
 
     fn do_sync(&mut self) -> Box<Future<Item = SyncStreamItem, Error = Error>> {
-        let request = self.create_request();
+        let url = self.sync_url();
 
         // If we've previously errored getting the sync, lets back off
-        // a bit
-        let sleep_fut = if self.state.borrow().errored {
-            Box::new(sleep(Duration::from_secs(5)).map_err(Error::from))
-                as Box<Future<Item = _, Error = Error>>
+        // a bit, for as long as the server asked if it did.
+        let delay = {
+            let state = self.state.borrow();
+            if state.failures > 0 {
+                Some(
+                    state
+                        .retry_after
+                        .unwrap_or_else(|| with_jitter(sync_retry_delay(state.failures))),
+                )
+            } else {
+                None
+            }
+        };
+
+        let sleep_fut = if let Some(delay) = delay {
+            debug!(self.logger, "Backing off before syncing"; "delay_ms" => duration_millis(delay));
+            Box::new(sleep(delay).map_err(Error::from)) as Box<Future<Item = _, Error = Error>>
         } else {
             Box::new(future::ok(()))
         };
 
-        let request_future = self
-            .client
-            .request(request)
-            .then(|res| res.context("Failed to make HTTP sync request"))
-            .from_err()
-            .with_flag(self.stop_flag.clone(), StopError.into());
+        let request_future = authed_request(
+            &self.client,
+            &self.base_host,
+            &self.access_token,
+            move |access_token| get_request(&url, access_token),
+        )
+        .then(|res| res.context("Failed to make HTTP sync request"))
+        .from_err()
+        .with_flag(self.stop_flag.clone(), StopError.into());
 
         let logger = self.logger.clone();
         let logger2 = self.logger.clone();
+        let logger3 = self.logger.clone();
         let state = self.state.clone();
         let state2 = self.state.clone();
 
+        let since = self.state.borrow().next_batch.clone();
+        let client = self.client.clone();
+        let base_host = self.base_host.clone();
+        let access_token = self.access_token.clone();
+
         let f = sleep_fut
             .with_flag(self.stop_flag.clone(), StopError.into())
             .and_then(move |_| {
                 trace!(logger, "Making sync request");
                 request_future
             })
-            .and_then(|res| {
-                if res.status().is_success() {
-                    Ok(res)
-                } else {
-                    Err(format_err!("Got HTTP response: {}", res.status()))
-                }
-            })
+            .and_then(
+                |res| -> Box<Future<Item = hyper::Response<hyper::Body>, Error = Error>> {
+                    if res.status().is_success() {
+                        Box::new(future::ok(res))
+                    } else {
+                        error_from_response(res)
+                    }
+                },
+            )
             .and_then(|res| res.into_body().concat2().from_err())
             .and_then(|body: hyper::Chunk| {
                 let body: SyncResponse =
                     serde_json::from_slice(&body).context("Failed to parse sync response")?;
                 Ok(body)
             })
+            .and_then(
+                move |sync_response| -> Box<Future<Item = SyncResponse, Error = Error>> {
+                    match since {
+                        Some(since) => backfill_limited(
+                            &client,
+                            &base_host,
+                            &access_token,
+                            logger3,
+                            sync_response,
+                            &since,
+                        ),
+                        None => Box::new(future::ok(sync_response)),
+                    }
+                },
+            )
             .map(move |sync_response| {
                 let is_live = state2.borrow().is_live;
 
@@ -139,11 +249,20 @@ where
                 }
 
                 // Set the error state
-                state.borrow_mut().errored = res.is_err();
-
-                if let Ok(ref resp) = res {
-                    state.borrow_mut().next_batch = Some(resp.sync_response.next_batch.clone());
-                    state.borrow_mut().is_live = true;
+                let mut state = state.borrow_mut();
+                match res {
+                    Ok(ref resp) => {
+                        state.failures = 0;
+                        state.retry_after = None;
+                        state.next_batch = Some(resp.sync_response.next_batch.clone());
+                        state.is_live = true;
+                    }
+                    Err(ref err) => {
+                        state.failures += 1;
+                        state.retry_after = err
+                            .downcast_ref::<RateLimited>()
+                            .and_then(|limited| limited.retry_after);
+                    }
                 }
 
                 res
@@ -172,14 +291,257 @@ where
     }
 }
 
+/// Turns an unsuccessful response into an error. Rate limiting becomes
+/// `RateLimited`, using the `Retry-After` header or the `retry_after_ms` of
+/// an `M_LIMIT_EXCEEDED` error, and `M_UNKNOWN_TOKEN` becomes `UnknownToken`.
+fn error_from_response(
+    res: hyper::Response<hyper::Body>,
+) -> Box<Future<Item = hyper::Response<hyper::Body>, Error = Error>> {
+    let status = res.status();
+
+    let header_delay = res
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok())
+        .map(Duration::from_secs);
+
+    let f = res
+        .into_body()
+        .concat2()
+        .from_err()
+        .and_then(move |body: hyper::Chunk| {
+            let error_response = serde_json::from_slice::<ErrorResponse>(&body).ok();
+            let errcode = error_response.as_ref().map(|err| err.errcode.as_str());
+
+            if status == StatusCode::TOO_MANY_REQUESTS || errcode == Some("M_LIMIT_EXCEEDED") {
+                let body_delay = error_response
+                    .as_ref()
+                    .and_then(|err| err.retry_after_ms)
+                    .map(Duration::from_millis);
+
+                return Err(RateLimited {
+                    retry_after: header_delay.or(body_delay),
+                }
+                .into());
+            }
+
+            if errcode == Some("M_UNKNOWN_TOKEN") {
+                return Err(UnknownToken.into());
+            }
+
+            Err(format_err!(
+                "Got HTTP response: {} {}",
+                status,
+                errcode.unwrap_or("")
+            ))
+        });
+
+    Box::new(f)
+}
+
+/// How long to wait before the next sync after the given number of failures
+/// in a row.
+fn sync_retry_delay(failures: u32) -> Duration {
+    let mut ms = BASE_SYNC_RETRY_MS;
+    for _ in 1..failures {
+        ms *= 2;
+        if ms >= MAX_SYNC_RETRY_MS {
+            return Duration::from_millis(MAX_SYNC_RETRY_MS);
+        }
+    }
+
+    Duration::from_millis(ms)
+}
+
+/// Randomly shortens the delay by up to half, so that lots of clients don't
+/// all retry at once.
+fn with_jitter(delay: Duration) -> Duration {
+    let ms = duration_millis(delay);
+    Duration::from_millis(thread_rng().gen_range(ms / 2, ms + 1))
+}
+
+fn duration_millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + u64::from(duration.subsec_millis())
+}
+
+/// The sync filter, which leaves out everything but messages in rooms so
+/// that syncs are much smaller on busy accounts.
+fn sync_filter() -> serde_json::Value {
+    json!({
+        "presence": { "types": [] },
+        "account_data": { "types": [] },
+        "room": {
+            "state": {
+                "lazy_load_members": true,
+                "types": [
+                    "m.room.member",
+                    "m.room.name",
+                    "m.room.power_levels",
+                    ROOM_CONFIG_EVENT_TYPE,
+                ],
+            },
+            "timeline": {
+                "types": [
+                    "m.room.message",
+                    "m.reaction",
+                    "m.room.member",
+                    "m.room.name",
+                    "m.room.power_levels",
+                    ROOM_CONFIG_EVENT_TYPE,
+                ],
+            },
+            "ephemeral": { "types": [] },
+            "account_data": { "types": [] },
+        },
+    })
+}
+
+/// Fills in the events missing from any rooms with gaps in their timelines,
+/// i.e. those sent between the `since` token and the start of the timeline.
+/// Failing to fetch them is logged rather than failing the whole sync.
+fn backfill_limited<C: Connect + 'static>(
+    client: &hyper::Client<C>,
+    base_host: &str,
+    access_token: &AccessToken,
+    logger: Logger,
+    mut sync_response: SyncResponse,
+    since: &str,
+) -> Box<Future<Item = SyncResponse, Error = Error>> {
+    let fetches: Vec<_> = sync_response
+        .rooms
+        .join
+        .iter()
+        .filter(|(_, room)| room.timeline.limited)
+        .filter_map(|(room_id, room)| {
+            let prev_batch = room.timeline.prev_batch.as_ref()?;
+
+            let url = format!(
+                "{}/_matrix/client/r0/rooms/{}/messages?from={}&to={}&dir=b&limit={}",
+                base_host,
+                utf8_percent_encode(room_id, PATH_SEGMENT_ENCODE_SET),
+                encode_query_value(prev_batch),
+                encode_query_value(since),
+                BACKFILL_LIMIT
+            );
+
+            info!(logger, "Backfilling room with gap in timeline"; "room_id" => room_id);
+
+            let room_id = room_id.clone();
+            let logger = logger.clone();
+            let f = get_json(client, base_host, url, access_token).then(move |res| {
+                let events = match res {
+                    Ok(MessagesResponse { chunk }) => chunk,
+                    Err(err) => {
+                        error!(logger, "Failed to backfill room";
+                            "room_id" => &room_id,
+                            "error" => %err,
+                        );
+                        Vec::new()
+                    }
+                };
+
+                Ok::<_, Error>((room_id, events))
+            });
+
+            Some(f)
+        })
+        .collect();
+
+    let f = future::join_all(fetches).map(move |results| {
+        for (room_id, mut events) in results {
+            if let Some(room) = sync_response.rooms.join.get_mut(&room_id) {
+                // We paginated backwards, so the events are newest first
+                events.reverse();
+                events.append(&mut room.timeline.events);
+                room.timeline.events = events;
+            }
+        }
+
+        sync_response
+    });
+
+    Box::new(f)
+}
+
 pub trait MessageSender {
-    fn send_text_message(&self, room_id: &str, msg: &str) -> Box<Future<Item = (), Error = ()>>;
+    /// Sends a notice to the room, with an HTML version of the text for
+    /// clients that render it. Fails if the server didn't accept it.
+    fn send_text_message(
+        &self,
+        room_id: &str,
+        msg: &str,
+    ) -> Box<Future<Item = EventId, Error = Error>>;
+
+    /// Like `send_text_message`, but sent as a reply to the given event,
+    /// in the same thread if it was sent in one.
+    fn send_reply(
+        &self,
+        room_id: &str,
+        event: &Event,
+        msg: &str,
+    ) -> Box<Future<Item = EventId, Error = Error>>;
+
+    /// Sets a state event in the room, replacing any existing one with the
+    /// same type and state key.
+    fn send_state_event(
+        &self,
+        room_id: &str,
+        event_type: &str,
+        state_key: &str,
+        content: serde_json::Value,
+    ) -> Box<Future<Item = EventId, Error = Error>>;
+
+    /// Annotates the event with a reaction, e.g. an emoji.
+    fn send_reaction(
+        &self,
+        room_id: &str,
+        event_id: &str,
+        key: &str,
+    ) -> Box<Future<Item = EventId, Error = Error>>;
+
+    /// Sends an image that's already been uploaded, given its `mxc://` URL.
+    fn send_image(
+        &self,
+        room_id: &str,
+        url: &str,
+        name: &str,
+        mimetype: Option<&str>,
+    ) -> Box<Future<Item = EventId, Error = Error>>;
+
+    /// Creates a new 1:1 room with the user, returning the new room ID.
+    fn create_direct_room(&self, user_id: &str) -> Box<Future<Item = String, Error = ()>>;
+
+    /// Gets the IDs of all the rooms the bot is in.
+    fn get_joined_rooms(&self) -> Box<Future<Item = Vec<String>, Error = ()>>;
+
+    /// Joins a room the bot has been invited to.
+    fn join_room(&self, room_id: &str) -> Box<Future<Item = (), Error = ()>>;
+
+    /// Marks the event, and everything before it in the room, as read.
+    fn send_read_receipt(
+        &self,
+        room_id: &str,
+        event_id: &str,
+    ) -> Box<Future<Item = (), Error = ()>>;
+
+    /// Shows or clears the typing indicator for the user in the room.
+    fn set_typing(
+        &self,
+        room_id: &str,
+        user_id: &str,
+        typing: bool,
+    ) -> Box<Future<Item = (), Error = ()>>;
+
+    /// Leaves and forgets a room, so it no longer appears in syncs.
+    fn leave_room(&self, room_id: &str) -> Box<Future<Item = (), Error = ()>>;
 }
 
 pub struct MessageSenderHyper<C: Connect + 'static> {
     client: hyper::Client<C>,
     base_host: String,
-    access_token: String,
+    access_token: AccessToken,
+    queue: SendQueue,
     logger: Logger,
 }
 
@@ -190,55 +552,762 @@ where
     pub fn new(
         client: hyper::Client<C>,
         base_host: String,
-        access_token: String,
+        access_token: AccessToken,
         logger: Logger,
     ) -> MessageSenderHyper<C> {
         MessageSenderHyper {
             client,
             base_host,
             access_token,
+            queue: SendQueue::new(logger.clone()),
             logger,
         }
     }
+
+    fn send_event(
+        &self,
+        room_id: &str,
+        event_type: &str,
+        content: serde_json::Value,
+    ) -> Box<Future<Item = EventId, Error = Error>> {
+        // The server ignores a repeat of a transaction ID, so if we retry the
+        // request (e.g. after refreshing the token) it's only sent once.
+        let txn_id: String = thread_rng().sample_iter(&Alphanumeric).take(20).collect();
+
+        let url = format!(
+            "{}/_matrix/client/r0/rooms/{}/send/{}/{}",
+            self.base_host, room_id, event_type, txn_id
+        );
+
+        self.put_event(room_id, url, content)
+    }
+
+    /// PUTs the event to the URL through the room's send queue.
+    fn put_event(
+        &self,
+        room_id: &str,
+        url: String,
+        content: serde_json::Value,
+    ) -> Box<Future<Item = EventId, Error = Error>> {
+        let content = serde_json::to_vec(&content).expect("valid json");
+
+        info!(self.logger, "Sending message"; "url" => &url);
+
+        let client = self.client.clone();
+        let base_host = self.base_host.clone();
+        let access_token = self.access_token.clone();
+
+        let send = move || -> Box<Future<Item = EventId, Error = Error>> {
+            let url = url.clone();
+            let content = content.clone();
+
+            let f = authed_request(&client, &base_host, &access_token, move |access_token| {
+                put_request(&url, access_token, content.clone())
+            })
+            .and_then(
+                |res| -> Box<Future<Item = hyper::Response<hyper::Body>, Error = Error>> {
+                    if res.status().is_success() {
+                        Box::new(future::ok(res))
+                    } else {
+                        error_from_response(res)
+                    }
+                },
+            )
+            .and_then(|res| res.into_body().concat2().from_err())
+            .and_then(|body: hyper::Chunk| {
+                let resp: SendEventResponse =
+                    serde_json::from_slice(&body).context("Failed to parse send response")?;
+                Ok(resp.event_id)
+            });
+
+            Box::new(f)
+        };
+
+        let logger = self.logger.clone();
+        let logger2 = self.logger.clone();
+        let fut = self
+            .queue
+            .enqueue(room_id, send)
+            .map(move |event_id| {
+                info!(logger, "Sent message"; "event_id" => &event_id);
+                event_id
+            })
+            .map_err(move |err| {
+                error!(logger2, "Failed to send matrix message"; "error" => %err);
+                err
+            });
+
+        Box::new(fut)
+    }
 }
 
 impl<C> MessageSender for MessageSenderHyper<C>
 where
     C: Connect + 'static,
 {
-    fn send_text_message(&self, room_id: &str, msg: &str) -> Box<Future<Item = (), Error = ()>> {
+    fn send_text_message(
+        &self,
+        room_id: &str,
+        msg: &str,
+    ) -> Box<Future<Item = EventId, Error = Error>> {
+        self.send_event(
+            room_id,
+            "m.room.message",
+            json!({
+                "body": msg,
+                "msgtype": "m.notice",
+                "format": "org.matrix.custom.html",
+                "formatted_body": text_to_html(msg),
+            }),
+        )
+    }
+
+    fn send_state_event(
+        &self,
+        room_id: &str,
+        event_type: &str,
+        state_key: &str,
+        content: serde_json::Value,
+    ) -> Box<Future<Item = EventId, Error = Error>> {
+        let url = format!(
+            "{}/_matrix/client/r0/rooms/{}/state/{}/{}",
+            self.base_host,
+            utf8_percent_encode(room_id, PATH_SEGMENT_ENCODE_SET),
+            event_type,
+            utf8_percent_encode(state_key, PATH_SEGMENT_ENCODE_SET)
+        );
+
+        self.put_event(room_id, url, content)
+    }
+
+    fn send_reply(
+        &self,
+        room_id: &str,
+        event: &Event,
+        msg: &str,
+    ) -> Box<Future<Item = EventId, Error = Error>> {
+        // Clients show edits in place of the original, so that's what we
+        // reply to.
+        let event_id = event.replaces().unwrap_or(&event.event_id);
+
+        let relates_to = if let Some(thread_id) = event.thread_id() {
+            json!({
+                "rel_type": "m.thread",
+                "event_id": thread_id,
+                "m.in_reply_to": {
+                    "event_id": event_id,
+                },
+            })
+        } else {
+            json!({
+                "m.in_reply_to": {
+                    "event_id": event_id,
+                },
+            })
+        };
+
+        self.send_event(
+            room_id,
+            "m.room.message",
+            json!({
+                "body": msg,
+                "msgtype": "m.notice",
+                "format": "org.matrix.custom.html",
+                "formatted_body": text_to_html(msg),
+                "m.relates_to": relates_to,
+            }),
+        )
+    }
+
+    fn send_image(
+        &self,
+        room_id: &str,
+        url: &str,
+        name: &str,
+        mimetype: Option<&str>,
+    ) -> Box<Future<Item = EventId, Error = Error>> {
+        let mut content = json!({
+            "body": name,
+            "msgtype": "m.image",
+            "url": url,
+        });
+
+        if let Some(mimetype) = mimetype {
+            content["info"] = json!({ "mimetype": mimetype });
+        }
+
+        self.send_event(room_id, "m.room.message", content)
+    }
+
+    fn send_reaction(
+        &self,
+        room_id: &str,
+        event_id: &str,
+        key: &str,
+    ) -> Box<Future<Item = EventId, Error = Error>> {
+        self.send_event(
+            room_id,
+            "m.reaction",
+            json!({
+                "m.relates_to": {
+                    "rel_type": "m.annotation",
+                    "event_id": event_id,
+                    "key": key,
+                },
+            }),
+        )
+    }
+
+    fn create_direct_room(&self, user_id: &str) -> Box<Future<Item = String, Error = ()>> {
         let content = serde_json::to_vec(&json!({
-            "body": msg,
-            "msgtype": "m.notice",
-        })).expect("valid json");
+            "preset": "trusted_private_chat",
+            "is_direct": true,
+            "invite": [user_id],
+        }))
+        .expect("valid json");
+
+        let url = format!("{}/_matrix/client/r0/createRoom", self.base_host);
+
+        info!(self.logger, "Creating direct room"; "user_id" => user_id);
+
+        let logger = self.logger.clone();
+        let logger2 = self.logger.clone();
+        let fut = authed_request(
+            &self.client,
+            &self.base_host,
+            &self.access_token,
+            move |access_token| post_request(&url, access_token, content.clone()),
+        )
+        .and_then(|res| {
+            if res.status().is_success() {
+                Ok(res)
+            } else {
+                Err(format_err!("Got HTTP response: {}", res.status()))
+            }
+        })
+        .and_then(|res| res.into_body().concat2().from_err())
+        .and_then(|body: hyper::Chunk| {
+            let resp: CreateRoomResponse =
+                serde_json::from_slice(&body).context("Failed to parse createRoom response")?;
+            Ok(resp.room_id)
+        })
+        .map(move |room_id| {
+            info!(logger, "Created direct room"; "room_id" => &room_id);
+            room_id
+        })
+        .map_err(move |err| {
+            error!(logger2, "Failed to create direct room"; "error" => %err);
+        });
 
+        Box::new(fut)
+    }
+
+    fn join_room(&self, room_id: &str) -> Box<Future<Item = (), Error = ()>> {
         let url = format!(
-            "{}/_matrix/client/r0/rooms/{}/send/m.room.message",
-            self.base_host, room_id
+            "{}/_matrix/client/r0/rooms/{}/join",
+            self.base_host,
+            utf8_percent_encode(room_id, PATH_SEGMENT_ENCODE_SET)
         );
 
-        info!(self.logger, "Sending message"; "url" => &url);
+        info!(self.logger, "Joining room"; "room_id" => room_id);
 
-        let request = hyper::Request::post(url)
-            .header(
-                "Authorization",
-                &format!("Bearer {}", &self.access_token) as &str,
-            )
-            .body(hyper::Body::from(content))
-            .expect("valid http request");
+        let logger = self.logger.clone();
+        let logger2 = self.logger.clone();
+        let fut = authed_request(
+            &self.client,
+            &self.base_host,
+            &self.access_token,
+            move |access_token| post_request(&url, access_token, b"{}".to_vec()),
+        )
+        .and_then(|res| {
+            if res.status().is_success() {
+                Ok(())
+            } else {
+                Err(format_err!("Got HTTP response: {}", res.status()))
+            }
+        })
+        .map(move |()| {
+            info!(logger, "Joined room");
+        })
+        .map_err(move |err| {
+            error!(logger2, "Failed to join room"; "error" => %err);
+        });
+
+        Box::new(fut)
+    }
+
+    fn send_read_receipt(
+        &self,
+        room_id: &str,
+        event_id: &str,
+    ) -> Box<Future<Item = (), Error = ()>> {
+        let url = format!(
+            "{}/_matrix/client/r0/rooms/{}/receipt/m.read/{}",
+            self.base_host,
+            utf8_percent_encode(room_id, PATH_SEGMENT_ENCODE_SET),
+            utf8_percent_encode(event_id, PATH_SEGMENT_ENCODE_SET)
+        );
+
+        let logger = self.logger.clone();
+        let fut = authed_request(
+            &self.client,
+            &self.base_host,
+            &self.access_token,
+            move |access_token| post_request(&url, access_token, b"{}".to_vec()),
+        )
+        .and_then(|res| {
+            if res.status().is_success() {
+                Ok(())
+            } else {
+                Err(format_err!("Got HTTP response: {}", res.status()))
+            }
+        })
+        .map_err(move |err| {
+            warn!(logger, "Failed to send read receipt"; "error" => %err);
+        });
+
+        Box::new(fut)
+    }
+
+    fn set_typing(
+        &self,
+        room_id: &str,
+        user_id: &str,
+        typing: bool,
+    ) -> Box<Future<Item = (), Error = ()>> {
+        let content = serde_json::to_vec(&json!({
+            "typing": typing,
+            "timeout": TYPING_TIMEOUT_MS,
+        }))
+        .expect("valid json");
+
+        let url = format!(
+            "{}/_matrix/client/r0/rooms/{}/typing/{}",
+            self.base_host,
+            utf8_percent_encode(room_id, PATH_SEGMENT_ENCODE_SET),
+            utf8_percent_encode(user_id, PATH_SEGMENT_ENCODE_SET)
+        );
+
+        let logger = self.logger.clone();
+        let fut = authed_request(
+            &self.client,
+            &self.base_host,
+            &self.access_token,
+            move |access_token| put_request(&url, access_token, content.clone()),
+        )
+        .and_then(|res| {
+            if res.status().is_success() {
+                Ok(())
+            } else {
+                Err(format_err!("Got HTTP response: {}", res.status()))
+            }
+        })
+        .map_err(move |err| {
+            // Only cosmetic, so not worth more than a warning
+            warn!(logger, "Failed to set typing indicator"; "error" => %err);
+        });
+
+        Box::new(fut)
+    }
+
+    fn leave_room(&self, room_id: &str) -> Box<Future<Item = (), Error = ()>> {
+        let room_id_encoded = utf8_percent_encode(room_id, PATH_SEGMENT_ENCODE_SET).to_string();
+        let leave_url = format!(
+            "{}/_matrix/client/r0/rooms/{}/leave",
+            self.base_host, room_id_encoded
+        );
+        let forget_url = format!(
+            "{}/_matrix/client/r0/rooms/{}/forget",
+            self.base_host, room_id_encoded
+        );
+
+        info!(self.logger, "Leaving room"; "room_id" => room_id);
+
+        let client = self.client.clone();
+        let base_host = self.base_host.clone();
+        let access_token = self.access_token.clone();
 
         let logger = self.logger.clone();
         let logger2 = self.logger.clone();
-        let fut = self
-            .client
-            .request(request)
-            .map(move |_| {
-                info!(logger, "Sent message");
+        let fut = authed_request(
+            &self.client,
+            &self.base_host,
+            &self.access_token,
+            move |access_token| post_request(&leave_url, access_token, b"{}".to_vec()),
+        )
+        .and_then(|res| {
+            if res.status().is_success() {
+                Ok(())
+            } else {
+                Err(format_err!("Got HTTP response: {}", res.status()))
+            }
+        })
+        // We can only forget a room once we've left it.
+        .and_then(move |()| {
+            authed_request(&client, &base_host, &access_token, move |access_token| {
+                post_request(&forget_url, access_token, b"{}".to_vec())
             })
+        })
+        .and_then(|res| {
+            if res.status().is_success() {
+                Ok(())
+            } else {
+                Err(format_err!("Got HTTP response: {}", res.status()))
+            }
+        })
+        .map(move |()| {
+            info!(logger, "Left room");
+        })
+        .map_err(move |err| {
+            error!(logger2, "Failed to leave room"; "error" => %err);
+        });
+
+        Box::new(fut)
+    }
+
+    fn get_joined_rooms(&self) -> Box<Future<Item = Vec<String>, Error = ()>> {
+        let url = format!("{}/_matrix/client/r0/joined_rooms", self.base_host);
+
+        let logger = self.logger.clone();
+        let fut = get_json(&self.client, &self.base_host, url, &self.access_token)
+            .map(|resp: JoinedRoomsResponse| resp.joined_rooms)
             .map_err(move |err| {
-                error!(logger2, "Failed to send matrix message"; "error" => %err);
+                error!(logger, "Failed to get joined rooms"; "error" => %err);
             });
 
         Box::new(fut)
     }
 }
+
+/// Works out the base URL of the client API. URLs are used as is, while for
+/// a server name we look at its `.well-known/matrix/client`, falling back to
+/// the server name over HTTPS if it doesn't have one.
+pub fn discover_homeserver<C: Connect + 'static>(
+    client: &hyper::Client<C>,
+    host: &str,
+) -> Box<Future<Item = String, Error = Error>> {
+    if host.starts_with("https://") || host.starts_with("http://") {
+        return Box::new(future::ok(host.trim_end_matches('/').to_string()));
+    }
+
+    let fallback = format!("https://{}", host);
+
+    let url = format!("https://{}/.well-known/matrix/client", host);
+    let request = hyper::Request::get(url)
+        .body(hyper::Body::empty())
+        .expect("valid http request");
+
+    let f = client
+        .request(request)
+        .from_err::<Error>()
+        .and_then(|res| {
+            if res.status().is_success() {
+                Ok(res)
+            } else {
+                Err(format_err!("Got HTTP response: {}", res.status()))
+            }
+        })
+        .and_then(|res| res.into_body().concat2().from_err())
+        .and_then(|body: hyper::Chunk| {
+            let resp: WellKnownResponse =
+                serde_json::from_slice(&body).context("Failed to parse .well-known response")?;
+            Ok(resp.homeserver.base_url.trim_end_matches('/').to_string())
+        })
+        .or_else(move |_| Ok(fallback));
+
+    Box::new(f)
+}
+
+/// Logs in with a password, creating a new device.
+pub fn login<C: Connect + 'static>(
+    client: &hyper::Client<C>,
+    base_host: &str,
+    user: &str,
+    password: &str,
+) -> Box<Future<Item = LoginResponse, Error = Error>> {
+    let content = serde_json::to_vec(&json!({
+        "type": "m.login.password",
+        "identifier": {
+            "type": "m.id.user",
+            "user": user,
+        },
+        "password": password,
+        "initial_device_display_name": "Reminder bot",
+        "refresh_token": true,
+    }))
+    .expect("valid json");
+
+    let url = format!("{}/_matrix/client/r0/login", base_host);
+
+    let request = hyper::Request::post(url)
+        .body(hyper::Body::from(content))
+        .expect("valid http request");
+
+    let f = client
+        .request(request)
+        .from_err::<Error>()
+        .and_then(|res| {
+            if res.status().is_success() {
+                Ok(res)
+            } else {
+                Err(format_err!("Got HTTP response: {}", res.status()))
+            }
+        })
+        .and_then(|res| res.into_body().concat2().from_err())
+        .and_then(|body: hyper::Chunk| {
+            let resp = serde_json::from_slice(&body).context("Failed to parse login response")?;
+            Ok(resp)
+        });
+
+    Box::new(f)
+}
+
+/// Gets the user ID and display name of the account we're using.
+pub fn get_own_profile<C: Connect + 'static>(
+    client: &hyper::Client<C>,
+    base_host: &str,
+    access_token: &AccessToken,
+) -> Box<Future<Item = (String, Option<String>), Error = Error>> {
+    let url = format!("{}/_matrix/client/r0/account/whoami", base_host);
+
+    let client = client.clone();
+    let base_host = base_host.to_string();
+    let access_token = access_token.clone();
+
+    let f = get_json(&client, &base_host, url, &access_token).and_then(
+        move |whoami: WhoamiResponse| {
+            let url = format!(
+                "{}/_matrix/client/r0/profile/{}/displayname",
+                base_host,
+                utf8_percent_encode(&whoami.user_id, PATH_SEGMENT_ENCODE_SET)
+            );
+
+            get_json(&client, &base_host, url, &access_token)
+                .map(move |resp: DisplayNameResponse| (whoami.user_id, resp.displayname))
+        },
+    );
+
+    Box::new(f)
+}
+
+/// Sets the display name on the user's profile.
+pub fn set_display_name<C: Connect + 'static>(
+    client: &hyper::Client<C>,
+    base_host: &str,
+    access_token: &AccessToken,
+    user_id: &str,
+    display_name: &str,
+) -> Box<Future<Item = (), Error = Error>> {
+    let url = format!(
+        "{}/_matrix/client/r0/profile/{}/displayname",
+        base_host,
+        utf8_percent_encode(user_id, PATH_SEGMENT_ENCODE_SET)
+    );
+
+    put_json(
+        client,
+        base_host,
+        url,
+        access_token,
+        json!({ "displayname": display_name }),
+    )
+}
+
+/// Uploads the image and sets it as the user's avatar.
+pub fn set_avatar<C: Connect + 'static>(
+    client: &hyper::Client<C>,
+    base_host: &str,
+    access_token: &AccessToken,
+    user_id: &str,
+    content_type: &str,
+    image: Vec<u8>,
+) -> Box<Future<Item = (), Error = Error>> {
+    let upload_url = format!("{}/_matrix/media/v3/upload", base_host);
+    let content_type = content_type.to_string();
+
+    let avatar_url = format!(
+        "{}/_matrix/client/r0/profile/{}/avatar_url",
+        base_host,
+        utf8_percent_encode(user_id, PATH_SEGMENT_ENCODE_SET)
+    );
+
+    let client2 = client.clone();
+    let base_host2 = base_host.to_string();
+    let access_token2 = access_token.clone();
+
+    let f = authed_request(client, base_host, access_token, move |access_token| {
+        hyper::Request::post(&upload_url as &str)
+            .header("Authorization", &format!("Bearer {}", access_token) as &str)
+            .header("Content-Type", &content_type as &str)
+            .body(hyper::Body::from(image.clone()))
+            .expect("valid http request")
+    })
+    .and_then(|res| {
+        if res.status().is_success() {
+            Ok(res)
+        } else {
+            Err(format_err!("Got HTTP response: {}", res.status()))
+        }
+    })
+    .and_then(|res| res.into_body().concat2().from_err())
+    .and_then(|body: hyper::Chunk| {
+        let resp: UploadResponse =
+            serde_json::from_slice(&body).context("Failed to parse upload response")?;
+        Ok(resp.content_uri)
+    })
+    .and_then(move |content_uri| {
+        put_json(
+            &client2,
+            &base_host2,
+            avatar_url,
+            &access_token2,
+            json!({ "avatar_url": content_uri }),
+        )
+    });
+
+    Box::new(f)
+}
+
+fn get_json<C: Connect + 'static, T: DeserializeOwned + 'static>(
+    client: &hyper::Client<C>,
+    base_host: &str,
+    url: String,
+    access_token: &AccessToken,
+) -> Box<Future<Item = T, Error = Error>> {
+    let f = authed_request(client, base_host, access_token, move |access_token| {
+        get_request(&url, access_token)
+    })
+    .and_then(|res| {
+        if res.status().is_success() {
+            Ok(res)
+        } else {
+            Err(format_err!("Got HTTP response: {}", res.status()))
+        }
+    })
+    .and_then(|res| res.into_body().concat2().from_err())
+    .and_then(|body: hyper::Chunk| {
+        let resp = serde_json::from_slice(&body).context("Failed to parse response")?;
+        Ok(resp)
+    });
+
+    Box::new(f)
+}
+
+/// Turns a plain text reply into HTML. Replies with a heading line ending
+/// in ':' followed by more lines, e.g. lists of reminders, become a bulleted
+/// list.
+fn text_to_html(text: &str) -> String {
+    let lines: Vec<String> = text.lines().map(escape_html).collect();
+
+    if lines.len() > 1 && lines[0].ends_with(':') {
+        let items: String = lines[1..]
+            .iter()
+            .map(|line| format!("<li>{}</li>", line))
+            .collect();
+
+        return format!("<p>{}</p><ul>{}</ul>", lines[0], items);
+    }
+
+    lines.join("<br>")
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn put_json<C: Connect + 'static>(
+    client: &hyper::Client<C>,
+    base_host: &str,
+    url: String,
+    access_token: &AccessToken,
+    content: serde_json::Value,
+) -> Box<Future<Item = (), Error = Error>> {
+    let content = serde_json::to_vec(&content).expect("valid json");
+
+    let f = authed_request(client, base_host, access_token, move |access_token| {
+        put_request(&url, access_token, content.clone())
+    })
+    .and_then(|res| {
+        if res.status().is_success() {
+            Ok(())
+        } else {
+            Err(format_err!("Got HTTP response: {}", res.status()))
+        }
+    });
+
+    Box::new(f)
+}
+
+fn get_request(url: &str, access_token: &str) -> hyper::Request<hyper::Body> {
+    hyper::Request::get(url)
+        .header("Authorization", &format!("Bearer {}", access_token) as &str)
+        .body(hyper::Body::empty())
+        .expect("valid http request")
+}
+
+/// Encodes a value for a query string. Pagination tokens are opaque, so
+/// may contain e.g. "&" or "+".
+fn encode_query_value(value: &str) -> String {
+    form_urlencoded::byte_serialize(value.as_bytes()).collect()
+}
+
+fn post_request(url: &str, access_token: &str, content: Vec<u8>) -> hyper::Request<hyper::Body> {
+    hyper::Request::post(url)
+        .header("Authorization", &format!("Bearer {}", access_token) as &str)
+        .body(hyper::Body::from(content))
+        .expect("valid http request")
+}
+
+fn put_request(url: &str, access_token: &str, content: Vec<u8>) -> hyper::Request<hyper::Body> {
+    hyper::Request::put(url)
+        .header("Authorization", &format!("Bearer {}", access_token) as &str)
+        .body(hyper::Body::from(content))
+        .expect("valid http request")
+}
+
+#[test]
+fn sync_retry_delay_test() {
+    assert_eq!(sync_retry_delay(1), Duration::from_secs(1));
+    assert_eq!(sync_retry_delay(2), Duration::from_secs(2));
+    assert_eq!(sync_retry_delay(5), Duration::from_secs(16));
+    assert_eq!(sync_retry_delay(9), Duration::from_secs(256));
+    assert_eq!(sync_retry_delay(10), Duration::from_secs(300));
+    assert_eq!(sync_retry_delay(100), Duration::from_secs(300));
+}
+
+#[test]
+fn text_to_html_test() {
+    assert_eq!(text_to_html("Hello"), "Hello");
+    assert_eq!(text_to_html("a < b & c"), "a &lt; b &amp; c");
+    assert_eq!(text_to_html("One\nTwo"), "One<br>Two");
+    assert_eq!(
+        text_to_html("Pending reminders:\nabc123: 'Milk'\ndef456: 'Eggs'"),
+        "<p>Pending reminders:</p><ul><li>abc123: 'Milk'</li><li>def456: 'Eggs'</li></ul>"
+    );
+}
+
+#[test]
+fn is_unknown_token_test() {
+    let err: Error = UnknownToken.into();
+    assert!(is_unknown_token(&err));
+
+    let err: Error = Err::<(), _>(err)
+        .context("Failed to make HTTP sync request")
+        .unwrap_err()
+        .into();
+    assert!(is_unknown_token(&err));
+
+    assert!(!is_unknown_token(&format_err!("Got HTTP response: 500")));
+}
+
+#[test]
+fn encode_query_value_test() {
+    assert_eq!(encode_query_value("s72595_4483_1934"), "s72595_4483_1934");
+    assert_eq!(
+        encode_query_value("t1-2&x=3 4+5/6"),
+        "t1-2%26x%3D3+4%2B5%2F6"
+    );
+}
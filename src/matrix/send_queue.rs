@@ -0,0 +1,186 @@
+use failure::Error;
+use futures::future::{loop_fn, Loop, Shared};
+use futures::sync::oneshot;
+use futures::{future, Future};
+use slog::Logger;
+use tokio_timer::sleep;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use super::{duration_millis, sync_retry_delay, with_jitter, RateLimited};
+
+/// The least time between starting any two sends, so that bursts of due
+/// reminders don't trip the homeserver's rate limits.
+const MIN_SEND_INTERVAL_MS: u64 = 200;
+
+/// How many times we retry a send the homeserver rate limited.
+const MAX_SEND_RETRIES: u32 = 5;
+
+type Done = Shared<Box<Future<Item = (), Error = ()>>>;
+
+struct QueueState {
+    /// Completes when the last send queued for the room has finished, along
+    /// with that send's number. Rooms are removed once their queue is empty.
+    rooms: HashMap<String, (u64, Done)>,
+    /// The number given to the next send queued
+    next_send: u64,
+    /// The earliest the next send can start
+    next_slot: Instant,
+}
+
+/// Queues outgoing sends, so that each room gets them one at a time and in
+/// order, and retries them if the homeserver says to slow down.
+#[derive(Clone)]
+pub struct SendQueue {
+    logger: Logger,
+    state: Rc<RefCell<QueueState>>,
+}
+
+impl SendQueue {
+    pub fn new(logger: Logger) -> SendQueue {
+        SendQueue {
+            logger,
+            state: Rc::new(RefCell::new(QueueState {
+                rooms: HashMap::new(),
+                next_send: 0,
+                next_slot: Instant::now(),
+            })),
+        }
+    }
+
+    /// Runs the send once everything queued for the room before it has
+    /// finished. `send` is called again for each retry.
+    pub fn enqueue<T, F>(&self, room_id: &str, send: F) -> Box<Future<Item = T, Error = Error>>
+    where
+        T: 'static,
+        F: Fn() -> Box<Future<Item = T, Error = Error>> + 'static,
+    {
+        let (done_tx, done_rx) = oneshot::channel::<()>();
+        let done: Box<Future<Item = (), Error = ()>> = Box::new(done_rx.then(|_| Ok(())));
+
+        let (send_number, previous) = {
+            let mut state = self.state.borrow_mut();
+            let send_number = state.next_send;
+            state.next_send += 1;
+
+            let previous = state
+                .rooms
+                .insert(room_id.to_string(), (send_number, done.shared()));
+
+            (send_number, previous)
+        };
+
+        let wait: Box<Future<Item = (), Error = Error>> = match previous {
+            Some((_, previous)) => Box::new(previous.then(|_| Ok(()))),
+            None => Box::new(future::ok(())),
+        };
+
+        let queue = self.clone();
+        let done_queue = self.clone();
+        let room_id = room_id.to_string();
+        let logger = self.logger.clone();
+        let f = wait
+            .and_then(move |()| queue.wait_for_slot())
+            .and_then(move |()| send_with_retries(logger, send))
+            .then(move |res| {
+                // The next send in the room can go now, whether or not this
+                // one worked. If nothing was queued after this one the room
+                // is forgotten.
+                let _ = done_tx.send(());
+                done_queue.finished(&room_id, send_number);
+                res
+            });
+
+        Box::new(f)
+    }
+
+    /// Forgets the room if the given send is still the last one queued in it.
+    fn finished(&self, room_id: &str, send_number: u64) {
+        let mut state = self.state.borrow_mut();
+
+        let is_last = state
+            .rooms
+            .get(room_id)
+            .map_or(false, |&(last, _)| last == send_number);
+
+        if is_last {
+            state.rooms.remove(room_id);
+        }
+    }
+
+    /// Waits until enough time has passed since the previous send started.
+    fn wait_for_slot(&self) -> Box<Future<Item = (), Error = Error>> {
+        let now = Instant::now();
+
+        let mut state = self.state.borrow_mut();
+        let start = if state.next_slot > now {
+            state.next_slot
+        } else {
+            now
+        };
+        state.next_slot = start + Duration::from_millis(MIN_SEND_INTERVAL_MS);
+
+        if start == now {
+            return Box::new(future::ok(()));
+        }
+
+        Box::new(sleep(start - now).map_err(Error::from))
+    }
+}
+
+fn send_with_retries<T, F>(logger: Logger, send: F) -> Box<Future<Item = T, Error = Error>>
+where
+    T: 'static,
+    F: Fn() -> Box<Future<Item = T, Error = Error>> + 'static,
+{
+    let f = loop_fn(0, move |retries| {
+        let logger = logger.clone();
+        send().then(
+            move |res| -> Box<Future<Item = Loop<T, u32>, Error = Error>> {
+                let err = match res {
+                    Ok(item) => return Box::new(future::ok(Loop::Break(item))),
+                    Err(err) => err,
+                };
+
+                let limited = err
+                    .downcast_ref::<RateLimited>()
+                    .map(|limited| limited.retry_after);
+
+                let retry_after = match limited {
+                    Some(retry_after) if retries < MAX_SEND_RETRIES => retry_after,
+                    _ => return Box::new(future::err(err)),
+                };
+
+                let delay =
+                    retry_after.unwrap_or_else(|| with_jitter(sync_retry_delay(retries + 1)));
+
+                info!(logger, "Rate limited, retrying send"; "delay_ms" => duration_millis(delay));
+
+                let f = sleep(delay)
+                    .map_err(Error::from)
+                    .map(move |()| Loop::Continue(retries + 1));
+
+                Box::new(f)
+            },
+        )
+    });
+
+    Box::new(f)
+}
+
+#[test]
+fn send_queue_forgets_idle_rooms_test() {
+    let queue = SendQueue::new(Logger::root(::slog::Discard, o!()));
+
+    let f = queue.enqueue(
+        "!room:example.com",
+        || -> Box<Future<Item = u32, Error = Error>> { Box::new(future::ok(1)) },
+    );
+    assert_eq!(queue.state.borrow().rooms.len(), 1);
+
+    assert_eq!(f.wait().unwrap(), 1);
+    assert!(queue.state.borrow().rooms.is_empty());
+}
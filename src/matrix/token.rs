@@ -0,0 +1,315 @@
+use failure::{Error, ResultExt};
+use futures::future::Shared;
+use futures::{future, Future, Stream};
+use hyper;
+use hyper::client::connect::Connect;
+use hyper::StatusCode;
+use serde_json;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use super::types::{ErrorResponse, LoginResponse, RefreshResponse};
+use super::{error_from_response, is_unknown_token, login, UnknownToken};
+
+type PendingRefresh = Shared<Box<Future<Item = String, Error = Error>>>;
+
+struct TokenState {
+    access_token: String,
+    refresh_token: Option<String>,
+    /// A refresh that's in progress, which anything else finding the token
+    /// has expired waits for rather than refreshing again.
+    pending: Option<PendingRefresh>,
+}
+
+/// The user and password to log in with again if we're logged out.
+struct Credentials {
+    user: String,
+    password: String,
+    /// Called with the result of logging in again
+    on_login: Box<Fn(Result<&LoginResponse, &Error>)>,
+}
+
+/// The access token shared by everything talking to the homeserver. If we
+/// have a refresh token then it's replaced when the server says it has
+/// expired, and if we have a password we log in again if the session has
+/// been logged out.
+#[derive(Clone)]
+pub struct AccessToken {
+    state: Rc<RefCell<TokenState>>,
+    /// Called with the new access and refresh tokens after a refresh
+    on_refresh: Rc<Fn(&str, Option<&str>)>,
+    credentials: Option<Rc<Credentials>>,
+}
+
+impl AccessToken {
+    pub fn new<F>(access_token: String, refresh_token: Option<String>, on_refresh: F) -> AccessToken
+    where
+        F: Fn(&str, Option<&str>) + 'static,
+    {
+        AccessToken {
+            state: Rc::new(RefCell::new(TokenState {
+                access_token,
+                refresh_token,
+                pending: None,
+            })),
+            on_refresh: Rc::new(on_refresh),
+            credentials: None,
+        }
+    }
+
+    /// Logs in again with the user and password if the session is logged
+    /// out, rather than giving up.
+    pub fn with_credentials<F>(mut self, user: String, password: String, on_login: F) -> AccessToken
+    where
+        F: Fn(Result<&LoginResponse, &Error>) + 'static,
+    {
+        self.credentials = Some(Rc::new(Credentials {
+            user,
+            password,
+            on_login: Box::new(on_login),
+        }));
+        self
+    }
+
+    /// The current access token.
+    pub fn get(&self) -> String {
+        self.state.borrow().access_token.clone()
+    }
+
+    /// Whether we can get a new access token if the session has been logged
+    /// out entirely, rather than just expired.
+    fn can_log_in(&self) -> bool {
+        self.credentials.is_some()
+    }
+
+    /// Gets a new access token to replace the given rejected one, returning
+    /// it. Expired tokens are refreshed if we have a refresh token, and
+    /// otherwise we log in again if we can. Fails with `UnknownToken` if
+    /// neither works.
+    fn renew<C: Connect + 'static>(
+        &self,
+        client: &hyper::Client<C>,
+        base_host: &str,
+        expired: &str,
+        soft_logout: bool,
+    ) -> Box<Future<Item = String, Error = Error>> {
+        let mut state = self.state.borrow_mut();
+
+        // Someone else has already renewed it
+        if state.access_token != expired {
+            return Box::new(future::ok::<_, Error>(state.access_token.clone()));
+        }
+
+        if state.pending.is_none() {
+            let f = match (
+                soft_logout,
+                state.refresh_token.as_ref(),
+                self.credentials.as_ref(),
+            ) {
+                (true, Some(refresh_token), _) => self.refresh(client, base_host, refresh_token),
+                (_, _, Some(credentials)) => self.log_in(client, base_host, credentials.clone()),
+                _ => return Box::new(future::err::<String, _>(UnknownToken.into())),
+            };
+
+            state.pending = Some(f.shared());
+        }
+
+        let pending = state.pending.clone().expect("pending refresh");
+
+        let f = pending
+            .map(|access_token| (*access_token).clone())
+            .map_err(|err| {
+                if is_unknown_token(&err) {
+                    UnknownToken.into()
+                } else {
+                    format_err!("Failed to refresh access token: {}", *err)
+                }
+            });
+
+        Box::new(f)
+    }
+
+    /// Swaps the refresh token for a new access token. If the refresh token
+    /// has been revoked we log in again if we can, and otherwise fail with
+    /// `UnknownToken`.
+    fn refresh<C: Connect + 'static>(
+        &self,
+        client: &hyper::Client<C>,
+        base_host: &str,
+        refresh_token: &str,
+    ) -> Box<Future<Item = String, Error = Error>> {
+        let token = self.clone();
+        let client = client.clone();
+        let base_host = base_host.to_string();
+        let f = refresh_access_token(&client, &base_host, refresh_token).then(
+            move |res| -> Box<Future<Item = String, Error = Error>> {
+                let resp = match res {
+                    Ok(resp) => resp,
+                    Err(err) => {
+                        // The refresh token itself has been revoked, so the
+                        // only way back in is to log in again
+                        if is_unknown_token(&err) {
+                            if let Some(credentials) = token.credentials.clone() {
+                                return token.log_in(&client, &base_host, credentials);
+                            }
+                        }
+
+                        token.state.borrow_mut().pending = None;
+                        return Box::new(future::err(err));
+                    }
+                };
+
+                let mut state = token.state.borrow_mut();
+                state.pending = None;
+
+                state.access_token = resp.access_token;
+                if resp.refresh_token.is_some() {
+                    state.refresh_token = resp.refresh_token;
+                }
+
+                (token.on_refresh)(
+                    &state.access_token,
+                    state.refresh_token.as_ref().map(String::as_str),
+                );
+
+                Box::new(future::ok(state.access_token.clone()))
+            },
+        );
+
+        Box::new(f)
+    }
+
+    /// Logs in again, as the session has been logged out. If that fails
+    /// there's no way to get a working token, so it fails with
+    /// `UnknownToken`.
+    fn log_in<C: Connect + 'static>(
+        &self,
+        client: &hyper::Client<C>,
+        base_host: &str,
+        credentials: Rc<Credentials>,
+    ) -> Box<Future<Item = String, Error = Error>> {
+        let token = self.clone();
+        let f = login(client, base_host, &credentials.user, &credentials.password).then(
+            move |res| -> Result<String, Error> {
+                let mut state = token.state.borrow_mut();
+                state.pending = None;
+
+                let resp = match res {
+                    Ok(resp) => resp,
+                    Err(err) => {
+                        (credentials.on_login)(Err(&err));
+                        return Err(UnknownToken.into());
+                    }
+                };
+
+                (credentials.on_login)(Ok(&resp));
+
+                state.access_token = resp.access_token;
+                state.refresh_token = resp.refresh_token;
+
+                Ok(state.access_token.clone())
+            },
+        );
+
+        Box::new(f)
+    }
+}
+
+/// Makes a request using the access token, refreshing the token or logging
+/// in again and then trying again if the server rejects it.
+pub fn authed_request<C, F>(
+    client: &hyper::Client<C>,
+    base_host: &str,
+    token: &AccessToken,
+    make_request: F,
+) -> Box<Future<Item = hyper::Response<hyper::Body>, Error = Error>>
+where
+    C: Connect + 'static,
+    F: Fn(&str) -> hyper::Request<hyper::Body> + 'static,
+{
+    let access_token = token.get();
+
+    let client2 = client.clone();
+    let base_host = base_host.to_string();
+    let token = token.clone();
+
+    let f = client
+        .request(make_request(&access_token))
+        .from_err::<Error>()
+        .and_then(
+            move |res| -> Box<Future<Item = hyper::Response<hyper::Body>, Error = Error>> {
+                if res.status() != StatusCode::UNAUTHORIZED {
+                    return Box::new(future::ok(res));
+                }
+
+                // We need to look at the error to see whether refreshing or
+                // logging in will help, so put the body back together
+                // afterwards.
+                let (parts, body) = res.into_parts();
+                let f = body.concat2().from_err().and_then(
+                    move |body| -> Box<Future<Item = _, Error = Error>> {
+                        let error_response = serde_json::from_slice::<ErrorResponse>(&body).ok();
+                        let soft_logout =
+                            error_response.as_ref().map_or(false, |err| err.soft_logout);
+                        let logged_out = error_response
+                            .as_ref()
+                            .map_or(false, |err| err.errcode == "M_UNKNOWN_TOKEN");
+
+                        if !soft_logout && !(logged_out && token.can_log_in()) {
+                            let res = hyper::Response::from_parts(parts, hyper::Body::from(body));
+                            return Box::new(future::ok(res));
+                        }
+
+                        let f = token
+                            .renew(&client2, &base_host, &access_token, soft_logout)
+                            .and_then(move |access_token| {
+                                client2.request(make_request(&access_token)).from_err()
+                            });
+
+                        Box::new(f)
+                    },
+                );
+
+                Box::new(f)
+            },
+        );
+
+    Box::new(f)
+}
+
+fn refresh_access_token<C: Connect + 'static>(
+    client: &hyper::Client<C>,
+    base_host: &str,
+    refresh_token: &str,
+) -> Box<Future<Item = RefreshResponse, Error = Error>> {
+    let content = serde_json::to_vec(&json!({
+        "refresh_token": refresh_token,
+    }))
+    .expect("valid json");
+
+    let url = format!("{}/_matrix/client/v3/refresh", base_host);
+
+    let request = hyper::Request::post(url)
+        .body(hyper::Body::from(content))
+        .expect("valid http request");
+
+    let f = client
+        .request(request)
+        .from_err::<Error>()
+        .and_then(|res| -> Box<Future<Item = _, Error = Error>> {
+            if res.status().is_success() {
+                Box::new(future::ok(res))
+            } else {
+                // A revoked refresh token becomes `UnknownToken`
+                error_from_response(res)
+            }
+        })
+        .and_then(|res| res.into_body().concat2().from_err())
+        .and_then(|body: hyper::Chunk| {
+            let resp = serde_json::from_slice(&body).context("Failed to parse refresh response")?;
+            Ok(resp)
+        });
+
+    Box::new(f)
+}
@@ -10,26 +10,155 @@ pub struct SyncResponse {
 #[derive(Clone, Debug, Deserialize, Default)]
 pub struct RoomsSyncResponse {
     pub join: BTreeMap<String, JoinedRoomsSyncResponse>,
+    #[serde(default)]
+    pub invite: BTreeMap<String, InvitedRoomSyncResponse>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct InvitedRoomSyncResponse {
+    #[serde(default)]
+    pub invite_state: InviteState,
+}
+
+#[derive(Clone, Debug, Deserialize, Default)]
+pub struct InviteState {
+    pub events: Vec<StrippedStateEvent>,
+}
+
+/// A cut down state event, as given for rooms we've been invited to.
+#[derive(Clone, Debug, Deserialize)]
+pub struct StrippedStateEvent {
+    #[serde(rename = "type")]
+    pub etype: String,
+    pub state_key: String,
+    pub sender: String,
+    pub content: BTreeMap<String, serde_json::Value>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct JoinedRoomsSyncResponse {
     pub timeline: RoomTimeline,
+    /// State from before the timeline, which changed since the last sync
+    #[serde(default)]
+    pub state: RoomState,
+    #[serde(default)]
+    pub summary: RoomSummary,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct RoomState {
+    pub events: Vec<Event>,
+}
+
+/// Counts of the room's members. Only included when they've changed.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct RoomSummary {
+    #[serde(rename = "m.joined_member_count")]
+    pub joined_member_count: Option<u64>,
+    #[serde(rename = "m.invited_member_count")]
+    pub invited_member_count: Option<u64>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct RoomTimeline {
     pub events: Vec<Event>,
+    /// Whether events were left out since the last sync, in which case they
+    /// can be fetched from `prev_batch`
+    #[serde(default)]
+    pub limited: bool,
+    pub prev_batch: Option<String>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct Event {
     #[serde(rename = "type")]
     pub etype: String,
+    pub event_id: String,
     pub state_key: Option<String>,
     pub sender: String,
     pub origin_server_ts: u64,
     pub content: BTreeMap<String, serde_json::Value>,
+    #[serde(default)]
+    pub unsigned: UnsignedData,
+}
+
+/// Extra information about an event added by the homeserver.
+#[derive(Clone, Debug, Deserialize, Default)]
+pub struct UnsignedData {
+    /// Milliseconds since the event was sent, according to the server
+    pub age: Option<u64>,
+    /// Only present on events sent by this access token
+    pub transaction_id: Option<String>,
+}
+
+impl Event {
+    /// The ID of the event this one edits, if it's an edit.
+    pub fn replaces(&self) -> Option<&str> {
+        let relates_to = self.content.get("m.relates_to")?;
+
+        if relates_to.get("rel_type")?.as_str()? != "m.replace" {
+            return None;
+        }
+
+        relates_to.get("event_id")?.as_str()
+    }
+
+    /// Gets a field of the content, taking it from the new content for
+    /// edits.
+    pub fn content_value(&self, key: &str) -> Option<&serde_json::Value> {
+        if self.replaces().is_some() {
+            self.content.get("m.new_content")?.get(key)
+        } else {
+            self.content.get(key)
+        }
+    }
+
+    /// Like `content_value`, for string fields.
+    pub fn content_str(&self, key: &str) -> Option<&str> {
+        self.content_value(key)?.as_str()
+    }
+
+    /// The ID of the thread root, if the event was sent in a thread.
+    pub fn thread_id(&self) -> Option<&str> {
+        let relates_to = self.content.get("m.relates_to")?;
+
+        if relates_to.get("rel_type")?.as_str()? != "m.thread" {
+            return None;
+        }
+
+        relates_to.get("event_id")?.as_str()
+    }
+
+    /// The event this reacts to and the reaction's key, e.g. an emoji, if
+    /// it's a reaction.
+    pub fn reaction(&self) -> Option<(&str, &str)> {
+        let relates_to = self.content.get("m.relates_to")?;
+
+        if relates_to.get("rel_type")?.as_str()? != "m.annotation" {
+            return None;
+        }
+
+        Some((
+            relates_to.get("event_id")?.as_str()?,
+            relates_to.get("key")?.as_str()?,
+        ))
+    }
+
+    /// The ID of the event this is a reply to. Replies clients only add
+    /// for the sake of clients that don't support threads aren't counted.
+    pub fn in_reply_to(&self) -> Option<&str> {
+        let relates_to = self.content.get("m.relates_to")?;
+
+        let is_falling_back = relates_to
+            .get("is_falling_back")
+            .and_then(|value| value.as_bool())
+            .unwrap_or(false);
+        if is_falling_back {
+            return None;
+        }
+
+        relates_to.get("m.in_reply_to")?.get("event_id")?.as_str()
+    }
 }
 
 impl SyncResponse {
@@ -42,6 +171,110 @@ impl SyncResponse {
                 .map(move |ev| (room_id as &str, ev))
         })
     }
+
+    /// State events for joined rooms, both from before and in the
+    /// timeline, in the order they happened.
+    pub fn state_events(&self) -> impl Iterator<Item = (&str, &Event)> {
+        self.rooms.join.iter().flat_map(|(room_id, entry)| {
+            entry
+                .state
+                .events
+                .iter()
+                .chain(entry.timeline.events.iter())
+                .filter(|ev| ev.state_key.is_some())
+                .map(move |ev| (room_id as &str, ev))
+        })
+    }
+
+    /// The rooms the user has been invited to, along with who invited them
+    /// if we know.
+    pub fn invites<'a>(
+        &'a self,
+        user_id: &'a str,
+    ) -> impl Iterator<Item = (&'a str, Option<&'a str>)> {
+        self.rooms.invite.iter().map(move |(room_id, entry)| {
+            let inviter = entry
+                .invite_state
+                .events
+                .iter()
+                .find(|ev| ev.etype == "m.room.member" && ev.state_key == user_id)
+                .map(|ev| &ev.sender as &str);
+
+            (room_id as &str, inviter)
+        })
+    }
+}
+
+/// The body of an error response from the homeserver.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ErrorResponse {
+    pub errcode: String,
+    /// Set with `M_UNKNOWN_TOKEN` if the token can be refreshed
+    #[serde(default)]
+    pub soft_logout: bool,
+    /// How long to wait before retrying, if we were rate limited
+    pub retry_after_ms: Option<u64>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct MessagesResponse {
+    pub chunk: Vec<Event>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct UploadResponse {
+    pub content_uri: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct SendEventResponse {
+    pub event_id: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct CreateRoomResponse {
+    pub room_id: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct JoinedRoomsResponse {
+    pub joined_rooms: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct WellKnownResponse {
+    #[serde(rename = "m.homeserver")]
+    pub homeserver: WellKnownHomeserver,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct WellKnownHomeserver {
+    pub base_url: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct LoginResponse {
+    pub user_id: String,
+    pub access_token: String,
+    pub device_id: String,
+    pub refresh_token: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct RefreshResponse {
+    pub access_token: String,
+    /// Only given if the refresh token was replaced too
+    pub refresh_token: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct WhoamiResponse {
+    pub user_id: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct DisplayNameResponse {
+    pub displayname: Option<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -49,3 +282,188 @@ pub struct SyncStreamItem {
     pub sync_response: SyncResponse,
     pub is_live: bool,
 }
+
+#[test]
+fn event_unsigned_test() {
+    let event: Event = serde_json::from_str(
+        r#"{
+            "type": "m.room.message",
+            "event_id": "$abc:example.com",
+            "sender": "@alice:example.com",
+            "origin_server_ts": 1532000000000,
+            "content": {"msgtype": "m.text", "body": "hi"},
+            "unsigned": {"age": 1234, "transaction_id": "m1532000000"}
+        }"#,
+    )
+    .unwrap();
+
+    assert_eq!(event.event_id, "$abc:example.com");
+    assert_eq!(event.unsigned.age, Some(1234));
+    assert_eq!(
+        event.unsigned.transaction_id,
+        Some("m1532000000".to_string())
+    );
+
+    let event: Event = serde_json::from_str(
+        r#"{
+            "type": "m.room.message",
+            "event_id": "$def:example.com",
+            "sender": "@alice:example.com",
+            "origin_server_ts": 1532000000000,
+            "content": {}
+        }"#,
+    )
+    .unwrap();
+
+    assert_eq!(event.unsigned.age, None);
+    assert_eq!(event.unsigned.transaction_id, None);
+}
+
+#[test]
+fn invites_test() {
+    let resp: SyncResponse = serde_json::from_str(
+        r#"{
+            "next_batch": "s1",
+            "rooms": {
+                "join": {},
+                "invite": {
+                    "!room:example.com": {
+                        "invite_state": {
+                            "events": [
+                                {
+                                    "type": "m.room.name",
+                                    "state_key": "",
+                                    "sender": "@alice:example.com",
+                                    "content": {"name": "Chores"}
+                                },
+                                {
+                                    "type": "m.room.member",
+                                    "state_key": "@bot:example.com",
+                                    "sender": "@bob:example.com",
+                                    "content": {"membership": "invite"}
+                                }
+                            ]
+                        }
+                    }
+                }
+            }
+        }"#,
+    )
+    .unwrap();
+
+    let invites: Vec<_> = resp.invites("@bot:example.com").collect();
+    assert_eq!(
+        invites,
+        vec![("!room:example.com", Some("@bob:example.com"))]
+    );
+}
+
+#[test]
+fn thread_id_test() {
+    let event: Event = serde_json::from_str(
+        r#"{
+            "type": "m.room.message",
+            "event_id": "$reply:example.com",
+            "sender": "@alice:example.com",
+            "origin_server_ts": 1532000000000,
+            "content": {
+                "msgtype": "m.text",
+                "body": "testbot: list",
+                "m.relates_to": {
+                    "rel_type": "m.thread",
+                    "event_id": "$root:example.com",
+                    "is_falling_back": true,
+                    "m.in_reply_to": {"event_id": "$root:example.com"}
+                }
+            }
+        }"#,
+    )
+    .unwrap();
+
+    assert_eq!(event.thread_id(), Some("$root:example.com"));
+    assert_eq!(event.in_reply_to(), None);
+
+    let event: Event = serde_json::from_str(
+        r#"{
+            "type": "m.room.message",
+            "event_id": "$reply:example.com",
+            "sender": "@alice:example.com",
+            "origin_server_ts": 1532000000000,
+            "content": {
+                "msgtype": "m.text",
+                "body": "testbot: list",
+                "m.relates_to": {"m.in_reply_to": {"event_id": "$other:example.com"}}
+            }
+        }"#,
+    )
+    .unwrap();
+
+    assert_eq!(event.thread_id(), None);
+    assert_eq!(event.in_reply_to(), Some("$other:example.com"));
+}
+
+#[test]
+fn edit_test() {
+    let event: Event = serde_json::from_str(
+        r#"{
+            "type": "m.room.message",
+            "event_id": "$edit:example.com",
+            "sender": "@alice:example.com",
+            "origin_server_ts": 1532000000000,
+            "content": {
+                "msgtype": "m.text",
+                "body": "* testbot: remind me at 5pm to call mum",
+                "m.new_content": {
+                    "msgtype": "m.text",
+                    "body": "testbot: remind me at 5pm to call mum"
+                },
+                "m.relates_to": {"rel_type": "m.replace", "event_id": "$original:example.com"}
+            }
+        }"#,
+    )
+    .unwrap();
+
+    assert_eq!(event.replaces(), Some("$original:example.com"));
+    assert_eq!(
+        event.content_str("body"),
+        Some("testbot: remind me at 5pm to call mum")
+    );
+    assert_eq!(event.thread_id(), None);
+
+    let event: Event = serde_json::from_str(
+        r#"{
+            "type": "m.room.message",
+            "event_id": "$original:example.com",
+            "sender": "@alice:example.com",
+            "origin_server_ts": 1532000000000,
+            "content": {"msgtype": "m.text", "body": "testbot: list"}
+        }"#,
+    )
+    .unwrap();
+
+    assert_eq!(event.replaces(), None);
+    assert_eq!(event.content_str("body"), Some("testbot: list"));
+}
+
+#[test]
+fn reaction_test() {
+    let event: Event = serde_json::from_str(
+        r#"{
+            "type": "m.reaction",
+            "event_id": "$reaction:example.com",
+            "sender": "@alice:example.com",
+            "origin_server_ts": 1532000000000,
+            "content": {
+                "m.relates_to": {
+                    "rel_type": "m.annotation",
+                    "event_id": "$reminder:example.com",
+                    "key": "💤"
+                }
+            }
+        }"#,
+    )
+    .unwrap();
+
+    assert_eq!(event.reaction(), Some(("$reminder:example.com", "💤")));
+    assert_eq!(event.in_reply_to(), None);
+}
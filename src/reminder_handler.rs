@@ -1,96 +1,374 @@
-use chrono::Utc;
-use db::{Reminder, Reminders};
-use failure::ResultExt;
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
+use chrono_tz::{Tz, UTC};
+use db::{
+    Channel, DigestSettings, FailedReminder, FailedReminders, Reminder, Reminders, UserSettings,
+    DIGEST_ID,
+};
+use failure::Error;
 use futures::{future, Future};
 use slog::Logger;
 use tokio_core::reactor::Handle;
-use twilio_rust::messages::{MessageFrom, Messages, OutboundMessageBuilder};
-use twilio_rust::Client;
 
-use db::AddressBook;
-use Config;
+use delivery::{DeliveryChannels, PermanentFailure};
+
+/// How many times we try to deliver a reminder before giving up on it.
+const MAX_ATTEMPTS: u32 = 5;
+
+/// The delay before the first retry, doubled after each further failure.
+const BASE_RETRY_DELAY_SECS: i64 = 30;
+
+/// The longest we wait between retries.
+const MAX_RETRY_DELAY_SECS: i64 = 60 * 60;
+
+/// How many times a nagging reminder is sent again before we give up on it
+/// being marked done.
+const MAX_NAGS: u32 = 12;
 
 pub struct ReminderHandler {
     logger: Logger,
-    client: Client,
-    config: Config,
+    /// The name to tell users to address the bot by, e.g. "testbot"
+    command_prefix: String,
     reminders: Reminders,
-    address_book: AddressBook,
+    failed_reminders: FailedReminders,
+    user_settings: UserSettings,
+    channels: DeliveryChannels,
 }
 
 impl ReminderHandler {
     pub fn new(
         logger: Logger,
-        client: Client,
-        config: Config,
+        command_prefix: String,
         reminders: Reminders,
-        address_book: AddressBook,
+        failed_reminders: FailedReminders,
+        user_settings: UserSettings,
+        channels: DeliveryChannels,
     ) -> ReminderHandler {
         ReminderHandler {
             logger,
-            client,
-            config,
+            command_prefix,
             reminders,
-            address_book,
+            failed_reminders,
+            user_settings,
+            channels,
         }
     }
 
     pub fn do_reminders(&self, handle: &Handle) {
         let now = Utc::now();
 
+        self.do_digests(handle, now);
+
         let reminders = self
             .reminders
             .get_reminders_before(&now)
             .expect("failed to get reminders from database");
 
         for reminder in reminders {
-            let f = self.handle_reminder(&reminder);
-            handle.spawn(f);
+            let tz = self
+                .user_settings
+                .get_timezone(&reminder.destination)
+                .expect("failed to get timezone from database")
+                .unwrap_or(UTC);
+
+            let local_now = now.with_timezone(&tz);
+
+            // Don't wake people up with texts or calls during their quiet
+            // hours, instead wait until the end of them.
+            if reminder.channel == Channel::Sms || reminder.channel == Channel::Call {
+                let quiet_hours = self
+                    .user_settings
+                    .get_quiet_hours(&reminder.destination)
+                    .expect("failed to get quiet hours from database");
+
+                if let Some(end) = quiet_hours.and_then(|q| q.end_after(local_now)) {
+                    info!(self.logger, "Deferring reminder until end of quiet hours";
+                        "id" => &reminder.id,
+                        "until" => %end.to_rfc2822(),
+                    );
+
+                    self.reminders
+                        .defer_reminder(&reminder.id, &end.with_timezone(&Utc))
+                        .expect("failed to update database");
+
+                    continue;
+                }
+            }
 
+            let next_due = next_occurrence_after(&reminder, local_now);
+
+            // We only mark the reminder as sent once delivery has succeeded,
+            // until then make sure we don't pick it up again.
             self.reminders
-                .delete_reminder(&reminder.id)
-                .expect("failed to delete from database");
+                .mark_in_flight(&reminder.id)
+                .expect("failed to update database");
+
+            let f = self.handle_reminder(&reminder, next_due);
+            handle.spawn(f);
         }
     }
 
-    fn handle_reminder(&self, reminder: &Reminder) -> Box<Future<Item = (), Error = ()>> {
-        let logger = self.logger.new(o!("id" => reminder.id.clone()));
+    /// Sends the daily digest to any users whose digest time has passed
+    /// today.
+    fn do_digests(&self, handle: &Handle, now: DateTime<Utc>) {
+        let users = self
+            .user_settings
+            .get_digest_users()
+            .expect("failed to get digest users from database");
+
+        for user in users {
+            let tz = user.timezone.unwrap_or(UTC);
+            let today = now.with_timezone(&tz).date().naive_local();
+
+            let digest_at = match digest_time_on(&user, tz, today) {
+                Some(digest_at) => digest_at,
+                None => continue,
+            };
 
-        info!(logger, "Sending message");
+            if now < digest_at || user.last_digest.map_or(false, |last| last >= digest_at) {
+                continue;
+            }
+
+            let end_of_day = tz
+                .from_local_datetime(&today.succ().and_hms(0, 0, 0))
+                .earliest()
+                .map(|end| end.with_timezone(&Utc))
+                .unwrap_or_else(|| now + Duration::days(1));
+
+            // Record the digest as sent up front, so that a failure doesn't
+            // get retried every tick. Anything left over goes in tomorrow's
+            // digest instead, while reminders created later today are
+            // delivered as usual.
+            self.user_settings
+                .set_last_digest(&user.user_id, &now, &end_of_day)
+                .expect("failed to update database");
 
-        let msisdn_res = self
-            .address_book
-            .get_msisdn_for_user(&reminder.destination)
-            .context("failed to get msisdn from DB");
+            let reminders = self
+                .reminders
+                .get_digest_reminders(&user.user_id, &end_of_day)
+                .expect("failed to get reminders from database");
+
+            // Anything delivered since the last digest that wasn't marked as
+            // done gets mentioned again
+            let undone = match user.last_digest {
+                Some(last) => self
+                    .reminders
+                    .get_undone_reminders(&user.user_id, &last)
+                    .expect("failed to get reminders from database"),
+                None => Vec::new(),
+            };
+
+            if reminders.is_empty() && undone.is_empty() {
+                continue;
+            }
 
-        let msisdn = match msisdn_res {
-            Ok(Some(msisdn)) => msisdn,
-            Ok(None) => {
-                warn!(logger, "Failed to find msisdn"; "destination" => reminder.destination.clone());
-                return Box::new(future::ok(()));
+            for reminder in &reminders {
+                self.reminders
+                    .mark_in_flight(&reminder.id)
+                    .expect("failed to update database");
             }
+
+            let f = self.send_digest(&user.user_id, tz, reminders, &undone, end_of_day);
+            handle.spawn(f);
+        }
+    }
+
+    fn send_digest(
+        &self,
+        user_id: &str,
+        tz: Tz,
+        reminders: Vec<Reminder>,
+        undone: &[Reminder],
+        end_of_day: DateTime<Utc>,
+    ) -> Box<Future<Item = (), Error = ()>> {
+        let logger = self.logger.new(o!("digest" => user_id.to_string()));
+
+        let channel = match self.user_settings.get_channel(user_id) {
+            Ok(channel) => channel.unwrap_or(Channel::Sms),
             Err(err) => {
-                error!(logger, "Failed to get msisdn"; "destination" => reminder.destination.clone(), "err" => %err);
-                return Box::new(future::ok(()));
+                error!(logger, "Failed to get delivery channel"; "error" => %err);
+                Channel::Sms
             }
         };
 
-        let messages = Messages::new(&self.client);
+        info!(logger, "Sending digest"; "channel" => channel.as_str(), "count" => reminders.len());
+
+        let digest = Reminder {
+            id: DIGEST_ID.to_string(),
+            due: Utc::now(),
+            destination: user_id.to_string(),
+            text: format_digest(
+                &reminders,
+                undone,
+                tz,
+                &end_of_day.with_timezone(&tz),
+                &self.command_prefix,
+            ),
+            recurrence: None,
+            repeat_until: None,
+            room_id: reminders
+                .iter()
+                .chain(undone)
+                .filter_map(|r| r.room_id.clone())
+                .next(),
+            channel,
+            attempts: 0,
+            creator: user_id.to_string(),
+            created: None,
+            event_id: None,
+            image: None,
+            timezone: Some(tz),
+            nag_interval: None,
+            parent_id: None,
+        };
+
+        let f = if let Some(delivery_channel) = self.channels.get(channel.as_str()) {
+            delivery_channel.deliver(logger.clone(), &digest)
+        } else {
+            Box::new(future::err(format_err!(
+                "unknown delivery channel {}",
+                channel.as_str()
+            )))
+        };
+
+        // Recurring reminders move on to their first occurrence after today
+        let next_dues: Vec<_> = reminders
+            .iter()
+            .map(|reminder| next_occurrence_after(reminder, end_of_day.with_timezone(&tz)))
+            .collect();
+
+        let reminders_db = self.reminders.clone();
+
+        let f = f.then(move |res| {
+            for (reminder, next_due) in reminders.iter().zip(next_dues) {
+                let res = if res.is_ok() {
+                    reminders_db
+                        .mark_delivered(&reminder.id, &Utc::now())
+                        .and_then(|_| complete_reminder(&reminders_db, &reminder.id, next_due))
+                } else {
+                    reminders_db.defer_reminder(&reminder.id, &end_of_day)
+                };
+
+                if let Err(err) = res {
+                    error!(logger, "Failed to update reminder after digest"; "error" => %err);
+                }
+            }
+
+            if let Err(err) = res {
+                error!(logger, "Failed to send digest"; "error" => %err);
+            }
+
+            Ok(())
+        });
+
+        Box::new(f)
+    }
+
+    fn handle_reminder(
+        &self,
+        reminder: &Reminder,
+        next_due: Option<DateTime<Utc>>,
+    ) -> Box<Future<Item = (), Error = ()>> {
+        let logger = self.logger.new(o!("id" => reminder.id.clone()));
+
+        let name = reminder.channel.as_str();
+
+        info!(logger, "Sending message"; "channel" => name, "attempt" => reminder.attempts + 1);
+
+        // Nagging reminders say how to stop them
+        let mut message = reminder.clone();
+        if reminder.nag_interval.is_some() {
+            message.text = format!(
+                "{} (reply '{}: done {}' to stop reminders)",
+                reminder.text, self.command_prefix, reminder.id
+            );
+        }
+
+        let f = if let Some(channel) = self.channels.get(name) {
+            channel.deliver(logger.clone(), &message)
+        } else {
+            Box::new(future::err(
+                PermanentFailure(format!("unknown delivery channel {}", name)).into(),
+            ))
+        };
+
+        let reminders = self.reminders.clone();
+        let failed_reminders = self.failed_reminders.clone();
+        let reminder = reminder.clone();
+        let attempts = reminder.attempts + 1;
+
+        let f = f.then(move |res| {
+            let err = match res {
+                Ok(()) => {
+                    let now = Utc::now();
+                    let nag_at = reminder.nag_interval.map(|interval| now + interval);
+
+                    // Nagging stops once the next occurrence is due anyway,
+                    // or after it has nagged too many times
+                    let res = reminders
+                        .mark_delivered(&reminder.id, &now)
+                        .and_then(|_| match nag_at {
+                            Some(nag_at) if next_due.map_or(true, |due| nag_at < due) => {
+                                reminders.nag_reminder(&reminder.id, next_due, &nag_at, MAX_NAGS)
+                            }
+                            _ => Ok(false),
+                        })
+                        .and_then(|nagging| {
+                            if nagging {
+                                Ok(())
+                            } else {
+                                complete_reminder(&reminders, &reminder.id, next_due)
+                            }
+                        });
 
-        let outbound_sms = OutboundMessageBuilder::new_sms(
-            MessageFrom::From(&self.config.twilio.from_num),
-            &msisdn,
-            &reminder.text,
-        ).build();
+                    if let Err(err) = res {
+                        error!(logger, "Failed to mark reminder as sent"; "error" => %err);
+                    }
+                    return Ok(());
+                }
+                Err(err) => err,
+            };
 
-        let f = messages.send_message(&outbound_sms).then(move |res| {
-            match res {
-                Ok(msg) => if let Some(error) = msg.error_message {
-                    error!(logger, "Error from twilio"; "error" => error);
+            warn!(logger, "Failed to deliver reminder"; "error" => %err, "attempts" => attempts);
+
+            let retry_at = Utc::now() + retry_delay(attempts);
+
+            // Recurring reminders give up once the next occurrence is due
+            // anyway, so that retries don't push back the schedule.
+            let give_up = err.downcast_ref::<PermanentFailure>().is_some()
+                || attempts >= MAX_ATTEMPTS
+                || next_due.map_or(false, |due| retry_at >= due);
+
+            if !give_up {
+                if let Err(err) = reminders.retry_reminder(&reminder.id, attempts, &retry_at) {
+                    error!(logger, "Failed to record delivery attempt"; "error" => %err);
+                }
+                return Ok(());
+            }
+
+            error!(logger, "Giving up on reminder"; "attempts" => attempts);
+
+            let res = failed_reminders.add_failed_reminder(&FailedReminder {
+                reminder_id: reminder.id.clone(),
+                due: reminder.due,
+                destination: reminder.destination.clone(),
+                text: reminder.text.clone(),
+                channel: reminder.channel.as_str().to_string(),
+                attempts,
+                error: err.to_string(),
+                failed_at: Utc::now(),
+            });
+
+            // Recurring reminders stay scheduled for their next occurrence
+            let res = res.and_then(|_| {
+                if next_due.is_none() {
+                    reminders.remove_reminder(&reminder.id)
                 } else {
-                    info!(logger, "Message sent"; "status" => ?msg.status)
-                },
-                Err(err) => error!(logger, "Error sending sms"; "error" => ?err),
+                    complete_reminder(&reminders, &reminder.id, next_due)
+                }
+            });
+
+            if let Err(err) = res {
+                error!(logger, "Failed to record failed reminder"; "error" => %err);
             }
 
             Ok(())
@@ -99,3 +377,107 @@ impl ReminderHandler {
         Box::new(f)
     }
 }
+
+/// Works out the first occurrence of a recurring reminder after the given
+/// time, in the timezone it was set in (or else the user's) so that e.g.
+/// "every monday at 9:00" means 9:00 on monday where they are, even after
+/// the clocks change. Returns None for one off reminders, and once a
+/// limited series such as "every hour for the next 6 hours" is over.
+fn next_occurrence_after(reminder: &Reminder, after: DateTime<Tz>) -> Option<DateTime<Utc>> {
+    let recurrence = reminder.recurrence.as_ref()?;
+    let tz = reminder.timezone.unwrap_or_else(|| after.timezone());
+
+    let mut next = Some(reminder.due.with_timezone(&tz));
+    while let Some(date) = next {
+        if date > after {
+            break;
+        }
+        next = recurrence.next_occurrence(date);
+    }
+
+    next.map(|next| next.with_timezone(&Utc))
+        .filter(|next| reminder.repeat_until.map_or(true, |until| *next <= until))
+}
+
+/// When the user's digest is due on the given day, if that time exists.
+fn digest_time_on(user: &DigestSettings, tz: Tz, date: NaiveDate) -> Option<DateTime<Utc>> {
+    tz.from_local_datetime(&date.and_time(user.time))
+        .earliest()
+        .map(|digest_at| digest_at.with_timezone(&Utc))
+}
+
+/// The text of a digest listing the given reminders, which are due before
+/// the end of the day, followed by the undone ones from before. Overdue
+/// reminders from earlier days include the date.
+fn format_digest(
+    reminders: &[Reminder],
+    undone: &[Reminder],
+    tz: Tz,
+    end_of_day: &DateTime<Tz>,
+    command_prefix: &str,
+) -> String {
+    let start_of_day = *end_of_day - Duration::days(1);
+
+    let lines: Vec<String> = reminders
+        .iter()
+        .map(|reminder| {
+            let due = reminder.due.with_timezone(&tz);
+            let time = if due < start_of_day {
+                due.format("%a %-d %b %H:%M")
+            } else {
+                due.format("%H:%M")
+            };
+
+            format!("{} {} ({})", time, reminder.text, reminder.id)
+        })
+        .collect();
+
+    let mut sections = Vec::new();
+
+    if !lines.is_empty() {
+        sections.push(format!("Your reminders for today:\n{}", lines.join("\n")));
+    }
+
+    if !undone.is_empty() {
+        let undone_lines: Vec<String> = undone
+            .iter()
+            .map(|reminder| format!("{} ({})", reminder.text, reminder.id))
+            .collect();
+
+        sections.push(format!(
+            "Not done yet, reply '{}: done <id>' once they are:\n{}",
+            command_prefix,
+            undone_lines.join("\n")
+        ));
+    }
+
+    sections.join("\n\n")
+}
+
+/// Marks the reminder as sent, or moves recurring reminders on to their next
+/// occurrence.
+fn complete_reminder(
+    reminders: &Reminders,
+    id: &str,
+    next_due: Option<DateTime<Utc>>,
+) -> Result<(), Error> {
+    if let Some(next_due) = next_due {
+        reminders.reschedule_recurring_reminder(id, &next_due)
+    } else {
+        reminders.delete_reminder(id)
+    }
+}
+
+/// How long to wait before retrying after the given number of failed
+/// attempts.
+fn retry_delay(attempts: u32) -> Duration {
+    let mut secs = BASE_RETRY_DELAY_SECS;
+    for _ in 1..attempts {
+        secs *= 2;
+        if secs >= MAX_RETRY_DELAY_SECS {
+            return Duration::seconds(MAX_RETRY_DELAY_SECS);
+        }
+    }
+
+    Duration::seconds(secs)
+}
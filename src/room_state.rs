@@ -0,0 +1,361 @@
+use failure::Error;
+use serde_json::{self, Value};
+use slog::Logger;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use db::{RoomConfig, RoomState};
+use matrix::types::{Event, SyncResponse};
+use matrix::ROOM_CONFIG_EVENT_TYPE;
+
+#[derive(Default)]
+struct Cache {
+    /// Room names, None if the room doesn't have one
+    room_names: HashMap<String, Option<String>>,
+    /// Display names keyed by room and user, None if we don't know of the
+    /// user being in the room or they don't have one
+    member_names: HashMap<(String, String), Option<String>>,
+    room_configs: HashMap<String, RoomConfig>,
+    /// The content of each room's power levels event, None if we haven't
+    /// seen one
+    power_levels: HashMap<String, Option<Value>>,
+}
+
+/// Keeps track of the names of rooms and their members from sync, so that
+/// we can refer to them by name, and of each room's settings. These are
+/// stored in the database and cached in memory.
+#[derive(Clone)]
+pub struct RoomStateCache {
+    logger: Logger,
+    room_state: RoomState,
+    cache: Rc<RefCell<Cache>>,
+}
+
+impl RoomStateCache {
+    pub fn new(logger: Logger, room_state: RoomState) -> RoomStateCache {
+        RoomStateCache {
+            logger,
+            room_state,
+            cache: Rc::new(RefCell::new(Cache::default())),
+        }
+    }
+
+    /// Picks up any name, membership or settings changes in the sync.
+    pub fn handle_sync(&self, sync_response: &SyncResponse) {
+        // Power levels go first, as they decide who can change the settings
+        let (power_levels, others): (Vec<_>, Vec<_>) = sync_response
+            .state_events()
+            .partition(|&(_, event)| event.etype == "m.room.power_levels");
+
+        for (room_id, event) in power_levels.into_iter().chain(others) {
+            if let Err(err) = self.handle_state_event(room_id, event) {
+                error!(self.logger, "Failed to store room state";
+                    "room_id" => room_id, "error" => %err);
+            }
+        }
+    }
+
+    /// The room's name, or its ID if it doesn't have one.
+    pub fn room_name(&self, room_id: &str) -> String {
+        if let Some(name) = self.cache.borrow().room_names.get(room_id) {
+            return name.clone().unwrap_or_else(|| room_id.to_string());
+        }
+
+        let name = self
+            .room_state
+            .get_room_name(room_id)
+            .unwrap_or_else(|err| {
+                error!(self.logger, "Failed to get room name"; "error" => %err);
+                None
+            });
+
+        self.cache
+            .borrow_mut()
+            .room_names
+            .insert(room_id.to_string(), name.clone());
+
+        name.unwrap_or_else(|| room_id.to_string())
+    }
+
+    /// The user's display name in the room, or their user ID if they don't
+    /// have one.
+    pub fn display_name(&self, room_id: &str, user_id: &str) -> String {
+        let key = (room_id.to_string(), user_id.to_string());
+
+        if let Some(name) = self.cache.borrow().member_names.get(&key) {
+            return name.clone().unwrap_or_else(|| user_id.to_string());
+        }
+
+        let name = self
+            .room_state
+            .get_member_name(room_id, user_id)
+            .unwrap_or_else(|err| {
+                error!(self.logger, "Failed to get display name"; "error" => %err);
+                None
+            })
+            .and_then(|name| name);
+
+        self.cache
+            .borrow_mut()
+            .member_names
+            .insert(key, name.clone());
+
+        name.unwrap_or_else(|| user_id.to_string())
+    }
+
+    /// The room's settings, which are all unset if it hasn't been
+    /// configured.
+    pub fn room_config(&self, room_id: &str) -> RoomConfig {
+        if let Some(config) = self.cache.borrow().room_configs.get(room_id) {
+            return config.clone();
+        }
+
+        let config = self
+            .room_state
+            .get_room_config(room_id)
+            .unwrap_or_else(|err| {
+                error!(self.logger, "Failed to get room config"; "error" => %err);
+                RoomConfig::default()
+            });
+
+        self.cache
+            .borrow_mut()
+            .room_configs
+            .insert(room_id.to_string(), config.clone());
+
+        config
+    }
+
+    /// The content of the room's power levels event, if we've seen one.
+    fn power_levels(&self, room_id: &str) -> Option<Value> {
+        if let Some(content) = self.cache.borrow().power_levels.get(room_id) {
+            return content.clone();
+        }
+
+        let content = self
+            .room_state
+            .get_power_levels(room_id)
+            .and_then(|content| match content {
+                Some(content) => Ok(Some(serde_json::from_str(&content)?)),
+                None => Ok(None),
+            })
+            .unwrap_or_else(|err| {
+                error!(self.logger, "Failed to get power levels"; "error" => %err);
+                None
+            });
+
+        self.cache
+            .borrow_mut()
+            .power_levels
+            .insert(room_id.to_string(), content.clone());
+
+        content
+    }
+
+    fn handle_state_event(&self, room_id: &str, event: &Event) -> Result<(), Error> {
+        let content = || Value::Object(event.content.clone().into_iter().collect());
+        let content_str = |key| event.content.get(key).and_then(|value| value.as_str());
+
+        match (&event.etype as &str, event.state_key.as_ref()) {
+            ("m.room.name", Some(_)) => {
+                let name = content_str("name").filter(|name| !name.is_empty());
+
+                self.room_state.set_room_name(room_id, name)?;
+                self.cache
+                    .borrow_mut()
+                    .room_names
+                    .insert(room_id.to_string(), name.map(str::to_string));
+            }
+            ("m.room.member", Some(user_id)) => {
+                let display_name = if content_str("membership") == Some("join") {
+                    let display_name = content_str("displayname").filter(|name| !name.is_empty());
+                    self.room_state.set_member(room_id, user_id, display_name)?;
+                    display_name.map(str::to_string)
+                } else {
+                    self.room_state.remove_member(room_id, user_id)?;
+                    None
+                };
+
+                self.cache
+                    .borrow_mut()
+                    .member_names
+                    .insert((room_id.to_string(), user_id.clone()), display_name);
+            }
+            ("m.room.power_levels", Some(state_key)) if state_key.is_empty() => {
+                let content = content();
+
+                self.room_state
+                    .set_power_levels(room_id, &content.to_string())?;
+                self.cache
+                    .borrow_mut()
+                    .power_levels
+                    .insert(room_id.to_string(), Some(content));
+            }
+            (ROOM_CONFIG_EVENT_TYPE, Some(state_key)) if state_key.is_empty() => {
+                let power_levels = self.power_levels(room_id);
+                if !can_send_state(power_levels.as_ref(), &event.sender, ROOM_CONFIG_EVENT_TYPE) {
+                    warn!(self.logger, "Ignoring room config from user without permission";
+                        "room_id" => room_id, "sender" => &event.sender);
+                    return Ok(());
+                }
+
+                let config = parse_room_config(&content());
+
+                info!(self.logger, "Got room config";
+                    "room_id" => room_id, "config" => ?config);
+
+                self.room_state.set_room_config(room_id, &config)?;
+                self.cache
+                    .borrow_mut()
+                    .room_configs
+                    .insert(room_id.to_string(), config);
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether the user's power level is at least what's needed to send state
+/// events of the given type, going by the content of the room's power levels
+/// event. Without one we don't know, so assume not.
+fn can_send_state(power_levels: Option<&Value>, user_id: &str, event_type: &str) -> bool {
+    let power_levels = match power_levels {
+        Some(power_levels) => power_levels,
+        None => return false,
+    };
+
+    // Levels can be strings in old rooms
+    let level = |value: &Value| {
+        value
+            .as_i64()
+            .or_else(|| value.as_str().and_then(|value| value.trim().parse().ok()))
+    };
+    let get = |key: &str| power_levels.get(key).and_then(&level);
+
+    let user_level = power_levels
+        .get("users")
+        .and_then(|users| users.get(user_id))
+        .and_then(&level)
+        .or_else(|| get("users_default"))
+        .unwrap_or(0);
+
+    let required = power_levels
+        .get("events")
+        .and_then(|events| events.get(event_type))
+        .and_then(&level)
+        .or_else(|| get("state_default"))
+        .unwrap_or(50);
+
+    user_level >= required
+}
+
+/// Reads the settings from the content of a room config event. Anything
+/// missing or invalid is left unset.
+pub fn parse_room_config(content: &Value) -> RoomConfig {
+    let content_str = |key| {
+        content
+            .get(key)
+            .and_then(|value| value.as_str())
+            .filter(|value| !value.is_empty())
+    };
+
+    RoomConfig {
+        prefix: content_str("prefix").map(str::to_string),
+        channel: content_str("delivery").and_then(|channel| channel.to_lowercase().parse().ok()),
+        timezone: content_str("timezone").and_then(|tz| tz.parse().ok()),
+        locale: content_str("language").and_then(|locale| locale.parse().ok()),
+    }
+}
+
+/// The content of a room config event with the given settings.
+pub fn room_config_content(config: &RoomConfig) -> Value {
+    json!({
+        "prefix": config.prefix,
+        "delivery": config.channel.map(|channel| channel.as_str()),
+        "timezone": config.timezone.map(|tz| tz.name()),
+        "language": config.locale.map(|locale| locale.as_str()),
+    })
+}
+
+#[test]
+fn parse_room_config_test() {
+    use chrono_tz::Europe::London;
+    use date::Locale;
+    use db::Channel;
+
+    let config = parse_room_config(&json!({
+        "prefix": "remindme",
+        "delivery": "DM",
+        "timezone": "Europe/London",
+        "language": "fr",
+    }));
+    assert_eq!(config.prefix, Some("remindme".to_string()));
+    assert_eq!(config.channel, Some(Channel::Direct));
+    assert_eq!(config.timezone, Some(London));
+    assert_eq!(config.locale, Some(Locale::French));
+
+    let config = parse_room_config(&json!({
+        "prefix": "",
+        "delivery": "pigeon",
+        "timezone": 12,
+    }));
+    assert_eq!(config, RoomConfig::default());
+
+    let config = RoomConfig {
+        prefix: Some("remindme".to_string()),
+        channel: Some(Channel::Sms),
+        timezone: None,
+        locale: Some(Locale::German),
+    };
+    assert_eq!(parse_room_config(&room_config_content(&config)), config);
+}
+
+#[test]
+fn can_send_state_test() {
+    let event_type = "org.reminderbot.config";
+
+    let power_levels = json!({
+        "users": {"@admin:example.com": 100, "@mod:example.com": "50"},
+        "users_default": 0,
+        "state_default": 50,
+    });
+    assert!(can_send_state(
+        Some(&power_levels),
+        "@admin:example.com",
+        event_type
+    ));
+    assert!(can_send_state(
+        Some(&power_levels),
+        "@mod:example.com",
+        event_type
+    ));
+    assert!(!can_send_state(
+        Some(&power_levels),
+        "@alice:example.com",
+        event_type
+    ));
+
+    // The level for the event type overrides the default for state events
+    let power_levels = json!({
+        "users": {"@mod:example.com": 50},
+        "events": {"org.reminderbot.config": 100},
+    });
+    assert!(!can_send_state(
+        Some(&power_levels),
+        "@mod:example.com",
+        event_type
+    ));
+
+    let power_levels = json!({"users_default": 0, "state_default": 0});
+    assert!(can_send_state(
+        Some(&power_levels),
+        "@alice:example.com",
+        event_type
+    ));
+
+    assert!(!can_send_state(None, "@admin:example.com", event_type));
+}
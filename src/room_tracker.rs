@@ -0,0 +1,123 @@
+use chrono::{DateTime, Duration, Utc};
+use slog::Logger;
+use tokio_core::reactor::Handle;
+
+use std::rc::Rc;
+
+use db::{DirectRooms, Rooms};
+use matrix::types::SyncResponse;
+use matrix::MessageSender;
+
+/// Keeps track of the rooms the bot is in, and leaves those nobody else is
+/// in or that haven't been used for a while, to keep syncs small.
+pub struct RoomTracker {
+    logger: Logger,
+    /// The account whose rooms we're tracking
+    user_id: String,
+    rooms: Rooms,
+    /// Rooms we've created to deliver reminders in, which we never leave
+    direct_rooms: DirectRooms,
+    message_sender: Rc<MessageSender>,
+    /// How long a room can go without a command before we leave it, if at
+    /// all
+    leave_after: Option<Duration>,
+}
+
+impl RoomTracker {
+    pub fn new(
+        logger: Logger,
+        user_id: String,
+        rooms: Rooms,
+        direct_rooms: DirectRooms,
+        message_sender: Rc<MessageSender>,
+        leave_after: Option<Duration>,
+    ) -> RoomTracker {
+        RoomTracker {
+            logger,
+            user_id,
+            rooms,
+            direct_rooms,
+            message_sender,
+            leave_after,
+        }
+    }
+
+    /// Notes any new rooms in the sync, and leaves any rooms that are now
+    /// empty or unused.
+    pub fn handle_sync(&self, handle: &Handle, sync_response: &SyncResponse, now: DateTime<Utc>) {
+        for (room_id, room) in &sync_response.rooms.join {
+            // A room we've just created, e.g. to DM someone, has us as the
+            // only joined member until they accept the invite.
+            let empty = room.summary.joined_member_count == Some(1)
+                && room.summary.invited_member_count == Some(0);
+
+            if empty && !self.is_direct_room(room_id) {
+                info!(self.logger, "Leaving room everyone else has left"; "room_id" => room_id);
+                self.leave_room(handle, room_id);
+                continue;
+            }
+
+            if let Err(err) = self.rooms.add_room(&self.user_id, room_id, &now) {
+                error!(self.logger, "Failed to add room"; "room_id" => room_id, "error" => %err);
+            }
+        }
+
+        let leave_after = match self.leave_after {
+            Some(leave_after) => leave_after,
+            None => return,
+        };
+
+        let inactive = match self
+            .rooms
+            .get_inactive_rooms(&self.user_id, &(now - leave_after))
+        {
+            Ok(inactive) => inactive,
+            Err(err) => {
+                error!(self.logger, "Failed to get inactive rooms"; "error" => %err);
+                return;
+            }
+        };
+
+        for room_id in inactive {
+            if self.is_direct_room(&room_id) {
+                continue;
+            }
+
+            info!(self.logger, "Leaving unused room"; "room_id" => &room_id);
+            self.leave_room(handle, &room_id);
+        }
+    }
+
+    /// Notes that a command was used in the room.
+    pub fn record_activity(&self, room_id: &str) {
+        if let Err(err) = self
+            .rooms
+            .record_activity(&self.user_id, room_id, &Utc::now())
+        {
+            error!(self.logger, "Failed to record room activity";
+                "room_id" => room_id, "error" => %err);
+        }
+    }
+
+    fn is_direct_room(&self, room_id: &str) -> bool {
+        match self.direct_rooms.is_direct_room(room_id) {
+            Ok(is_direct) => is_direct,
+            Err(err) => {
+                error!(self.logger, "Failed to check for direct room";
+                    "room_id" => room_id, "error" => %err);
+                // Err on the side of staying in the room
+                true
+            }
+        }
+    }
+
+    fn leave_room(&self, handle: &Handle, room_id: &str) {
+        // Stop tracking the room up front so we don't try to leave it again
+        // while the request is in flight.
+        if let Err(err) = self.rooms.remove_room(&self.user_id, room_id) {
+            error!(self.logger, "Failed to remove room"; "room_id" => room_id, "error" => %err);
+        }
+
+        handle.spawn(self.message_sender.leave_room(room_id));
+    }
+}
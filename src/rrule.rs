@@ -0,0 +1,357 @@
+use chrono::{
+    DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, TimeZone, Timelike, Utc, Weekday,
+};
+use chrono_tz::Tz;
+use failure::{Error, ResultExt};
+
+use std::fmt;
+
+/// How many days ahead we search for the next matching day.
+const MAX_SEARCH_DAYS: i64 = 8 * 366;
+
+/// How many steps we take when searching for the next match of a minutely
+/// or hourly rule.
+const MAX_SEARCH_STEPS: i32 = 366 * 24 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Frequency {
+    Minutely,
+    Hourly,
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+/// A subset of RFC 5545 recurrence rules, e.g. "FREQ=WEEKLY;BYDAY=MO,WE".
+///
+/// Rules don't carry a DTSTART, instead each occurrence is calculated
+/// relative to the previous one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RRule {
+    spec: String,
+    freq: Frequency,
+    interval: i64,
+    until: Option<DateTime<Utc>>,
+    by_month: Vec<u32>,
+    by_month_day: Vec<u32>,
+    by_day: Vec<Weekday>,
+    by_hour: Vec<u32>,
+    by_minute: Vec<u32>,
+}
+
+impl RRule {
+    pub fn parse(spec: &str) -> Result<RRule, Error> {
+        let spec = spec.trim().to_uppercase();
+        let spec = if spec.starts_with("RRULE:") {
+            spec[6..].to_string()
+        } else {
+            spec
+        };
+
+        // Occurrences follow on from when the reminder is first due, so a
+        // start of its own can't be honoured
+        if spec.contains("DTSTART") {
+            bail!("DTSTART isn't supported, give the first time instead");
+        }
+
+        let mut freq = None;
+        let mut rule = RRule {
+            spec: spec.clone(),
+            freq: Frequency::Daily,
+            interval: 1,
+            until: None,
+            by_month: Vec::new(),
+            by_month_day: Vec::new(),
+            by_day: Vec::new(),
+            by_hour: Vec::new(),
+            by_minute: Vec::new(),
+        };
+
+        for part in spec.split(';').filter(|p| !p.is_empty()) {
+            let mut split = part.splitn(2, '=');
+            let key = split.next().unwrap_or("");
+            let value = split
+                .next()
+                .ok_or_else(|| format_err!("invalid rule part {}", part))?;
+
+            match key {
+                "FREQ" => {
+                    freq = Some(match value {
+                        "MINUTELY" => Frequency::Minutely,
+                        "HOURLY" => Frequency::Hourly,
+                        "DAILY" => Frequency::Daily,
+                        "WEEKLY" => Frequency::Weekly,
+                        "MONTHLY" => Frequency::Monthly,
+                        "YEARLY" => Frequency::Yearly,
+                        _ => bail!("unsupported frequency {}", value),
+                    })
+                }
+                "INTERVAL" => {
+                    rule.interval = value.parse::<i64>().context("invalid interval")?;
+                    if rule.interval <= 0 {
+                        bail!("interval must be positive");
+                    }
+                }
+                "UNTIL" => rule.until = Some(parse_until(value)?),
+                "BYMONTH" => rule.by_month = parse_list(value, 1, 12)?,
+                "BYMONTHDAY" => rule.by_month_day = parse_list(value, 1, 31)?,
+                "BYHOUR" => rule.by_hour = parse_list(value, 0, 23)?,
+                "BYMINUTE" => rule.by_minute = parse_list(value, 0, 59)?,
+                "BYDAY" => {
+                    rule.by_day = value
+                        .split(',')
+                        .map(parse_weekday)
+                        .collect::<Result<_, _>>()?
+                }
+                "WKST" if value == "MO" => {}
+                // Each occurrence is worked out from the one before, so
+                // there's no way of counting them
+                "COUNT" => bail!("COUNT isn't supported, use UNTIL instead"),
+                _ => bail!("unsupported rule part {}", part),
+            }
+        }
+
+        rule.freq = freq.ok_or_else(|| format_err!("rule is missing FREQ"))?;
+
+        Ok(rule)
+    }
+
+    /// The normalized rule, suitable for parsing again.
+    pub fn spec(&self) -> &str {
+        &self.spec
+    }
+
+    /// Finds the first occurrence strictly after the given one, evaluated in
+    /// the local time of the given timezone.
+    pub fn next_after(&self, prev: DateTime<Tz>) -> Option<DateTime<Tz>> {
+        let tz = prev.timezone();
+        let anchor = prev.naive_local();
+
+        let next = match self.freq {
+            Frequency::Minutely | Frequency::Hourly => self.next_sub_daily(anchor)?,
+            _ => self.next_daily(anchor)?,
+        };
+
+        // If the local time doesn't exist due to DST then shift it forward
+        let next = tz.from_local_datetime(&next).earliest().or_else(|| {
+            tz.from_local_datetime(&(next + Duration::hours(1)))
+                .earliest()
+        })?;
+
+        if let Some(until) = self.until {
+            if next.with_timezone(&Utc) > until {
+                return None;
+            }
+        }
+
+        Some(next)
+    }
+
+    fn next_sub_daily(&self, anchor: NaiveDateTime) -> Option<NaiveDateTime> {
+        let step = match self.freq {
+            Frequency::Minutely => Duration::minutes(self.interval),
+            _ => Duration::hours(self.interval),
+        };
+
+        let mut candidate = anchor;
+        for _ in 0..MAX_SEARCH_STEPS {
+            candidate = candidate + step;
+
+            if self.matches_filters(&candidate.date())
+                && (self.by_hour.is_empty() || self.by_hour.contains(&candidate.hour()))
+                && (self.by_minute.is_empty() || self.by_minute.contains(&candidate.minute()))
+            {
+                return Some(candidate);
+            }
+        }
+
+        None
+    }
+
+    fn next_daily(&self, anchor: NaiveDateTime) -> Option<NaiveDateTime> {
+        let hours = if self.by_hour.is_empty() {
+            vec![anchor.hour()]
+        } else {
+            self.by_hour.clone()
+        };
+        let minutes = if self.by_minute.is_empty() {
+            vec![anchor.minute()]
+        } else {
+            self.by_minute.clone()
+        };
+        let second = if self.by_hour.is_empty() && self.by_minute.is_empty() {
+            anchor.second()
+        } else {
+            0
+        };
+
+        let anchor_date = anchor.date();
+
+        for offset in 0..MAX_SEARCH_DAYS {
+            let date = anchor_date + Duration::days(offset);
+
+            if !self.in_period(&anchor_date, &date) || !self.matches_day(&anchor_date, &date) {
+                continue;
+            }
+
+            for &hour in &hours {
+                for &minute in &minutes {
+                    let candidate = date.and_hms(hour, minute, second);
+                    if candidate > anchor {
+                        return Some(candidate);
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Whether the date falls in a period that is a multiple of the interval
+    /// from the anchor.
+    fn in_period(&self, anchor: &NaiveDate, date: &NaiveDate) -> bool {
+        let diff = match self.freq {
+            Frequency::Weekly => (week_start(date) - week_start(anchor)).num_days() / 7,
+            Frequency::Monthly => {
+                i64::from(date.year() - anchor.year()) * 12 + i64::from(date.month())
+                    - i64::from(anchor.month())
+            }
+            Frequency::Yearly => i64::from(date.year() - anchor.year()),
+            _ => (*date - *anchor).num_days(),
+        };
+
+        diff % self.interval == 0
+    }
+
+    fn matches_day(&self, anchor: &NaiveDate, date: &NaiveDate) -> bool {
+        if !self.matches_filters(date) {
+            return false;
+        }
+
+        // With no explicit day restrictions we repeat on the same day of
+        // the period as the anchor.
+        if self.by_month_day.is_empty() && self.by_day.is_empty() {
+            match self.freq {
+                Frequency::Weekly => return date.weekday() == anchor.weekday(),
+                Frequency::Monthly => return date.day() == anchor.day(),
+                Frequency::Yearly => {
+                    return date.day() == anchor.day()
+                        && (!self.by_month.is_empty() || date.month() == anchor.month())
+                }
+                _ => {}
+            }
+        }
+
+        true
+    }
+
+    fn matches_filters(&self, date: &NaiveDate) -> bool {
+        (self.by_month.is_empty() || self.by_month.contains(&date.month()))
+            && (self.by_month_day.is_empty() || self.by_month_day.contains(&date.day()))
+            && (self.by_day.is_empty() || self.by_day.contains(&date.weekday()))
+    }
+}
+
+impl fmt::Display for RRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.spec)
+    }
+}
+
+fn week_start(date: &NaiveDate) -> NaiveDate {
+    *date - Duration::days(i64::from(date.weekday().num_days_from_monday()))
+}
+
+fn parse_list(value: &str, min: u32, max: u32) -> Result<Vec<u32>, Error> {
+    let mut values = Vec::new();
+
+    for v in value.split(',') {
+        let v = v
+            .parse::<u32>()
+            .with_context(|_| format!("invalid value {}", v))?;
+
+        if v < min || v > max {
+            bail!("{} out of range {}-{}", v, min, max);
+        }
+
+        values.push(v);
+    }
+
+    values.sort();
+    values.dedup();
+
+    Ok(values)
+}
+
+fn parse_weekday(value: &str) -> Result<Weekday, Error> {
+    Ok(match value {
+        "MO" => Weekday::Mon,
+        "TU" => Weekday::Tue,
+        "WE" => Weekday::Wed,
+        "TH" => Weekday::Thu,
+        "FR" => Weekday::Fri,
+        "SA" => Weekday::Sat,
+        "SU" => Weekday::Sun,
+        _ => bail!("unsupported day {}", value),
+    })
+}
+
+fn parse_until(value: &str) -> Result<DateTime<Utc>, Error> {
+    if let Ok(date) = Utc.datetime_from_str(value, "%Y%m%dT%H%M%SZ") {
+        return Ok(date);
+    }
+
+    // A plain date includes the whole of that day
+    let date = NaiveDate::parse_from_str(value, "%Y%m%d")
+        .with_context(|_| format!("invalid UNTIL {}", value))?;
+
+    Ok(Utc.from_utc_datetime(&date.and_hms(23, 59, 59)))
+}
+
+#[test]
+fn rrule_test() {
+    use chrono_tz::UTC;
+
+    // A Tuesday
+    let dt = UTC.ymd(2014, 7, 8).and_hms(9, 0, 0);
+
+    let rule = RRule::parse("FREQ=WEEKLY;BYDAY=MO,WE").unwrap();
+    let next = rule.next_after(dt).unwrap();
+    assert_eq!(next, Utc.ymd(2014, 7, 9).and_hms(9, 0, 0));
+    assert_eq!(
+        rule.next_after(next).unwrap(),
+        Utc.ymd(2014, 7, 14).and_hms(9, 0, 0)
+    );
+
+    let rule = RRule::parse("RRULE:FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,WE").unwrap();
+    let next = rule.next_after(dt).unwrap();
+    assert_eq!(next, Utc.ymd(2014, 7, 9).and_hms(9, 0, 0));
+    assert_eq!(
+        rule.next_after(next).unwrap(),
+        Utc.ymd(2014, 7, 21).and_hms(9, 0, 0)
+    );
+
+    let rule = RRule::parse("freq=daily;byhour=17;byminute=30").unwrap();
+    assert_eq!(
+        rule.next_after(dt).unwrap(),
+        Utc.ymd(2014, 7, 8).and_hms(17, 30, 0)
+    );
+
+    let rule = RRule::parse("FREQ=MONTHLY;BYMONTHDAY=1").unwrap();
+    assert_eq!(
+        rule.next_after(dt).unwrap(),
+        Utc.ymd(2014, 8, 1).and_hms(9, 0, 0)
+    );
+
+    let rule = RRule::parse("FREQ=DAILY;UNTIL=20140709").unwrap();
+    let next = rule.next_after(dt).unwrap();
+    assert_eq!(next, Utc.ymd(2014, 7, 9).and_hms(9, 0, 0));
+    assert!(rule.next_after(next).is_none());
+
+    assert!(RRule::parse("BYDAY=MO").is_err());
+    assert!(RRule::parse("FREQ=WEEKLY;BYDAY=1MO").is_err());
+    assert!(RRule::parse("FREQ=WEEKLY;COUNT=3").is_err());
+    assert!(RRule::parse("DTSTART:20140708T090000Z\nRRULE:FREQ=DAILY").is_err());
+    assert!(RRule::parse("FREQ=DAILY;DTSTART=20140708T090000Z").is_err());
+}
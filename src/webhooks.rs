@@ -0,0 +1,542 @@
+use chrono::Utc;
+use chrono_tz::UTC;
+use failure::{Error, ResultExt};
+use futures::sync::mpsc::{self, UnboundedSender};
+use futures::sync::oneshot;
+use futures::{future, Future, Stream};
+use hmac::{Hmac, Mac};
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{self, Body, Method, Request, Response, StatusCode};
+use sha1::Sha1;
+use slog::Logger;
+use tokio_core::net::TcpListener;
+use tokio_core::reactor::Handle;
+use url::form_urlencoded;
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use date::parse_human_datetime;
+use db::{redact_msisdn, AddressBook, DeliveryStatus, DeliveryStatuses, Reminders, UserSettings};
+
+/// Path Twilio posts message status callbacks to.
+pub const STATUS_CALLBACK_PATH: &str = "/twilio/status";
+
+/// Path Twilio posts incoming SMS to. This needs to be configured as the
+/// messaging webhook of the Twilio number.
+pub const INBOUND_SMS_PATH: &str = "/twilio/sms";
+
+/// Path Twilio fetches the TwiML for reminder calls from, which reads out
+/// the `message` query param. Only signed requests are answered, so it
+/// can't be used by anyone else to have text read out.
+pub const CALL_TWIML_PATH: &str = "/twilio/call";
+
+/// Header Twilio puts the request signature in.
+const SIGNATURE_HEADER: &str = "X-Twilio-Signature";
+
+/// How long "SNOOZE" snoozes for if no time is given.
+const DEFAULT_SNOOZE: &str = "10 minutes";
+
+type ResponseFuture = Box<Future<Item = Response<Body>, Error = hyper::Error> + Send>;
+
+/// A webhook request received from Twilio.
+#[derive(Debug)]
+pub enum Webhook {
+    MessageStatus {
+        reminder_id: String,
+        message_sid: String,
+        status: String,
+        error_code: Option<String>,
+    },
+    InboundSms {
+        from: String,
+        body: String,
+        /// Where to send the text to reply with, if any
+        reply: oneshot::Sender<Option<String>>,
+    },
+}
+
+/// Checks requests were signed by Twilio with our auth token, so that
+/// nobody else can e.g. opt users out of SMS.
+///
+/// See https://www.twilio.com/docs/usage/security#validating-requests
+#[derive(Debug, Clone)]
+pub struct SignatureValidator {
+    auth_token: String,
+    /// The URL Twilio is configured to use, without a trailing slash. The
+    /// signature covers the full URL Twilio requested.
+    public_url: String,
+}
+
+impl SignatureValidator {
+    pub fn new(auth_token: String, public_url: String) -> SignatureValidator {
+        SignatureValidator {
+            auth_token,
+            public_url,
+        }
+    }
+
+    /// Checks the base64 encoded signature matches the path and query that
+    /// was requested and the POST params.
+    fn is_valid(
+        &self,
+        path_and_query: &str,
+        params: &HashMap<String, String>,
+        signature: &str,
+    ) -> bool {
+        let signature = match base64::decode(signature) {
+            Ok(signature) => signature,
+            Err(_) => return false,
+        };
+
+        let mut keys: Vec<_> = params.keys().collect();
+        keys.sort();
+
+        let mut mac = Hmac::<Sha1>::new_varkey(self.auth_token.as_bytes())
+            .expect("HMAC accepts keys of any length");
+
+        mac.input(self.public_url.as_bytes());
+        mac.input(path_and_query.as_bytes());
+        for key in keys {
+            mac.input(key.as_bytes());
+            mac.input(params[key].as_bytes());
+        }
+
+        // `verify` compares in constant time
+        mac.verify(&signature).is_ok()
+    }
+}
+
+/// Starts listening for Twilio webhooks on the given address.
+///
+/// Requests are parsed on the connection tasks and then handed back as a
+/// stream, so that they can be acted on by code that isn't `Send`.
+pub fn listen(
+    handle: &Handle,
+    addr: &SocketAddr,
+    validator: SignatureValidator,
+    logger: Logger,
+) -> Result<impl Stream<Item = Webhook, Error = ()>, Error> {
+    let listener = TcpListener::bind(addr, handle).context("failed to bind webhook listener")?;
+
+    info!(logger, "Listening for webhooks"; "address" => %addr);
+
+    let (sender, receiver) = mpsc::unbounded();
+    let validator = Arc::new(validator);
+
+    let http = Http::new();
+    let conn_handle = handle.clone();
+    let accept_logger = logger.clone();
+
+    let server = listener
+        .incoming()
+        .for_each(move |(socket, _)| {
+            let sender = sender.clone();
+            let validator = validator.clone();
+            let logger = logger.clone();
+            let conn_logger = logger.clone();
+
+            let conn = http
+                .serve_connection(
+                    socket,
+                    service_fn(move |req| handle_request(&logger, &validator, &sender, req)),
+                )
+                .map_err(move |err| {
+                    warn!(conn_logger, "Webhook connection failed"; "error" => %err);
+                });
+
+            conn_handle.spawn(conn);
+
+            Ok(())
+        })
+        .map_err(move |err| {
+            error!(accept_logger, "Webhook listener failed"; "error" => %err);
+        });
+
+    handle.spawn(server);
+
+    Ok(receiver)
+}
+
+fn handle_request(
+    logger: &Logger,
+    validator: &Arc<SignatureValidator>,
+    sender: &UnboundedSender<Webhook>,
+    req: Request<Body>,
+) -> ResponseFuture {
+    let path = req.uri().path().to_string();
+    let path_and_query = req
+        .uri()
+        .path_and_query()
+        .map(|p| p.as_str().to_string())
+        .unwrap_or_else(|| path.clone());
+
+    let known_path =
+        path == STATUS_CALLBACK_PATH || path == INBOUND_SMS_PATH || path == CALL_TWIML_PATH;
+    if req.method() != Method::POST || !known_path {
+        return Box::new(future::ok(empty_response(StatusCode::NOT_FOUND)));
+    }
+
+    let signature = req
+        .headers()
+        .get(SIGNATURE_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string());
+
+    let signature = match signature {
+        Some(signature) => signature,
+        None => {
+            warn!(logger, "Webhook request missing signature"; "path" => &path);
+            return Box::new(future::ok(empty_response(StatusCode::FORBIDDEN)));
+        }
+    };
+
+    let query = parse_form(req.uri().query().unwrap_or("").as_bytes());
+
+    let logger = logger.clone();
+    let validator = validator.clone();
+    let sender = sender.clone();
+
+    let f = req
+        .into_body()
+        .concat2()
+        .and_then(move |body| -> ResponseFuture {
+            let params = parse_form(&body);
+
+            if !validator.is_valid(&path_and_query, &params, &signature) {
+                warn!(logger, "Webhook request has invalid signature"; "path" => &path);
+                return Box::new(future::ok(empty_response(StatusCode::FORBIDDEN)));
+            }
+
+            if path == INBOUND_SMS_PATH {
+                handle_inbound_sms(&logger, &sender, &params)
+            } else if path == CALL_TWIML_PATH {
+                Box::new(future::ok(call_twiml_response(query.get("message"))))
+            } else {
+                Box::new(future::ok(handle_status_callback(
+                    &logger, &sender, &query, &params,
+                )))
+            }
+        });
+
+    Box::new(f)
+}
+
+fn handle_status_callback(
+    logger: &Logger,
+    sender: &UnboundedSender<Webhook>,
+    query: &HashMap<String, String>,
+    params: &HashMap<String, String>,
+) -> Response<Body> {
+    match parse_status_callback(query, params) {
+        Some(webhook) => {
+            debug!(logger, "Got webhook"; "webhook" => ?webhook);
+
+            if sender.unbounded_send(webhook).is_err() {
+                return empty_response(StatusCode::SERVICE_UNAVAILABLE);
+            }
+
+            empty_response(StatusCode::OK)
+        }
+        None => {
+            warn!(logger, "Invalid status callback"; "params" => ?params.keys().collect::<Vec<_>>());
+            empty_response(StatusCode::BAD_REQUEST)
+        }
+    }
+}
+
+/// Hands the SMS off to be handled, then replies with whatever message the
+/// handler comes back with.
+fn handle_inbound_sms(
+    logger: &Logger,
+    sender: &UnboundedSender<Webhook>,
+    params: &HashMap<String, String>,
+) -> ResponseFuture {
+    let (from, body) = match (params.get("From"), params.get("Body")) {
+        (Some(from), Some(body)) => (from.clone(), body.clone()),
+        _ => {
+            warn!(logger, "Invalid inbound SMS"; "params" => ?params.keys().collect::<Vec<_>>());
+            return Box::new(future::ok(empty_response(StatusCode::BAD_REQUEST)));
+        }
+    };
+
+    info!(logger, "Got inbound SMS"; "from" => redact_msisdn(&from));
+
+    let (reply, reply_receiver) = oneshot::channel();
+
+    if sender
+        .unbounded_send(Webhook::InboundSms { from, body, reply })
+        .is_err()
+    {
+        return Box::new(future::ok(empty_response(StatusCode::SERVICE_UNAVAILABLE)));
+    }
+
+    let f = reply_receiver.then(|res| Ok(twiml_response(res.ok().and_then(|reply| reply))));
+
+    Box::new(f)
+}
+
+fn parse_form(input: &[u8]) -> HashMap<String, String> {
+    form_urlencoded::parse(input).into_owned().collect()
+}
+
+/// Parses a message status callback. We add the reminder ID to the callback
+/// URL when sending the message.
+fn parse_status_callback(
+    query: &HashMap<String, String>,
+    params: &HashMap<String, String>,
+) -> Option<Webhook> {
+    Some(Webhook::MessageStatus {
+        reminder_id: query.get("reminder_id")?.clone(),
+        message_sid: params.get("MessageSid")?.clone(),
+        status: params.get("MessageStatus")?.clone(),
+        error_code: params.get("ErrorCode").cloned(),
+    })
+}
+
+/// A TwiML response, optionally replying to the sender with a message.
+fn twiml_response(message: Option<String>) -> Response<Body> {
+    let body = match message {
+        Some(message) => format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?><Response><Message>{}</Message></Response>",
+            escape_xml(&message)
+        ),
+        None => "<?xml version=\"1.0\" encoding=\"UTF-8\"?><Response></Response>".to_string(),
+    };
+
+    Response::builder()
+        .header("Content-Type", "text/xml")
+        .body(Body::from(body))
+        .expect("valid http response")
+}
+
+/// The TwiML for a reminder call, reading out the message.
+fn call_twiml_response(message: Option<&String>) -> Response<Body> {
+    let message = match message {
+        Some(message) => message,
+        None => return empty_response(StatusCode::BAD_REQUEST),
+    };
+
+    let body = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?><Response><Say>{}</Say></Response>",
+        escape_xml(message)
+    );
+
+    Response::builder()
+        .header("Content-Type", "text/xml")
+        .body(Body::from(body))
+        .expect("valid http response")
+}
+
+fn escape_xml(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+
+    for c in input.chars() {
+        match c {
+            '&' => output.push_str("&amp;"),
+            '<' => output.push_str("&lt;"),
+            '>' => output.push_str("&gt;"),
+            '"' => output.push_str("&quot;"),
+            '\'' => output.push_str("&apos;"),
+            c => output.push(c),
+        }
+    }
+
+    output
+}
+
+fn empty_response(status: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::empty())
+        .expect("valid http response")
+}
+
+/// Acts on webhooks received from Twilio.
+pub struct WebhookHandler {
+    logger: Logger,
+    delivery_statuses: DeliveryStatuses,
+    reminders: Reminders,
+    address_book: AddressBook,
+    user_settings: UserSettings,
+}
+
+impl WebhookHandler {
+    pub fn new(
+        logger: Logger,
+        delivery_statuses: DeliveryStatuses,
+        reminders: Reminders,
+        address_book: AddressBook,
+        user_settings: UserSettings,
+    ) -> WebhookHandler {
+        WebhookHandler {
+            logger,
+            delivery_statuses,
+            reminders,
+            address_book,
+            user_settings,
+        }
+    }
+
+    pub fn handle_webhook(&self, webhook: Webhook) {
+        match webhook {
+            Webhook::MessageStatus {
+                reminder_id,
+                message_sid,
+                status,
+                error_code,
+            } => {
+                info!(self.logger, "Got message status";
+                    "reminder_id" => &reminder_id,
+                    "status" => &status,
+                );
+
+                let res = self.delivery_statuses.set_status(&DeliveryStatus {
+                    message_sid,
+                    reminder_id,
+                    status,
+                    error_code,
+                    updated: Utc::now(),
+                });
+
+                if let Err(err) = res {
+                    error!(self.logger, "Failed to record message status"; "error" => %err);
+                }
+            }
+            Webhook::InboundSms { from, body, reply } => {
+                let message = match self.handle_inbound_sms(&from, &body) {
+                    Ok(message) => message,
+                    Err(err) => {
+                        error!(self.logger, "Failed to handle inbound SMS"; "error" => %err);
+                        Some("Sorry, something went wrong".to_string())
+                    }
+                };
+
+                // The request may have gone away, in which case there's no
+                // one to reply to.
+                let _ = reply.send(message);
+            }
+        }
+    }
+
+    /// Handles replies like "SNOOZE 30" or "STOP", returning the message to
+    /// reply with.
+    fn handle_inbound_sms(&self, from: &str, body: &str) -> Result<Option<String>, Error> {
+        let user_id = match self.address_book.get_user_for_msisdn(from)? {
+            Some(user_id) => user_id,
+            None => {
+                warn!(self.logger, "Got SMS from unknown number"; "from" => redact_msisdn(from));
+                return Ok(None);
+            }
+        };
+
+        let body = body.trim();
+        let (command, args) = match body.find(char::is_whitespace) {
+            Some(idx) => (&body[..idx], body[idx..].trim()),
+            None => (body, ""),
+        };
+
+        match &command.to_uppercase() as &str {
+            "STOP" => {
+                self.address_book.set_opted_out(&user_id, true)?;
+                info!(self.logger, "User opted out of SMS"; "user_id" => &user_id);
+
+                // Twilio sends its own confirmation for STOP
+                Ok(None)
+            }
+            "START" => {
+                self.address_book.set_opted_out(&user_id, false)?;
+                info!(self.logger, "User opted in to SMS"; "user_id" => &user_id);
+
+                Ok(None)
+            }
+            "SNOOZE" => self.snooze_last_reminder(&user_id, args).map(Some),
+            _ => Ok(Some(
+                "Reply SNOOZE <minutes> to snooze your last reminder, or STOP to stop reminders"
+                    .to_string(),
+            )),
+        }
+    }
+
+    fn snooze_last_reminder(&self, user_id: &str, when: &str) -> Result<String, Error> {
+        let reminder = match self.reminders.get_last_snoozable_reminder(user_id)? {
+            Some(reminder) => reminder,
+            None => return Ok("No reminder to snooze".to_string()),
+        };
+
+        let tz = self.user_settings.get_timezone(user_id)?.unwrap_or(UTC);
+        let now = Utc::now().with_timezone(&tz);
+        let settings = self.user_settings.get_parse_settings(user_id)?;
+
+        // A bare number is a number of minutes
+        let when = if when.is_empty() {
+            DEFAULT_SNOOZE.to_string()
+        } else if when.chars().all(|c| c.is_ascii_digit()) {
+            format!("{} minutes", when)
+        } else {
+            when.to_string()
+        };
+
+        let due = match parse_human_datetime(&format!("in {}", when), now, &settings) {
+            Ok(due) => due,
+            Err(_) => return Ok(format!("Couldn't understand '{}'", when)),
+        };
+
+        self.reminders
+            .snooze_reminder(&reminder.id, &due.with_timezone(&Utc))?;
+
+        info!(self.logger, "Snoozed reminder via SMS"; "reminder_id" => &reminder.id);
+
+        Ok(format!(
+            "Snoozed '{}' until '{}'",
+            reminder.text,
+            due.to_rfc2822()
+        ))
+    }
+}
+
+/// The example request from Twilio's docs.
+#[test]
+fn test_signature() {
+    let validator =
+        SignatureValidator::new("12345".to_string(), "https://mycompany.com".to_string());
+
+    let mut params: HashMap<String, String> = vec![
+        ("CallSid", "CA1234567890ABCDE"),
+        ("Caller", "+12349013030"),
+        ("Digits", "1234"),
+        ("From", "+12349013030"),
+        ("To", "+18005551212"),
+    ]
+    .into_iter()
+    .map(|(k, v)| (k.to_string(), v.to_string()))
+    .collect();
+
+    let path = "/myapp.php?foo=1&bar=2";
+    let signature = "0/KCTR6DLpKmkAf8muzZqo1nDgQ=";
+
+    assert!(validator.is_valid(path, &params, signature));
+
+    assert!(!validator.is_valid(path, &params, "not base64!"));
+    assert!(!validator.is_valid(path, &params, "RSOYDt4T1cUTdK1PDd93/VVr8B8="));
+    assert!(!validator.is_valid("/myapp.php?foo=1&bar=3", &params, signature));
+
+    params.insert("Body".to_string(), "STOP".to_string());
+    assert!(!validator.is_valid(path, &params, signature));
+}
+
+#[test]
+fn call_twiml_response_test() {
+    let res = call_twiml_response(Some(&"Feed <the> cat".to_string()));
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let body = res.into_body().concat2().wait().unwrap();
+    assert_eq!(
+        &body[..],
+        &b"<?xml version=\"1.0\" encoding=\"UTF-8\"?><Response><Say>Feed &lt;the&gt; cat</Say></Response>"[..]
+    );
+
+    assert_eq!(call_twiml_response(None).status(), StatusCode::BAD_REQUEST);
+}